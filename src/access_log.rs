@@ -0,0 +1,128 @@
+//! Per HTTP request logging, separate from task logs.
+//!
+//! Each request runs in a `request` span carrying its method, path and
+//! [request id][`crate::request_id`], so that events emitted
+//! while handling it are attributed to it. Once responded, status and latency are logged, as
+//! `WARN` if slower than `--slow-request-ms`.
+use std::time::Duration;
+
+use axum::http::{Request, Response};
+use tower_http::{
+    classify::{ServerErrorsAsFailures, SharedClassifier},
+    trace::{MakeSpan, OnResponse, TraceLayer},
+};
+use tracing::Span;
+
+use crate::request_id::RequestId;
+
+pub type AccessLogLayer =
+    TraceLayer<SharedClassifier<ServerErrorsAsFailures>, RequestSpan, (), LogResponse>;
+
+/// Build the layer, warning about requests taking `slow` or longer.
+pub fn access_log_layer(slow: Option<Duration>) -> AccessLogLayer {
+    TraceLayer::new_for_http()
+        .make_span_with(RequestSpan)
+        .on_request(())
+        .on_response(LogResponse { slow })
+}
+
+#[derive(Clone)]
+pub struct RequestSpan;
+
+impl<B> MakeSpan<B> for RequestSpan {
+    fn make_span(&mut self, req: &Request<B>) -> Span {
+        let id = req.extensions().get::<RequestId>().map(|id| id.0.as_str());
+        tracing::info_span!(
+            "request",
+            method = %req.method(),
+            path = %req.uri().path(),
+            request_id = %id.unwrap_or_default()
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct LogResponse {
+    slow: Option<Duration>,
+}
+
+impl<B> OnResponse<B> for LogResponse {
+    fn on_response(self, resp: &Response<B>, latency: Duration, _span: &Span) {
+        let status = resp.status();
+        let millis = latency.as_millis();
+        if self.slow.is_some_and(|slow| latency >= slow) {
+            tracing::warn!("\nSlow request responds {status} in {millis} ms.");
+        } else {
+            tracing::info!("\nRequest responds {status} in {millis} ms.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::access_log_layer;
+
+    /// Log lines written by tests.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl MakeWriter<'_> for Captured {
+        type Writer = Self;
+
+        fn make_writer(&self) -> Self {
+            self.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_request() {
+        let log = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(log.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route("/fast", get(|| async {}))
+            .route(
+                "/slow",
+                get(|| tokio::time::sleep(Duration::from_millis(100))),
+            )
+            .layer(access_log_layer(Some(Duration::from_millis(50))));
+        for path in ["/fast", "/slow"] {
+            let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        assert!(log.contains("INFO request{method=GET path=/fast"), "{log}");
+        assert!(log.contains("WARN request{method=GET path=/slow"), "{log}");
+        assert_eq!(
+            log.matches("Slow request responds 200 OK").count(),
+            1,
+            "{log}"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Guard of `/admin/*` endpoints, mounted only when `--admin-token` is set.
+//!
+//! Requests must carry `Authorization: Bearer <token>`, otherwise they are rejected with
+//! [`ClientError::Unauthorized`] (HTTP 401).
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::{
+    exception::{AppError, ClientError},
+    models::{AppResp, ServerState},
+};
+
+/// Middleware rejecting requests without the admin token.
+pub async fn require_admin(State(state): State<ServerState>, req: Request, next: Next) -> Response {
+    let token = state.runtime.read().unwrap().admin_token.clone();
+    let authorized = match token.as_deref() {
+        Some(token) => bearer(req.headers())
+            .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes())),
+        None => false,
+    };
+    if authorized {
+        return next.run(req).await;
+    }
+    tracing::warn!("\nUnauthorized request to {}.", req.uri().path());
+    let body: AppResp<()> = AppResp::Exception(AppError::from(ClientError::Unauthorized));
+    (StatusCode::UNAUTHORIZED, Json(body)).into_response()
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    value.strip_prefix("Bearer ").map(str::trim)
+}
+
+/// Compare without short-circuiting on the first differing byte, so that timing does not leak
+/// how much of a token or signature is guessed right.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    use super::{bearer, constant_time_eq};
+
+    #[test]
+    fn test_bearer() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer(&headers), None);
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Basic abc"));
+        assert_eq!(bearer(&headers), None);
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer abc"),
+        );
+        assert_eq!(bearer(&headers), Some("abc"));
+
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+    }
+}
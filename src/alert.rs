@@ -0,0 +1,134 @@
+//! Webhook alert on bursts of [`ServerError`], enabled by `--alert-webhook`.
+//!
+//! Failures of tasks due to server fault are counted in a sliding window of `--alert-window-secs`.
+//! Once more than `--alert-threshold` of them fall in the window, a JSON payload
+//! `{ "text": "...", "count": 6, "window_secs": 300, "code": "ai_model" }`
+//! is posted to the webhook with `curl`, at most once per window.
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::exception::ServerError;
+
+pub struct Alerter {
+    webhook: String,
+    threshold: usize,
+    window: Duration,
+    state: Mutex<AlertState>,
+}
+
+#[derive(Default)]
+struct AlertState {
+    /// When each error in the window occurred, oldest first.
+    errors: VecDeque<Instant>,
+    last_alert: Option<Instant>,
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    text: String,
+    count: usize,
+    window_secs: u64,
+    /// Code of the error triggering the alert.
+    code: &'a str,
+}
+
+impl Alerter {
+    pub fn new(webhook: String, threshold: usize, window: Duration) -> Self {
+        Self {
+            webhook,
+            threshold,
+            window,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Count `err`, posting an alert in background if the threshold is exceeded.
+    pub fn record(&self, err: &ServerError) {
+        let Some(count) = self.record_at(Instant::now()) else {
+            return;
+        };
+        let window_secs = self.window.as_secs();
+        let payload = Payload {
+            text: format!("{count} server errors within {window_secs} seconds, latest: {err}"),
+            count,
+            window_secs,
+            code: err.code(),
+        };
+        let body = serde_json::to_string(&payload).unwrap_or_default();
+        let webhook = self.webhook.clone();
+        tracing::warn!("\nDispatch alert of {count} server errors to webhook.");
+        tokio::spawn(async move {
+            let result = tokio::process::Command::new("curl")
+                .args(["-fsS", "-m", "10", "-X", "POST"])
+                .args([
+                    "-H",
+                    "content-type: application/json",
+                    "-d",
+                    &body,
+                    &webhook,
+                ])
+                .output()
+                .await;
+            match result {
+                Ok(output) if output.status.success() => {
+                    tracing::info!("\nAlert is delivered to webhook.");
+                }
+                Ok(output) => tracing::error!(
+                    "\nAlert webhook fails: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+                Err(e) => tracing::error!("\nFailed to issue curl for alert: {e}"),
+            }
+        });
+    }
+
+    /// Count an error at `now`, returning the count in window if an alert is due.
+    fn record_at(&self, now: Instant) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.errors.push_back(now);
+        while let Some(&oldest) = state.errors.front() {
+            if now.saturating_duration_since(oldest) < self.window {
+                break;
+            }
+            state.errors.pop_front();
+        }
+        let count = state.errors.len();
+        let debounced = state
+            .last_alert
+            .is_some_and(|last| now.saturating_duration_since(last) < self.window);
+        if count <= self.threshold || debounced {
+            return None;
+        }
+        state.last_alert = Some(now);
+        Some(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::Alerter;
+
+    #[test]
+    fn test_alert_window() {
+        let alerter = Alerter::new(String::new(), 2, Duration::from_secs(60));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(alerter.record_at(at(0)), None);
+        assert_eq!(alerter.record_at(at(1)), None);
+        assert_eq!(alerter.record_at(at(2)), Some(3));
+        // debounced within the same window
+        assert_eq!(alerter.record_at(at(3)), None);
+        // still debounced, though 0 and 1 have left the window
+        assert_eq!(alerter.record_at(at(61)), None);
+        assert_eq!(alerter.record_at(at(63)), None);
+        assert_eq!(alerter.record_at(at(64)), Some(3));
+    }
+}
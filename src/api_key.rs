@@ -0,0 +1,45 @@
+//! Shared key gating the API, see `--api-key`.
+//!
+//! With a key configured, each request but those of `/doc` must carry it in `X-Api-Key`,
+//! otherwise it is rejected with [`ClientError::Unauthorized`] (HTTP 401) before the handler
+//! runs. Unlike `--api-secret`, the key travels with every request, so it only suits deployments
+//! behind TLS, but any client can send it, including browsers. Without a key, the API is open.
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::{
+    admin::constant_time_eq,
+    exception::{AppError, ClientError},
+    models::{AppResp, ServerState},
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Middleware rejecting requests without `--api-key`, if set.
+pub async fn require_api_key(
+    State(state): State<ServerState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(key) = state.config.api_key.as_deref() else {
+        return next.run(req).await;
+    };
+    let given = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if given.is_some_and(|given| constant_time_eq(given.as_bytes(), key.as_bytes())) {
+        return next.run(req).await;
+    }
+    match given {
+        Some(_) => tracing::warn!("\nRequest to {} has wrong API key.", req.uri().path()),
+        None => tracing::warn!("\nRequest to {} lacks API key.", req.uri().path()),
+    }
+    let body: AppResp<()> = AppResp::Exception(AppError::from(ClientError::Unauthorized));
+    (StatusCode::UNAUTHORIZED, Json(body)).into_response()
+}
@@ -0,0 +1,182 @@
+//! Version of the response envelope a client pins, see [`AppResp`][`crate::models::AppResp`].
+//!
+//! Chosen by `?api_version=2`, or else by `Accept: application/vnd.summary.v2+json`, and `1` if
+//! neither is given. Version 1 is the original envelope, where `err` wraps the error object in
+//! another `{ success, err }`:
+//! `{ success: false, err: { success: false, err: { source, info, code, ... } }, request_id }`
+//! while version 2 has the error object right in `err`, along with its `request_id`:
+//! `{ success: false, err: { source, info, code, ..., request_id } }`
+//! Successful responses are the same in both. [`api_version`] extracts the version and keeps it
+//! for the serialization of responses of the request.
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request},
+    http::{header, request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::{exception::ClientError, models::AppResp};
+
+tokio::task_local! {
+    static API_VERSION: ApiVersion;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// Envelope with the error object wrapped twice.
+    #[default]
+    V1,
+    /// Envelope with the error object right in `err`.
+    V2,
+}
+
+impl ApiVersion {
+    fn parse(version: &str) -> Option<Self> {
+        match version {
+            "1" => Some(Self::V1),
+            "2" => Some(Self::V2),
+            _ => None,
+        }
+    }
+}
+
+/// Requested version, rejected with `406` and `malformed_request` error if unknown.
+#[async_trait]
+impl<S> FromRequestParts<S> for ApiVersion
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let queried = parts.uri.query().and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("api_version="))
+        });
+        let accepted = || {
+            parts
+                .headers
+                .get_all(header::ACCEPT)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .find_map(|media_type| {
+                    let media_type = media_type.split(';').next().unwrap_or_default().trim();
+                    media_type
+                        .strip_prefix("application/vnd.summary.v")?
+                        .strip_suffix("+json")
+                })
+        };
+        let Some(requested) = queried.or_else(accepted) else {
+            return Ok(Self::default());
+        };
+        Self::parse(requested).ok_or_else(|| {
+            let e = ClientError::MalformedRequest(format!("api version {requested} is unknown"));
+            let body: AppResp<()> = AppResp::Exception(e.into());
+            (StatusCode::NOT_ACCEPTABLE, Json(body)).into_response()
+        })
+    }
+}
+
+/// Middleware keeping the [`ApiVersion`] of each request for its responses, to be inside
+/// [`crate::request_id::request_id`] so that rejections carry a request id too.
+pub async fn api_version(version: ApiVersion, req: Request, next: Next) -> Response {
+    API_VERSION.scope(version, next.run(req)).await
+}
+
+/// Version of the request being handled by current task, [`ApiVersion::V1`] outside of
+/// [`api_version`].
+pub fn current() -> ApiVersion {
+    API_VERSION.try_with(|version| *version).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Request, StatusCode},
+        middleware,
+        routing::get,
+        Json, Router,
+    };
+    use tower::ServiceExt;
+
+    use super::api_version;
+    use crate::{
+        exception::{AppError, ServerError},
+        models::{AppResp, AppRespOwned, InitiateResp},
+    };
+
+    async fn call(uri: &str, accept: Option<&str>) -> (StatusCode, String) {
+        let app = Router::new()
+            .route(
+                "/ok",
+                get(|| async {
+                    Json(AppResp::Success(InitiateResp {
+                        uuid: "123".into(),
+                        deduplicated: false,
+                    }))
+                }),
+            )
+            .route(
+                "/err",
+                get(|| async {
+                    Json(AppResp::<()>::Exception(AppError::Server(
+                        ServerError::BindPort(80),
+                    )))
+                }),
+            )
+            .layer(middleware::from_fn(api_version));
+        let mut req = Request::get(uri);
+        if let Some(accept) = accept {
+            req = req.header(header::ACCEPT, accept);
+        }
+        let resp = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+        let status = resp.status();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_api_version() {
+        const MEDIA_TYPE_V2: &str = "application/vnd.summary.v2+json";
+        let success = r#"{"success":true,"data":{"uuid":"123","deduplicated":false}}"#;
+        let v1 = r#"{"success":false,"err":{"success":false,"err":{"source":"server","info":"Listen to port 80 failed.","code":"bind_port","recoverable":false}}}"#;
+        let v2 = r#"{"success":false,"err":{"source":"server","info":"Listen to port 80 failed.","code":"bind_port","recoverable":false}}"#;
+        for (uri, accept) in [
+            ("/ok", None),
+            ("/ok?api_version=2", None),
+            ("/ok", Some(MEDIA_TYPE_V2)),
+        ] {
+            assert_eq!(call(uri, accept).await, (StatusCode::OK, success.into()));
+        }
+        for (uri, accept, expected) in [
+            ("/err", None, v1),
+            ("/err?api_version=1", None, v1),
+            ("/err", Some("application/vnd.summary.v1+json"), v1),
+            ("/err?x=1&api_version=2", None, v2),
+            (
+                "/err",
+                Some("text/html, application/vnd.summary.v2+json; q=0.9"),
+                v2,
+            ),
+            // query wins
+            ("/err?api_version=1", Some(MEDIA_TYPE_V2), v1),
+        ] {
+            assert_eq!(call(uri, accept).await, (StatusCode::OK, expected.into()));
+        }
+        // both parse alike
+        let parse = |json: &str| serde_json::from_str::<AppRespOwned<()>>(json).unwrap();
+        assert_eq!(parse(v1), parse(v2));
+
+        let (status, body) = call("/ok?api_version=3", None).await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+        let AppRespOwned::<()>::Exception(e) = serde_json::from_str(&body).unwrap() else {
+            panic!("unknown version accepted");
+        };
+        assert_eq!(e.code, "malformed_request");
+    }
+}
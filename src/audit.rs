@@ -0,0 +1,120 @@
+//! Append-only JSONL record of finished tasks, enabled by `--audit-log`.
+//!
+//! Unlike the tracing log, each line is a self-contained JSON object meant for analytics:  
+//! ```text
+//! {"ts":"2024-12-07T01:01:22Z","uuid":"bb58281b-...","stage":"Done","duration_secs":74.1,"error":null}
+//! {"ts":"2024-12-07T01:38:48Z","uuid":"7b846c96-...","stage":"Err","duration_secs":1.2,"error":{"source":"client"}}
+//! ```
+//! Urls never appear in the audit log, error details are reduced to their source for the same
+//! reason, as they may quote the url.
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+
+use crate::models::TaskStatus;
+
+/// Handle to the audit file, written by a dedicated thread so that tasks never block on IO.
+pub struct AuditLog {
+    writer: NonBlocking,
+}
+
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    ts: String,
+    uuid: &'a str,
+    stage: &'a TaskStatus,
+    duration_secs: f64,
+    error: Option<AuditError>,
+}
+
+#[derive(Serialize)]
+struct AuditError {
+    source: &'static str,
+    code: &'static str,
+}
+
+impl AuditLog {
+    /// Open `path` for appending, creating it if absent.
+    ///
+    /// Pending lines are flushed when the returned [`WorkerGuard`] drops.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<(Self, WorkerGuard)> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (writer, guard) = tracing_appender::non_blocking(file);
+        Ok((Self { writer }, guard))
+    }
+
+    /// Append the terminal `status` of `uuid`, reached `duration` after the task started.
+    pub fn record(&self, uuid: &str, status: &TaskStatus, duration: Duration) {
+        let error = match status {
+            TaskStatus::Err(e) => Some(AuditError {
+                source: e.source(),
+                code: e.code(),
+            }),
+            _ => None,
+        };
+        let entry = AuditEntry {
+            ts: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            uuid,
+            stage: status,
+            duration_secs: duration.as_secs_f64(),
+            error,
+        };
+        let Ok(mut line) = serde_json::to_string(&entry) else {
+            return;
+        };
+        line.push('\n');
+        // a single write is sent to the worker as a whole, so lines never interleave
+        let mut writer = self.writer.clone();
+        if let Err(e) = writer.write_all(line.as_bytes()) {
+            tracing::error!("\nFailed to write audit log: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, time::Duration};
+
+    use uuid::Uuid;
+
+    use super::AuditLog;
+    use crate::{
+        exception::{AppError, ClientError},
+        models::TaskStatus,
+    };
+
+    #[test]
+    fn test_record() {
+        let path = std::env::temp_dir().join(format!("{}.jsonl", Uuid::new_v4()));
+        let (audit, guard) = AuditLog::open(&path).unwrap();
+        audit.record("a", &TaskStatus::Done, Duration::from_secs(3));
+        let err = ClientError::VideoLinkNotExist("https://secret.url".into());
+        audit.record("b", &TaskStatus::Err(AppError::from(err)), Duration::ZERO);
+        drop(guard);
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["uuid"], "a");
+        assert_eq!(lines[0]["stage"], "Done");
+        assert_eq!(lines[0]["duration_secs"], 3.0);
+        assert!(lines[0]["error"].is_null());
+        assert_eq!(lines[1]["stage"], "Err");
+        assert_eq!(lines[1]["error"]["source"], "client");
+        assert_eq!(lines[1]["error"]["code"], "video_link_not_exist");
+        assert!(!content.contains("secret"));
+        fs::remove_file(path).unwrap();
+    }
+}
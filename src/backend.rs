@@ -0,0 +1,120 @@
+//! Named model scripts selectable per task, see `--backend`.
+//!
+//! E.g. `--backend local=run_model.sh --backend cloud=run_cloud.sh --default-backend local`.
+//! Every script takes the arguments of `run_model.sh`, which is the only backend, named
+//! `default`, if none is configured.
+use std::collections::BTreeMap;
+
+/// Script run when no `--backend` is configured.
+pub const DEFAULT_MODEL_SCRIPT: &str = "run_model.sh";
+/// Name of [`DEFAULT_MODEL_SCRIPT`] as a backend.
+const DEFAULT_BACKEND: &str = "default";
+
+/// Registry of model scripts by backend name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backends {
+    scripts: BTreeMap<String, String>,
+    default: String,
+}
+
+impl Default for Backends {
+    fn default() -> Self {
+        Self {
+            scripts: BTreeMap::from([(DEFAULT_BACKEND.into(), DEFAULT_MODEL_SCRIPT.into())]),
+            default: DEFAULT_BACKEND.into(),
+        }
+    }
+}
+
+impl Backends {
+    /// Registry of `backends`, where `default` falls back to the first of them.
+    ///
+    /// Fails if a name is repeated or `default` is not one of them.
+    pub fn new(backends: Vec<(String, String)>, default: Option<String>) -> Result<Self, String> {
+        let Some((first, _)) = backends.first() else {
+            return match default {
+                Some(name) => Err(format!("default backend \"{name}\" is not configured")),
+                None => Ok(Self::default()),
+            };
+        };
+        let default = default.unwrap_or_else(|| first.clone());
+        let mut scripts = BTreeMap::new();
+        for (name, script) in backends {
+            if scripts.insert(name.clone(), script).is_some() {
+                return Err(format!("backend \"{name}\" is configured twice"));
+            }
+        }
+        if !scripts.contains_key(&default) {
+            return Err(format!("default backend \"{default}\" is not configured"));
+        }
+        Ok(Self { scripts, default })
+    }
+
+    /// Name of the backend chosen by `name`, the default if `None`, or `None` if unknown.
+    pub fn resolve(&self, name: Option<&str>) -> Option<&str> {
+        let name = name.unwrap_or(&self.default);
+        self.scripts
+            .get_key_value(name)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Script of the backend `name`, the default if `None`.
+    pub fn script(&self, name: Option<&str>) -> Option<&str> {
+        self.scripts
+            .get(name.unwrap_or(&self.default))
+            .map(String::as_str)
+    }
+
+    /// Name of the backend used when a task names none.
+    pub fn default_name(&self) -> &str {
+        &self.default
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.scripts.keys().map(String::as_str)
+    }
+}
+
+/// Parse `--backend`, a `name=script` pair.
+pub fn parse_backend(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, script)) if !name.is_empty() && !script.is_empty() => {
+            Ok((name.to_string(), script.to_string()))
+        }
+        _ => Err(format!(
+            "malformed backend \"{s}\", expect format like cloud=run_cloud.sh"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_backend, Backends, DEFAULT_MODEL_SCRIPT};
+
+    #[test]
+    fn test_backends() {
+        let builtin = Backends::new(Vec::new(), None).unwrap();
+        assert_eq!(builtin.script(None), Some(DEFAULT_MODEL_SCRIPT));
+        assert_eq!(builtin.resolve(None), Some("default"));
+
+        let pair = |s: &str| parse_backend(s).unwrap();
+        let backends = vec![pair("local=run_model.sh"), pair("cloud=run_cloud.sh")];
+        let registry = Backends::new(backends.clone(), None).unwrap();
+        assert_eq!(registry.resolve(None), Some("local"));
+        assert_eq!(registry.resolve(Some("cloud")), Some("cloud"));
+        assert_eq!(registry.resolve(Some("default")), None);
+        assert_eq!(registry.script(Some("cloud")), Some("run_cloud.sh"));
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["cloud", "local"]);
+
+        let registry = Backends::new(backends.clone(), Some("cloud".into())).unwrap();
+        assert_eq!(registry.script(None), Some("run_cloud.sh"));
+        assert!(Backends::new(backends.clone(), Some("gpu".into())).is_err());
+        assert!(Backends::new(Vec::new(), Some("gpu".into())).is_err());
+        let twice = vec![pair("a=x.sh"), pair("a=y.sh")];
+        assert!(Backends::new(twice, None).is_err());
+
+        for malformed in ["cloud", "=x.sh", "cloud="] {
+            assert!(parse_backend(malformed).is_err(), "{malformed}");
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Content-addressed store of task audio, enabled by `--dedup-audio`.
+//!
+//! Once a task has its audio, the file is hashed and replaced by a link to an identical blob in
+//! `.blobs` under the work dir if there is one, or becomes that blob otherwise. A video popular
+//! among users is thus stored once, while nothing about the store shows through the API.
+//!
+//! Blobs are hard links rather than symlinks, so that the link count of a blob is its reference
+//! count: removing a task dir by whatever means releases its reference, and blobs no task links
+//! to are dropped by the sweeper. The hash only finds a candidate, whose content is compared in
+//! full before linking, so that a collision never mixes up audio of different tasks.
+use std::{
+    fs::{self, File},
+    hash::{DefaultHasher, Hasher},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use uuid::Uuid;
+
+/// Dir of blobs under the work dir.
+pub const BLOB_DIR: &str = ".blobs";
+/// Suffix of a link being moved into a task dir.
+const TMP_SUFFIX: &str = ".tmp";
+
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Deduplicate the file at `path` against the store, returning the bytes saved.
+    pub async fn store(&self, path: &Path) -> io::Result<u64> {
+        let (dir, path) = (self.dir.clone(), path.to_path_buf());
+        tokio::task::spawn_blocking(move || store(&dir, &path))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    /// Remove blobs no task links to, returning how many.
+    pub async fn purge_unreferenced(&self) -> usize {
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || purge(&dir))
+            .await
+            .unwrap_or_default()
+    }
+}
+
+fn store(dir: &Path, path: &Path) -> io::Result<u64> {
+    let (len, hash) = digest(path)?;
+    let name = format!("{hash:016x}-{len:x}");
+    let blob = dir.join(&name);
+    fs::create_dir_all(dir)?;
+    let blob_metadata = match fs::metadata(&blob) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return match fs::hard_link(path, &blob) {
+                // lost a race against the same content, which is deduplicated next time
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => Err(e),
+                _ => Ok(0),
+            };
+        }
+        Err(e) => return Err(e),
+    };
+    if same_file(&blob_metadata, &fs::metadata(path)?) || !same_content(&blob, path)? {
+        return Ok(0);
+    }
+    // replace atomically, readers of `path` see either file in full
+    let tmp = dir.join(format!("{name}.{}{TMP_SUFFIX}", Uuid::new_v4()));
+    match fs::hard_link(&blob, &tmp) {
+        // purged in between, as the last task linking it is gone
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        result => result?,
+    }
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(len)
+}
+
+fn purge(dir: &Path) -> usize {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // a link left over by a crash in the middle of `store`
+        let stray = path.to_str().is_some_and(|path| path.ends_with(TMP_SUFFIX));
+        let unreferenced = entry.metadata().is_ok_and(|metadata| links(&metadata) <= 1);
+        if !stray && !unreferenced {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(e) => tracing::error!("\nFailed to remove blob \"{}\": {e}", path.display()),
+        }
+    }
+    removed
+}
+
+/// Length and hash of the content of `path`.
+///
+/// The hash is not stable across Rust releases, which only costs deduplication against blobs
+/// stored by a build of another release.
+fn digest(path: &Path) -> io::Result<(u64, u64)> {
+    let mut file = File::open(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut len = 0;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok((len, hasher.finish()));
+        }
+        hasher.write(&buf[..n]);
+        len += n as u64;
+    }
+}
+
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    let (mut buf_a, mut buf_b) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(b.read(&mut buf_b[..1])? == 0);
+        }
+        if b.read_exact(&mut buf_b[..n]).is_err() || buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+#[cfg(unix)]
+fn links(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink()
+}
+
+/// Files are never taken as the same on other platforms, so linking is attempted again.
+#[cfg(not(unix))]
+fn same_file(_a: &fs::Metadata, _b: &fs::Metadata) -> bool {
+    false
+}
+
+/// Link counts are unknown on other platforms, so blobs are never purged.
+#[cfg(not(unix))]
+fn links(_metadata: &fs::Metadata) -> u64 {
+    u64::MAX
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::{links, same_file, BlobStore, BLOB_DIR};
+
+    #[tokio::test]
+    async fn test_store() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let tasks = ["a", "b", "c", "d"].map(|task| dir.join(task).join("audio.mp3"));
+        for (task, content) in tasks.iter().zip(["audio", "audio", "other", "audio"]) {
+            fs::create_dir_all(task.parent().unwrap()).unwrap();
+            fs::write(task, content).unwrap();
+        }
+        let store = BlobStore::new(dir.join(BLOB_DIR));
+        assert_eq!(store.store(&tasks[0]).await.unwrap(), 0);
+        assert_eq!(store.store(&tasks[1]).await.unwrap(), 5);
+        assert_eq!(store.store(&tasks[2]).await.unwrap(), 0);
+        // already linked
+        assert_eq!(store.store(&tasks[1]).await.unwrap(), 0);
+        let metadata = |i: usize| fs::metadata(&tasks[i]).unwrap();
+        assert!(same_file(&metadata(0), &metadata(1)));
+        assert!(!same_file(&metadata(0), &metadata(2)));
+        assert_eq!(fs::read_to_string(&tasks[1]).unwrap(), "audio");
+        assert_eq!(links(&metadata(0)), 3);
+
+        // blobs are kept while some task links to them
+        fs::remove_dir_all(dir.join("a")).unwrap();
+        fs::remove_dir_all(dir.join("c")).unwrap();
+        assert_eq!(store.purge_unreferenced().await, 1);
+        assert_eq!(store.store(&tasks[3]).await.unwrap(), 5);
+        assert!(same_file(&metadata(1), &metadata(3)));
+        fs::remove_dir_all(dir.join("b")).unwrap();
+        assert_eq!(store.purge_unreferenced().await, 0);
+        fs::remove_dir_all(dir.join("d")).unwrap();
+        assert_eq!(store.purge_unreferenced().await, 1);
+        assert_eq!(fs::read_dir(dir.join(BLOB_DIR)).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! `clean` subcommand, removing task dirs left behind in `work_dir`.
+//!
+//! Task table lives in memory, so a task dir outlives its task whenever the server crashes or is
+//! killed before the result is fetched. Only dirs named by a uuid are considered, and a dir is
+//! stale when nothing in it was modified within `--older-than`.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use uuid::Uuid;
+
+/// Stale task dirs found by [`scan`].
+#[derive(Debug, Default)]
+pub struct CleanReport {
+    pub dirs: Vec<(PathBuf, u64)>,
+}
+
+impl CleanReport {
+    pub fn total_bytes(&self) -> u64 {
+        self.dirs.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+/// Find task dirs in `work_dir` not modified since `now - older_than`, along with their sizes.
+pub fn scan(work_dir: &Path, older_than: Duration, now: SystemTime) -> io::Result<CleanReport> {
+    let mut report = CleanReport::default();
+    for entry in fs::read_dir(work_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_task = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| Uuid::parse_str(name).is_ok());
+        if !is_task || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let (modified, bytes) = usage(&path)?;
+        let age = now.duration_since(modified).unwrap_or_default();
+        if age >= older_than {
+            report.dirs.push((path, bytes));
+        }
+    }
+    report.dirs.sort();
+    Ok(report)
+}
+
+/// Latest modification time and total size of files under `dir`, including itself.
+pub fn usage(dir: &Path) -> io::Result<(SystemTime, u64)> {
+    let mut modified = fs::metadata(dir)?.modified()?;
+    let mut bytes = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let (entry_modified, entry_bytes) = match metadata.is_dir() {
+            true => usage(&entry.path())?,
+            false => (metadata.modified()?, metadata.len()),
+        };
+        modified = modified.max(entry_modified);
+        bytes += entry_bytes;
+    }
+    Ok((modified, bytes))
+}
+
+/// Run `clean`, printing what is (or would be with `dry_run`) removed.
+pub fn clean(work_dir: &Path, older_than: Duration, dry_run: bool) -> io::Result<()> {
+    let report = scan(work_dir, older_than, SystemTime::now())?;
+    let mut freed = 0;
+    for (dir, bytes) in &report.dirs {
+        if dry_run {
+            println!("would remove {} ({bytes} bytes)", dir.display());
+            continue;
+        }
+        match fs::remove_dir_all(dir) {
+            Ok(()) => {
+                println!("removed {} ({bytes} bytes)", dir.display());
+                freed += bytes;
+            }
+            Err(e) => eprintln!("failed to remove {}: {e}", dir.display()),
+        }
+    }
+    match dry_run {
+        true => println!(
+            "{} stale task dirs, {} bytes would be freed.",
+            report.dirs.len(),
+            report.total_bytes()
+        ),
+        false => println!(
+            "{} stale task dirs, {freed} bytes freed.",
+            report.dirs.len()
+        ),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    use uuid::Uuid;
+
+    use super::scan;
+
+    #[test]
+    fn test_scan() {
+        let work_dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let task_dir = work_dir.join(Uuid::new_v4().to_string());
+        fs::create_dir_all(task_dir.join("nested")).unwrap();
+        fs::write(task_dir.join("summary.txt"), "a summary").unwrap();
+        fs::write(task_dir.join("nested").join("audio.mp3"), "audio").unwrap();
+        // not a task dir
+        fs::create_dir_all(work_dir.join("other")).unwrap();
+
+        let hour = Duration::from_secs(3600);
+        let now = SystemTime::now();
+        assert!(scan(&work_dir, hour, now).unwrap().dirs.is_empty());
+        let report = scan(&work_dir, hour, now + 2 * hour).unwrap();
+        assert_eq!(report.dirs, [(task_dir, 14)]);
+        assert_eq!(report.total_bytes(), 14);
+        fs::remove_dir_all(work_dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+//! Address of the client behind reverse proxies, see `--trust-proxy`.
+//!
+//! Each proxy appends the address it received from to `X-Forwarded-For`, so with
+//! `--trusted-proxy-hops` proxies in front of the server, the client is the entry that many
+//! from the end. Entries before it are whatever the client sent, and are never trusted.
+//! `X-Real-IP` is consulted only without `X-Forwarded-For` and with a single hop, as it is
+//! overwritten by each proxy. Without `--trust-proxy`, or if neither header is usable, the
+//! socket peer is the client.
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+
+use crate::models::ServerState;
+
+/// Client address of a request, `None` if the server is not serving with connect info, as in
+/// tests, and no proxy header is trusted.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl FromRequestParts<ServerState> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let hops = match state.config.trust_proxy {
+            true => state.config.trusted_proxy_hops,
+            false => 0,
+        };
+        Ok(Self(client_ip(&parts.headers, peer, hops)))
+    }
+}
+
+/// Client address of a request from `peer` through `hops` trusted proxies, 0 if none.
+fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, hops: usize) -> Option<IpAddr> {
+    if hops == 0 {
+        return peer;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    let proxied = match forwarded.is_empty() {
+        true if hops == 1 => headers
+            .get("x-real-ip")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_ip),
+        true => None,
+        // fewer entries than hops, all appended by trusted proxies
+        false => parse_ip(forwarded[forwarded.len().saturating_sub(hops)]),
+    };
+    proxied.or(peer)
+}
+
+/// An address, with or without port as some proxies add, e.g. `203.0.113.7:4321`.
+fn parse_ip(s: &str) -> Option<IpAddr> {
+    let s = s.trim();
+    s.parse()
+        .ok()
+        .or_else(|| s.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::client_ip;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let peer = Some(ip("10.0.0.1"));
+
+        // headers are ignored unless trusted
+        let spoofed = headers(&[("x-forwarded-for", "1.1.1.1"), ("x-real-ip", "2.2.2.2")]);
+        assert_eq!(client_ip(&spoofed, peer, 0), peer);
+        assert_eq!(client_ip(&HeaderMap::new(), None, 0), None);
+
+        // client prepends a fake entry, the proxy appends the real one
+        let forwarded = headers(&[("x-forwarded-for", "1.1.1.1, 203.0.113.7")]);
+        assert_eq!(client_ip(&forwarded, peer, 1), Some(ip("203.0.113.7")));
+        // through two proxies, the second appending the first
+        let forwarded = headers(&[
+            ("x-forwarded-for", "1.1.1.1, 203.0.113.7"),
+            ("x-forwarded-for", "10.0.0.2"),
+        ]);
+        assert_eq!(client_ip(&forwarded, peer, 2), Some(ip("203.0.113.7")));
+        let forwarded = headers(&[("x-forwarded-for", "203.0.113.7:4321")]);
+        assert_eq!(client_ip(&forwarded, peer, 3), Some(ip("203.0.113.7")));
+        let forwarded = headers(&[("x-forwarded-for", "[2001:db8::1]:80")]);
+        assert_eq!(client_ip(&forwarded, peer, 1), Some(ip("2001:db8::1")));
+
+        let real = headers(&[("x-real-ip", "203.0.113.7")]);
+        assert_eq!(client_ip(&real, peer, 1), Some(ip("203.0.113.7")));
+        assert_eq!(client_ip(&real, peer, 2), peer);
+
+        let garbage = headers(&[("x-forwarded-for", "unknown")]);
+        assert_eq!(client_ip(&garbage, peer, 1), peer);
+    }
+}
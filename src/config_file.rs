@@ -0,0 +1,260 @@
+//! Settings of `serve` from a JSON file, see `--config`.
+//!
+//! The file is an object keyed by flag names, e.g.
+//! `{ "port": 8080, "work_dir": "/srv/work", "allowed-languages": ["en", "zh"], "dedup-urls": true }`,
+//! where `-` and `_` in keys are interchangeable.
+//!
+//! Precedence, from highest:
+//! 1. `--runtime-config`, for the few hot-reloadable settings, see [`crate::reload`]
+//! 2. flags on the command line
+//! 3. `--config`
+//! 4. defaults of flags
+//!
+//! Fields are turned into flags in front of the command line ones, so they are parsed and
+//! validated exactly like flags. Arrays repeat the flag, and boolean switches are passed if `true`.
+use std::{ffi::OsString, fs, path::PathBuf};
+
+use clap::{builder::Resettable, parser::ValueSource, Arg, ArgAction, ArgMatches, Command};
+use serde_json::{Map, Value};
+
+use crate::exception::ServerError;
+
+/// Id of the flag naming the file.
+const CONFIG_ARG: &str = "config";
+
+/// `args` with fields of `--config` inserted as flags, if the flag is present.
+///
+/// Only `serve` is affected, whether or not its subcommand name is given. `args` that fail to
+/// parse are returned as is, for clap to report. Fails with [`ServerError::InvalidConfig`] naming
+/// the file, and the field if any, when the file is unreadable or has unacceptable values.
+pub fn with_config_file(cmd: Command, args: Vec<OsString>) -> Result<Vec<OsString>, ServerError> {
+    // flags required on the command line may come from the file
+    let optional = |cmd: Command| cmd.mut_args(|arg| arg.required(false));
+    let relaxed = optional(cmd.clone()).mut_subcommand("serve", optional);
+    let Ok(matches) = relaxed.try_get_matches_from(&args) else {
+        return Ok(args);
+    };
+    let (serve, matches, insert_at) = match matches.subcommand() {
+        None => (&cmd, &matches, 1),
+        Some(("serve", sub_matches)) => {
+            let Some(serve) = cmd.find_subcommand("serve") else {
+                return Ok(args);
+            };
+            let at = args
+                .iter()
+                .position(|arg| arg == "serve")
+                .map_or(1, |i| i + 1);
+            (serve, sub_matches, at)
+        }
+        Some(_) => return Ok(args),
+    };
+    let Some(path) = matches.get_one::<PathBuf>(CONFIG_ARG) else {
+        return Ok(args);
+    };
+
+    let invalid = |e: String| ServerError::InvalidConfig(format!("{}: {e}", path.display()));
+    let content = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+    let fields: Map<String, Value> =
+        serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+    let mut flags = Vec::new();
+    for (key, value) in &fields {
+        let field = |e: String| invalid(format!("field `{key}`: {e}"));
+        let Some(arg) = find_arg(serve, key) else {
+            return Err(field("unknown flag".into()));
+        };
+        if arg.get_id() == CONFIG_ARG {
+            return Err(field("cannot be nested".into()));
+        }
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        flags.extend(to_flags(arg, value).map_err(field)?);
+    }
+    let mut merged = args;
+    merged.splice(insert_at..insert_at, flags);
+    Ok(merged)
+}
+
+/// Arg of `cmd` whose long name is `key`, with `-` and `_` interchangeable.
+fn find_arg<'a>(cmd: &'a Command, key: &str) -> Option<&'a Arg> {
+    let normalize = |name: &str| name.replace('_', "-");
+    cmd.get_arguments().find(|arg| {
+        arg.get_long()
+            .is_some_and(|long| normalize(long) == normalize(key))
+    })
+}
+
+/// Command line flags setting `arg` to `value`, validated by the parser of `arg`.
+fn to_flags(arg: &Arg, value: &Value) -> Result<Vec<OsString>, String> {
+    let Some(long) = arg.get_long() else {
+        return Err("not a long flag".into());
+    };
+    let flag = OsString::from(format!("--{long}"));
+    if matches!(arg.get_action(), ArgAction::SetTrue) {
+        return match value {
+            Value::Bool(true) => Ok(vec![flag]),
+            Value::Bool(false) => Ok(Vec::new()),
+            _ => Err("expect true or false".into()),
+        };
+    }
+    let values = match value {
+        Value::Array(values) => values.iter().map(to_string).collect::<Result<_, _>>()?,
+        value => vec![to_string(value)?],
+    };
+    let mut flags = Vec::new();
+    for value in values {
+        validate(arg, &value)?;
+        flags.extend([flag.clone(), value.into()]);
+    }
+    Ok(flags)
+}
+
+fn to_string(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Null | Value::Array(_) | Value::Object(_) => {
+            Err(format!("expect a string, number or boolean, got {value}"))
+        }
+    }
+}
+
+/// Run the parser of `arg` alone on `value`, so that an error is attributed to its field.
+fn validate(arg: &Arg, value: &str) -> Result<(), String> {
+    let arg = arg
+        .clone()
+        .required(false)
+        .conflicts_with(Resettable::Reset);
+    let long = format!("--{}", arg.get_long().unwrap_or_default());
+    let result: Result<ArgMatches, _> = Command::new(CONFIG_ARG)
+        .arg(arg)
+        .try_get_matches_from([CONFIG_ARG, &long, value]);
+    result.map(|_| ()).map_err(|e| {
+        let msg = e.to_string();
+        let first = msg.lines().next().unwrap_or_default();
+        first.trim_start_matches("error: ").to_string()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::OsString, fs};
+
+    use clap::{Args, CommandFactory, Parser, Subcommand};
+    use uuid::Uuid;
+
+    use super::with_config_file;
+    use crate::exception::ServerError;
+
+    #[derive(Parser, Debug)]
+    #[command(args_conflicts_with_subcommands = true)]
+    struct Cli {
+        #[command(subcommand)]
+        command: Option<Command>,
+        #[command(flatten)]
+        serve: Option<ServeArgs>,
+    }
+
+    #[derive(Subcommand, Debug)]
+    enum Command {
+        Serve(ServeArgs),
+    }
+
+    #[derive(Args, Debug, PartialEq)]
+    struct ServeArgs {
+        #[arg(short = 'p', long = "port")]
+        port: u16,
+        #[arg(long = "work_dir", default_value = "work")]
+        work_dir: String,
+        #[arg(long = "allowed-languages", value_delimiter = ',')]
+        allowed_languages: Vec<String>,
+        #[arg(long = "dedup-urls")]
+        dedup_urls: bool,
+        #[arg(long = "config")]
+        config: Option<std::path::PathBuf>,
+    }
+
+    fn parse(args: &[&str]) -> Result<ServeArgs, ServerError> {
+        let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        let args = with_config_file(Cli::command(), args)?;
+        let cli = Cli::parse_from(args);
+        Ok(match cli.command {
+            Some(Command::Serve(serve)) => serve,
+            None => cli.serve.unwrap(),
+        })
+    }
+
+    #[test]
+    fn test_with_config_file() {
+        let path = std::env::temp_dir().join(format!("{}.json", Uuid::new_v4()));
+        let config = path.to_str().unwrap();
+        fs::write(
+            &path,
+            r#"{ "port": 8080, "work-dir": "/srv", "allowed_languages": ["en", "zh"],
+                "dedup-urls": true }"#,
+        )
+        .unwrap();
+
+        // file overrides defaults
+        let serve = parse(&["server", "--config", config]).unwrap();
+        assert_eq!((serve.port, serve.work_dir.as_str()), (8080, "/srv"));
+        assert_eq!(serve.allowed_languages, ["en", "zh"]);
+        assert!(serve.dedup_urls);
+
+        // command line overrides file
+        for args in [
+            &[
+                "server",
+                "--config",
+                config,
+                "-p",
+                "80",
+                "--allowed-languages",
+                "ja",
+            ][..],
+            &[
+                "server",
+                "serve",
+                "-p",
+                "80",
+                "--config",
+                config,
+                "--allowed-languages",
+                "ja",
+            ],
+        ] {
+            let serve = parse(args).unwrap();
+            assert_eq!((serve.port, serve.work_dir.as_str()), (80, "/srv"));
+            assert_eq!(serve.allowed_languages, ["ja"]);
+        }
+
+        for (content, expected) in [
+            (
+                r#"{ "port": "http" }"#,
+                "field `port`: invalid value 'http'",
+            ),
+            (
+                r#"{ "port": 80, "prot": 80 }"#,
+                "field `prot`: unknown flag",
+            ),
+            (
+                r#"{ "port": 80, "dedup-urls": 1 }"#,
+                "field `dedup-urls`: expect true",
+            ),
+            (
+                r#"{ "config": "a.json" }"#,
+                "field `config`: cannot be nested",
+            ),
+            ("{\n  \"port\": 80,\n}", "line 3 column 1"),
+        ] {
+            fs::write(&path, content).unwrap();
+            match parse(&["server", "--config", config]) {
+                Err(ServerError::InvalidConfig(e)) => assert!(e.contains(expected), "{e}"),
+                other => panic!("{content}: {other:?}"),
+            }
+        }
+        fs::remove_file(&path).unwrap();
+        assert!(parse(&["server", "--config", config]).is_err());
+    }
+}
@@ -1,26 +1,85 @@
 //! API controllers to which the [`axum::Router`] routes.
-use std::{fs::create_dir_all, path::Path, sync::Arc};
+use std::{
+    any::Any,
+    collections::HashSet,
+    fmt::Write as _,
+    fs::create_dir_all,
+    future::{poll_fn, Future},
+    io::{ErrorKind, SeekFrom},
+    mem,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant, SystemTime},
+};
 
 use axum::{
-    body::Body,
-    extract::{Json, State},
-    http::{header, HeaderMap, HeaderValue},
-    response::IntoResponse,
+    body::{Body, Bytes, HttpBody},
+    extract::{Json, Path as UrlPath, Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::{
+        sse::{KeepAlive, Sse},
+        IntoResponse, Response,
+    },
 };
+use futures_util::{stream, Stream, StreamExt};
 use serde::Serialize;
-use tokio::fs::read_to_string;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::{
+    fs::read_to_string,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    task::JoinError,
+};
 use tokio_util::io;
+use tracing::Instrument;
 
 use crate::{
-    exception::{AppError, ClientError, ServerError},
+    base64,
+    disk::available_bytes,
+    events::{status_events, MAX_BATCH_UUIDS},
+    exception::{AppError, ClientError, FieldError, ServerError, TransientError},
+    executor::Warnings,
+    export::{zip_stream, Content},
+    history::{HistoryQuery, HistoryResp},
+    idempotency::idempotency_key,
+    metrics::Stage,
     models::{
-        AppResp, FetchArchiveReq, FetchArchiveResp, InitiateReq, InitiateResp, PollStatusReq,
-        PollStatusResp, ServerState, TaskStatus,
+        AppResp, BatchEventsReq, CapabilitiesResp, Config, DeleteReq, DeleteResp, DownloadFile,
+        EstimateResp, FetchArchiveReq, FetchArchiveResp, FetchAudioReq, FileEntry, FlushLogsResp,
+        HealthResp, InitiateReq, InitiateResp, InlineArchiveResp, ListFilesReq, ListFilesResp,
+        MetadataReq, MetadataResp, ModelOptions, OutputResp, PartialManifest, PauseResp,
+        PollStatusReq, PollStatusResp, ReloadResp, RetryReq, RetryResp, SelftestResp, SelftestStep,
+        ServerState, SessionTasksReq, SessionTasksResp, SummaryFormat, TaskMode, TaskRequest,
+        TaskStatus, TranscriptSegment, UploadChunkReq, UploadReq, UploadResp, ValidateResp,
+        VersionResp, RUN_LOG_FILENAME, TRANSCRIPT_FILENAME,
     },
+    output::publish,
+    output_tail::{self, OutputTail},
+    queue::MAX_PRIORITY,
+    redact::redact,
+    reload::reload,
+    session::session_id,
+    slots::Slot,
+    stats::StatsResp,
+    stream::summary_events,
+    task_log::{self, task_span},
+    video::{check_url, VideoMetadata},
+    watchdog::stalled,
 };
 use ::uuid::Uuid;
 type JsonResp<T> = Json<AppResp<T>>;
 
+/// Video title saved in user dir, for naming the archive.
+const TITLE_FILE: &str = "title.txt";
+/// Request persisted in user dir until the task finishes, see `--recover-tasks`.
+const REQUEST_FILE: &str = "request.json";
+/// At most this much of a partial summary is returned, see `--stream-partial`.
+const PARTIAL_TAIL_BYTES: u64 = 16 * 1024;
+/// Manifest of a partial archive, see [`partial_archive`].
+const PARTIAL_MANIFEST: &str = "manifest.json";
+/// Response header of `/init` carrying the task uuid, for intermediaries to log.
+pub const TASK_ID_HEADER: HeaderName = HeaderName::from_static("x-task-id");
+
 fn ok<T: Serialize>(resp: T) -> JsonResp<T> {
     Json(AppResp::Success(resp))
 }
@@ -29,317 +88,6069 @@ fn err<T: Serialize>(err: impl Into<AppError>) -> JsonResp<T> {
     Json(AppResp::Exception(err.into()))
 }
 
-fn task_err(err: impl Into<AppError>) -> TaskStatus {
-    TaskStatus::Err(err.into())
+/// Successful `/init` response, with uuid in both [`TASK_ID_HEADER`] and body.
+fn initiated(uuid: String, deduplicated: bool) -> (HeaderMap, JsonResp<InitiateResp>) {
+    let mut headers = HeaderMap::new();
+    // uuid of a re-submission comes from client, skip the header if it cannot be one
+    match HeaderValue::from_str(&uuid) {
+        Ok(value) => {
+            headers.insert(TASK_ID_HEADER, value);
+        }
+        Err(_) => tracing::warn!("\nUuid {uuid:?} is not a valid header value."),
+    }
+    (headers, ok(InitiateResp { uuid, deduplicated }))
 }
 
 /// Submit a task that may or may not complete in future.
 ///
 /// `POST` `/init` with body:  
-/// `{ url: "a valid youtube link", uuid: "" }`  
+/// `{ url: "a valid youtube link", uuid: "", language: "en", model: "medium" }`  
+/// where `language` and `model` are optional, and must be allowed by `--allowed-languages` and
+/// `--allowed-models` respectively.  
+/// Invalid fields are rejected all at once, by `validation` error listing each in `fields`.  
 /// It guarantees to return  
-/// `{ success: true, data = { uuid = "unique ID asigned to this task" } }`  
+/// `{ success: true, data = { uuid = "unique ID asigned to this task", deduplicated: false } }`  
+/// with the same uuid in response header `X-Task-Id`, where `deduplicated` is true if the uuid
+/// is of an existing task instead, e.g. a re-submission or an identical url request.  
 /// Returning success does not imply the task will success, failure will be indicated in subsequent poll
-/// requests
+/// requests, except that videos longer than `--max-duration-secs` are rejected right away.
+///
+/// With `validate_only: true` in body, nothing is spawned and no uuid is allocated. The url is
+/// checked by fetching its metadata instead, returning  
+/// `{ success: true, data = { valid: true, title: "...", duration_secs: 123 } }`  
+/// or the error that a real task would end up with.
+///
+/// With `mode: "transcript"` in body, the model stops after the transcript, which `/poll` then
+/// returns in place of the summary. `mode` defaults to `summary`.
+///
+/// With `priority` from 0 to 9 in body, a higher one is granted a model first under
+/// `--max-concurrent-models`. Every minute spent waiting raises it by one, so that low priority
+/// tasks still run eventually. `priority` defaults to 0.
+///
+/// With `output_language: "fr"` in body, one of `--output-languages`, the result is translated
+/// by `--translate-script` after the model run, into `summary.fr.txt` which `/poll` then returns.
+/// Nothing is translated if it equals `language`.
+///
+/// With `--idempotency-window`, a request with an `Idempotency-Key` header seen within the window
+/// returns the uuid assigned the first time, without spawning another task, whether or not the
+/// client kept that uuid.
 pub async fn init_summary(
     State(state): State<ServerState>,
+    headers: HeaderMap,
     Json(init_body): Json<InitiateReq>,
-) -> JsonResp<InitiateResp> {
-    let req_uuid = init_body.uuid;
-    if state.has_task(&req_uuid).await {
+) -> Response {
+    let req_uuid = init_body.uuid.clone();
+    // empty uuid comes from a client without task, it never identifies one
+    if !req_uuid.is_empty() && state.has_task(&req_uuid).await {
         // no-op for re-submission
         tracing::warn!("\nUser {req_uuid} re-submits a task");
-        return ok(InitiateResp { uuid: req_uuid });
+        return initiated(req_uuid, true).into_response();
+    }
+    let idempotency_key = match request_idempotency_key(&state, &headers) {
+        Ok(key) => key,
+        Err(e) => return err::<InitiateResp>(e).into_response(),
+    };
+    if let (Some(keys), Some(key)) = (&state.idempotency_keys, &idempotency_key) {
+        if let Some(uuid) = keys.get(key) {
+            tracing::warn!("\nUser {uuid} replays idempotency key \"{key}\".");
+            return initiated(uuid, true).into_response();
+        }
     }
 
-    let uuid = Arc::new(Uuid::new_v4().to_string());
-    let url = Arc::new(init_body.url);
-
-    // spawn task
-    let uuid_copy = Arc::clone(&uuid);
-    let url_copy = Arc::clone(&url);
-    tokio::spawn(async move {
-        let uuid = uuid_copy.clone();
-        let url = url_copy;
-        let user_dir = state.work_dir.join(uuid.as_ref());
-        let user_dir_str = user_dir.to_str().unwrap();
-        let audio_path = user_dir.join("audio.mp3");
-        let audio_path_str = audio_path.to_str().unwrap();
-
-        if create_dir_all(&user_dir).is_err() {
-            tracing::error!("\nFailed to prepare user path \"{user_dir_str}\".");
-            state
-                .update_task(
-                    &uuid,
-                    task_err(ServerError::ParsePath(user_dir_str.to_string())),
-                )
-                .await;
-            return;
+    let (options, priority) = match init_options(&state.config, &init_body) {
+        Ok(valid) => valid,
+        Err(e) => {
+            tracing::warn!("\nUser {req_uuid} requests with invalid options: {e}");
+            return err::<InitiateResp>(e).into_response();
         }
+    };
+    let session = match request_session(&state, &headers) {
+        Ok(session) => session,
+        Err(e) => return err::<InitiateResp>(e).into_response(),
+    };
 
-        state.update_task(&uuid, TaskStatus::Download).await;
-        // download video from youtube
-        let args = [
-            "run",
-            "-n",
-            "server",
-            "download_mp3.sh",
-            &url.clone(),
-            audio_path_str,
-        ];
-        let Ok(download_cmd) = tokio::process::Command::new("conda")
-            .args(args)
-            .output()
-            .await
-        else {
-            // failed to issue command
-            let command = format!("conda {}", args.join(" "));
-            tracing::error!("\nFailed to issue command {command}");
+    if init_body.validate_only {
+        return validate_video(&state, &init_body.url).await.into_response();
+    }
 
-            // set failure task status
-            state
-                .update_task(&uuid, task_err(ServerError::IssueCommand(command)))
-                .await;
-            return;
-        };
+    if let Err(e) = check_disk_space(&state) {
+        return e.into_response();
+    }
+    let admission = match check_capacity(&state) {
+        Ok(admission) => admission,
+        Err(e) => return e.into_response(),
+    };
 
-        if !download_cmd.status.success() {
-            // download failed
-            let stderr = String::from_utf8_lossy(&download_cmd.stderr).to_string();
-            tracing::debug!("\nDownload failed with error message: \n{stderr}");
-            if is_url_problem(&stderr) {
-                // invalid url
-                tracing::warn!("\nUser {uuid} requested a invalid video url \"{url}\".");
-                state
-                    .update_task(
-                        &uuid,
-                        task_err(ClientError::VideoLinkNotExist(url.to_string())),
-                    )
-                    .await;
-            } else {
-                // other fault
-                tracing::error!("\n`yt-dlp` throws unexpected error: \n{stderr}");
-                state
-                    .update_task(&uuid, task_err(ServerError::VideoDownload(stderr)))
-                    .await;
+    // metadata query delays the response, only pay for it when duration is limited
+    let metadata = match state.config.max_duration_secs {
+        Some(_) => match probe_video(&state, &init_body.url).await {
+            Ok(metadata) => Some(metadata),
+            Err(e) => {
+                tracing::warn!(
+                    "\nUser {req_uuid} requests a rejected video {}: {}",
+                    redact(&init_body.url),
+                    e.code()
+                );
+                return err::<InitiateResp>(e).into_response();
             }
-            return;
+        },
+        None => None,
+    };
+
+    let uuid = match fresh_uuid(&state, Uuid::new_v4).await {
+        Ok(uuid) => Arc::new(uuid),
+        Err(e) => return err::<InitiateResp>(e).into_response(),
+    };
+    let request = Arc::new(TaskRequest {
+        url: init_body.url,
+        options,
+        upload: None,
+        priority,
+    });
+
+    let claimed_key = match (&state.idempotency_keys, &idempotency_key) {
+        (Some(keys), Some(key)) => match keys.claim(key, &uuid) {
+            // raced by a replay of this request
+            Some(existing) => return initiated(existing, true).into_response(),
+            None => Some((keys, key)),
+        },
+        _ => None,
+    };
+    let claims = InitClaims {
+        state: state.clone(),
+        uuid: Arc::clone(&uuid),
+        request: Arc::clone(&request),
+        key: claimed_key.map(|(_, key)| key.clone()),
+        kept: false,
+    };
+    if let Some(existing) = state.claim_url(&request.url, &request.options, &uuid).await {
+        tracing::info!("\nUser {existing} shares in-progress task with an identical url request.");
+        if let Some((keys, key)) = claimed_key {
+            keys.reassign(key, &existing);
         }
-        tracing::info!("\nDownload success for uuid: \"{uuid}\", link: \"{url}\".");
+        claims.keep();
+        join_session(&state, session.as_deref(), &existing);
+        return initiated(existing, true).into_response();
+    }
 
-        state.update_task(&uuid, TaskStatus::Pending).await;
-        // run AI model to generate
-        let args = [
-            "run",
-            "-n",
-            "server",
-            "run_model.sh",
-            audio_path_str,
-            user_dir_str,
-        ];
+    // register before responding, so that the returned uuid is immediately known to /poll
+    state.update_task(&uuid, TaskStatus::Download).await;
+    let duration_secs = metadata.as_ref().and_then(VideoMetadata::duration_secs);
+    state
+        .modify_task(&uuid, |task| {
+            task.duration_secs = duration_secs;
+            task.request = Some(Arc::clone(&request));
+            task.slot = Some(Arc::new(admission.task));
+        })
+        .await;
+    join_session(&state, session.as_deref(), &uuid);
 
-        tracing::info!("\nLaunching AI model for uuid: \"{uuid}\", link: \"{url}\".");
-        let Ok(model_cmd) = tokio::process::Command::new("conda")
-            .args(args)
-            .output()
-            .await
-        else {
-            // failed to issue command
-            let command = format!("conda {}", args.join(" "));
-            tracing::error!("\nFailed to issue command \"{command}\".");
+    // spawn task
+    state.metrics.task_initiated();
+    let url = request.url.clone();
+    spawn_summarize(
+        state,
+        Arc::clone(&uuid),
+        request,
+        metadata,
+        DownloadMode::Fresh,
+        admission.queued,
+    );
+    claims.keep();
+
+    tracing::info!("\nUser {uuid} requests video url: {}.", redact(&url));
+    initiated(uuid.to_string(), false).into_response()
+}
+
+/// Url and idempotency key claimed by an `/init`, released on drop unless kept once its task is
+/// spawned or shared, e.g. if the request is cut off by `--request-timeout-secs` in between.
+struct InitClaims {
+    state: ServerState,
+    uuid: Arc<String>,
+    request: Arc<TaskRequest>,
+    key: Option<String>,
+    kept: bool,
+}
+
+impl InitClaims {
+    fn keep(mut self) {
+        self.kept = true;
+    }
+}
 
-            // set failure task status
+impl Drop for InitClaims {
+    fn drop(&mut self) {
+        if self.kept {
+            return;
+        }
+        let uuid = Arc::clone(&self.uuid);
+        tracing::warn!("\nInit of user {uuid} is cancelled, releasing its claims.");
+        if let (Some(keys), Some(key)) = (&self.state.idempotency_keys, &self.key) {
+            keys.release(key, &uuid);
+        }
+        let (state, request) = (self.state.clone(), Arc::clone(&self.request));
+        tokio::spawn(async move {
+            // perhaps registered, but never spawned
+            state.remove_task(&uuid).await;
             state
-                .update_task(&uuid, task_err(ServerError::IssueCommand(command)))
+                .release_url(&request.url, &request.options, &uuid)
                 .await;
-            return;
+        });
+    }
+}
+
+/// Session id in `headers` if `--enable-sessions` is set, see [`crate::session`].
+fn request_session(
+    state: &ServerState,
+    headers: &HeaderMap,
+) -> Result<Option<String>, ClientError> {
+    match &state.sessions {
+        Some(_) => session_id(headers),
+        None => Ok(None),
+    }
+}
+
+/// Idempotency key in `headers` if `--idempotency-window` is set, see [`crate::idempotency`].
+fn request_idempotency_key(
+    state: &ServerState,
+    headers: &HeaderMap,
+) -> Result<Option<String>, ClientError> {
+    match &state.idempotency_keys {
+        Some(_) => idempotency_key(headers),
+        None => Ok(None),
+    }
+}
+
+fn join_session(state: &ServerState, session: Option<&str>, uuid: &str) {
+    if let (Some(sessions), Some(session)) = (&state.sessions, session) {
+        sessions.add(session, uuid);
+    }
+}
+
+/// Run [`summarize`] in background, then release the url claim and record the outcome in audit
+/// log and history.
+///
+/// `queued` counts the task against `--queue-capacity` until it runs a model.
+fn spawn_summarize(
+    state: ServerState,
+    uuid: Arc<String>,
+    request: Arc<TaskRequest>,
+    metadata: Option<VideoMetadata>,
+    mode: DownloadMode,
+    queued: Slot,
+) {
+    tokio::spawn(async move {
+        let start = Instant::now();
+        // run in a task of its own, so that a panic is observed here instead of vanishing
+        let pipeline = {
+            let (state, uuid, request) = (state.clone(), Arc::clone(&uuid), Arc::clone(&request));
+            let tail = match state.get_task_entry(&uuid).await {
+                Some(task) => task.output,
+                None => OutputTail::new(0),
+            };
+            let span = task_span(
+                state.config.per_task_logs,
+                &uuid,
+                &state.work_dir.join(&*uuid),
+            );
+            let summarize =
+                async move { summarize(&state, &uuid, &request, metadata, mode, queued).await };
+            tokio::spawn(output_tail::scope(tail, summarize.instrument(span)))
         };
-        if !model_cmd.status.success() {
-            let stderr = String::from_utf8_lossy(&download_cmd.stderr).to_string();
-            tracing::error!("\nAI model failed with error message: \n{stderr}");
-            // set failure task status
-            state
-                .update_task(&uuid, task_err(ServerError::AiModel(stderr)))
+        let abort = pipeline.abort_handle();
+        state
+            .modify_task(&uuid, |task| task.abort = Some(abort))
+            .await;
+        let status = match pipeline.await {
+            Ok(status) => status,
+            Err(e) if e.is_cancelled() => {
+                // the pipeline may have written again between deletion and abort
+                state.remove_task(&uuid).await;
+                let _ = tokio::fs::remove_dir_all(state.work_dir.join(&*uuid)).await;
+                state
+                    .release_url(&request.url, &request.options, &uuid)
+                    .await;
+                tracing::info!("\nTask of user {uuid} is aborted for deletion.");
+                return;
+            }
+            Err(e) => {
+                let cause = panic_message(e);
+                tracing::error!("\nTask of user {uuid} panics: {cause}");
+                fail_task(&state, &uuid, ServerError::Internal(cause)).await
+            }
+        };
+        state
+            .release_url(&request.url, &request.options, &uuid)
+            .await;
+        if state.config.recover_tasks {
+            // finished, nothing left to recover
+            let _ = state
+                .storage
+                .delete(&format!("{uuid}/{REQUEST_FILE}"))
                 .await;
-            return;
         }
-        tracing::info!("\nAI model success for uuid: \"{uuid}\", link: \"{url}\".");
-
-        state.update_task(&uuid, TaskStatus::Done).await;
+        if let Some(audit) = state.audit.as_ref() {
+            audit.record(&uuid, &status, start.elapsed());
+        }
+        if let Some(history) = state.history.as_ref() {
+            history.record(&uuid, &status, start.elapsed(), &request.url);
+        }
     });
+}
 
-    tracing::info!("\nUser {uuid} requests video url: {url}.");
-    let resp = InitiateResp {
-        uuid: uuid.to_string(),
+fn panic_message(err: JoinError) -> String {
+    match err.try_into_panic() {
+        Ok(payload) => payload_message(payload),
+        Err(e) => e.to_string(),
+    }
+}
+
+fn payload_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/// Submit a task on uploaded audio, which skips the download stage.
+///
+/// `POST` `/init/upload?filename=talk.mp3&language=en&model=medium` with the audio as body, where
+/// all parameters are optional.  
+/// The audio type is told by `Content-Type`, or by the extension of `filename` if `Content-Type`
+/// is absent or `application/octet-stream`, and must be one of mp3, wav, m4a, ogg and flac.
+/// Bodies larger than `--max-upload-bytes` are rejected.  
+/// Once the upload completes, it returns  
+/// `{ success: true, data = { uuid = "unique ID asigned to this task" } }`  
+/// and the task is polled as usual, starting from `Pending` stage.
+pub async fn init_upload(
+    State(state): State<ServerState>,
+    Query(upload): Query<UploadReq>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let mut validator = Validator::default();
+    let options = model_options(
+        &state.config,
+        &mut validator,
+        upload.language,
+        upload.model,
+        upload.mode.as_deref(),
+        upload.backend.as_deref(),
+    );
+    let options = match validator.finish(options) {
+        Ok(options) => options,
+        Err(e) => {
+            tracing::warn!("\nUpload with invalid options is rejected: {e}");
+            return err::<InitiateResp>(e).into_response();
+        }
     };
-    ok(resp)
+    let session = match request_session(&state, &headers) {
+        Ok(session) => session,
+        Err(e) => return err::<InitiateResp>(e).into_response(),
+    };
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let Some(extension) = upload_extension(content_type, upload.filename.as_deref()) else {
+        tracing::warn!("\nUpload of unsupported type {content_type:?} is rejected.");
+        let e = ClientError::MalformedRequest("audio type is not supported".into());
+        return err::<InitiateResp>(e).into_response();
+    };
+    if let Err(e) = check_disk_space(&state) {
+        return e.into_response();
+    }
+    let admission = match check_capacity(&state) {
+        Ok(admission) => admission,
+        Err(e) => return e.into_response(),
+    };
+
+    let uuid = match fresh_uuid(&state, Uuid::new_v4).await {
+        Ok(uuid) => uuid,
+        Err(e) => return err::<InitiateResp>(e).into_response(),
+    };
+    let user_dir = state.work_dir.join(&uuid);
+    let audio_filename = format!("audio.{extension}");
+    let max_bytes = state.config.max_upload_bytes;
+    if let Err(e) = save_upload(body, &user_dir, &audio_filename, max_bytes).await {
+        let _ = tokio::fs::remove_dir_all(&user_dir).await;
+        return err::<InitiateResp>(e).into_response();
+    }
+
+    join_session(&state, session.as_deref(), &uuid);
+    start_upload_task(state, &uuid, options, audio_filename, admission).await;
+    tracing::info!("\nUser {uuid} uploads audio.");
+    initiated(uuid, false).into_response()
 }
 
-/// Query the server the status of specified task.
+/// Run the model on `audio_filename` uploaded into the dir of `uuid`, starting from `Pending`.
+async fn start_upload_task(
+    state: ServerState,
+    uuid: &str,
+    options: ModelOptions,
+    audio_filename: String,
+    admission: Admission,
+) {
+    let request = Arc::new(TaskRequest {
+        url: String::new(),
+        options,
+        upload: Some(audio_filename),
+        priority: 0,
+    });
+    state.update_task(uuid, TaskStatus::Pending).await;
+    state
+        .modify_task(uuid, |task| {
+            task.request = Some(Arc::clone(&request));
+            task.slot = Some(Arc::new(admission.task));
+        })
+        .await;
+    state.metrics.task_initiated();
+    spawn_summarize(
+        state,
+        Arc::new(uuid.to_string()),
+        request,
+        None,
+        DownloadMode::Skip,
+        admission.queued,
+    );
+}
+
+/// Open a resumable upload, for large audio over flaky connections, see [`crate::upload`].
 ///
-/// `POST` `/poll` with body:  
-/// `{ uuid: "unique ID assigned by /init" }`  
+/// `POST` `/upload/init?filename=talk.mp3&language=en&model=medium` without body, where
+/// parameters are the same as [`init_upload`], and so is `Content-Type` telling the audio type.  
 /// It returns  
-/// `{ success: true, data = { ... } }`  
-/// where `data =` one of:  
-/// - Your task has been completed.  
-///   `{ done: true, stage: Done, result: "the summary of your video link" }`  
-/// - Server is downloading your specified video.  
-///   `{ done: false, stage: Download, result: null }`  
-/// - Your video is under AI processing.  
-///   `{ done: false, stage: Pending, result: null }`  
-///
-/// Or, Your task failed.  
-/// - Wrong uuid.  
-///   `{ success: false, err = { source: "client", info: "..." } }`  
-/// - Error occured during processing.  
-///   `{ success: false, err = { source: "server", info: "..." } }`  
-#[axum::debug_handler]
-pub async fn poll_status(
+/// `{ success: true, data = { upload_id: "...", offset: 0 } }`  
+/// Then the audio is sent in chunks by [`upload_chunk`], and turned into a task by
+/// [`upload_finish`]. Uploads receiving no chunk for `--upload-idle-timeout` are dropped.
+pub async fn upload_init(
     State(state): State<ServerState>,
-    Json(poll_body): Json<PollStatusReq>,
-) -> JsonResp<PollStatusResp> {
-    let uuid = poll_body.uuid;
-    let guard = state.task_status.read().await;
-    let Some(status) = guard.get(&uuid).cloned() else {
-        drop(guard);
-        tracing::warn!("\nUser {uuid} without a task attempts to poll.");
-        return err(ClientError::TokenNotExist(uuid));
+    Query(upload): Query<UploadReq>,
+    headers: HeaderMap,
+) -> Response {
+    let mut validator = Validator::default();
+    let options = model_options(
+        &state.config,
+        &mut validator,
+        upload.language,
+        upload.model,
+        upload.mode.as_deref(),
+        upload.backend.as_deref(),
+    );
+    let options = match validator.finish(options) {
+        Ok(options) => options,
+        Err(e) => {
+            tracing::warn!("\nUpload with invalid options is rejected: {e}");
+            return err::<UploadResp>(e).into_response();
+        }
     };
-    drop(guard);
-    match status {
-        TaskStatus::Download => ok(PollStatusResp {
-            done: false,
-            stage: TaskStatus::Download,
-            result: None,
-        }),
-        TaskStatus::Pending => ok(PollStatusResp {
-            done: false,
-            stage: TaskStatus::Pending,
-            result: None,
-        }),
-        TaskStatus::Done => {
-            tracing::info!("\nUser {uuid} obtains summary result, remove entry from task table.");
-            state.remove_task(&uuid).await;
-            let user_dir = state.work_dir.join(&uuid);
-            let summary_path = user_dir.join("summary.txt");
-            let sum_str = summary_path.to_string_lossy().to_string();
-            let Ok(content) = read_to_string(&sum_str).await else {
-                tracing::error!("\nFailed to read summary result at {sum_str}.");
-                return err(ServerError::ReadFile(sum_str));
-            };
-            ok(PollStatusResp {
-                done: true,
-                stage: TaskStatus::Done,
-                result: Some(content),
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let Some(extension) = upload_extension(content_type, upload.filename.as_deref()) else {
+        tracing::warn!("\nUpload of unsupported type {content_type:?} is rejected.");
+        let e = ClientError::MalformedRequest("audio type is not supported".into());
+        return err::<UploadResp>(e).into_response();
+    };
+    if let Err(e) = check_disk_space(&state) {
+        return e.into_response();
+    }
+    // checked again by `/upload/finish`, no slot is held meanwhile
+    if let Err(e) = check_capacity(&state) {
+        return e.into_response();
+    }
+    match state.uploads.open(options, extension).await {
+        Ok(upload_id) => {
+            tracing::info!("\nUpload {upload_id} is opened.");
+            ok(UploadResp {
+                upload_id,
+                offset: 0,
             })
+            .into_response()
         }
-        TaskStatus::Err(app_err) => {
-            tracing::info!("\nUser {uuid} observes error status, remove entry from task table.");
-            state.remove_task(&uuid).await;
-            err(app_err.clone())
-        }
+        Err(e) => err::<UploadResp>(e).into_response(),
     }
 }
 
-/// Poll download entire archive for diagnosis.
+/// Where a resumable upload continues, e.g. after a chunk is interrupted.
 ///
-/// `POST` `/download` with body:  
-/// `{ uuid: "unique ID assigned by /init" }`  
+/// `GET` `/upload/:id`  
 /// It returns  
-/// - error if processing failed, or uuid does not exist.  
-///   `{ success: false, err = { source: "client"/"server", info: "..." } }`  
-/// - dummy JSON if executed for the first time (let server compress).  
-/// - http response with  
-///   `content-type: application/zip`  
+/// `{ success: true, data = { upload_id: "...", offset: 1048576 } }`  
+/// or `token_not_exist` error if the upload is unknown, finished or dropped.
+pub async fn upload_status(
+    State(state): State<ServerState>,
+    UrlPath(upload_id): UrlPath<String>,
+) -> JsonResp<UploadResp> {
+    let Some(upload) = state.uploads.get(&upload_id) else {
+        return err(ClientError::TokenNotExist(upload_id));
+    };
+    let upload = upload.lock().await;
+    if upload.closed {
+        return err(ClientError::TokenNotExist(upload_id));
+    }
+    let offset = upload.received;
+    ok(UploadResp { upload_id, offset })
+}
+
+/// Append a chunk to a resumable upload.
 ///
-/// Frontend should poll until error or `content-type = application/zip`  
-pub async fn fetch_archive(
+/// `PUT` `/upload/:id?offset=N` with the chunk as body, where `N` is the number of bytes received
+/// so far.  
+/// It returns  
+/// `{ success: true, data = { upload_id: "...", offset: N + chunk size } }`  
+/// or `upload_offset_mismatch` error if the chunk does not begin at `N`. Bytes of an interrupted
+/// chunk that did arrive are kept, see [`upload_status`] for where to continue.
+pub async fn upload_chunk(
     State(state): State<ServerState>,
-    Json(fetch_body): Json<FetchArchiveReq>,
-) -> impl IntoResponse {
-    let uuid = fetch_body.uuid;
+    UrlPath(upload_id): UrlPath<String>,
+    Query(chunk): Query<UploadChunkReq>,
+    body: Body,
+) -> JsonResp<UploadResp> {
+    let Some(upload) = state.uploads.get(&upload_id) else {
+        return err(ClientError::TokenNotExist(upload_id));
+    };
+    let mut upload = upload.lock().await;
+    if upload.closed {
+        return err(ClientError::TokenNotExist(upload_id));
+    }
+    if chunk.offset != upload.received {
+        return err(ClientError::UploadOffsetMismatch {
+            expected: upload.received,
+            actual: chunk.offset,
+        });
+    }
+    let path = state.uploads.path(&upload_id);
+    let mut file = match tokio::fs::OpenOptions::new().append(true).open(&path).await {
+        Ok(file) => file,
+        Err(e) => return err(upload_err(&path, e)),
+    };
+    let mut received = upload.received;
+    let result = append_body(
+        body,
+        &mut file,
+        &path,
+        &mut received,
+        state.config.max_upload_bytes,
+    )
+    .await;
+    upload.received = received;
+    upload.last_active = Instant::now();
+    match result {
+        Ok(()) => ok(UploadResp {
+            upload_id,
+            offset: received,
+        }),
+        Err(e) => err(e),
+    }
+}
 
+/// Turn a resumable upload into a task.
+///
+/// `POST` `/upload/finish/:id`  
+/// It returns the same as [`init_upload`], after which the upload is gone.
+pub async fn upload_finish(
+    State(state): State<ServerState>,
+    UrlPath(upload_id): UrlPath<String>,
+) -> Response {
+    let Some(upload) = state.uploads.get(&upload_id) else {
+        return err::<InitiateResp>(ClientError::TokenNotExist(upload_id)).into_response();
+    };
+    let mut upload = upload.lock().await;
+    if upload.closed {
+        return err::<InitiateResp>(ClientError::TokenNotExist(upload_id)).into_response();
+    }
+    if upload.received == 0 {
+        let e = ClientError::MalformedRequest("upload is empty".into());
+        return err::<InitiateResp>(e).into_response();
+    }
+    let admission = match check_capacity(&state) {
+        Ok(admission) => admission,
+        Err(e) => return e.into_response(),
+    };
+
+    let uuid = match fresh_uuid(&state, Uuid::new_v4).await {
+        Ok(uuid) => uuid,
+        Err(e) => return err::<InitiateResp>(e).into_response(),
+    };
     let user_dir = state.work_dir.join(&uuid);
-    let archive_path = user_dir.join("archive.zip");
-    if !user_dir.exists() {
-        tracing::warn!("\nUser {uuid} attempts to download without init task.");
-        let uuid_err = ClientError::TokenNotExist(uuid);
-        return <Json<AppResp<FetchArchiveResp>> as IntoResponse>::into_response(err(uuid_err))
-            .into_response();
+    let audio_filename = format!("audio.{}", upload.extension);
+    let audio_path = user_dir.join(&audio_filename);
+    let moved = match tokio::fs::create_dir_all(&user_dir).await {
+        Ok(()) => tokio::fs::rename(state.uploads.path(&upload_id), &audio_path).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = moved {
+        let _ = tokio::fs::remove_dir_all(&user_dir).await;
+        return err::<InitiateResp>(upload_err(&audio_path, e)).into_response();
     }
+    upload.closed = true;
+    state.uploads.remove(&upload_id);
 
-    let user_dir_str = user_dir.to_str().unwrap().to_string();
-    let archive_path_str = archive_path.to_str().unwrap().to_string();
-    if archive_path.exists() {
-        tracing::info!("\nUser {uuid} downloads \"{archive_path_str}\".");
-        return download_resp(archive_path_str, "archive.zip")
-            .await
-            .into_response();
+    let options = mem::take(&mut upload.options);
+    start_upload_task(state, &uuid, options, audio_filename, admission).await;
+    tracing::info!("\nUser {uuid} finishes upload {upload_id}.");
+    initiated(uuid, false).into_response()
+}
+
+/// Audio types accepted by [`init_upload`], as mime type and file extension.
+const UPLOAD_TYPES: [(&str, &str); 7] = [
+    ("audio/mpeg", "mp3"),
+    ("audio/wav", "wav"),
+    ("audio/x-wav", "wav"),
+    ("audio/mp4", "m4a"),
+    ("audio/x-m4a", "m4a"),
+    ("audio/ogg", "ogg"),
+    ("audio/flac", "flac"),
+];
+
+/// Extension of an upload in [`UPLOAD_TYPES`], `None` if not supported.
+fn upload_extension(content_type: Option<&str>, filename: Option<&str>) -> Option<&'static str> {
+    let mime = content_type
+        .and_then(|value| value.split(';').next())
+        .map(|mime| mime.trim().to_ascii_lowercase());
+    match mime.as_deref() {
+        None | Some("application/octet-stream") => {
+            let extension = Path::new(filename?)
+                .extension()?
+                .to_str()?
+                .to_ascii_lowercase();
+            UPLOAD_TYPES
+                .iter()
+                .find(|(_, ext)| *ext == extension)
+                .map(|(_, ext)| *ext)
+        }
+        Some(mime) => UPLOAD_TYPES
+            .iter()
+            .find(|(type_, _)| *type_ == mime)
+            .map(|(_, ext)| *ext),
     }
-    let state = Arc::new(state);
-    let state_copy = Arc::clone(&state);
-    let status = state.get_task(&uuid).await;
-    if let Some(TaskStatus::Err(e)) = status {
-        return <Json<AppResp<FetchArchiveResp>> as IntoResponse>::into_response(err(e))
-            .into_response();
+}
+
+/// Stream request body into `user_dir/file_name`, failing once it exceeds `max_bytes`.
+async fn save_upload(
+    body: Body,
+    user_dir: &Path,
+    file_name: &str,
+    max_bytes: u64,
+) -> Result<(), AppError> {
+    let path = user_dir.join(file_name);
+    let open_err = |e| upload_err(&path, e);
+    tokio::fs::create_dir_all(user_dir)
+        .await
+        .map_err(open_err)?;
+    let mut file = tokio::fs::File::create(&path).await.map_err(open_err)?;
+    let mut received = 0;
+    append_body(body, &mut file, &path, &mut received, max_bytes).await?;
+    if received == 0 {
+        return Err(ClientError::MalformedRequest("upload is empty".into()).into());
     }
+    Ok(())
+}
 
-    let uuid_copy = uuid.clone();
-    tokio::spawn(async move {
-        let state = state_copy;
-        let uuid = uuid_copy;
-        tracing::info!("\nUser {uuid} compressing \"{archive_path_str}\".");
-        let args = ["-r", &archive_path_str, "."];
-        let command = format!("zip {}", args.join(" "));
-        let Ok(zip_cmd) = tokio::process::Command::new("zip")
-            .args(args)
-            .current_dir(&user_dir_str)
-            .output()
-            .await
-        else {
-            tracing::error!("\nFailed to issue command \"{command}\".");
-            state
-                .update_task(&uuid, task_err(ServerError::IssueCommand(command)))
-                .await;
-            return;
-        };
-        if !zip_cmd.status.success() {
-            tracing::error!("\nFailed to compress archive \"{command}\".");
-            state
-                .update_task(&uuid, task_err(ServerError::CompressFile))
-                .await;
-            return;
+fn upload_err(path: &Path, e: std::io::Error) -> AppError {
+    tracing::error!("\nFailed to save upload to \"{}\": {e}", path.display());
+    ServerError::OpenFile(path.display().to_string()).into()
+}
+
+/// Stream request body to the end of `file` at `path`, which holds `received` bytes, failing
+/// once it exceeds `max_bytes`.
+///
+/// Bytes written before a failure are kept and counted in `received`, so that a resumable upload
+/// continues from there.
+async fn append_body(
+    mut body: Body,
+    file: &mut tokio::fs::File,
+    path: &Path,
+    received: &mut u64,
+    max_bytes: u64,
+) -> Result<(), AppError> {
+    let result = async {
+        while let Some(frame) = poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+            let frame = frame.map_err(|e| {
+                tracing::warn!("\nUpload is interrupted: {e}");
+                ClientError::MalformedRequest("upload is interrupted".into())
+            })?;
+            let Ok(data) = frame.into_data() else {
+                continue;
+            };
+            if *received + data.len() as u64 > max_bytes {
+                tracing::warn!("\nUpload exceeding {max_bytes} bytes is rejected.");
+                let msg = format!("upload exceeds {max_bytes} bytes");
+                return Err(ClientError::MalformedRequest(msg).into());
+            }
+            file.write_all(&data)
+                .await
+                .map_err(|e| upload_err(path, e))?;
+            *received += data.len() as u64;
         }
-        tracing::info!("\nUser {uuid} compressing \"{archive_path_str}\" complete.");
-    });
-    ok(FetchArchiveResp { init: true }).into_response()
+        Ok(())
+    }
+    .await;
+    file.flush().await.map_err(|e| upload_err(path, e))?;
+    result
 }
 
-async fn download_resp(path: impl AsRef<Path>, name: &str) -> impl IntoResponse {
-    let Ok(file) = tokio::fs::File::open(path).await else {
-        return Err(());
+/// Re-run a failed task under the same uuid.
+///
+/// `POST` `/retry` with body:  
+/// `{ uuid: "unique ID assigned by /init" }`  
+/// The task restarts from download if the audio is missing, otherwise from the AI model, returning  
+/// `{ success: true, data = { stage: "Download"/"Pending" } }`  
+/// Only tasks observed as failed by `/poll` with `--keep-completed-secs`, or not yet polled,
+/// are still known to the server.
+pub async fn retry_task(
+    State(state): State<ServerState>,
+    Json(retry_body): Json<RetryReq>,
+) -> Response {
+    let uuid = retry_body.uuid;
+    let user_dir = state.work_dir.join(&uuid);
+    match state.get_task(&uuid).await {
+        None => {
+            tracing::warn!("\nUser {uuid} without a task attempts to retry.");
+            return err::<RetryResp>(ClientError::TokenNotExist(uuid)).into_response();
+        }
+        Some(TaskStatus::Err(_)) if user_dir.exists() => {}
+        Some(_) => return err::<RetryResp>(ClientError::NotRetryable(uuid)).into_response(),
+    }
+    if let Err(e) = check_disk_space(&state) {
+        return e.into_response();
+    }
+    let queued = match check_retry_capacity(&state) {
+        Ok(queued) => queued,
+        Err(e) => return e.into_response(),
     };
-    let stream = io::ReaderStream::new(file);
-    let body = Body::from_stream(stream);
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        header::CONTENT_TYPE,
-        HeaderValue::from_static("application/zip"),
-    );
-    headers.insert(
-        header::CONTENT_DISPOSITION,
-        HeaderValue::from_str(&format!("attachment; filename=\"{}\"", name)).unwrap(),
-    );
-    Ok((headers, body))
-}
 
-fn is_url_problem(err_msg: &str) -> bool {
-    let list = [
-        "is not a valid URL",
-        "Failed to resolve",
-        "Video unavailable",
-        "Incomplete YouTube ID",
-    ];
-    list.iter().any(|&s| err_msg.contains(s))
+    let request = state.read_task(&uuid, |task| task.request.clone()).await;
+    let Some(request) = request.flatten() else {
+        return err::<RetryResp>(ClientError::NotRetryable(uuid)).into_response();
+    };
+    let (stage, mode) = match user_dir
+        .join(request.audio_filename(&state.config))
+        .exists()
+    {
+        true => (TaskStatus::Pending, DownloadMode::Skip),
+        // uploaded audio cannot be downloaded again
+        false if request.upload.is_some() => {
+            return err::<RetryResp>(ClientError::NotRetryable(uuid)).into_response()
+        }
+        false => (TaskStatus::Download, DownloadMode::Fresh),
+    };
+    if state.restart_task(&uuid, stage.clone()).await.is_none() {
+        return err::<RetryResp>(ClientError::NotRetryable(uuid)).into_response();
+    }
+    if request.upload.is_none() {
+        state
+            .reclaim_url(&request.url, &request.options, &uuid)
+            .await;
+    }
+    tracing::info!("\nUser {uuid} retries failed task.");
+    spawn_summarize(state, Arc::new(uuid), request, None, mode, queued);
+    ok(RetryResp { stage }).into_response()
+}
+
+/// Erase a task and its files on demand, rather than waiting for them to expire.
+///
+/// `POST` `/delete` with body:  
+/// `{ uuid: "unique ID assigned by /init" }`  
+/// A running task is aborted along with its download or model process. It returns  
+/// `{ success: true, data = { task_removed: true, files_removed: true } }`  
+/// telling which of the task and its dir existed, so that deleting again succeeds as well, with
+/// `token_not_exist` only if neither exists.
+pub async fn delete_task(
+    State(state): State<ServerState>,
+    Json(body): Json<DeleteReq>,
+) -> JsonResp<DeleteResp> {
+    let uuid = body.uuid;
+    // anything but a uuid may escape work_dir
+    if Uuid::parse_str(&uuid).is_err() {
+        tracing::warn!("\nUser {uuid} attempts to delete with a malformed uuid.");
+        return err(ClientError::MalformedRequest(format!(
+            "\"{uuid}\" is not a uuid"
+        )));
+    }
+    let task = state.remove_task(&uuid).await;
+    if let Some(abort) = task.as_ref().and_then(|task| task.abort.as_ref()) {
+        abort.abort();
+    }
+    let mut files_removed = false;
+    let output_dir = state.output_dir.as_ref().map(|dir| dir.join(&uuid));
+    for user_dir in [Some(state.work_dir.join(&uuid)), output_dir]
+        .into_iter()
+        .flatten()
+    {
+        match tokio::fs::remove_dir_all(&user_dir).await {
+            Ok(()) => files_removed = true,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => {
+                tracing::error!("\nFailed to remove \"{}\": {e}", user_dir.display());
+                return err(ServerError::RemoveFile(user_dir.display().to_string()));
+            }
+        }
+    }
+    if task.is_none() && !files_removed {
+        tracing::warn!("\nUser {uuid} without a task attempts to delete.");
+        return err(ClientError::TokenNotExist(uuid));
+    }
+    tracing::info!("\nUser {uuid} deletes task and its files.");
+    ok(DeleteResp {
+        task_removed: task.is_some(),
+        files_removed,
+    })
+}
+
+/// Check that `url` points to an accessible video, without downloading it.
+async fn validate_video(state: &ServerState, url: &str) -> JsonResp<ValidateResp> {
+    match probe_video(state, url).await {
+        Ok(metadata) => {
+            tracing::info!("\nValidated video url: {}.", redact(url));
+            ok(ValidateResp {
+                valid: true,
+                duration_secs: metadata.duration_secs(),
+                title: metadata.title,
+            })
+        }
+        Err(e) => {
+            tracing::warn!("\nValidation failed for video url: {}.", redact(url));
+            err(e)
+        }
+    }
+}
+
+/// Preview a video before submitting it, without processing.
+///
+/// `POST` `/metadata` with body:  
+/// `{ url: "video link" }`  
+/// It returns  
+/// `{ success: true, data = { title: "...", uploader: "...", duration_secs: 123, thumbnail_url:
+/// "https://..." } }`  
+/// where all but `title` may be `null`, or the error that a task of the url would end up with,
+/// e.g. `video_link_not_exist`, `video_private` or `video_requires_auth`. Videos longer than `--max-duration-secs`
+/// are previewed all the same.
+pub async fn video_metadata(
+    State(state): State<ServerState>,
+    Json(body): Json<MetadataReq>,
+) -> JsonResp<MetadataResp> {
+    match fetch_video_metadata(&state, &body.url).await {
+        Ok(metadata) => ok(MetadataResp {
+            duration_secs: metadata.duration_secs(),
+            title: metadata.title,
+            uploader: metadata.uploader,
+            thumbnail_url: metadata.thumbnail,
+        }),
+        Err(e) => {
+            tracing::warn!(
+                "\nMetadata query failed for video url: {}.",
+                redact(&body.url)
+            );
+            err(e)
+        }
+    }
+}
+
+/// Fetch metadata of `url`, rejecting videos longer than `--max-duration-secs`.
+///
+/// Live streams have no duration and are never rejected.
+async fn probe_video(state: &ServerState, url: &str) -> Result<VideoMetadata, AppError> {
+    let metadata = fetch_video_metadata(state, url).await?;
+    if let (Some(secs), Some(max)) = (metadata.duration_secs(), state.config.max_duration_secs) {
+        if secs > max {
+            return Err(ClientError::VideoTooLong { secs, max }.into());
+        }
+    }
+    Ok(metadata)
+}
+
+/// Fetch metadata of `url`, which is checked to be a web url first.
+async fn fetch_video_metadata(state: &ServerState, url: &str) -> Result<VideoMetadata, AppError> {
+    check_url(url)?;
+    state.executor.metadata(url).await
+}
+
+/// Reject new task if `work_dir` is running out of space, warn if it is getting close.
+///
+/// Failure to inspect free space is logged but does not reject.
+fn check_disk_space(state: &ServerState) -> Result<(), TransientError> {
+    let config = &state.config;
+    if config.min_free_bytes.is_none() && config.warn_free_bytes.is_none() {
+        return Ok(());
+    }
+    let available = match available_bytes(&state.work_dir) {
+        Ok(available) => available,
+        Err(e) => {
+            tracing::error!("\nFailed to inspect free space of work dir: {e}");
+            return Ok(());
+        }
+    };
+    if let Some(needed) = config.min_free_bytes.filter(|&needed| available < needed) {
+        tracing::error!("\nReject task, only {available} bytes left in work dir.");
+        return Err(TransientError::InsufficientDiskSpace { needed, available });
+    }
+    if config.warn_free_bytes.is_some_and(|soft| available < soft) {
+        tracing::warn!("\nWork dir is running out of space, {available} bytes left.");
+    }
+    Ok(())
+}
+
+/// Slots of a task admitted by [`check_capacity`], see [`crate::slots`].
+struct Admission {
+    /// Held by the entry of the task.
+    task: Slot,
+    /// Held by the pipeline until it runs a model.
+    queued: Slot,
+}
+
+/// Reject new task if the server is shutting down or paused, or task table is full, see
+/// `--max-active-tasks`, or model queue is, see `--queue-capacity`.
+///
+/// Otherwise the task is admitted with a slot of each limit taken at once, so that concurrent
+/// requests cannot exceed them together.
+fn check_capacity(state: &ServerState) -> Result<Admission, TransientError> {
+    let queued = check_retry_capacity(state)?;
+    let max = state.runtime.read().unwrap().max_active_tasks;
+    let task = match max {
+        Some(max) => state.task_slots.try_take(max).map_err(|active| {
+            tracing::warn!("\nReject task, {active} tasks are active.");
+            TransientError::ServerBusy(max)
+        })?,
+        None => state.task_slots.take(),
+    };
+    Ok(Admission { task, queued })
+}
+
+/// [`check_capacity`] of a `/retry`, whose task keeps its slot of `--max-active-tasks`. Returns
+/// the slot of `--queue-capacity` for the new run.
+fn check_retry_capacity(state: &ServerState) -> Result<Slot, TransientError> {
+    if state.shutting_down.load(Ordering::Relaxed) {
+        tracing::warn!("\nReject task, server is shutting down.");
+        return Err(TransientError::ShuttingDown);
+    }
+    if state.paused.load(Ordering::Relaxed) {
+        tracing::warn!("\nReject task, server is paused.");
+        return Err(TransientError::ServicePaused);
+    }
+    // tasks never wait for a model without `--max-concurrent-models`
+    match (state.config.queue_capacity, &state.model_queue) {
+        (Some(capacity), Some(_)) => state.queue_slots.try_take(capacity).map_err(|depth| {
+            tracing::warn!("\nReject task, {depth} tasks are bound for a model.");
+            TransientError::QueueFull(capacity)
+        }),
+        _ => Ok(state.queue_slots.take()),
+    }
+}
+
+/// Tasks admitted but not yet running a model, i.e. downloading or waiting for one, 0 without
+/// `--max-concurrent-models` as tasks never wait then.
+fn queue_depth(state: &ServerState) -> usize {
+    match &state.model_queue {
+        Some(_) => state.queue_slots.held(),
+        None => 0,
+    }
+}
+
+/// Collects every invalid field of a request, rather than stopping at the first.
+#[derive(Default)]
+struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    fn reject(&mut self, field: &str, message: String) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            message,
+        });
+    }
+
+    /// `value` if nothing is rejected, [`ClientError::Validation`] listing all rejections otherwise.
+    fn finish<T>(self, value: T) -> Result<T, ClientError> {
+        match self.errors.is_empty() {
+            true => Ok(value),
+            false => Err(ClientError::Validation(self.errors)),
+        }
+    }
+}
+
+/// Options of `/init`, validated along with its url.
+fn init_options(config: &Config, req: &InitiateReq) -> Result<(ModelOptions, u8), ClientError> {
+    let mut validator = Validator::default();
+    if check_url(&req.url).is_err() {
+        validator.reject("url", format!("\"{}\" is not an http(s) url", req.url));
+    }
+    let mut options = model_options(
+        config,
+        &mut validator,
+        req.language.clone(),
+        req.model.clone(),
+        req.mode.as_deref(),
+        req.backend.as_deref(),
+    );
+    if let Some(language) = &req.output_language {
+        if !config.output_languages.contains(language) {
+            validator.reject(
+                "output_language",
+                format!("\"{language}\" is not supported"),
+            );
+        }
+    }
+    // nothing to translate if transcribed in that language already
+    options.output_language = req
+        .output_language
+        .clone()
+        .filter(|language| req.language.as_ref() != Some(language));
+    let priority = req.priority.unwrap_or_default();
+    if priority > MAX_PRIORITY {
+        validator.reject("priority", format!("{priority} is above {MAX_PRIORITY}"));
+    }
+    validator.finish((options, priority))
+}
+
+/// Attempts of [`fresh_uuid`] before giving up.
+const UUID_ATTEMPTS: usize = 3;
+
+/// A uuid by `generate` of neither a task nor a dir in `work_dir` or `--output-dir`, e.g. one
+/// left by a previous run, so that a new task never shares files with another.
+///
+/// Fails with [`ServerError::Internal`] if none is free within [`UUID_ATTEMPTS`].
+async fn fresh_uuid(
+    state: &ServerState,
+    mut generate: impl FnMut() -> Uuid,
+) -> Result<String, ServerError> {
+    for _ in 0..UUID_ATTEMPTS {
+        let uuid = generate().to_string();
+        let exists = |dir: &PathBuf| dir.join(&uuid).exists();
+        let taken = exists(&state.work_dir) || state.output_dir.as_deref().is_some_and(exists);
+        if !state.has_task(&uuid).await && !taken {
+            return Ok(uuid);
+        }
+        tracing::error!("\nGenerated uuid \"{uuid}\" is taken, regenerate.");
+    }
+    Err(ServerError::Internal(format!(
+        "no free uuid within {UUID_ATTEMPTS} attempts"
+    )))
+}
+
+/// Model options requested by client, checked against the allowlists in [`Config`].
+///
+/// Invalid fields are rejected into `validator`, and replaced by defaults in the result.
+fn model_options(
+    config: &Config,
+    validator: &mut Validator,
+    language: Option<String>,
+    model: Option<String>,
+    mode: Option<&str>,
+    backend: Option<&str>,
+) -> ModelOptions {
+    let mut check = |field: &str, value: &Option<String>, allowed: &[String]| match value {
+        Some(value) if !allowed.contains(value) => {
+            validator.reject(field, format!("\"{value}\" is not supported"));
+        }
+        _ => (),
+    };
+    check("language", &language, &config.allowed_languages);
+    check("model", &model, &config.allowed_models);
+    let mode = match mode {
+        None => TaskMode::Summary,
+        Some(name) => TaskMode::parse(name).unwrap_or_else(|| {
+            let msg = format!("\"{name}\" is not one of summary and transcript");
+            validator.reject("mode", msg);
+            TaskMode::default()
+        }),
+    };
+    let resolved = config.backends.resolve(backend);
+    if resolved.is_none() {
+        let names = config.backends.names().collect::<Vec<_>>().join(", ");
+        let msg = format!("\"{}\" is not one of {names}", backend.unwrap_or_default());
+        validator.reject("backend", msg);
+    }
+    ModelOptions {
+        language,
+        model,
+        mode,
+        backend: resolved.map(String::from),
+        output_language: None,
+    }
+}
+
+/// How [`summarize`] obtains the audio.
+#[derive(Clone, Copy)]
+enum DownloadMode {
+    Fresh,
+    /// Resume the partial download left by an interrupted run.
+    Continue,
+    /// Reuse the audio left by a previous run.
+    Skip,
+}
+
+/// Re-enqueue tasks interrupted by a restart, see `--recover-tasks`.
+///
+/// A task dir without summary is recovered if it holds the persisted request, and abandoned
+/// otherwise. The download is resumed rather than restarted.
+pub async fn recover_tasks(state: &ServerState) {
+    let mut entries = match tokio::fs::read_dir(&*state.work_dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::error!("\nFailed to scan work dir for interrupted tasks: {e}");
+            return;
+        }
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let user_dir = entry.path();
+        let Some(uuid) = entry.file_name().to_str().map(String::from) else {
+            continue;
+        };
+        if Uuid::parse_str(&uuid).is_err() || !user_dir.is_dir() {
+            continue;
+        }
+        let summary_key = format!("{uuid}/{}", state.config.summary_filename);
+        if state.storage.exists(&summary_key).await {
+            continue;
+        }
+        let request = match state.storage.read(&format!("{uuid}/{REQUEST_FILE}")).await {
+            Ok(json) => serde_json::from_slice::<TaskRequest>(&json).ok(),
+            Err(_) => None,
+        };
+        let Some(request) = request else {
+            tracing::warn!(
+                "\nAbandon interrupted task of uuid \"{uuid}\", its request is unknown."
+            );
+            continue;
+        };
+        let result_filename = request.options.mode.result_filename(&state.config);
+        if state
+            .storage
+            .exists(&format!("{uuid}/{result_filename}"))
+            .await
+        {
+            continue;
+        }
+        let request = Arc::new(request);
+        state.update_task(&uuid, TaskStatus::Download).await;
+        // admitted before the restart, never rejected by limits
+        let slot = Arc::new(state.task_slots.take());
+        state
+            .modify_task(&uuid, |task| {
+                task.request = Some(Arc::clone(&request));
+                task.slot = Some(slot);
+            })
+            .await;
+        let mode = match request.upload {
+            Some(_) => DownloadMode::Skip,
+            None => {
+                state.claim_url(&request.url, &request.options, &uuid).await;
+                DownloadMode::Continue
+            }
+        };
+        state.metrics.task_initiated();
+        tracing::info!("\nRecover interrupted task of uuid \"{uuid}\".");
+        let queued = state.queue_slots.take();
+        spawn_summarize(state.clone(), Arc::new(uuid), request, None, mode, queued);
+    }
+}
+
+/// Fails with [`ClientError::VideoTooLarge`] if downloaded audio exceeds `--max-audio-bytes`,
+/// which the downloader may not know up front, e.g. of a livestream.
+async fn check_audio_size(state: &ServerState, audio_path: &Path) -> Result<(), AppError> {
+    let Some(max) = state.config.max_audio_bytes else {
+        return Ok(());
+    };
+    match tokio::fs::metadata(audio_path).await {
+        Ok(metadata) if metadata.len() > max => {
+            let bytes = metadata.len();
+            tracing::warn!(
+                "\nAudio \"{}\" of {bytes} bytes exceeds {max}.",
+                audio_path.display()
+            );
+            Err(ClientError::VideoTooLarge { bytes, max }.into())
+        }
+        // missing audio is left to the model to report
+        _ => Ok(()),
+    }
+}
+
+/// Download the video and run AI model on it, updating task status along the way.
+///
+/// The model script of the task's backend, `run_model.sh` by default, is invoked as  
+/// `<script> <audio_path> <output_dir> <language> <model> [--transcript-only]`  
+/// where `language` and `model` are empty strings when unspecified by client, and
+/// `--transcript-only` is passed in transcript mode.
+///
+/// `metadata` is reused if already queried by [`init_summary`]. See [`DownloadMode`] about how
+/// audio is obtained. `queued` is released once the model runs.
+///
+/// Returns the terminal status, which is also stored in task table.
+async fn summarize(
+    state: &ServerState,
+    uuid: &str,
+    request: &TaskRequest,
+    metadata: Option<VideoMetadata>,
+    mode: DownloadMode,
+    queued: Slot,
+) -> TaskStatus {
+    let TaskRequest { url, options, .. } = request;
+    let user_dir = state.work_dir.join(uuid);
+    let user_dir_str = user_dir.display();
+    let audio_path = user_dir.join(request.audio_filename(&state.config));
+
+    if create_dir_all(&user_dir).is_err() {
+        tracing::error!("\nFailed to prepare user path \"{user_dir_str}\".");
+        return fail_task(
+            state,
+            uuid,
+            ServerError::ParsePath(user_dir_str.to_string()),
+        )
+        .await;
+    }
+    // a recovered task may name a backend no longer configured
+    let backend = options.backend.as_deref();
+    let Some(script) = state.config.backends.script(backend) else {
+        let name = backend.unwrap_or_default();
+        tracing::error!("\nBackend \"{name}\" of uuid \"{uuid}\" is not configured.");
+        let e = ServerError::InvalidConfig(format!("backend \"{name}\" is not configured"));
+        return fail_task(state, uuid, e).await;
+    };
+
+    if state.config.recover_tasks {
+        let json = serde_json::to_vec(request).unwrap_or_default();
+        if let Err(e) = state
+            .storage
+            .write(&format!("{uuid}/{REQUEST_FILE}"), json)
+            .await
+        {
+            tracing::error!("\nFailed to persist request of uuid \"{uuid}\": {e}");
+        }
+    }
+
+    if request.upload.is_none() && state.config.download_name_template.contains("{title}") {
+        // best effort, archive name falls back to `untitled`
+        let metadata = match metadata {
+            Some(metadata) => Some(metadata),
+            None => state.executor.metadata(url).await.ok(),
+        };
+        if let Some(metadata) = metadata {
+            let _ = tokio::fs::write(user_dir.join(TITLE_FILE), metadata.title).await;
+        }
+    }
+
+    if !matches!(mode, DownloadMode::Skip) {
+        state.update_task(uuid, TaskStatus::Download).await;
+        let _download_timer = state.metrics.enter_stage(Stage::Download);
+        // download video from youtube
+        let resume = matches!(mode, DownloadMode::Continue);
+        let downloaded = match state.executor.download(url, &audio_path, resume).await {
+            Ok(warnings) => check_audio_size(state, &audio_path)
+                .await
+                .map(|()| warnings),
+            Err(e) => Err(e),
+        };
+        match downloaded {
+            Ok(warnings) => add_warnings(state, uuid, warnings).await,
+            Err(e) => {
+                if matches!(e, AppError::Client(ClientError::VideoTooLarge { .. })) {
+                    // partial, or whole but useless
+                    let _ = tokio::fs::remove_file(&audio_path).await;
+                }
+                return fail_task(state, uuid, e).await;
+            }
+        }
+        tracing::info!(
+            "\nDownload success for uuid: \"{uuid}\", link: \"{}\".",
+            redact(url)
+        );
+    }
+
+    if let Some(blobs) = &state.blobs {
+        match blobs.store(&audio_path).await {
+            Ok(0) => (),
+            Ok(saved) => {
+                tracing::info!("\nAudio of uuid \"{uuid}\" is deduplicated, {saved} bytes saved.")
+            }
+            Err(e) => tracing::warn!("\nFailed to deduplicate audio of uuid \"{uuid}\": {e}"),
+        }
+    }
+
+    let _permit = match &state.model_queue {
+        Some(queue) => {
+            state.update_task(uuid, TaskStatus::Queued).await;
+            Some(queue.acquire(uuid, request.priority).await)
+        }
+        None => None,
+    };
+    drop(queued);
+    state.update_task(uuid, TaskStatus::Pending).await;
+    let _model_timer = state.metrics.enter_stage(Stage::Model);
+    // run AI model to generate
+    tracing::info!(
+        "\nLaunching AI model for uuid: \"{uuid}\", link: \"{}\".",
+        redact(url)
+    );
+    let run = state
+        .executor
+        .run_model(script, &audio_path, &user_dir, options);
+    let result = match state.config.stall_timeout {
+        // dropping the run kills the model process
+        Some(stall) => tokio::select! {
+            result = run => result,
+            idle = stalled(&user_dir, stall) => {
+                tracing::error!("\nAI model for uuid \"{uuid}\" makes no progress for {idle:?}, kill it.");
+                Err(ServerError::ModelStalled(idle.as_secs()).into())
+            }
+        },
+        None => run.await,
+    };
+    match result {
+        Ok(warnings) => add_warnings(state, uuid, warnings).await,
+        Err(e) => return fail_task(state, uuid, e).await,
+    }
+    state.model_ready.store(true, Ordering::Relaxed);
+    let result_path = match options.mode {
+        TaskMode::Summary => summary_file(&state.config, &user_dir).await,
+        TaskMode::Transcript => user_dir.join(TRANSCRIPT_FILENAME),
+    };
+    if let Err(e) = check_result_size(&state.config, &result_path).await {
+        tracing::error!("\nAI model for uuid \"{uuid}\" exits successfully without a result: {e}");
+        return fail_task(state, uuid, e).await;
+    }
+    tracing::info!(
+        "\nAI model success for uuid: \"{uuid}\", link: \"{}\".",
+        redact(url)
+    );
+    if let Some(language) = &options.output_language {
+        state.update_task(uuid, TaskStatus::Translating).await;
+        let result_path = user_dir.join(options.mode.result_filename(&state.config));
+        let translated_path =
+            user_dir.join(options.mode.translated_filename(&state.config, language));
+        let translated = state
+            .executor
+            .translate(
+                &state.config.translate_script,
+                &result_path,
+                &translated_path,
+                options.language.as_deref(),
+                language,
+            )
+            .await;
+        match translated {
+            Ok(warnings) => add_warnings(state, uuid, warnings).await,
+            Err(e) => return fail_task(state, uuid, e).await,
+        }
+        tracing::info!("\nTranslation into \"{language}\" success for uuid: \"{uuid}\".");
+    }
+    if state.config.delete_audio_after_model {
+        delete_audio(
+            &state.config,
+            uuid,
+            &user_dir,
+            request.audio_filename(&state.config),
+        )
+        .await;
+    }
+
+    if let Some(output_dir) = &state.output_dir {
+        // scratch audio stays, to be retried or fetched by `/audio`
+        let keep = [request.audio_filename(&state.config), REQUEST_FILE];
+        if let Err(e) = publish(&user_dir, &output_dir.join(uuid), &keep).await {
+            tracing::error!("\nFailed to move results of uuid \"{uuid}\" to output dir: {e}");
+            let e = ServerError::MoveFile(user_dir_str.to_string());
+            return fail_task(state, uuid, e).await;
+        }
+        task_log::moved_to(&output_dir.join(uuid));
+    }
+
+    state.metrics.task_completed();
+    state.finish_task(uuid, TaskStatus::Done).await;
+    TaskStatus::Done
+}
+
+/// Remove the audio no longer needed by the model, see `--delete-audio-after-model`.
+///
+/// Audio put into the archive is kept with `--keep-archived-audio`.
+async fn delete_audio(config: &Config, uuid: &str, user_dir: &Path, filename: &str) {
+    let archived = config.archive_include.is_empty()
+        || config
+            .archive_include
+            .iter()
+            .any(|pattern| glob_match(pattern, filename));
+    if config.keep_archived_audio && archived {
+        return;
+    }
+    let path = user_dir.join(filename);
+    let size = match tokio::fs::metadata(&path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            tracing::warn!("\nAudio of uuid \"{uuid}\" is absent for deletion: {e}");
+            return;
+        }
+    };
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => tracing::info!("\nAudio of uuid \"{uuid}\" deleted, {size} bytes freed."),
+        Err(e) => tracing::error!("\nFailed to delete audio of uuid \"{uuid}\": {e}"),
+    }
+}
+
+async fn add_warnings(state: &ServerState, uuid: &str, warnings: Warnings) {
+    if warnings.is_empty() {
+        return;
+    }
+    tracing::info!("\nUser {uuid} task step succeeds with warnings: {warnings:?}");
+    state
+        .modify_task(uuid, |task| task.warnings.extend(warnings))
+        .await;
+}
+
+/// Set `uuid` to failure status, recording it in metrics and the alerter, and removing its task
+/// dir unless kept by `--keep-failed-dirs`.
+async fn fail_task(state: &ServerState, uuid: &str, err: impl Into<AppError>) -> TaskStatus {
+    let err = err.into();
+    state.metrics.task_failed(&err);
+    if let (Some(alerter), AppError::Server(e)) = (state.alerter.as_ref(), &err) {
+        alerter.record(e);
+    }
+    if !state.config.keep_failed_dirs.keeps(&err) {
+        let user_dir = state.work_dir.join(uuid);
+        match tokio::fs::remove_dir_all(&user_dir).await {
+            Ok(()) => tracing::info!("\nRemove dir of uuid \"{uuid}\" failed by {}.", err.code()),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => tracing::warn!(
+                "\nFailed to remove dir of failed task \"{}\": {e}",
+                user_dir.display()
+            ),
+        }
+    }
+    let status = TaskStatus::Err(err);
+    state.finish_task(uuid, status.clone()).await;
+    status
+}
+
+/// Query the server the status of specified task.
+///
+/// `POST` `/poll` with body:  
+/// `{ uuid: "unique ID assigned by /init", format: "md" }`  
+/// where optional `format` is one of `txt` (default), `md`, `json` and `json-segments`, and
+/// optional `preview_only: true` omits `result` and `segments` of a done task.  
+/// It returns  
+/// `{ success: true, data = { ... } }`  
+/// where `data =` one of:  
+/// - Your task has been completed.  
+///   `{ done: true, stage: Done, result: "the summary of your video link", format: "md", warnings: [...] }`  
+///   where `format` falls back to `txt` if the model did not generate the requested one, and
+///   `warnings` lists quality caveats reported along the way, usually empty. The `txt` summary
+///   is the newest file matching `--summary-glob` if set. `preview` is `result` cut at a word
+///   within `--preview-chars` characters, ending with `…` if cut. A leading BOM and trailing
+///   whitespace of `result` are stripped unless `--normalize-summary false`.  
+/// - Your task has been completed, with `json-segments` requested.  
+///   `{ done: true, stage: Done, result: "Hello\nworld", format: "json-segments", segments: [{ start_secs: 0, end_secs: 1.5, text: " Hello" }, ...] }`  
+///   where `segments` is the transcript with timestamps from `transcript.json`, `result` holding
+///   their text line by line. Falls back to the plain transcript as `txt` if the model did not
+///   write valid segments.  
+/// - Server is downloading your specified video.  
+///   `{ done: false, stage: Download, result: null }`  
+/// - Your video waits for a free model, behind `queue_position - 1` others.  
+///   `{ done: false, stage: Queued, result: null, queue_position: 3, priority: 1 }`  
+///   where `priority` is the effective one, raised the longer it waits.  
+/// - Your video is under AI processing.  
+///   `{ done: false, stage: Pending, result: null, partial_result: "...so far" }`  
+///   where `partial_result` is present only with `--stream-partial`, holding the tail of the
+///   summary written so far.  
+/// - Your result is being translated into `output_language` of `/init`.  
+///   `{ done: false, stage: Translating, result: null }`  
+///
+/// Or, Your task failed.  
+/// - Wrong uuid.  
+///   `{ success: false, err = { source: "client", info: "...", code: "token_not_exist" } }`  
+/// - Polled again within `--min-poll-interval-ms`.  
+///   `{ success: false, err = { source: "client", info: "...", code: "poll_too_fast", retry_after_ms: 420 } }`  
+/// - Error occured during processing.  
+///   `{ success: false, err = { source: "server", info: "...", code: "..." } }`  
+#[axum::debug_handler]
+pub async fn poll_status(
+    State(state): State<ServerState>,
+    Json(poll_body): Json<PollStatusReq>,
+) -> JsonResp<PollStatusResp> {
+    let uuid = poll_body.uuid;
+    let format = match poll_body.format.as_deref() {
+        None => SummaryFormat::Txt,
+        Some(name) => match SummaryFormat::parse(name) {
+            Some(format) => format,
+            None => {
+                return err(ClientError::MalformedRequest(format!(
+                    "unknown format \"{name}\""
+                )))
+            }
+        },
+    };
+    if let Some(min) = state.config.min_poll_interval {
+        let mut wait = None;
+        state
+            .modify_task(&uuid, |task| wait = task.throttle_poll(Instant::now(), min))
+            .await;
+        if let Some(wait) = wait {
+            tracing::warn!("\nUser {uuid} polls too fast.");
+            // rounded up, so that polling right after does not fail again
+            let retry_after_ms = wait.as_micros().div_ceil(1000) as u64;
+            return err(ClientError::PollTooFast { retry_after_ms });
+        }
+    }
+    // copy only what the stage needs, without allocating while in progress
+    let polled = state
+        .read_task(&uuid, |task| {
+            let mode = task
+                .request
+                .as_ref()
+                .map(|request| request.options.mode)
+                .unwrap_or_default();
+            let finished = matches!(task.status, TaskStatus::Done).then(|| {
+                let output_language = task
+                    .request
+                    .as_ref()
+                    .and_then(|request| request.options.output_language.clone());
+                (task.timings(), task.warnings.clone(), output_language)
+            });
+            (task.status.clone(), mode, finished)
+        })
+        .await;
+    let Some((status, mode, finished)) = polled else {
+        tracing::warn!("\nUser {uuid} without a task attempts to poll.");
+        return err(ClientError::TokenNotExist(uuid));
+    };
+    let retry_after_ms = match state.config.poll_interval_base {
+        Some(base) => {
+            let active = state.task_count().await;
+            poll_interval(base, &status, active).map(|interval| jitter(interval, &uuid))
+        }
+        None => None,
+    };
+    match status {
+        TaskStatus::Queued => ok(PollStatusResp {
+            queue_position: state
+                .model_queue
+                .as_ref()
+                .and_then(|queue| queue.position(&uuid)),
+            priority: state
+                .model_queue
+                .as_ref()
+                .and_then(|queue| queue.priority(&uuid)),
+            ..PollStatusResp::in_progress(TaskStatus::Queued, retry_after_ms)
+        }),
+        TaskStatus::Pending => ok(PollStatusResp {
+            partial_result: match state.config.stream_partial {
+                true => {
+                    let result_path = state
+                        .work_dir
+                        .join(&uuid)
+                        .join(mode.result_filename(&state.config));
+                    read_tail(&result_path, PARTIAL_TAIL_BYTES).await
+                }
+                false => None,
+            },
+            ..PollStatusResp::in_progress(TaskStatus::Pending, retry_after_ms)
+        }),
+        stage @ (TaskStatus::Download | TaskStatus::Translating | TaskStatus::Compressing) => {
+            ok(PollStatusResp::in_progress(stage, retry_after_ms))
+        }
+        TaskStatus::Done => {
+            let (timings, warnings, output_language) = finished.unwrap_or_default();
+            let user_dir = state.result_dir(&uuid);
+            let requested = user_dir.join(format.file_name(&state.config));
+            let segments = match format {
+                SummaryFormat::JsonSegments => read_segments(&requested).await,
+                _ => None,
+            };
+            let translated = output_language
+                .map(|language| user_dir.join(mode.translated_filename(&state.config, &language)));
+            let (summary_path, format) = match (mode, translated) {
+                // plain transcript if the model does not keep timestamps
+                _ if format == SummaryFormat::JsonSegments => (
+                    user_dir.join(TRANSCRIPT_FILENAME),
+                    match segments {
+                        Some(_) => SummaryFormat::JsonSegments,
+                        None => SummaryFormat::Txt,
+                    },
+                ),
+                // only the plain result is translated
+                (_, Some(translated)) => (translated, SummaryFormat::Txt),
+                (TaskMode::Summary, None) if format != SummaryFormat::Txt && requested.exists() => {
+                    (requested, format)
+                }
+                (TaskMode::Summary, None) => (
+                    summary_file(&state.config, &user_dir).await,
+                    SummaryFormat::Txt,
+                ),
+                (TaskMode::Transcript, None) => (
+                    user_dir.join(mode.result_filename(&state.config)),
+                    SummaryFormat::Txt,
+                ),
+            };
+            // dropped here if client disconnects meanwhile, so read before removing the task
+            // to keep it for the next poll
+            let content = match &segments {
+                Some(segments) => Ok(segments
+                    .iter()
+                    .map(|segment| segment.text.trim())
+                    .collect::<Vec<_>>()
+                    .join("\n")),
+                None => read_summary(&summary_path, &uuid).await,
+            };
+            if state.config.keep_completed.is_none() && state.observe_finished(&uuid).await {
+                tracing::info!(
+                    "\nUser {uuid} obtains summary result, remove entry from task table."
+                );
+            }
+            let content = match content {
+                Ok(content) if state.config.normalize_summary => normalize_summary(content),
+                Ok(content) => content,
+                Err(e) => return err(e),
+            };
+            let preview = preview(&content, state.config.preview_chars);
+            let (result, segments) = match poll_body.preview_only {
+                true => (None, None),
+                false => (Some(content), segments),
+            };
+            ok(PollStatusResp {
+                done: true,
+                stage: TaskStatus::Done,
+                result,
+                format: Some(format),
+                timings,
+                warnings: Some(warnings),
+                segments,
+                preview: Some(preview),
+                queue_position: None,
+                priority: None,
+                partial_result: None,
+                retry_after_ms: None,
+            })
+        }
+        TaskStatus::Err(app_err) => {
+            if state.config.keep_completed.is_none() && state.observe_finished(&uuid).await {
+                tracing::info!(
+                    "\nUser {uuid} observes error status, remove entry from task table."
+                );
+            }
+            err(app_err)
+        }
+    }
+}
+
+/// Upper bound of `retry_after_ms` in `/poll`.
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Suggested interval before polling a task in `stage` again, with `active` tasks in the table.
+///
+/// Stages expected to last long are polled less often, e.g. a model run, and each 10 active tasks
+/// add another `base`. `None` once the task is finished.
+fn poll_interval(base: Duration, stage: &TaskStatus, active: usize) -> Option<Duration> {
+    let stage_factor = match stage {
+        TaskStatus::Download => 2,
+        TaskStatus::Queued => 4,
+        TaskStatus::Pending => 3,
+        TaskStatus::Translating => 2,
+        TaskStatus::Compressing => 1,
+        TaskStatus::Done | TaskStatus::Err(_) => return None,
+    };
+    let load_factor = u32::try_from(active / 10).unwrap_or(u32::MAX);
+    let interval = base
+        .saturating_mul(stage_factor)
+        .saturating_add(base.saturating_mul(load_factor));
+    Some(interval.min(MAX_POLL_INTERVAL))
+}
+
+/// Milliseconds of `interval` spread by up to +20% per task, so that clients created together do
+/// not keep polling together.
+fn jitter(interval: Duration, uuid: &str) -> u64 {
+    let millis = interval.as_millis() as u64;
+    let seed = uuid
+        .bytes()
+        .fold(0u64, |acc, b| acc.wrapping_mul(31).wrapping_add(b as u64));
+    millis + seed % (millis / 5 + 1)
+}
+
+/// Summary file of a done task in `user_dir`, the newest match of `--summary-glob` if set, or
+/// `--summary-filename`.
+///
+/// The path returned may not exist, e.g. if nothing matches.
+async fn summary_file(config: &Config, user_dir: &Path) -> PathBuf {
+    let Some(pattern) = &config.summary_glob else {
+        return user_dir.join(&config.summary_filename);
+    };
+    let mut newest: Option<(SystemTime, String)> = None;
+    if let Ok(mut entries) = tokio::fs::read_dir(user_dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if !metadata.is_file() || !glob_match(pattern, &name) {
+                continue;
+            }
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            // ties go to the greater name, so that the choice does not depend on listing order
+            if newest
+                .as_ref()
+                .is_none_or(|newest| (modified, &name) > (newest.0, &newest.1))
+            {
+                newest = Some((modified, name));
+            }
+        }
+    }
+    match newest {
+        Some((_, name)) => user_dir.join(name),
+        None => user_dir.join(pattern),
+    }
+}
+
+/// Fails with [`ServerError::EmptySummary`] if result at `path` is shorter than
+/// `--min-summary-bytes` without surrounding whitespace, a missing result counting as empty.
+async fn check_result_size(config: &Config, path: &Path) -> Result<(), ServerError> {
+    let min = config.min_summary_bytes;
+    if min == 0 {
+        return Ok(());
+    }
+    let bytes = tokio::fs::read(path).await.unwrap_or_default();
+    let bytes = bytes.trim_ascii().len() as u64;
+    match bytes < min {
+        true => Err(ServerError::EmptySummary { bytes, min }),
+        false => Ok(()),
+    }
+}
+
+/// Read the summary generated for `uuid`.
+///
+/// A missing file yields [`ServerError::ResultMissing`], as it was probably cleaned up and the
+/// client may re-init, while any other IO failure yields [`ServerError::ReadFile`].
+async fn read_summary(path: &Path, uuid: &str) -> Result<String, ServerError> {
+    let path_str = path.to_string_lossy().to_string();
+    if !path.exists() {
+        tracing::error!("\nSummary result at {path_str} is missing.");
+        return Err(ServerError::ResultMissing(uuid.to_string()));
+    }
+    read_text(path).await
+}
+
+/// `summary` without a leading UTF-8 BOM nor trailing whitespace, line breaks included.
+fn normalize_summary(summary: String) -> String {
+    let normalized = summary
+        .strip_prefix('\u{feff}')
+        .unwrap_or(&summary)
+        .trim_end();
+    match normalized.len() == summary.len() {
+        true => summary,
+        false => normalized.to_string(),
+    }
+}
+
+/// Segments in `path` if written by the model, `None` if absent or malformed.
+async fn read_segments(path: &Path) -> Option<Vec<TranscriptSegment>> {
+    if !path.exists() {
+        return None;
+    }
+    let json = read_text(path).await.ok()?;
+    TranscriptSegment::parse_all(&json)
+        .inspect_err(|e| {
+            tracing::warn!("\nSegments at {} are malformed: {e}", path.display());
+        })
+        .ok()
+}
+
+/// Read a text file written by the model script, rejecting invalid UTF-8.
+async fn read_text(path: &Path) -> Result<String, ServerError> {
+    let path_str = path.to_string_lossy().to_string();
+    let bytes = tokio::fs::read(path).await.map_err(|_| {
+        tracing::error!("\nFailed to read text result at {path_str}.");
+        ServerError::ReadFile(path_str.clone())
+    })?;
+    String::from_utf8(bytes).map_err(|e| {
+        tracing::error!("\nText result at {path_str} is not UTF-8: {e}");
+        ServerError::InvalidUtf8(path_str)
+    })
+}
+
+/// `text` cut to at most `max_chars` characters, ellipsis included, at the end of a word.
+///
+/// A word longer than that, or text without spaces such as Chinese, is cut in the middle.
+fn preview(text: &str, max_chars: usize) -> String {
+    let text = text.trim();
+    let Some((end, _)) = text.char_indices().nth(max_chars) else {
+        return text.to_string();
+    };
+    // room for the ellipsis
+    let end = text[..end]
+        .char_indices()
+        .next_back()
+        .map_or(0, |(last, _)| last);
+    let head = match text[end..].starts_with(char::is_whitespace) {
+        true => &text[..end],
+        false => text[..end]
+            .rfind(char::is_whitespace)
+            .map_or(&text[..end], |space| &text[..space]),
+    };
+    format!("{}…", head.trim_end())
+}
+
+/// Last `max_bytes` of a text file that may still be written, `None` if it cannot be read.
+///
+/// Characters cut at either end are dropped, other invalid UTF-8 is replaced.
+async fn read_tail(path: &Path, max_bytes: u64) -> Option<String> {
+    let mut file = tokio::fs::File::open(path).await.ok()?;
+    let len = file.metadata().await.ok()?.len();
+    let start = len.saturating_sub(max_bytes);
+    file.seek(SeekFrom::Start(start)).await.ok()?;
+    let mut bytes = Vec::new();
+    file.take(max_bytes).read_to_end(&mut bytes).await.ok()?;
+    let head = match start {
+        0 => 0,
+        // skip continuation bytes of a character starting before the tail
+        _ => bytes.iter().take_while(|&&b| b & 0xC0 == 0x80).count(),
+    };
+    let bytes = &bytes[head..];
+    let end = match std::str::from_utf8(bytes) {
+        // incomplete character being written
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => bytes.len(),
+    };
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+/// Poll download entire archive for diagnosis.
+///
+/// `POST` `/download` with body:  
+/// `{ uuid: "unique ID assigned by /init", file: "archive" }`  
+/// where `file` is optional. With `file` being `summary` or `transcript`, the corresponding text
+/// file is returned right away as `content-type: text/plain`, without compression.  
+/// It returns  
+/// - error if processing failed, or uuid does not exist.  
+///   `{ success: false, err = { source: "client"/"server", info: "...", code: "..." } }`  
+///   A failed compression is reported once by the next request, the task staying done, so that
+///   the request after it compresses again.  
+/// - dummy JSON `{ init: true }` while compressing, the task stage is `Compressing` meanwhile.
+///   Concurrent requests share one compression, which writes aside and renames the archive into
+///   place once complete, so that no request sees it half written.  
+/// - dummy JSON `{ init: false }` if the task is still in progress.  
+/// - http response with  
+///   `content-type: application/zip`  
+/// - with `inline: true` in the body, the archive in JSON instead of the binary response  
+///   `{ success: true, data = { archive_base64: "UEsDBA...", filename: "summary.zip", size: 1024 } }`  
+///   or `inline_too_large` error beyond `--max-inline-bytes`, to be downloaded as binary.  
+/// - with `partial: true` in the body, while the task is in progress or after it failed, a zip
+///   of its files so far along with `manifest.json`  
+///   `{ uuid, stage: "Pending", err?: { ... }, files: [{ path, size, modified }], present:
+///   ["audio.mp3"], missing: ["summary.txt", "raw_sub.txt", "run.log"] }`  
+///   for debugging a stuck or failed task. A complete task is downloaded as usual.  
+///
+/// Frontend should poll until error or `content-type = application/zip`, or `archive_base64`
+/// with `inline`.  
+pub async fn fetch_archive(
+    State(state): State<ServerState>,
+    Json(fetch_body): Json<FetchArchiveReq>,
+) -> impl IntoResponse {
+    let uuid = fetch_body.uuid;
+    let file = match fetch_body.file.as_deref() {
+        None => DownloadFile::Archive,
+        Some(name) => match DownloadFile::parse(name) {
+            Some(file) => file,
+            None => {
+                let e = ClientError::MalformedRequest(format!("unknown file \"{name}\""));
+                return err::<FetchArchiveResp>(e).into_response();
+            }
+        },
+    };
+
+    let user_dir = state.result_dir(&uuid);
+    let archive_path = user_dir.join(&state.config.archive_filename);
+    if Uuid::parse_str(&uuid).is_err() || !user_dir.is_dir() {
+        tracing::warn!("\nUser {uuid} attempts to download without init task.");
+        let uuid_err = ClientError::TokenNotExist(uuid);
+        return <Json<AppResp<FetchArchiveResp>> as IntoResponse>::into_response(err(uuid_err))
+            .into_response();
+    }
+
+    if file != DownloadFile::Archive {
+        let path = user_dir.join(file.file_name(&state.config));
+        if !path.exists() {
+            tracing::warn!(
+                "\nUser {uuid} attempts to download absent {}.",
+                file.file_name(&state.config)
+            );
+            return err::<FetchArchiveResp>(ServerError::ResultMissing(uuid)).into_response();
+        }
+        // text is small, validate it so that the declared charset holds
+        let text = match read_text(&path).await {
+            Ok(text) => text,
+            Err(e) => return err::<FetchArchiveResp>(e).into_response(),
+        };
+        tracing::info!("\nUser {uuid} downloads \"{}\".", path.display());
+        let headers = [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(file.content_type()),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition(file.file_name(&state.config)),
+            ),
+        ];
+        return (headers, text).into_response();
+    }
+
+    let archive_path_str = archive_path.display().to_string();
+    let status = state.get_task(&uuid).await;
+    let incomplete = matches!(
+        status,
+        Some(
+            TaskStatus::Download
+                | TaskStatus::Queued
+                | TaskStatus::Pending
+                | TaskStatus::Translating
+                | TaskStatus::Err(_)
+        )
+    );
+    if let (true, Some(status)) = (fetch_body.partial && incomplete, status.clone()) {
+        return partial_archive(&state, &uuid, &user_dir, status)
+            .await
+            .into_response();
+    }
+    if let Some(TaskStatus::Compressing) = status {
+        // archive on disk is incomplete
+        return ok(FetchArchiveResp { init: true }).into_response();
+    }
+    if archive_path.exists() {
+        tracing::info!("\nUser {uuid} downloads \"{archive_path_str}\".");
+        let title = read_to_string(user_dir.join(TITLE_FILE)).await.ok();
+        let name = archive_name(
+            &state.config.download_name_template,
+            title.as_deref(),
+            &uuid,
+        );
+        if fetch_body.inline {
+            return inline_archive(&state.config, &archive_path, name)
+                .await
+                .into_response();
+        }
+        let extra = &state.config.download_headers;
+        return download_resp(&archive_path, &name, file.content_type(), extra)
+            .await
+            .into_response();
+    }
+    match status {
+        Some(TaskStatus::Err(e)) => {
+            return <Json<AppResp<FetchArchiveResp>> as IntoResponse>::into_response(err(e))
+                .into_response();
+        }
+        // nothing complete to compress yet
+        Some(TaskStatus::Download | TaskStatus::Queued | TaskStatus::Pending) => {
+            return ok(FetchArchiveResp { init: false }).into_response();
+        }
+        _ => {}
+    }
+    // reported once, compressed again by the next request
+    let mut failed = None;
+    state
+        .modify_task(&uuid, |task| failed = task.archive_err.take())
+        .await;
+    if let Some(e) = failed {
+        return err::<FetchArchiveResp>(e).into_response();
+    }
+    // swapped under the task table lock, so only one request gets to compress
+    let previous = state.update_task(&uuid, TaskStatus::Compressing).await;
+    if let Some(TaskStatus::Compressing) = previous {
+        return ok(FetchArchiveResp { init: true }).into_response();
+    }
+
+    let state = Arc::new(state);
+    let state_copy = Arc::clone(&state);
+    let uuid_copy = uuid.clone();
+    tokio::spawn(async move {
+        let state = state_copy;
+        let uuid = uuid_copy;
+        tracing::info!("\nUser {uuid} compressing \"{archive_path_str}\".");
+        let partial_path = user_dir.join(partial_archive_name(&state.config));
+        // `zip` appends to an archive left by a crash
+        let _ = tokio::fs::remove_file(&partial_path).await;
+        let files = archive_files(&user_dir, &state.config).await;
+        let compressed = match files {
+            Ok(files) => {
+                state
+                    .executor
+                    .compress(&user_dir, &files, &partial_path)
+                    .await
+            }
+            Err(e) => Err(e.into()),
+        };
+        let compressed = match compressed {
+            Ok(()) => tokio::fs::rename(&partial_path, &archive_path)
+                .await
+                .map_err(|_| {
+                    tracing::error!("\nFailed to move archive to \"{archive_path_str}\".");
+                    ServerError::CompressFile.into()
+                }),
+            Err(e) => Err(e),
+        };
+        let archive_err = match compressed {
+            Ok(()) => {
+                tracing::info!("\nUser {uuid} compressing \"{archive_path_str}\" complete.");
+                None
+            }
+            Err(e) => Some(e),
+        };
+        match previous {
+            // the task itself still succeeded
+            Some(previous) => {
+                state.update_task(&uuid, previous).await;
+                state
+                    .modify_task(&uuid, |task| task.archive_err = archive_err)
+                    .await;
+            }
+            // result was already polled and removed, do not resurrect the entry
+            None => {
+                state.remove_task(&uuid).await;
+            }
+        }
+    });
+    ok(FetchArchiveResp { init: true }).into_response()
+}
+
+/// Expose task counters, stage gauges and stage durations in Prometheus text format, along with
+/// the model queue depth and `--queue-capacity` if set.
+///
+/// `GET` `/metrics`
+pub async fn metrics(State(state): State<ServerState>) -> impl IntoResponse {
+    let mut out = state.metrics.render();
+    if state.model_queue.is_some() {
+        out.push_str(
+            "# HELP summary_queue_depth Tasks waiting for a model or downloading for one.\n",
+        );
+        out.push_str("# TYPE summary_queue_depth gauge\n");
+        let _ = writeln!(out, "summary_queue_depth {}", queue_depth(&state));
+    }
+    if let (Some(_), Some(capacity)) = (&state.model_queue, state.config.queue_capacity) {
+        out.push_str(
+            "# HELP summary_queue_capacity Queue depth beyond which tasks are rejected.\n",
+        );
+        out.push_str("# TYPE summary_queue_capacity gauge\n");
+        let _ = writeln!(out, "summary_queue_capacity {capacity}");
+    }
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        out,
+    )
+}
+
+/// List tasks submitted with the same `X-Session-Id`, see [`crate::session`].
+///
+/// `POST` `/session/tasks` with body:  
+/// `{ session_id: "the X-Session-Id header of /init" }`  
+/// It returns  
+/// `{ success: true, data = { uuids: ["...", "..."] } }`  
+/// with tasks still in the task table, earliest first, and empty for an unknown session.  
+/// Only routed with `--enable-sessions`.
+pub async fn session_tasks(
+    State(state): State<ServerState>,
+    Json(body): Json<SessionTasksReq>,
+) -> JsonResp<SessionTasksResp> {
+    let Some(sessions) = &state.sessions else {
+        return ok(SessionTasksResp { uuids: Vec::new() });
+    };
+    let mut uuids = Vec::new();
+    for uuid in sessions.tasks(&body.session_id) {
+        if state.has_task(&uuid).await {
+            uuids.push(uuid);
+        }
+    }
+    ok(SessionTasksResp { uuids })
+}
+
+/// Estimate how long a task submitted now waits for a model, for clients to show an ETA.
+///
+/// `GET` `/estimate`  
+/// It returns  
+/// `{ success: true, data = { queued: 2, running: 1, est_wait_secs: 1200, queue_depth: 3,
+/// queue_capacity: 10 } }`  
+/// where `est_wait_secs` counts queued and running tasks as whole runs of the mean model
+/// duration so far, or of `--default-model-duration` before any run. It is always 0 without
+/// `--max-concurrent-models`, as models never wait then. `queue_depth` also counts downloading
+/// tasks, `/init` failing with `queue_full` once it reaches `--queue-capacity`.
+pub async fn estimate(State(state): State<ServerState>) -> JsonResp<EstimateResp> {
+    let per_run = state
+        .metrics
+        .mean_duration(Stage::Model)
+        .unwrap_or(state.config.default_model_duration);
+    let resp = match &state.model_queue {
+        Some(queue) => {
+            let (queued, running) = queue.load();
+            let wait = queue.estimate_wait(per_run);
+            EstimateResp {
+                queued,
+                running,
+                // rounded up, better to overestimate
+                est_wait_secs: wait.as_secs() + u64::from(wait.subsec_nanos() > 0),
+                queue_depth: queue_depth(&state),
+                queue_capacity: state.config.queue_capacity,
+            }
+        }
+        None => EstimateResp {
+            queued: 0,
+            running: state.metrics.active(Stage::Model),
+            est_wait_secs: 0,
+            queue_depth: 0,
+            queue_capacity: None,
+        },
+    };
+    ok(resp)
+}
+
+/// Whether the server accepts new tasks.
+///
+/// `GET` `/health`  
+/// It returns  
+/// `{ success: true, data = { status: "ok", accepting_tasks: true, model_ready: true } }`  
+/// where `status` is `paused` after `/admin/pause`, and `shutting_down` while draining for
+/// shutdown, in which cases `accepting_tasks` is false. Existing tasks can be polled and
+/// downloaded regardless, so the response is HTTP 200 either way. `model_ready` tells whether
+/// the last warmup or model run succeeded, `null` unless `--model-warmup` is set.
+pub async fn health(State(state): State<ServerState>) -> JsonResp<HealthResp> {
+    let status = match (
+        state.shutting_down.load(Ordering::Relaxed),
+        state.paused.load(Ordering::Relaxed),
+    ) {
+        (true, _) => "shutting_down",
+        (false, true) => "paused",
+        (false, false) => "ok",
+    };
+    let model_ready = state.model_ready.load(Ordering::Relaxed);
+    ok(HealthResp {
+        status: status.to_string(),
+        accepting_tasks: status == "ok",
+        model_ready: state.config.model_warmup.is_some().then_some(model_ready),
+    })
+}
+
+/// Choices of `/init` and `/poll` allowed by the server configuration, for clients to offer, along
+/// with its limits.
+///
+/// `GET` `/capabilities`  
+/// It returns  
+/// `{ success: true, data = { backends: ["default"], default_backend: "default", languages,
+/// models, modes: ["summary", "transcript"], output_languages, formats, max_duration_secs,
+/// max_upload_bytes, max_audio_bytes } }`
+pub async fn capabilities(State(state): State<ServerState>) -> JsonResp<CapabilitiesResp> {
+    let config = &state.config;
+    let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+    ok(CapabilitiesResp {
+        backends: config.backends.names().map(String::from).collect(),
+        default_backend: config.backends.default_name().to_string(),
+        languages: config.allowed_languages.clone(),
+        models: config.allowed_models.clone(),
+        modes: names(&TaskMode::NAMES),
+        output_languages: config.output_languages.clone(),
+        formats: names(&SummaryFormat::NAMES),
+        max_duration_secs: config.max_duration_secs,
+        max_upload_bytes: config.max_upload_bytes,
+        max_audio_bytes: config.max_audio_bytes,
+    })
+}
+
+/// Expose version and build metadata of the running binary.
+///
+/// `GET` `/version`
+pub async fn version() -> JsonResp<VersionResp> {
+    let build_time = env!("BUILD_UNIX_TIME")
+        .parse()
+        .ok()
+        .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_default();
+    ok(VersionResp {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("BUILD_GIT_SHA"),
+        build_time,
+        rustc: env!("BUILD_RUSTC_VERSION"),
+    })
+}
+
+/// Recently finished tasks, newest first.
+///
+/// `GET` `/admin/history?stage=err&limit=50&offset=0` with `Authorization: Bearer <token>`,
+/// where all parameters are optional, `stage` is either `done` or `err`.  
+/// It returns  
+/// `{ success: true, data = { total: 2, entries: [{ ts, uuid, stage, duration_secs, error }] } }`  
+/// where `total` counts all entries matching `stage`. Urls and error details are included only
+/// with `--admin-show-urls`.
+pub async fn admin_history(
+    State(state): State<ServerState>,
+    Query(query): Query<HistoryQuery>,
+) -> JsonResp<HistoryResp> {
+    let Some(history) = state.history.as_ref() else {
+        return err(ServerError::Internal("history is disabled".into()));
+    };
+    match history.query(&query) {
+        Some(resp) => ok(resp),
+        None => err(ClientError::MalformedRequest(format!(
+            "stage {:?} is not one of done and err",
+            query.stage.unwrap_or_default()
+        ))),
+    }
+}
+
+/// Performance of tasks finished within `--stats-window`, see [`crate::stats`].
+///
+/// `GET` `/admin/stats` with `Authorization: Bearer <token>`.  
+/// It returns  
+/// `{ success: true, data = { window_secs, tasks, succeeded, failed, success_rate, download: {
+/// count, median_secs, p95_secs }, model: { ... }, errors: { ai_model: 2 } } }`  
+/// where `success_rate` and percentiles are `null` until observed.
+pub async fn admin_stats(State(state): State<ServerState>) -> JsonResp<StatsResp> {
+    match state.stats.as_ref() {
+        Some(stats) => ok(stats.snapshot(Instant::now())),
+        None => err(ServerError::Internal("stats are disabled".into())),
+    }
+}
+
+/// Last lines printed by the download, model and translation commands of a task, as they run,
+/// see [`crate::output_tail`].
+///
+/// `GET` `/admin/output/:uuid` with `Authorization: Bearer <token>`.  
+/// It returns  
+/// `{ success: true, data = { stage: "Pending", lines: ["...", "..."] } }`  
+/// with the last [`OUTPUT_TAIL_LINES`][`crate::output_tail::OUTPUT_TAIL_LINES`] lines of stdout
+/// and stderr, oldest first, or `token_not_exist` error if the task is not in the task table.
+pub async fn admin_output(
+    State(state): State<ServerState>,
+    UrlPath(uuid): UrlPath<String>,
+) -> JsonResp<OutputResp> {
+    match state.get_task_entry(&uuid).await {
+        Some(task) => ok(OutputResp {
+            stage: task.status,
+            lines: task.output.lines(),
+        }),
+        None => err(ClientError::TokenNotExist(uuid)),
+    }
+}
+
+/// Summaries of all done tasks as one zip, e.g. for a migration, see [`crate::export`].
+///
+/// `GET` `/admin/export` with `Authorization: Bearer <token>`.  
+/// It returns  
+/// `content-type: application/zip` with `<uuid>/<summary file>` of each task dir in the output
+/// dir, or else the work dir, skipping dirs without a summary and tasks not done. Summaries
+/// past `--export-max-bytes` are left out.  
+/// Or `read_file` error as JSON if the dir cannot be listed.
+pub async fn admin_export(State(state): State<ServerState>) -> Response {
+    let dir = state.output_dir.as_deref().unwrap_or(&state.work_dir);
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::error!("\nFailed to list \"{}\" for export: {e}", dir.display());
+            return err::<()>(ServerError::ReadFile(dir.display().to_string())).into_response();
+        }
+    };
+    let mut uuids = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if Uuid::parse_str(&name).is_ok() && entry.path().is_dir() {
+            uuids.push(name);
+        }
+    }
+    uuids.sort();
+    let mut files = Vec::new();
+    for uuid in uuids {
+        let done = match state.get_task(&uuid).await {
+            Some(status) => matches!(status, TaskStatus::Done | TaskStatus::Compressing),
+            None => true,
+        };
+        let path = summary_file(&state.config, &dir.join(&uuid)).await;
+        if !done || !path.is_file() {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        files.push((format!("{uuid}/{name}"), Content::File(path)));
+    }
+    tracing::info!("\nExport of {} summaries starts.", files.len());
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/zip"),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        content_disposition("export.zip"),
+    );
+    let body = Body::from_stream(zip_stream(files, state.config.export_max_bytes));
+    (headers, body).into_response()
+}
+
+/// Reload hot-reloadable settings from `--runtime-config`, see [`crate::reload`].
+///
+/// `POST` `/admin/reload` with `Authorization: Bearer <token>`.  
+/// It returns  
+/// `{ success: true, data = { request_timeout_secs, rate_limit, max_active_tasks,
+/// max_concurrent_models } }`  
+/// with the values now in effect, or `invalid_config` error keeping the current ones.
+pub async fn admin_reload(State(state): State<ServerState>) -> JsonResp<ReloadResp> {
+    match reload(&state).await {
+        Ok(runtime) => {
+            let resp = ReloadResp {
+                request_timeout_secs: runtime.request_timeout.map(|timeout| timeout.as_secs()),
+                rate_limit: runtime.rate_limit,
+                max_active_tasks: runtime.max_active_tasks,
+                max_concurrent_models: runtime.max_concurrent_models,
+            };
+            tracing::info!("\nRuntime config reloaded: {resp:?}.");
+            ok(resp)
+        }
+        Err(e) => {
+            tracing::error!("\nReload of runtime config failed: {e}");
+            err(e)
+        }
+    }
+}
+
+/// Stop accepting new tasks, e.g. for maintenance, while existing ones go on.
+///
+/// `POST` `/admin/pause` with `Authorization: Bearer <token>`.  
+/// It returns  
+/// `{ success: true, data = { paused: true } }`  
+/// after which `/init` and uploads fail with `service_paused` (HTTP 503) until `/admin/resume`,
+/// while `/poll` and `/download` are unaffected.
+pub async fn admin_pause(State(state): State<ServerState>) -> JsonResp<PauseResp> {
+    if !state.paused.swap(true, Ordering::Relaxed) {
+        tracing::warn!("\nNew tasks are paused.");
+    }
+    ok(PauseResp { paused: true })
+}
+
+/// At most this much of the command output is returned by `/admin/selftest`.
+const SELFTEST_OUTPUT_BYTES: u64 = 4 * 1024;
+
+/// Run the download, model and compress steps on `--selftest-url`, e.g. as a smoke test after a
+/// deploy, in a scratch task dir removed afterwards.
+///
+/// `POST` `/admin/selftest` with `Authorization: Bearer <token>`.  
+/// It returns  
+/// `{ success: true, data = { passed: true, steps: [{ name: "download", passed: true, secs: 2.5 }, ...], error: null, output: "..." } }`  
+/// where `error` tells why the last step failed unless `passed`, and `output` is the tail of what
+/// the download and model commands printed. The run is cut at `--selftest-timeout`, and neither
+/// occupies the task table nor waits for `--max-concurrent-models`.
+pub async fn admin_selftest(State(state): State<ServerState>) -> JsonResp<SelftestResp> {
+    let uuid = match fresh_uuid(&state, Uuid::new_v4).await {
+        Ok(uuid) => uuid,
+        Err(e) => return err(e),
+    };
+    let dir = state.work_dir.join(&uuid);
+    let timeout = state.config.selftest_timeout;
+    tracing::info!("\nSelftest starts in \"{}\".", dir.display());
+    let started = Instant::now();
+    let mut steps = Vec::new();
+    let result = tokio::time::timeout(timeout, selftest(&state, &dir, &mut steps)).await;
+    let result = match result {
+        Ok(result) => result,
+        Err(_) => {
+            // cut in the middle of the last step
+            let finished: f64 = steps.iter().map(|step| step.secs).sum();
+            if let Some(step) = steps.last_mut() {
+                step.secs = started.elapsed().as_secs_f64() - finished;
+            }
+            Err(ServerError::Timeout(timeout.as_secs()).into())
+        }
+    };
+    let output = read_tail(&dir.join(RUN_LOG_FILENAME), SELFTEST_OUTPUT_BYTES).await;
+    if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+        tracing::error!("\nFailed to remove selftest dir \"{}\": {e}", dir.display());
+    }
+    match &result {
+        Ok(()) => tracing::info!("\nSelftest passes in {:?}.", started.elapsed()),
+        Err(e) => tracing::error!("\nSelftest fails: {e}"),
+    }
+    ok(SelftestResp {
+        passed: result.is_ok(),
+        steps,
+        error: result.err().map(|e| e.to_string()),
+        output,
+    })
+}
+
+/// Steps of `/admin/selftest` in `dir`, each recorded into `steps` as it starts.
+async fn selftest(
+    state: &ServerState,
+    dir: &Path,
+    steps: &mut Vec<SelftestStep>,
+) -> Result<(), AppError> {
+    create_dir_all(dir).map_err(|_| ServerError::ParsePath(dir.display().to_string()))?;
+    let config = &state.config;
+    let audio_path = dir.join(&config.audio_filename);
+    let download = state
+        .executor
+        .download(&config.selftest_url, &audio_path, false);
+    selftest_step(steps, "download", download).await?;
+    let script = config
+        .backends
+        .script(None)
+        .ok_or_else(|| ServerError::Internal("default backend has no script".into()))?;
+    let options = ModelOptions::default();
+    let model = state.executor.run_model(script, &audio_path, dir, &options);
+    selftest_step(steps, "model", model).await?;
+    let compress = async {
+        let files = archive_files(dir, config).await?;
+        let archive_path = dir.join(&config.archive_filename);
+        state.executor.compress(dir, &files, &archive_path).await
+    };
+    selftest_step(steps, "compress", compress).await
+}
+
+async fn selftest_step<T>(
+    steps: &mut Vec<SelftestStep>,
+    name: &str,
+    run: impl Future<Output = Result<T, AppError>>,
+) -> Result<(), AppError> {
+    let index = steps.len();
+    steps.push(SelftestStep {
+        name: name.to_string(),
+        passed: false,
+        secs: 0.0,
+    });
+    let started = Instant::now();
+    let result = run.await;
+    steps[index].passed = result.is_ok();
+    steps[index].secs = started.elapsed().as_secs_f64();
+    result.map(|_| ())
+}
+
+/// Accept new tasks again after `/admin/pause`.
+///
+/// `POST` `/admin/resume` with `Authorization: Bearer <token>`.  
+/// It returns  
+/// `{ success: true, data = { paused: false } }`
+pub async fn admin_resume(State(state): State<ServerState>) -> JsonResp<PauseResp> {
+    if state.paused.swap(false, Ordering::Relaxed) {
+        tracing::info!("\nNew tasks are resumed.");
+    }
+    ok(PauseResp { paused: false })
+}
+
+/// Longest `/admin/flush-logs` waits for the log writer.
+const LOG_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait for lines logged so far to be written to the log file, see [`crate::log::LogFlusher`].
+///
+/// `POST` `/admin/flush-logs` with `Authorization: Bearer <token>`.  
+/// It returns  
+/// `{ success: true, data = { pending_lines: 3 } }`  
+/// where `pending_lines` were not in the file yet when called, or `timeout` error if the writer
+/// does not catch up within 5 seconds.  
+/// Logging never waits for the file, so this usually returns within milliseconds, but takes as
+/// long as the writer needs to work through a backlog, e.g. while the disk is slow.
+pub async fn admin_flush_logs(State(state): State<ServerState>) -> JsonResp<FlushLogsResp> {
+    let Some(flusher) = state.log_flusher.as_ref() else {
+        return err(ServerError::Internal("log flushing is disabled".into()));
+    };
+    match flusher.flush(LOG_FLUSH_TIMEOUT).await {
+        Ok(pending_lines) => ok(FlushLogsResp { pending_lines }),
+        Err(pending_lines) => {
+            tracing::error!("\nLog writer is {pending_lines} lines behind after flushing.");
+            err(ServerError::Timeout(LOG_FLUSH_TIMEOUT.as_secs()))
+        }
+    }
+}
+
+/// Stream the summary as the model writes it, for models generating it token by token.
+///
+/// `GET` `/stream-summary/:uuid`  
+/// It returns `text/event-stream` of  
+/// - unnamed events, each the text appended to the summary since the previous one.  
+/// - a `done` event with empty data after the last of them once the task is done.  
+/// - an `error` event with data `{ success: false, err = { ... } }` if the task fails, even
+///   midway through the summary.  
+///
+/// or `token_not_exist` error as JSON if there is no such task. Text is that of `/poll` in `txt`
+/// format, the transcript in transcript mode. As with `/poll`, the finished task is removed
+/// unless `--keep-completed`.
+pub async fn stream_summary(
+    State(state): State<ServerState>,
+    UrlPath(uuid): UrlPath<String>,
+) -> Response {
+    let Some(task) = state.get_task_entry(&uuid).await else {
+        tracing::warn!("\nUser {uuid} without a task attempts to stream summary.");
+        return err::<()>(ClientError::TokenNotExist(uuid)).into_response();
+    };
+    let mode = task
+        .request
+        .as_ref()
+        .map(|request| request.options.mode)
+        .unwrap_or_default();
+    let filename = mode.result_filename(&state.config).to_string();
+    tracing::info!("\nUser {uuid} streams summary.");
+    Sse::new(summary_events(state, uuid, filename))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Stream the stage of several tasks at once, e.g. for a dashboard, see [`crate::events`].
+///
+/// `POST` `/events/batch` with body:  
+/// `{ uuids: ["unique ID assigned by /init", ...] }`  
+/// It returns `text/event-stream` of `status` events, each with data  
+/// `{ uuid: "...", stage: "Pending", err?: { success: false, err = { ... } } }`  
+/// sent for each task once, and again whenever its stage changes, until it is `Done` or `Err`.
+/// A task not in the task table gets a single event without `stage`, with `token_not_exist`
+/// error. The stream ends after the last task finishes. Watching does not remove finished
+/// tasks, unlike `/poll`.  
+/// Or `validation` error as JSON if there are no uuids, more than
+/// [`MAX_BATCH_UUIDS`][`crate::events::MAX_BATCH_UUIDS`], or any of them is not a uuid.
+pub async fn batch_events(
+    State(state): State<ServerState>,
+    Json(body): Json<BatchEventsReq>,
+) -> Response {
+    let mut validator = Validator::default();
+    match body.uuids.len() {
+        0 => validator.reject("uuids", "is empty".into()),
+        len if len > MAX_BATCH_UUIDS => {
+            validator.reject("uuids", format!("has {len} uuids, above {MAX_BATCH_UUIDS}"))
+        }
+        _ => (),
+    }
+    for (i, uuid) in body.uuids.iter().enumerate() {
+        if Uuid::parse_str(uuid).is_err() {
+            validator.reject(&format!("uuids[{i}]"), format!("\"{uuid}\" is not a uuid"));
+        }
+    }
+    let mut uuids = match validator.finish(body.uuids) {
+        Ok(uuids) => uuids,
+        Err(e) => return err::<()>(e).into_response(),
+    };
+    let mut seen = HashSet::new();
+    uuids.retain(|uuid| seen.insert(uuid.clone()));
+    tracing::info!("\nUser watches {} tasks.", uuids.len());
+    Sse::new(status_events(state, uuids))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Download the audio that the model of a task runs on.
+///
+/// `POST` `/audio` with body:  
+/// `{ uuid: "unique ID assigned by /init" }`  
+/// It returns  
+/// - the audio as is, with `content-type` by its extension, e.g. `audio/mpeg`.  
+/// - `not_ready` error while the task is still downloading.  
+/// - `token_not_exist` error if there is no audio of the task, e.g. deleted by
+///   `--delete-audio-after-model`.  
+pub async fn fetch_audio(
+    State(state): State<ServerState>,
+    Json(body): Json<FetchAudioReq>,
+) -> Response {
+    let uuid = body.uuid;
+    let task = state.get_task_entry(&uuid).await;
+    if task
+        .as_ref()
+        .is_some_and(|task| matches!(task.status, TaskStatus::Download))
+    {
+        return err::<()>(ClientError::NotReady(uuid)).into_response();
+    }
+    let filename = match task.as_ref().and_then(|task| task.request.as_ref()) {
+        Some(request) => request.audio_filename(&state.config).to_string(),
+        None => state.config.audio_filename.clone(),
+    };
+    let path = state.work_dir.join(&uuid).join(&filename);
+    // anything but a uuid may escape work_dir
+    if Uuid::parse_str(&uuid).is_err() || !path.is_file() {
+        tracing::warn!("\nUser {uuid} attempts to download absent audio.");
+        return err::<()>(ClientError::TokenNotExist(uuid)).into_response();
+    }
+    tracing::info!("\nUser {uuid} downloads \"{}\".", path.display());
+    let content_type = audio_content_type(&filename);
+    let extra = &state.config.download_headers;
+    let resp = download_resp(&path, &filename, content_type, extra).await;
+    match resp {
+        Ok(resp) => resp.into_response(),
+        Err(()) => err::<()>(ServerError::ReadFile(path.display().to_string())).into_response(),
+    }
+}
+
+/// Mime type of an audio file by its extension, per [`UPLOAD_TYPES`].
+fn audio_content_type(filename: &str) -> &'static str {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    UPLOAD_TYPES
+        .iter()
+        .find(|(_, ext)| extension.as_deref() == Some(*ext))
+        .map_or("application/octet-stream", |(mime, _)| mime)
+}
+
+/// List files of a task, without their contents.
+///
+/// `POST` `/files` with body:  
+/// `{ uuid: "unique ID assigned by /init" }`  
+/// It returns  
+/// `{ success: true, data = { files: [{ path: "summary.txt", size: 1024, modified: "2024-12-07T01:00:15Z" }] } }`  
+/// where `path` is relative to the task dir, or `token_not_exist` error if the task dir is absent.
+/// Results moved to `--output-dir` are listed along with the audio left in `work_dir`.
+pub async fn list_files(
+    State(state): State<ServerState>,
+    Json(body): Json<ListFilesReq>,
+) -> JsonResp<ListFilesResp> {
+    let uuid = body.uuid;
+    let user_dir = state.work_dir.join(&uuid);
+    // anything but a uuid may escape work_dir
+    if Uuid::parse_str(&uuid).is_err() || !user_dir.is_dir() {
+        tracing::warn!("\nUser {uuid} attempts to list files without init task.");
+        return err(ClientError::TokenNotExist(uuid));
+    }
+    let mut files = match dir_files(&user_dir).await {
+        Ok(files) => files,
+        Err(e) => return err(e),
+    };
+    let result_dir = state.result_dir(&uuid);
+    if result_dir != user_dir {
+        match dir_files(&result_dir).await {
+            Ok(results) => files.extend(results),
+            Err(e) => return err(e),
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    ok(ListFilesResp { files })
+}
+
+/// Files under `dir` with their sizes and modification times, sorted by path.
+async fn dir_files(dir: &Path) -> Result<Vec<FileEntry>, ServerError> {
+    let read_err = |_| {
+        tracing::error!("\nFailed to list \"{}\".", dir.display());
+        ServerError::ReadFile(dir.display().to_string())
+    };
+    let mut files = Vec::new();
+    let mut pending = vec![String::new()];
+    while let Some(prefix) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(dir.join(&prefix))
+            .await
+            .map_err(read_err)?;
+        while let Some(entry) = entries.next_entry().await.map_err(read_err)? {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let path = format!("{prefix}{name}");
+            let metadata = entry.metadata().await.map_err(read_err)?;
+            if metadata.is_dir() {
+                pending.push(format!("{path}/"));
+                continue;
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| OffsetDateTime::from(time).format(&Rfc3339).ok())
+                .unwrap_or_default();
+            files.push(FileEntry {
+                path,
+                size: metadata.len(),
+                modified,
+            });
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Describe the API in OpenAPI 3.
+///
+/// `GET` `/openapi.json`, the document itself rather than wrapped in [`AppResp`].
+pub async fn openapi() -> Json<serde_json::Value> {
+    Json(crate::openapi::spec())
+}
+
+/// Fallback of paths without route, `404` with [`ClientError::RouteNotFound`] in the usual
+/// envelope.
+pub async fn route_not_found(uri: Uri) -> Response {
+    let e = ClientError::RouteNotFound(uri.path().to_string());
+    (StatusCode::NOT_FOUND, err::<()>(e)).into_response()
+}
+
+/// Response of a handler that panics, `500` with [`ServerError::Internal`] in the usual envelope
+/// instead of a dropped connection.
+pub fn handler_panicked(payload: Box<dyn Any + Send>) -> Response {
+    let cause = payload_message(payload);
+    tracing::error!("\nHandler panics: {cause}");
+    let e = ServerError::Internal(cause);
+    (StatusCode::INTERNAL_SERVER_ERROR, err::<()>(e)).into_response()
+}
+
+/// Fallback of routed paths requested with another method, `405` with
+/// [`ClientError::MethodNotAllowed`] in the usual envelope.
+pub async fn method_not_allowed(method: Method, uri: Uri) -> Response {
+    let e = ClientError::MethodNotAllowed {
+        method: method.to_string(),
+        path: uri.path().to_string(),
+    };
+    (StatusCode::METHOD_NOT_ALLOWED, err::<()>(e)).into_response()
+}
+
+/// Parse a `--download-header` of format `Name: value`.
+pub fn parse_header(s: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let malformed = || format!("malformed header \"{s}\", expect format like \"Name: value\"");
+    let (name, value) = s.split_once(':').ok_or_else(malformed)?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| malformed())?;
+    let value = HeaderValue::from_str(value.trim()).map_err(|_| malformed())?;
+    Ok((name, value))
+}
+
+/// File at `path` as attachment `name`, with `extra` headers, e.g. `--download-header`.
+///
+/// `X-Content-Type-Options: nosniff` is sent unless `extra` overrides it, so that browsers do not
+/// render a downloaded file as another type than `content_type`.
+async fn download_resp(
+    path: impl AsRef<Path>,
+    name: &str,
+    content_type: &'static str,
+    extra: &[(HeaderName, HeaderValue)],
+) -> Result<impl IntoResponse, ()> {
+    let path = path.as_ref();
+    let Ok(file) = tokio::fs::File::open(path).await else {
+        return Err(());
+    };
+    let Ok(metadata) = file.metadata().await else {
+        return Err(());
+    };
+    let len = metadata.len();
+    let body = Body::from_stream(checked_stream(file, len, path.to_path_buf()));
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    headers.insert(header::CONTENT_DISPOSITION, content_disposition(name));
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    for (name, value) in extra {
+        headers.insert(name, value.clone());
+    }
+    Ok((headers, body))
+}
+
+/// Chunks of `file` at `path`, expected to be `len` bytes long.
+///
+/// The status is sent before the body, so a failed read, or a file shorter or longer than
+/// declared, e.g. truncated by cleanup meanwhile, ends the stream with an error instead, which
+/// aborts the response rather than completing a download that is silently cut short.
+fn checked_stream(
+    file: tokio::fs::File,
+    len: u64,
+    path: PathBuf,
+) -> impl Stream<Item = std::io::Result<Bytes>> {
+    let reader = io::ReaderStream::new(file);
+    stream::unfold(Some((reader, 0)), move |state| {
+        let path = path.clone();
+        async move {
+            let (mut reader, sent) = state?;
+            let e = match reader.next().await {
+                Some(Ok(chunk)) if sent + chunk.len() as u64 <= len => {
+                    let sent = sent + chunk.len() as u64;
+                    return Some((Ok(chunk), Some((reader, sent))));
+                }
+                Some(Err(e)) => {
+                    tracing::error!(
+                        "\nFailed to read \"{}\" after {sent} of {len} bytes: {e}",
+                        path.display()
+                    );
+                    e
+                }
+                None if sent == len => return None,
+                _ => {
+                    tracing::warn!(
+                        "\nFile \"{}\" changed while downloaded, {len} bytes declared.",
+                        path.display()
+                    );
+                    std::io::Error::new(ErrorKind::UnexpectedEof, "file changed while downloaded")
+                }
+            };
+            Some((Err(e), None))
+        }
+    })
+}
+
+/// Zip of the files of incomplete task `uuid` in `user_dir` so far, along with a
+/// [`PartialManifest`].
+async fn partial_archive(
+    state: &ServerState,
+    uuid: &str,
+    user_dir: &Path,
+    stage: TaskStatus,
+) -> Response {
+    let config = &state.config;
+    let mut entries = match dir_files(user_dir).await {
+        Ok(entries) => entries,
+        Err(e) => return err::<()>(e).into_response(),
+    };
+    let partial = partial_archive_name(config);
+    entries.retain(|entry| entry.path != config.archive_filename && entry.path != partial);
+
+    let request = state
+        .read_task(uuid, |task| task.request.clone())
+        .await
+        .flatten();
+    let (audio, mode) = match &request {
+        Some(request) => (request.audio_filename(config), request.options.mode),
+        None => (config.audio_filename.as_str(), TaskMode::default()),
+    };
+    let result = match mode {
+        TaskMode::Summary => summary_file(config, user_dir).await,
+        TaskMode::Transcript => user_dir.join(TRANSCRIPT_FILENAME),
+    };
+    let result = result.file_name().unwrap_or_default().to_string_lossy();
+    let mut expected = vec![audio, &result, TRANSCRIPT_FILENAME, RUN_LOG_FILENAME];
+    expected.dedup();
+    let (present, missing): (Vec<_>, Vec<_>) = expected
+        .into_iter()
+        .map(String::from)
+        .partition(|name| entries.iter().any(|entry| entry.path == *name));
+
+    let mut files: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            (
+                entry.path.clone(),
+                Content::File(user_dir.join(&entry.path)),
+            )
+        })
+        .collect();
+    let manifest = PartialManifest {
+        uuid: uuid.to_string(),
+        err: match &stage {
+            TaskStatus::Err(e) => Some(e.clone()),
+            _ => None,
+        },
+        stage,
+        files: entries,
+        present,
+        missing,
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest).unwrap_or_default();
+    files.insert(0, (PARTIAL_MANIFEST.to_string(), Content::Bytes(manifest)));
+    tracing::info!(
+        "\nUser {uuid} downloads {} files of incomplete task.",
+        files.len() - 1
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/zip"),
+    );
+    let name = format!("partial-{}.zip", &uuid[..8]);
+    headers.insert(header::CONTENT_DISPOSITION, content_disposition(&name));
+    let body = Body::from_stream(zip_stream(files, config.export_max_bytes));
+    (headers, body).into_response()
+}
+
+/// Archive at `path` as base64, unless larger than `--max-inline-bytes`.
+async fn inline_archive(
+    config: &Config,
+    path: &Path,
+    filename: String,
+) -> JsonResp<InlineArchiveResp> {
+    let read_err = || ServerError::ReadFile(path.display().to_string());
+    let Ok(metadata) = tokio::fs::metadata(path).await else {
+        return err(read_err());
+    };
+    let (size, max) = (metadata.len(), config.max_inline_bytes);
+    if size > max {
+        tracing::warn!(
+            "\nArchive \"{}\" of {size} bytes is too large to inline.",
+            path.display()
+        );
+        return err(ClientError::InlineTooLarge { size, max });
+    }
+    let Ok(bytes) = tokio::fs::read(path).await else {
+        return err(read_err());
+    };
+    ok(InlineArchiveResp {
+        archive_base64: base64::encode(&bytes),
+        filename,
+        size: bytes.len() as u64,
+    })
+}
+
+/// Render `--download-name-template` into a sanitized archive file name.
+///
+/// Placeholders are `{title}` (video title, `untitled` if unknown), `{uuid}` and `{uuid8}`
+/// (first 8 characters of uuid).
+fn archive_name(template: &str, title: Option<&str>, uuid: &str) -> String {
+    let uuid8: String = uuid.chars().take(8).collect();
+    let name = template
+        .replace("{title}", title.map(str::trim).unwrap_or("untitled"))
+        .replace("{uuid8}", &uuid8)
+        .replace("{uuid}", uuid);
+    sanitize_filename(&name)
+}
+
+/// Archive being written by `/download`, renamed to `--archive-filename` once complete.
+fn partial_archive_name(config: &Config) -> String {
+    format!("{}.partial", config.archive_filename)
+}
+
+/// Files under `dir` to be archived, as `/` separated paths relative to it, sorted.
+///
+/// Only those matching some pattern of `--archive-include` are kept, unless it is empty. The
+/// archive itself is never included, complete or not. Nothing left to archive is
+/// [`ServerError::ResultMissing`], as `zip` refuses to create an empty archive.
+async fn archive_files(dir: &Path, config: &Config) -> Result<Vec<String>, ServerError> {
+    let read_err = |_| {
+        tracing::error!("\nFailed to list \"{}\" for archive.", dir.display());
+        ServerError::ReadFile(dir.display().to_string())
+    };
+    let mut files = Vec::new();
+    let mut pending = vec![String::new()];
+    while let Some(prefix) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(dir.join(&prefix))
+            .await
+            .map_err(read_err)?;
+        while let Some(entry) = entries.next_entry().await.map_err(read_err)? {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let path = format!("{prefix}{name}");
+            if entry.file_type().await.map_err(read_err)?.is_dir() {
+                pending.push(format!("{path}/"));
+            } else if path != config.archive_filename && path != partial_archive_name(config) {
+                files.push(path);
+            }
+        }
+    }
+    if !config.archive_include.is_empty() {
+        files.retain(|path| {
+            config
+                .archive_include
+                .iter()
+                .any(|pattern| glob_match(pattern, path))
+        });
+    }
+    if files.is_empty() {
+        tracing::error!("\nNo file of \"{}\" to archive.", dir.display());
+        let uuid = dir.file_name().unwrap_or_default().to_string_lossy();
+        return Err(ServerError::ResultMissing(uuid.into_owned()));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Match `path` against `pattern`, where `*` matches any characters but `/`, and `?` matches one.
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let (pattern, path): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), path.chars().collect());
+    let (mut p, mut s) = (0, 0);
+    // position of last `*` in pattern, and where it started matching in path
+    let mut star: Option<(usize, usize)> = None;
+    while s < path.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, s));
+                p += 1;
+            }
+            Some(&c) if c == path[s] || (c == '?' && path[s] != '/') => {
+                p += 1;
+                s += 1;
+            }
+            _ => match star {
+                // let the last `*` swallow one more character
+                Some((star_p, star_s)) if path[star_s] != '/' => {
+                    p = star_p + 1;
+                    s = star_s + 1;
+                    star = Some((star_p, star_s + 1));
+                }
+                _ => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Replace characters unsafe for file systems or HTTP headers with `_`.
+///
+/// Letters of any script are kept, leading dots are dropped, length is capped at 120 characters.
+fn sanitize_filename(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() => c,
+            '-' | '_' | '.' | ' ' | '(' | ')' => c,
+            _ => '_',
+        })
+        .take(120)
+        .collect();
+    let sanitized = sanitized.trim().trim_start_matches('.');
+    if sanitized.is_empty() {
+        "download".to_string()
+    } else {
+        sanitized.to_string()
+    }
+}
+
+/// `attachment` disposition with an ASCII `filename` fallback, plus RFC 5987 `filename*` when
+/// `name` is not ASCII.
+fn content_disposition(name: &str) -> HeaderValue {
+    let ascii: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let mut value = format!("attachment; filename=\"{ascii}\"");
+    if !name.is_ascii() {
+        value.push_str("; filename*=UTF-8''");
+        for byte in name.bytes() {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => value.push(byte as char),
+                b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|'
+                | b'~' => value.push(byte as char),
+                _ => value.push_str(&format!("%{byte:02X}")),
+            }
+        }
+    }
+    HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        io::Write,
+        path::PathBuf,
+        sync::{atomic::Ordering, Arc},
+        time::{Duration, SystemTime},
+    };
+
+    use axum::{
+        body::{to_bytes, Body},
+        extract::{Json, Path as UrlPath, Query, State},
+        http::{header, HeaderMap, Request, StatusCode},
+        middleware,
+        response::{IntoResponse, Response},
+        routing::post,
+        Router,
+    };
+    use time::UtcOffset;
+    use tower::ServiceExt;
+    use tower_http::catch_panic::CatchPanicLayer;
+    use tracing_subscriber::layer::SubscriberExt;
+    use uuid::Uuid;
+
+    use super::{
+        admin_export, admin_history, admin_output, admin_pause, admin_reload, admin_resume,
+        admin_selftest, admin_stats, archive_files, archive_name, batch_events, capabilities,
+        content_disposition, delete_task, download_resp, estimate, fetch_archive, fetch_audio,
+        fresh_uuid, glob_match, handler_panicked, health, init_summary, init_upload, jitter,
+        list_files, method_not_allowed, metrics, normalize_summary, parse_header,
+        partial_archive_name, poll_interval, poll_status, preview, read_summary, read_tail,
+        recover_tasks, retry_task, route_not_found, sanitize_filename, session_tasks,
+        stream_summary, summarize, upload_chunk, upload_finish, upload_init, upload_status,
+        video_metadata, DownloadMode, InitClaims, MAX_POLL_INTERVAL, REQUEST_FILE, TASK_ID_HEADER,
+    };
+    use crate::{
+        backend::Backends,
+        base64,
+        events::{MAX_BATCH_UUIDS, STATUS_INTERVAL},
+        exception::{AppError, ClientError, ErrorSource, ServerError},
+        executor::MockExecutor,
+        history::{History, HistoryQuery},
+        idempotency::{IdempotencyKeys, IDEMPOTENCY_HEADER},
+        models::{
+            AppResp, AppRespOwned, BatchEventsReq, Config, DeleteReq, DeleteResp, FetchArchiveReq,
+            FetchAudioReq, InitiateReq, InitiateResp, InlineArchiveResp, KeepFailedDirs,
+            ListFilesReq, MetadataReq, MetadataResp, ModelOptions, PollStatusReq, PollStatusResp,
+            ReloadResp, RetryReq, ServerState, SessionTasksReq, SummaryFormat, TaskMode,
+            TaskRequest, TaskStatus, UploadChunkReq, UploadReq, UploadResp, RUN_LOG_FILENAME,
+            TRANSCRIPT_FILENAME,
+        },
+        queue::ModelQueue,
+        session::{Sessions, SESSION_HEADER},
+        stats::Stats,
+        stream::TAIL_INTERVAL,
+        task_log::{task_log_layer, TASK_LOG},
+        timeout::request_timeout,
+    };
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn init_req(uuid: &str) -> InitiateReq {
+        InitiateReq {
+            url: "https://a.b.c".into(),
+            uuid: uuid.into(),
+            language: None,
+            model: None,
+            validate_only: false,
+            mode: None,
+            backend: None,
+            priority: None,
+            output_language: None,
+        }
+    }
+
+    async fn init_uuid(state: &ServerState, uuid: &str) -> String {
+        let resp = init_summary(State(state.clone()), HeaderMap::new(), Json(init_req(uuid))).await;
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        match serde_json::from_slice(&body).unwrap() {
+            AppRespOwned::<InitiateResp>::Success(data) => data.uuid,
+            AppRespOwned::Exception(e) => panic!("{e:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fresh_uuid() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let (left, tracked, free) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        // left by a previous run, or known to the table before its dir is created
+        fs::create_dir(dir.join(left.to_string())).unwrap();
+        state
+            .update_task(&tracked.to_string(), TaskStatus::Download)
+            .await;
+        let mut generated = [left, tracked, free].into_iter();
+        let uuid = fresh_uuid(&state, || generated.next().unwrap()).await;
+        assert_eq!(uuid.unwrap(), free.to_string());
+
+        let uuid = fresh_uuid(&state, || left).await;
+        assert!(matches!(uuid, Err(ServerError::Internal(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transcript_mode() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let init = |mode: &str| {
+            let req = InitiateReq {
+                mode: Some(mode.to_string()),
+                ..init_req("")
+            };
+            init_summary(State(state.clone()), HeaderMap::new(), Json(req))
+        };
+        let body = to_bytes(init("transcript").await.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let AppRespOwned::<InitiateResp>::Success(data) = serde_json::from_slice(&body).unwrap()
+        else {
+            panic!("task is expected");
+        };
+        let data = loop {
+            match poll_format(&state, &data.uuid, Some("md")).await {
+                AppResp::Success(data) if data.done => break data,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        };
+        assert_eq!(data.result.unwrap(), "transcript");
+        assert!(matches!(data.format, Some(SummaryFormat::Txt)));
+
+        let body = to_bytes(init("subtitle").await.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let resp: AppRespOwned<InitiateResp> = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(resp, AppRespOwned::Exception(e) if e.code == "validation"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_translation() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            allowed_languages: vec!["en".into()],
+            output_languages: vec!["fr".into(), "en".into()],
+            ..Config::default()
+        });
+        let init = |state: &ServerState, language: Option<&str>, output_language: &str| {
+            let req = InitiateReq {
+                language: language.map(String::from),
+                output_language: Some(output_language.to_string()),
+                ..init_req("")
+            };
+            let state = state.clone();
+            async move {
+                let resp = init_summary(State(state), HeaderMap::new(), Json(req)).await;
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<AppRespOwned<InitiateResp>>(&body).unwrap()
+            }
+        };
+        let done = |state: &ServerState, uuid: String| {
+            let state = state.clone();
+            async move {
+                loop {
+                    match poll(&state, &uuid).await {
+                        AppResp::Success(data) if data.done => break Ok(data.result.unwrap()),
+                        AppResp::Success(_) => tokio::task::yield_now().await,
+                        AppResp::Exception(e) => break Err(e),
+                    }
+                }
+            }
+        };
+
+        let AppRespOwned::Success(data) = init(&state, None, "fr").await else {
+            panic!("task is expected");
+        };
+        let user_dir = dir.join(&data.uuid);
+        assert_eq!(done(&state, data.uuid).await.unwrap(), "[fr] a summary");
+        assert!(user_dir.join("summary.fr.txt").exists());
+        assert_eq!(
+            fs::read_to_string(user_dir.join("summary.txt")).unwrap(),
+            "a summary"
+        );
+
+        // transcribed in that language already
+        let AppRespOwned::Success(data) = init(&state, Some("en"), "en").await else {
+            panic!("task is expected");
+        };
+        let user_dir = dir.join(&data.uuid);
+        assert_eq!(done(&state, data.uuid).await.unwrap(), "a summary");
+        assert!(!user_dir.join("summary.en.txt").exists());
+
+        assert!(matches!(
+            init(&state, None, "de").await,
+            AppRespOwned::Exception(e) if e.code == "validation" && e.fields[0].field == "output_language"
+        ));
+
+        state.executor = Arc::new(MockExecutor {
+            translate_err: Some(ServerError::Translation("no quota".into()).into()),
+            summary: "a summary".into(),
+            ..MockExecutor::default()
+        });
+        let AppRespOwned::Success(data) = init(&state, None, "fr").await else {
+            panic!("task is expected");
+        };
+        let e = done(&state, data.uuid).await.unwrap_err();
+        assert_eq!(e.code(), "translation");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backend() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        let backends = vec![
+            ("local".into(), "run_model.sh".into()),
+            ("cloud".into(), "run_cloud.sh".into()),
+        ];
+        state.config = Arc::new(Config {
+            backends: Backends::new(backends, None).unwrap(),
+            ..Config::default()
+        });
+        let init = |backend: &str| {
+            let req = InitiateReq {
+                backend: Some(backend.to_string()),
+                ..init_req("")
+            };
+            let state = state.clone();
+            async move {
+                let resp = init_summary(State(state), HeaderMap::new(), Json(req)).await;
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<AppRespOwned<InitiateResp>>(&body).unwrap()
+            }
+        };
+        let AppRespOwned::Success(data) = init("cloud").await else {
+            panic!("task is expected");
+        };
+        loop {
+            match poll(&state, &data.uuid).await {
+                AppResp::Success(data) if data.done => break,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        }
+        assert!(matches!(
+            init("gpu").await,
+            AppRespOwned::Exception(e) if e.code == "validation" && e.info.contains("cloud, local")
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_validation() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            allowed_languages: vec!["en".into()],
+            ..Config::default()
+        });
+        let req = InitiateReq {
+            url: "ftp://a.b.c".into(),
+            language: Some("xx".into()),
+            mode: Some("subtitle".into()),
+            backend: Some("gpu".into()),
+            ..init_req("")
+        };
+        let resp = init_summary(State(state.clone()), HeaderMap::new(), Json(req)).await;
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let AppRespOwned::<InitiateResp>::Exception(e) = serde_json::from_slice(&body).unwrap()
+        else {
+            panic!("invalid request is accepted");
+        };
+        assert_eq!(e.code, "validation");
+        let fields: Vec<_> = e.fields.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["url", "language", "mode", "backend"]);
+        assert!(e.fields[1].message.contains("\"xx\""));
+        assert!(e.info.contains("mode \"subtitle\" is not one of"));
+        // nothing is spawned
+        assert_eq!(state.task_count().await, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_audio_after_model() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        for (keep, include, kept) in [
+            (false, vec![], false),
+            (true, vec![], true),
+            (true, vec!["*.txt".to_string()], false),
+        ] {
+            state.config = Arc::new(Config {
+                delete_audio_after_model: true,
+                keep_archived_audio: keep,
+                archive_include: include,
+                ..Config::default()
+            });
+            let uuid = init_uuid(&state, "").await;
+            while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+                tokio::task::yield_now().await;
+            }
+            assert!(dir.join(&uuid).join("summary.txt").exists());
+            assert_eq!(dir.join(&uuid).join("audio.mp3").exists(), kept);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_model_stalled() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.executor = Arc::new(MockExecutor {
+            model_stall: true,
+            ..MockExecutor::default()
+        });
+        state.config = Arc::new(Config {
+            stall_timeout: Some(Duration::from_millis(100)),
+            ..Config::default()
+        });
+        let uuid = init_uuid(&state, "").await;
+        let e = loop {
+            match poll(&state, &uuid).await {
+                AppResp::Success(data) => {
+                    assert!(!data.done);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                AppResp::Exception(e) => break e,
+            }
+        };
+        assert!(matches!(e, AppError::Server(ServerError::ModelStalled(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_init_empty_uuid() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        // even if some entry is keyed by empty string, it is not shared
+        state.update_task("", TaskStatus::Pending).await;
+
+        let (a, b) = tokio::join!(init_uuid(&state, ""), init_uuid(&state, ""));
+        assert!(!a.is_empty() && !b.is_empty());
+        assert_ne!(a, b);
+
+        // a non-empty uuid of existing task is still idempotent
+        assert_eq!(init_uuid(&state, &a).await, a);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_init_deduplicated() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let init = |uuid: String| {
+            let state = state.clone();
+            async move {
+                let resp =
+                    init_summary(State(state), HeaderMap::new(), Json(init_req(&uuid))).await;
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                let AppRespOwned::<InitiateResp>::Success(data) =
+                    serde_json::from_slice(&body).unwrap()
+                else {
+                    panic!("init is rejected");
+                };
+                data
+            }
+        };
+        let first = init(String::new()).await;
+        assert!(!first.deduplicated);
+        let second = init(first.uuid.clone()).await;
+        assert_eq!(second.uuid, first.uuid);
+        assert!(second.deduplicated);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_init_claims_released() {
+        let dir = temp_dir();
+        let mut state = ServerState::builder()
+            .work_dir(dir.clone())
+            .executor(MockExecutor::default())
+            .dedup_urls(true)
+            .build()
+            .unwrap();
+        let keys = Arc::new(IdempotencyKeys::new(Duration::from_secs(60)));
+        state.idempotency_keys = Some(Arc::clone(&keys));
+        let request = Arc::new(TaskRequest {
+            url: "https://a.b.c".into(),
+            options: ModelOptions::default(),
+            upload: None,
+            priority: 0,
+        });
+        let options = &request.options;
+        let claims = |uuid: &str| InitClaims {
+            state: state.clone(),
+            uuid: Arc::new(uuid.to_string()),
+            request: Arc::clone(&request),
+            key: Some("key".into()),
+            kept: false,
+        };
+
+        // cut off after registering the task, before spawning it
+        assert_eq!(keys.claim("key", "a"), None);
+        assert_eq!(state.claim_url(&request.url, options, "a").await, None);
+        state.update_task("a", TaskStatus::Download).await;
+        drop(claims("a"));
+        while state.has_task("a").await {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(keys.get("key"), None);
+        assert_eq!(state.claim_url(&request.url, options, "b").await, None);
+
+        // kept once spawned
+        assert_eq!(keys.claim("key", "b"), None);
+        state.update_task("b", TaskStatus::Download).await;
+        claims("b").keep();
+        tokio::task::yield_now().await;
+        assert!(state.has_task("b").await);
+        assert_eq!(keys.get("key").as_deref(), Some("b"));
+        assert_eq!(
+            state.claim_url(&request.url, options, "c").await.as_deref(),
+            Some("b")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dedup_urls() {
+        let dir = temp_dir();
+        let queue = Arc::new(ModelQueue::new(1));
+        let mut state = ServerState::builder()
+            .work_dir(dir.clone())
+            .executor(MockExecutor {
+                summary: "a summary".into(),
+                ..MockExecutor::default()
+            })
+            .dedup_urls(true)
+            .build()
+            .unwrap();
+        state.model_queue = Some(Arc::clone(&queue));
+        let running = queue.acquire("running", 0).await;
+        let first = init_uuid(&state, "").await;
+        let second = init_uuid(&state, "").await;
+        assert_eq!(first, second);
+        // options are part of the request
+        let req = InitiateReq {
+            mode: Some("transcript".into()),
+            ..init_req("")
+        };
+        let resp = init_summary(State(state.clone()), HeaderMap::new(), Json(req)).await;
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let AppRespOwned::<InitiateResp>::Success(other) = serde_json::from_slice(&body).unwrap()
+        else {
+            panic!("init is rejected");
+        };
+        assert_ne!(other.uuid, first);
+
+        // each client gets the result of the shared task
+        drop(running);
+        loop {
+            match poll(&state, &first).await {
+                AppResp::Success(data) if data.done => break,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        }
+        assert!(matches!(poll(&state, &second).await, AppResp::Success(data) if data.done));
+        assert!(matches!(
+            poll(&state, &first).await,
+            AppResp::Exception(AppError::Client(ClientError::TokenNotExist(_)))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_init_task_id_header() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        state.update_task("existing", TaskStatus::Pending).await;
+        for uuid in ["", "existing"] {
+            let resp =
+                init_summary(State(state.clone()), HeaderMap::new(), Json(init_req(uuid))).await;
+            let header = resp.headers()[TASK_ID_HEADER].to_str().unwrap().to_string();
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            let AppRespOwned::<InitiateResp>::Success(data) =
+                serde_json::from_slice(&body).unwrap()
+            else {
+                panic!("init is rejected");
+            };
+            assert_eq!(header, data.uuid);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_poll_keep_completed() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            keep_completed: Some(Duration::from_secs(60)),
+            ..Config::default()
+        });
+        fs::create_dir_all(dir.join("id")).unwrap();
+        fs::write(dir.join("id").join("summary.txt"), "a summary").unwrap();
+        state.update_task("id", TaskStatus::Done).await;
+
+        for _ in 0..2 {
+            let resp = poll(&state, "id").await;
+            assert!(matches!(resp, AppResp::Success(data) if data.done));
+        }
+        assert_eq!(state.purge_finished(Duration::from_secs(60)).await, 0);
+        assert_eq!(state.purge_finished(Duration::ZERO).await, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_poll_format() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            keep_completed: Some(Duration::from_secs(60)),
+            ..Config::default()
+        });
+        fs::create_dir_all(dir.join("id")).unwrap();
+        fs::write(dir.join("id").join("summary.txt"), "a summary").unwrap();
+        fs::write(dir.join("id").join("summary.md"), "# a summary").unwrap();
+        state.update_task("id", TaskStatus::Done).await;
+
+        for (requested, served, content) in [
+            (None, SummaryFormat::Txt, "a summary"),
+            (Some("md"), SummaryFormat::Md, "# a summary"),
+            // not generated, fall back to txt
+            (Some("json"), SummaryFormat::Txt, "a summary"),
+        ] {
+            let AppResp::Success(data) = poll_format(&state, "id", requested).await else {
+                panic!("{requested:?} fails");
+            };
+            assert_eq!(data.format, Some(served));
+            assert_eq!(data.result.as_deref(), Some(content));
+        }
+        assert!(matches!(
+            poll_format(&state, "id", Some("pdf")).await,
+            AppResp::Exception(AppError::Client(ClientError::MalformedRequest(_)))
+        ));
+
+        // the model keeps no timestamps, or garbles them, fall back to the plain transcript
+        fs::write(dir.join("id").join(TRANSCRIPT_FILENAME), "Hello world").unwrap();
+        let segments = dir.join("id").join("transcript.json");
+        for written in [None, Some(r#"[{ "start_secs": 1 }]"#)] {
+            if let Some(json) = written {
+                fs::write(&segments, json).unwrap();
+            }
+            let AppResp::Success(data) = poll_format(&state, "id", Some("json-segments")).await
+            else {
+                panic!("{written:?} fails");
+            };
+            assert_eq!(data.format, Some(SummaryFormat::Txt));
+            assert_eq!(data.result.as_deref(), Some("Hello world"));
+            assert!(data.segments.is_none());
+        }
+        let json = r#"[
+            { "start_secs": 0, "end_secs": 1.5, "text": " Hello" },
+            { "start_secs": 1.5, "end_secs": 2, "text": " world" }
+        ]"#;
+        fs::write(&segments, json).unwrap();
+        let AppResp::Success(data) = poll_format(&state, "id", Some("json-segments")).await else {
+            panic!("segments fail");
+        };
+        assert_eq!(data.format, Some(SummaryFormat::JsonSegments));
+        assert_eq!(data.result.as_deref(), Some("Hello\nworld"));
+        let segments = data.segments.unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!((segments[1].start_secs, segments[1].end_secs), (1.5, 2.0));
+
+        let req = PollStatusReq {
+            uuid: "id".into(),
+            format: Some("json-segments".into()),
+            preview_only: true,
+        };
+        let Json(AppResp::Success(data)) = poll_status(State(state.clone()), Json(req)).await
+        else {
+            panic!("preview fails");
+        };
+        assert_eq!(data.preview.as_deref(), Some("Hello\nworld"));
+        assert!(data.result.is_none() && data.segments.is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_preview() {
+        assert_eq!(preview("  short  ", 10), "short");
+        assert_eq!(preview("exactly ten", 11), "exactly ten");
+        // cut before the word that does not fit along with the ellipsis
+        assert_eq!(preview("the quick brown fox", 12), "the quick…");
+        assert_eq!(preview("the quick brown fox", 10), "the quick…");
+        assert_eq!(preview("the quick brown fox", 9), "the…");
+        assert_eq!(preview("supercalifragilistic", 6), "super…");
+        assert_eq!(preview("总结一段很长的视频", 5), "总结一段…");
+        for max_chars in 1..30 {
+            let cut = preview("the quick brown fox jumps over", max_chars);
+            assert!(cut.chars().count() <= max_chars, "{cut}");
+        }
+    }
+
+    #[test]
+    fn test_normalize_summary() {
+        assert_eq!(normalize_summary("\u{feff}a summary".into()), "a summary");
+        assert_eq!(
+            normalize_summary("first\r\nsecond\r\n".into()),
+            "first\r\nsecond"
+        );
+        assert_eq!(normalize_summary("a summary\n\n \t\n".into()), "a summary");
+        assert_eq!(normalize_summary("\u{feff}\r\n\r\n".into()), "");
+        // only a leading BOM, and leading whitespace may be indentation
+        assert_eq!(
+            normalize_summary("  a \u{feff} summary".into()),
+            "  a \u{feff} summary"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poll_normalized() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.executor = Arc::new(MockExecutor {
+            summary: "\u{feff}a summary\r\n\r\n".into(),
+            ..MockExecutor::default()
+        });
+        let uuid = init_uuid(&state, "").await;
+        let data = loop {
+            if let AppResp::Success(data) = poll(&state, &uuid).await {
+                if data.done {
+                    break data;
+                }
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(data.result.as_deref(), Some("a summary"));
+
+        state.config = Arc::new(Config {
+            normalize_summary: false,
+            ..Config::default()
+        });
+        let uuid = init_uuid(&state, "").await;
+        let data = loop {
+            if let AppResp::Success(data) = poll(&state, &uuid).await {
+                if data.done {
+                    break data;
+                }
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(data.result.as_deref(), Some("\u{feff}a summary\r\n\r\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    async fn poll(state: &ServerState, uuid: &str) -> AppResp<PollStatusResp> {
+        poll_format(state, uuid, None).await
+    }
+
+    async fn poll_format(
+        state: &ServerState,
+        uuid: &str,
+        format: Option<&str>,
+    ) -> AppResp<PollStatusResp> {
+        let req = PollStatusReq {
+            uuid: uuid.into(),
+            format: format.map(String::from),
+            preview_only: false,
+        };
+        let Json(resp) = poll_status(State(state.clone()), Json(req)).await;
+        resp
+    }
+
+    #[tokio::test]
+    async fn test_summary_glob() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            summary_glob: Some("*.md".into()),
+            keep_completed: Some(Duration::from_secs(60)),
+            ..Config::default()
+        });
+        let user_dir = dir.join("id");
+        fs::create_dir_all(&user_dir).unwrap();
+        state.update_task("id", TaskStatus::Done).await;
+        assert!(matches!(
+            poll(&state, "id").await,
+            AppResp::Exception(AppError::Server(ServerError::ResultMissing(_)))
+        ));
+
+        let now = SystemTime::now();
+        let candidates = [
+            ("output.md", 20),
+            ("result.md", 10),
+            ("older.md", 30),
+            // newest, but not a match
+            ("summary.txt", 0),
+        ];
+        for (name, age) in candidates {
+            let path = user_dir.join(name);
+            fs::write(&path, name).unwrap();
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        fs::create_dir(user_dir.join("newer.md")).unwrap();
+        match poll(&state, "id").await {
+            AppResp::Success(data) => assert_eq!(data.result.as_deref(), Some("result.md")),
+            AppResp::Exception(e) => panic!("{e:?}"),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_task_flow() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let uuid = init_uuid(&state, "").await;
+
+        let data = loop {
+            match poll(&state, &uuid).await {
+                AppResp::Success(data) if data.done => break data,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        };
+        assert_eq!(data.result.unwrap(), "a summary");
+        assert_eq!(data.warnings.unwrap(), ["WARNING: audio is silent"]);
+
+        let download = |file: Option<&str>| {
+            let req = FetchArchiveReq {
+                uuid: uuid.clone(),
+                file: file.map(String::from),
+                inline: false,
+                partial: false,
+            };
+            fetch_archive(State(state.clone()), Json(req))
+        };
+        let resp = download(Some("transcript")).await.into_response();
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"transcript");
+        let resp = loop {
+            let resp = download(None).await.into_response();
+            if resp.headers()[header::CONTENT_TYPE] == "application/zip" {
+                break resp;
+            }
+            tokio::task::yield_now().await;
+        };
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"PK");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_output_dir() {
+        let dir = temp_dir();
+        let output_dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.output_dir = Some(Arc::new(output_dir.clone()));
+        let uuid = init_uuid(&state, "").await;
+        let data = loop {
+            match poll(&state, &uuid).await {
+                AppResp::Success(data) if data.done => break data,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("task fails: {e}"),
+            }
+        };
+        assert_eq!(data.result.as_deref(), Some("a summary"));
+        let (scratch, results) = (dir.join(&uuid), output_dir.join(&uuid));
+        assert!(scratch.join("audio.mp3").exists());
+        assert!(!scratch.join(&state.config.summary_filename).exists());
+        assert!(results.join(&state.config.summary_filename).exists());
+        assert_eq!(state.result_dir(&uuid), results);
+
+        let req = || FetchArchiveReq {
+            uuid: uuid.clone(),
+            file: None,
+            inline: false,
+            partial: false,
+        };
+        while fetch_archive(State(state.clone()), Json(req()))
+            .await
+            .into_response()
+            .headers()[header::CONTENT_TYPE]
+            != "application/zip"
+        {
+            tokio::task::yield_now().await;
+        }
+        assert!(results.join(&state.config.archive_filename).exists());
+        let Json(AppResp::Success(listed)) = list_files(
+            State(state.clone()),
+            Json(ListFilesReq { uuid: uuid.clone() }),
+        )
+        .await
+        else {
+            panic!("list files fails");
+        };
+        let paths: Vec<_> = listed.files.iter().map(|file| file.path.as_str()).collect();
+        assert!(paths.contains(&"audio.mp3"), "{paths:?}");
+        assert!(
+            paths.contains(&state.config.summary_filename.as_str()),
+            "{paths:?}"
+        );
+
+        let req = DeleteReq { uuid: uuid.clone() };
+        let _ = delete_task(State(state.clone()), Json(req)).await;
+        assert!(!scratch.exists() && !results.exists());
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(output_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_compress_failed() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.executor = Arc::new(MockExecutor {
+            compress_err: Some(ServerError::CompressFile.into()),
+            ..MockExecutor::default()
+        });
+        let download = |state: &ServerState, uuid: &str| {
+            let req = FetchArchiveReq {
+                uuid: uuid.to_string(),
+                file: None,
+                inline: false,
+                partial: false,
+            };
+            let state = state.clone();
+            async move {
+                let resp = fetch_archive(State(state), Json(req)).await.into_response();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<AppRespOwned<serde_json::Value>>(&body).unwrap()
+            }
+        };
+
+        // polled and removed, not resurrected
+        let uuid = init_uuid(&state, "").await;
+        while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            download(&state, &uuid).await,
+            AppRespOwned::Success(data) if data["init"] == true
+        ));
+        while state.has_task(&uuid).await {
+            tokio::task::yield_now().await;
+        }
+
+        // kept, still done with the failure reported once
+        state.config = Arc::new(Config {
+            keep_completed: Some(Duration::from_secs(60)),
+            ..Config::default()
+        });
+        let uuid = init_uuid(&state, "").await;
+        while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+            tokio::task::yield_now().await;
+        }
+        download(&state, &uuid).await;
+        while matches!(state.get_task(&uuid).await, Some(TaskStatus::Compressing)) {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            state.get_task(&uuid).await,
+            Some(TaskStatus::Done)
+        ));
+        let AppRespOwned::Exception(e) = download(&state, &uuid).await else {
+            panic!("compression failure is not reported");
+        };
+        assert_eq!(e.code, "compress_file");
+        assert!(matches!(
+            download(&state, &uuid).await,
+            AppRespOwned::Success(data) if data["init"] == true
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_download() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        let executor = Arc::new(MockExecutor::default());
+        state.executor = executor.clone();
+        let uuid = init_uuid(&state, "").await;
+        while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+            tokio::task::yield_now().await;
+        }
+        let download = || {
+            let req = FetchArchiveReq {
+                uuid: uuid.clone(),
+                file: None,
+                inline: false,
+                partial: false,
+            };
+            tokio::spawn(fetch_archive(State(state.clone()), Json(req)))
+        };
+        // either shares the compression of the other, or finds it complete
+        let (first, second) = tokio::join!(download(), download());
+        first.unwrap();
+        second.unwrap();
+        let resp = loop {
+            let resp = download().await.unwrap().into_response();
+            if resp.headers()[header::CONTENT_TYPE] == "application/zip" {
+                break resp;
+            }
+            tokio::task::yield_now().await;
+        };
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"PK");
+        assert_eq!(executor.compressions.load(Ordering::Relaxed), 1);
+        let partial = partial_archive_name(&state.config);
+        assert!(!dir.join(&uuid).join(partial).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_headers() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        let uuid = init_uuid(&state, "").await;
+        while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+            tokio::task::yield_now().await;
+        }
+        let download = |state: &ServerState| {
+            let req = FetchArchiveReq {
+                uuid: uuid.clone(),
+                file: None,
+                inline: false,
+                partial: false,
+            };
+            fetch_archive(State(state.clone()), Json(req))
+        };
+        let resp = loop {
+            let resp = download(&state).await.into_response();
+            if resp.headers()[header::CONTENT_TYPE] == "application/zip" {
+                break resp;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(resp.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert!(!resp.headers().contains_key(header::CACHE_CONTROL));
+
+        state.config = Arc::new(Config {
+            download_headers: vec![
+                parse_header("Cache-Control: no-store").unwrap(),
+                parse_header("x-robots-tag:noindex").unwrap(),
+            ],
+            ..Config::default()
+        });
+        let resp = download(&state).await.into_response();
+        let headers = resp.headers();
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::CACHE_CONTROL], "no-store");
+        assert_eq!(headers["x-robots-tag"], "noindex");
+
+        for malformed in ["no-store", "Bad Name: x", ": x", "X-A: a\rb"] {
+            assert!(parse_header(malformed).is_err(), "{malformed}");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_partial_archive() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let config = Config::default();
+        let uuid = Uuid::new_v4().to_string();
+        let user_dir = dir.join(&uuid);
+        fs::create_dir_all(user_dir.join("parts")).unwrap();
+        fs::write(user_dir.join(&config.audio_filename), "audio").unwrap();
+        fs::write(user_dir.join(RUN_LOG_FILENAME), "$ yt-dlp ...").unwrap();
+        fs::write(user_dir.join("parts/0.wav"), "part").unwrap();
+        state.update_task(&uuid, TaskStatus::Download).await;
+        let download = |uuid: &str, partial: bool| {
+            let req = FetchArchiveReq {
+                uuid: uuid.to_string(),
+                file: None,
+                inline: false,
+                partial,
+            };
+            fetch_archive(State(state.clone()), Json(req))
+        };
+
+        // in progress without partial
+        let resp = download(&uuid, false).await.into_response();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let resp: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(resp["data"]["init"], false);
+
+        let resp = download(&uuid, true).await.into_response();
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/zip");
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let archive = dir.join("partial.zip");
+        fs::write(&archive, &body).unwrap();
+        let unzip = |flag: &str, members: &[&str]| {
+            let output = std::process::Command::new("unzip")
+                .arg(flag)
+                .arg(&archive)
+                .args(members)
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+        let listing: Vec<_> = unzip("-Z1", &[]).lines().map(String::from).collect();
+        assert_eq!(
+            listing,
+            ["manifest.json", "audio.mp3", "parts/0.wav", "run.log"]
+        );
+        let manifest: serde_json::Value =
+            serde_json::from_str(&unzip("-p", &["manifest.json"])).unwrap();
+        assert_eq!(manifest["uuid"], uuid.as_str());
+        assert_eq!(manifest["stage"], "Download");
+        assert!(manifest.get("err").is_none());
+        assert_eq!(manifest["files"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            manifest["present"],
+            serde_json::json!(["audio.mp3", "run.log"])
+        );
+        assert_eq!(
+            manifest["missing"],
+            serde_json::json!([config.summary_filename, TRANSCRIPT_FILENAME])
+        );
+
+        let e = ServerError::ModelStalled(60);
+        state.update_task(&uuid, TaskStatus::Err(e.into())).await;
+        let resp = download(&uuid, true).await.into_response();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        fs::write(&archive, &body).unwrap();
+        let manifest: serde_json::Value =
+            serde_json::from_str(&unzip("-p", &["manifest.json"])).unwrap();
+        assert_eq!(manifest["stage"], "Err");
+        assert_eq!(manifest["err"]["err"]["code"], "model_stalled");
+
+        for absent in [
+            Uuid::new_v4().to_string(),
+            "../".into(),
+            "partial.zip".into(),
+        ] {
+            let resp = download(&absent, true).await.into_response();
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            let resp: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(resp["err"]["err"]["code"], "token_not_exist", "{absent}");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_inline_archive() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        let uuid = init_uuid(&state, "").await;
+        while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+            tokio::task::yield_now().await;
+        }
+        let download = |state: &ServerState, inline: bool| {
+            let req = FetchArchiveReq {
+                uuid: uuid.clone(),
+                file: None,
+                inline,
+                partial: false,
+            };
+            fetch_archive(State(state.clone()), Json(req))
+        };
+        // compressed by the first request
+        while download(&state, false).await.into_response().headers()[header::CONTENT_TYPE]
+            != "application/zip"
+        {
+            tokio::task::yield_now().await;
+        }
+
+        let resp = download(&state, true).await.into_response();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let AppRespOwned::<InlineArchiveResp>::Success(data) =
+            serde_json::from_slice(&body).unwrap()
+        else {
+            panic!("archive is not inlined");
+        };
+        let archive = fs::read(dir.join(&uuid).join(&state.config.archive_filename)).unwrap();
+        assert_eq!(base64::decode(&data.archive_base64).unwrap(), archive);
+        assert_eq!(data.size, archive.len() as u64);
+        assert!(data.filename.ends_with(".zip"), "{}", data.filename);
+
+        state.config = Arc::new(Config {
+            max_inline_bytes: 1,
+            ..Config::default()
+        });
+        let resp = download(&state, true).await.into_response();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let resp: AppRespOwned<InlineArchiveResp> = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(resp, AppRespOwned::Exception(e) if e.code == "inline_too_large"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_task_model_failure() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.executor = Arc::new(MockExecutor {
+            model_err: Some(ServerError::AiModel("out of memory".into()).into()),
+            ..MockExecutor::default()
+        });
+        let uuid = init_uuid(&state, "").await;
+
+        let err = loop {
+            match poll(&state, &uuid).await {
+                AppResp::Success(data) => {
+                    assert!(!data.done);
+                    tokio::task::yield_now().await;
+                }
+                AppResp::Exception(e) => break e,
+            }
+        };
+        assert!(matches!(err, AppError::Server(ServerError::AiModel(_))));
+        // failure is reported once, then forgotten
+        assert!(matches!(
+            poll(&state, &uuid).await,
+            AppResp::Exception(AppError::Client(ClientError::TokenNotExist(_)))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keep_failed_dirs() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        let failed_dir_exists = |state: ServerState| async move {
+            let uuid = init_uuid(&state, "").await;
+            while let AppResp::Success(data) = poll(&state, &uuid).await {
+                assert!(!data.done);
+                tokio::task::yield_now().await;
+            }
+            state.work_dir.join(&uuid).exists()
+        };
+        let client_err: AppError = ClientError::VideoLinkNotExist("https://a.b.c".into()).into();
+        let server_err: AppError = ServerError::AiModel("out of memory".into()).into();
+        for (keep, client_kept, server_kept) in [
+            (KeepFailedDirs::Server, false, true),
+            (KeepFailedDirs::All, true, true),
+            (KeepFailedDirs::None, false, false),
+        ] {
+            state.config = Arc::new(Config {
+                keep_failed_dirs: keep,
+                ..Config::default()
+            });
+            state.executor = Arc::new(MockExecutor {
+                download_err: Some(client_err.clone()),
+                ..MockExecutor::default()
+            });
+            assert_eq!(
+                failed_dir_exists(state.clone()).await,
+                client_kept,
+                "{keep:?}"
+            );
+            state.executor = Arc::new(MockExecutor {
+                model_err: Some(server_err.clone()),
+                ..MockExecutor::default()
+            });
+            assert_eq!(
+                failed_dir_exists(state.clone()).await,
+                server_kept,
+                "{keep:?}"
+            );
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_empty_summary() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            min_summary_bytes: 4,
+            ..Config::default()
+        });
+        for (summary, bytes) in [("", 0), (" \n\n", 0), ("  abc\n", 3)] {
+            state.executor = Arc::new(MockExecutor {
+                summary: summary.into(),
+                ..MockExecutor::default()
+            });
+            let uuid = init_uuid(&state, "").await;
+            let err = loop {
+                match poll(&state, &uuid).await {
+                    AppResp::Success(data) => {
+                        assert!(!data.done);
+                        tokio::task::yield_now().await;
+                    }
+                    AppResp::Exception(e) => break e,
+                }
+            };
+            assert!(
+                matches!(err, AppError::Server(ServerError::EmptySummary { bytes: b, min: 4 }) if b == bytes)
+            );
+        }
+
+        state.executor = Arc::new(MockExecutor {
+            summary: "abcd".into(),
+            ..MockExecutor::default()
+        });
+        let uuid = init_uuid(&state, "").await;
+        while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+            tokio::task::yield_now().await;
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_admin_history() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.history = Some(Arc::new(History::new(10, false)));
+        state.executor = Arc::new(MockExecutor {
+            model_err: Some(ServerError::AiModel("out of memory".into()).into()),
+            ..MockExecutor::default()
+        });
+        let uuid = init_uuid(&state, "").await;
+
+        let query = |stage: &str| HistoryQuery {
+            stage: Some(stage.to_string()),
+            limit: 50,
+            offset: 0,
+        };
+        let resp = loop {
+            let Json(resp) = admin_history(State(state.clone()), Query(query("err"))).await;
+            match resp {
+                AppResp::Success(resp) if resp.total > 0 => break resp,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        };
+        assert_eq!(resp.entries[0].uuid, uuid);
+        assert_eq!(resp.entries[0].error.as_ref().unwrap().code, "ai_model");
+        assert!(resp.entries[0].url.is_none());
+
+        let Json(resp) = admin_history(State(state.clone()), Query(query("all"))).await;
+        assert!(matches!(
+            resp,
+            AppResp::Exception(AppError::Client(ClientError::MalformedRequest(_)))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_admin_output() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let uuid = init_uuid(&state, "").await;
+        while !matches!(state.get_task(&uuid).await, Some(TaskStatus::Done)) {
+            tokio::task::yield_now().await;
+        }
+        let Json(resp) = admin_output(State(state.clone()), UrlPath(uuid.clone())).await;
+        let AppResp::Success(data) = resp else {
+            panic!("output of {uuid} is absent");
+        };
+        // pushed by the mock model in scope of the task
+        assert_eq!(data.lines, ["transcribing"]);
+
+        let Json(resp) = admin_output(State(state), UrlPath("absent".into())).await;
+        assert!(matches!(
+            resp,
+            AppResp::Exception(AppError::Client(ClientError::TokenNotExist(_)))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_admin_export() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let uuids: Vec<_> = (0..4).map(|_| Uuid::new_v4().to_string()).collect();
+        for uuid in &uuids {
+            fs::create_dir_all(dir.join(uuid)).unwrap();
+        }
+        fs::write(dir.join(&uuids[0]).join("summary.txt"), "first").unwrap();
+        fs::write(dir.join(&uuids[1]).join("summary.txt"), "second").unwrap();
+        // still running
+        fs::write(dir.join(&uuids[3]).join("summary.txt"), "partial").unwrap();
+        state.update_task(&uuids[3], TaskStatus::Pending).await;
+        fs::create_dir_all(dir.join("notes")).unwrap();
+        fs::write(dir.join("notes/summary.txt"), "not a task").unwrap();
+
+        let resp = admin_export(State(state)).await;
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/zip");
+        let bytes = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let archive = temp_dir().join("export.zip");
+        fs::write(&archive, &bytes).unwrap();
+        let listing = std::process::Command::new("unzip")
+            .arg("-Z1")
+            .arg(&archive)
+            .output()
+            .unwrap();
+        let mut expected = vec![
+            format!("{}/summary.txt", uuids[0]),
+            format!("{}/summary.txt", uuids[1]),
+        ];
+        expected.sort();
+        let listing = String::from_utf8(listing.stdout).unwrap();
+        assert_eq!(listing.lines().collect::<Vec<_>>(), expected);
+        let printed = std::process::Command::new("unzip")
+            .arg("-p")
+            .arg(&archive)
+            .arg(format!("{}/summary.txt", uuids[1]))
+            .output()
+            .unwrap();
+        assert_eq!(printed.stdout, b"second");
+
+        fs::remove_dir_all(archive.parent().unwrap()).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_admin_stats() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.stats = Some(Arc::new(Stats::new(Duration::from_secs(3600))));
+        let stats = |state: ServerState| async move {
+            match admin_stats(State(state)).await.0 {
+                AppResp::Success(resp) => resp,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        };
+
+        let empty = stats(state.clone()).await;
+        assert_eq!((empty.tasks, empty.success_rate), (0, None));
+        assert_eq!(empty.model.median_secs, None);
+
+        let uuid = init_uuid(&state, "").await;
+        let resp = loop {
+            let resp = stats(state.clone()).await;
+            match resp.tasks {
+                0 => tokio::task::yield_now().await,
+                _ => break resp,
+            }
+        };
+        assert_eq!((resp.succeeded, resp.success_rate), (1, Some(1.0)));
+        assert_eq!((resp.download.count, resp.model.count), (1, 1));
+        assert!(resp.errors.is_empty());
+
+        // compressed by /download, and failing to, the same task is not counted again
+        state.update_task(&uuid, TaskStatus::Compressing).await;
+        state.update_task(&uuid, TaskStatus::Done).await;
+        state.update_task(&uuid, TaskStatus::Compressing).await;
+        let e = ServerError::CompressFile;
+        state.update_task(&uuid, TaskStatus::Err(e.into())).await;
+        let resp = stats(state.clone()).await;
+        assert_eq!((resp.tasks, resp.succeeded), (1, 1));
+        assert!(resp.errors.is_empty());
+
+        state.stats = None;
+        assert!(matches!(
+            admin_stats(State(state)).await.0,
+            AppResp::Exception(AppError::Server(ServerError::Internal(_)))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handler_panicked() {
+        async fn buggy() -> StatusCode {
+            panic!("handler bug")
+        }
+        let app = Router::new()
+            .route("/panic", post(buggy))
+            .layer(CatchPanicLayer::custom(handler_panicked));
+        let req = Request::post("/panic").body(Body::empty()).unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let AppRespOwned::<()>::Exception(e) = serde_json::from_slice(&body).unwrap() else {
+            panic!("panic answered as success");
+        };
+        assert_eq!(e.source, ErrorSource::Server);
+        assert_eq!((e.code.as_str(), e.recoverable), ("internal", false));
+        assert!(e.info.contains("handler bug"), "{}", e.info);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_admin_reload() {
+        let dir = temp_dir();
+        let path = dir.join("runtime.json");
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            runtime_config: Some(path.clone()),
+            ..Config::default()
+        });
+        let app = Router::new()
+            .route(
+                "/slow",
+                post(|| tokio::time::sleep(Duration::from_secs(5))).layer(
+                    middleware::from_fn_with_state(state.runtime.clone(), request_timeout),
+                ),
+            )
+            .route("/admin/reload", post(admin_reload))
+            .with_state(state.clone());
+        let call = |path: &str| {
+            let req = Request::post(path).body(Body::empty()).unwrap();
+            app.clone().oneshot(req)
+        };
+        assert_eq!(call("/slow").await.unwrap().status(), StatusCode::OK);
+
+        fs::write(
+            &path,
+            r#"{ "request_timeout_secs": 1, "max_active_tasks": 3 }"#,
+        )
+        .unwrap();
+        let resp = call("/admin/reload").await.unwrap();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let resp: AppRespOwned<ReloadResp> = serde_json::from_slice(&body).unwrap();
+        let expected = ReloadResp {
+            request_timeout_secs: Some(1),
+            rate_limit: None,
+            max_active_tasks: Some(3),
+            max_concurrent_models: None,
+        };
+        assert_eq!(resp, AppRespOwned::Success(expected));
+        let resp = call("/slow").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::REQUEST_TIMEOUT);
+
+        // an invalid file keeps the values in effect
+        fs::write(&path, r#"{ "rate_limit": 10 }"#).unwrap();
+        let resp = call("/admin/reload").await.unwrap();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let resp: AppRespOwned<ReloadResp> = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(resp, AppRespOwned::Exception(e) if e.code == "invalid_config"));
+        assert_eq!(state.runtime.read().unwrap().max_active_tasks, Some(3));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_insufficient_disk_space() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            min_free_bytes: Some(u64::MAX),
+            ..Config::default()
+        });
+        let resp = init_summary(State(state.clone()), HeaderMap::new(), Json(init_req(""))).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[header::RETRY_AFTER], "300");
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let AppRespOwned::<InitiateResp>::Exception(e) = serde_json::from_slice(&body).unwrap()
+        else {
+            panic!("task accepted without disk space");
+        };
+        assert_eq!(e.code, "insufficient_disk_space");
+        assert_eq!(e.source, ErrorSource::Transient);
+        assert!(e.recoverable);
+        assert_eq!(state.task_count().await, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutting_down() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let uuid = init_uuid(&state, "").await;
+        state.shutting_down.store(true, Ordering::Relaxed);
+
+        let resp = init_summary(State(state.clone()), HeaderMap::new(), Json(init_req(""))).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[header::RETRY_AFTER], "5");
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let resp: AppRespOwned<InitiateResp> = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(resp, AppRespOwned::Exception(e) if e.code == "shutting_down"));
+        assert_eq!(state.task_count().await, 1);
+
+        // existing tasks go on
+        while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+            tokio::task::yield_now().await;
+        }
+        state.shutting_down.store(false, Ordering::Relaxed);
+        init_uuid(&state, "").await;
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pause() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let uuid = init_uuid(&state, "").await;
+        let Json(AppResp::Success(paused)) = admin_pause(State(state.clone())).await else {
+            panic!("pause fails");
+        };
+        assert!(paused.paused);
+        let Json(AppResp::Success(data)) = health(State(state.clone())).await else {
+            panic!("health fails");
+        };
+        assert_eq!(
+            (data.status.as_str(), data.accepting_tasks),
+            ("paused", false)
+        );
+
+        let resp = init_summary(State(state.clone()), HeaderMap::new(), Json(init_req(""))).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let resp: AppRespOwned<InitiateResp> = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(resp, AppRespOwned::Exception(e) if e.code == "service_paused"));
+        // existing tasks go on and can be polled
+        while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+            tokio::task::yield_now().await;
+        }
+
+        let _ = admin_resume(State(state.clone())).await;
+        let Json(AppResp::Success(data)) = health(State(state.clone())).await else {
+            panic!("health fails");
+        };
+        assert_eq!((data.status.as_str(), data.accepting_tasks), ("ok", true));
+        init_uuid(&state, "").await;
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_capabilities() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        let backends = vec![
+            ("local".to_string(), "run_model.sh".to_string()),
+            ("cloud".to_string(), "run_cloud.sh".to_string()),
+        ];
+        state.config = Arc::new(Config {
+            allowed_languages: vec!["en".into(), "zh".into()],
+            allowed_models: vec!["small".into()],
+            output_languages: vec!["fr".into()],
+            backends: Backends::new(backends, Some("cloud".into())).unwrap(),
+            max_duration_secs: Some(3600),
+            max_upload_bytes: 1024,
+            ..Config::default()
+        });
+        let Json(AppResp::Success(data)) = capabilities(State(state.clone())).await else {
+            panic!("capabilities fails");
+        };
+        assert_eq!(data.backends, ["cloud", "local"]);
+        assert_eq!(data.default_backend, "cloud");
+        assert_eq!(data.languages, state.config.allowed_languages);
+        assert_eq!(data.models, state.config.allowed_models);
+        assert_eq!(data.output_languages, state.config.output_languages);
+        assert_eq!(data.modes, ["summary", "transcript"]);
+        assert_eq!(data.max_duration_secs, Some(3600));
+        assert_eq!(data.max_upload_bytes, 1024);
+        assert_eq!(data.max_audio_bytes, None);
+        // every advertised choice is accepted
+        for format in &data.formats {
+            assert!(SummaryFormat::parse(format).is_some(), "{format}");
+        }
+        for mode in &data.modes {
+            assert!(TaskMode::parse(mode).is_some(), "{mode}");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_admin_selftest() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        let selftest = |state: &ServerState| {
+            let state = state.clone();
+            async move {
+                let Json(AppResp::Success(data)) = admin_selftest(State(state)).await else {
+                    panic!("selftest fails to run");
+                };
+                data
+            }
+        };
+        // nothing left behind
+        let task_dirs = || {
+            fs::read_dir(&dir)
+                .unwrap()
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    Uuid::parse_str(name.to_str().unwrap()).is_ok()
+                })
+                .count()
+        };
+
+        let data = selftest(&state).await;
+        assert!(data.passed, "{data:?}");
+        let names: Vec<_> = data.steps.iter().map(|step| step.name.as_str()).collect();
+        assert_eq!(names, ["download", "model", "compress"]);
+        assert!(data.steps.iter().all(|step| step.passed));
+        assert_eq!(data.error, None);
+        assert_eq!(task_dirs(), 0);
+
+        state.executor = Arc::new(MockExecutor {
+            model_err: Some(ServerError::AiModel("conda env is broken".into()).into()),
+            ..MockExecutor::default()
+        });
+        let data = selftest(&state).await;
+        assert!(!data.passed);
+        let passed: Vec<_> = data.steps.iter().map(|step| step.passed).collect();
+        assert_eq!(passed, [true, false]);
+        assert!(data.error.unwrap().contains("conda env is broken"));
+        assert_eq!(task_dirs(), 0);
+
+        state.executor = Arc::new(MockExecutor {
+            model_stall: true,
+            ..MockExecutor::default()
+        });
+        state.config = Arc::new(Config {
+            selftest_timeout: Duration::from_millis(100),
+            ..Config::default()
+        });
+        let data = selftest(&state).await;
+        assert!(!data.passed);
+        assert_eq!(data.steps.len(), 2);
+        assert!(data.steps[1].secs >= 0.05, "{data:?}");
+        assert!(data.error.unwrap().contains("not answered"));
+        assert_eq!(task_dirs(), 0);
+        assert_eq!(state.task_count().await, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_active_tasks() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        state.runtime.write().unwrap().max_active_tasks = Some(2);
+        // finished tasks occupy the table until polled
+        let a = init_uuid(&state, "").await;
+        init_uuid(&state, "").await;
+        assert_eq!(state.task_count().await, 2);
+
+        let resp = init_summary(State(state.clone()), HeaderMap::new(), Json(init_req(""))).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[header::RETRY_AFTER], "30");
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let AppRespOwned::<InitiateResp>::Exception(e) = serde_json::from_slice(&body).unwrap()
+        else {
+            panic!("task accepted beyond the cap");
+        };
+        assert_eq!(e.code, "server_busy");
+        assert_eq!(e.source, ErrorSource::Transient);
+        assert_eq!(e.retry_after_secs, Some(30));
+        assert_eq!(state.task_count().await, 2);
+
+        // one below the cap again
+        loop {
+            match poll(&state, &a).await {
+                AppResp::Success(data) if data.done => break,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        }
+        init_uuid(&state, "").await;
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_max_active_tasks_concurrently() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        state.runtime.write().unwrap().max_active_tasks = Some(2);
+        let inits = (0..8)
+            .map(|_| {
+                let req = init_req("");
+                tokio::spawn(init_summary(
+                    State(state.clone()),
+                    HeaderMap::new(),
+                    Json(req),
+                ))
+            })
+            .collect::<Vec<_>>();
+        let mut accepted = 0;
+        for init in inits {
+            if init.await.unwrap().status() == StatusCode::OK {
+                accepted += 1;
+            }
+        }
+        // racing requests cannot all pass the check, only two take a slot
+        assert_eq!(accepted, 2);
+        assert_eq!(state.task_count().await, 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_queue_capacity() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            queue_capacity: Some(2),
+            ..Config::default()
+        });
+        let queue = Arc::new(ModelQueue::new(1));
+        state.model_queue = Some(Arc::clone(&queue));
+        let running = queue.acquire("running", 0).await;
+        let a = init_uuid(&state, "a").await;
+        init_uuid(&state, "b").await;
+        while queue.load().0 < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        let resp = init_summary(State(state.clone()), HeaderMap::new(), Json(init_req("c"))).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[header::RETRY_AFTER], "30");
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let AppRespOwned::<InitiateResp>::Exception(e) = serde_json::from_slice(&body).unwrap()
+        else {
+            panic!("task accepted beyond the queue capacity");
+        };
+        assert_eq!(e.code, "queue_full");
+        assert_eq!(e.source, ErrorSource::Transient);
+        assert_eq!(state.task_count().await, 2);
+
+        let AppResp::Success(data) = estimate(State(state.clone())).await.0 else {
+            panic!("estimate fails");
+        };
+        assert_eq!((data.queue_depth, data.queue_capacity), (2, Some(2)));
+        let resp = metrics(State(state.clone())).await.into_response();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let rendered = String::from_utf8(body.to_vec()).unwrap();
+        assert!(rendered.contains("summary_queue_depth 2\n"));
+        assert!(rendered.contains("summary_queue_capacity 2\n"));
+
+        // room again once the queue drains
+        drop(running);
+        loop {
+            match poll(&state, &a).await {
+                AppResp::Success(data) if data.done => break,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        }
+        init_uuid(&state, "c").await;
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_poll_cancelled() {
+        use std::ffi::CString;
+
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        fs::create_dir_all(dir.join("id")).unwrap();
+        // reading a fifo blocks until it is written, as a large summary would take a while
+        let summary_path = dir.join("id").join("summary.txt");
+        let c_path = CString::new(summary_path.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        state.update_task("id", TaskStatus::Done).await;
+
+        // client disconnects during read
+        let resp = tokio::time::timeout(Duration::from_millis(50), poll(&state, "id")).await;
+        assert!(resp.is_err());
+        assert!(state.has_task("id").await);
+
+        // unblock the abandoned read, then poll again
+        fs::write(&summary_path, "").unwrap();
+        fs::remove_file(&summary_path).unwrap();
+        fs::write(&summary_path, "a summary").unwrap();
+        let resp = poll(&state, "id").await;
+        assert!(matches!(resp, AppResp::Success(data) if data.done));
+        assert!(!state.has_task("id").await);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_task() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        let queue = Arc::new(ModelQueue::new(1));
+        state.model_queue = Some(Arc::clone(&queue));
+        let delete = |uuid: &str| {
+            let req = DeleteReq { uuid: uuid.into() };
+            let state = state.clone();
+            async move { delete_task(State(state), Json(req)).await.0 }
+        };
+
+        // running, held in queue
+        let running = queue.acquire("running", 0).await;
+        let uuid = init_uuid(&state, "").await;
+        while queue.position(&uuid).is_none() {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            delete(&uuid).await,
+            AppResp::Success(DeleteResp {
+                task_removed: true,
+                files_removed: true
+            })
+        ));
+        while queue.load() != (0, 1) {
+            tokio::task::yield_now().await;
+        }
+        assert!(!state.has_task(&uuid).await);
+        assert!(!dir.join(&uuid).exists());
+        drop(running);
+
+        // completed, then deleted again
+        let uuid = init_uuid(&state, "").await;
+        while !matches!(state.get_task(&uuid).await, Some(TaskStatus::Done)) {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            delete(&uuid).await,
+            AppResp::Success(DeleteResp {
+                task_removed: true,
+                files_removed: true
+            })
+        ));
+        assert!(!dir.join(&uuid).exists());
+        assert!(matches!(
+            delete(&uuid).await,
+            AppResp::Exception(AppError::Client(ClientError::TokenNotExist(_)))
+        ));
+
+        // polled away, only files left
+        let uuid = init_uuid(&state, "").await;
+        loop {
+            match poll(&state, &uuid).await {
+                AppResp::Success(data) if data.done => break,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        }
+        assert!(matches!(
+            delete(&uuid).await,
+            AppResp::Success(DeleteResp {
+                task_removed: false,
+                files_removed: true
+            })
+        ));
+
+        assert!(matches!(
+            delete(&Uuid::new_v4().to_string()).await,
+            AppResp::Exception(AppError::Client(ClientError::TokenNotExist(_)))
+        ));
+        // work_dir itself
+        let escape = format!("../{}", dir.file_name().unwrap().to_str().unwrap());
+        assert!(matches!(
+            delete(&escape).await,
+            AppResp::Exception(AppError::Client(ClientError::MalformedRequest(_)))
+        ));
+        assert!(dir.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_audio() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let fetch = |uuid: &str| {
+            let req = FetchAudioReq { uuid: uuid.into() };
+            fetch_audio(State(state.clone()), Json(req))
+        };
+        let error_code = |resp: axum::response::Response| async move {
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            match serde_json::from_slice(&body).unwrap() {
+                AppRespOwned::<()>::Exception(e) => e.code,
+                AppRespOwned::Success(_) => panic!("audio is returned"),
+            }
+        };
+
+        let uuid = Uuid::new_v4().to_string();
+        state.update_task(&uuid, TaskStatus::Download).await;
+        assert_eq!(error_code(fetch(&uuid).await).await, "not_ready");
+
+        fs::create_dir_all(dir.join(&uuid)).unwrap();
+        fs::write(dir.join(&uuid).join("audio.mp3"), "audio").unwrap();
+        state.update_task(&uuid, TaskStatus::Pending).await;
+        let resp = fetch(&uuid).await;
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "audio/mpeg");
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"audio");
+
+        // cleaned up, or never existed
+        fs::remove_file(dir.join(&uuid).join("audio.mp3")).unwrap();
+        assert_eq!(error_code(fetch(&uuid).await).await, "token_not_exist");
+        assert_eq!(error_code(fetch("../x").await).await, "token_not_exist");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_truncated() {
+        let dir = temp_dir();
+        let path = dir.join("archive.zip");
+        let download = || async {
+            download_resp(&path, "archive.zip", "application/zip", &[])
+                .await
+                .unwrap()
+                .into_response()
+        };
+        fs::write(&path, vec![b'a'; 10_000]).unwrap();
+        let resp = download().await;
+        assert_eq!(resp.headers()[header::CONTENT_LENGTH], "10000");
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.len(), 10_000);
+
+        // truncated by cleanup after the headers are sent
+        let resp = download().await;
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(5_000)
+            .unwrap();
+        assert!(to_bytes(resp.into_body(), usize::MAX).await.is_err());
+
+        // grown as well
+        let resp = download().await;
+        fs::write(&path, vec![b'a'; 20_000]).unwrap();
+        assert!(to_bytes(resp.into_body(), usize::MAX).await.is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_per_task_logs() {
+        let subscriber = tracing_subscriber::registry().with(task_log_layer(UtcOffset::UTC));
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        let run = |state: &ServerState, uuid: &str| {
+            let state = state.clone();
+            let uuid = uuid.to_string();
+            async move {
+                let uuid = init_uuid(&state, &uuid).await;
+                while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+                    tokio::task::yield_now().await;
+                }
+                uuid
+            }
+        };
+        let off = run(&state, "off").await;
+        assert!(!dir.join(&off).join(TASK_LOG).exists());
+
+        state.config = Arc::new(Config {
+            per_task_logs: true,
+            ..Config::default()
+        });
+        let uuid = run(&state, "on").await;
+        let log = fs::read_to_string(dir.join(&uuid).join(TASK_LOG)).unwrap();
+        let stages: Vec<_> = log
+            .lines()
+            .filter_map(|line| line.split_once("enters stage "))
+            .map(|(_, stage)| stage)
+            .collect();
+        assert_eq!(stages, ["Download.", "Pending.", "Done."], "{log}");
+        assert!(log.contains(&format!("Download success for uuid: \"{uuid}\"")));
+        // only lines of this task
+        assert!(!log.contains(&off), "{log}");
+
+        let req = ListFilesReq { uuid: uuid.clone() };
+        let Json(AppResp::Success(resp)) = list_files(State(state.clone()), Json(req)).await else {
+            panic!("listing is rejected");
+        };
+        assert!(resp.files.iter().any(|f| f.path == TASK_LOG));
+        let files = archive_files(&dir.join(&uuid), &state.config)
+            .await
+            .unwrap();
+        assert!(files.iter().any(|path| path == TASK_LOG));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_files() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let list = |uuid: &str| {
+            let req = ListFilesReq { uuid: uuid.into() };
+            list_files(State(state.clone()), Json(req))
+        };
+        let uuid = Uuid::new_v4().to_string();
+        fs::create_dir_all(dir.join(&uuid).join("logs")).unwrap();
+        fs::write(dir.join(&uuid).join("summary.txt"), "a summary").unwrap();
+        fs::write(dir.join(&uuid).join("logs").join("model.log"), "log").unwrap();
+
+        let Json(AppResp::Success(resp)) = list(&uuid).await else {
+            panic!("listing is rejected");
+        };
+        let files: Vec<_> = resp
+            .files
+            .iter()
+            .map(|f| (f.path.as_str(), f.size))
+            .collect();
+        assert_eq!(files, [("logs/model.log", 3), ("summary.txt", 9)]);
+        assert!(resp.files[0].modified.ends_with('Z'));
+
+        // absent task, and escaping work_dir
+        fs::create_dir_all(dir.join("sibling")).unwrap();
+        let missing = Uuid::new_v4().to_string();
+        for uuid in [missing.as_str(), "../sibling", "."] {
+            let resp = list(uuid).await;
+            assert!(
+                matches!(
+                    resp,
+                    Json(AppResp::Exception(AppError::Client(
+                        ClientError::TokenNotExist(_)
+                    )))
+                ),
+                "{uuid}"
+            );
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_routes() {
+        let dir = temp_dir();
+        let app = Router::new()
+            .route("/init", post(init_summary))
+            .fallback(route_not_found)
+            .method_not_allowed_fallback(method_not_allowed)
+            .with_state(ServerState::for_test(dir.clone()));
+        for (method, path, status, code) in [
+            ("POST", "/bogus", StatusCode::NOT_FOUND, "route_not_found"),
+            (
+                "GET",
+                "/init",
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method_not_allowed",
+            ),
+        ] {
+            let req = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::empty())
+                .unwrap();
+            let resp = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), status);
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            let resp: AppRespOwned<()> = serde_json::from_slice(&body).unwrap();
+            assert!(
+                matches!(resp, AppRespOwned::Exception(e) if e.code == code && e.info.contains(path))
+            );
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_tail() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("summary.txt");
+        assert_eq!(read_tail(&path, 4).await, None);
+        fs::write(&path, "ab").unwrap();
+        assert_eq!(read_tail(&path, 4).await.as_deref(), Some("ab"));
+        // the tail starts within a 2-byte character, and ends within a 3-byte one being written
+        fs::write(
+            &path,
+            [b"xe\xcc\x81".as_slice(), "é".as_bytes(), b"\xe4\xb8"].concat(),
+        )
+        .unwrap();
+        assert_eq!(read_tail(&path, 5).await.as_deref(), Some("é"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_poll_partial() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        fs::create_dir_all(dir.join("id")).unwrap();
+        state.update_task("id", TaskStatus::Pending).await;
+        let partial = |resp| match resp {
+            AppResp::Success(PollStatusResp { partial_result, .. }) => partial_result,
+            AppResp::Exception(e) => panic!("{e:?}"),
+        };
+        fs::write(dir.join("id").join("summary.txt"), "so far").unwrap();
+        assert_eq!(partial(poll(&state, "id").await), None);
+
+        state.config = Arc::new(Config {
+            stream_partial: true,
+            ..Config::default()
+        });
+        assert_eq!(partial(poll(&state, "id").await).as_deref(), Some("so far"));
+        fs::write(dir.join("id").join("summary.txt"), "so far, and the rest").unwrap();
+        state.update_task("id", TaskStatus::Done).await;
+        let resp = poll(&state, "id").await;
+        assert!(
+            matches!(resp, AppResp::Success(PollStatusResp { result: Some(r), partial_result: None, .. })
+            if r == "so far, and the rest")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_poll_interval() {
+        let base = Duration::from_millis(1000);
+        let interval = |stage, active| poll_interval(base, &stage, active);
+        assert_eq!(interval(TaskStatus::Download, 0), Some(base * 2));
+        assert_eq!(interval(TaskStatus::Pending, 0), Some(base * 3));
+        assert_eq!(interval(TaskStatus::Pending, 25), Some(base * 5));
+        assert_eq!(interval(TaskStatus::Compressing, 0), Some(base));
+        assert_eq!(
+            interval(TaskStatus::Queued, 10_000),
+            Some(MAX_POLL_INTERVAL)
+        );
+        assert_eq!(interval(TaskStatus::Done, 0), None);
+
+        for uuid in ["a", "b", "c"] {
+            let millis = jitter(base, uuid);
+            assert!((1000..=1200).contains(&millis), "{millis}");
+            assert_eq!(millis, jitter(base, uuid));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_poll_retry_after() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.update_task("id", TaskStatus::Pending).await;
+        let retry_after = |resp| match resp {
+            AppResp::Success(PollStatusResp { retry_after_ms, .. }) => retry_after_ms,
+            AppResp::Exception(e) => panic!("{e:?}"),
+        };
+        assert_eq!(retry_after(poll(&state, "id").await), None);
+
+        state.config = Arc::new(Config {
+            poll_interval_base: Some(Duration::from_millis(100)),
+            ..Config::default()
+        });
+        let millis = retry_after(poll(&state, "id").await).unwrap();
+        assert!((300..=360).contains(&millis), "{millis}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_queue_position() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        let queue = Arc::new(ModelQueue::new(1));
+        state.model_queue = Some(Arc::clone(&queue));
+        let running = queue.acquire("running", 0).await;
+
+        let mut queued = Vec::new();
+        for _ in 0..2 {
+            let uuid = init_uuid(&state, "").await;
+            while queue.position(&uuid).is_none() {
+                tokio::task::yield_now().await;
+            }
+            queued.push(uuid);
+        }
+        for (i, uuid) in queued.iter().enumerate() {
+            let resp = poll(&state, uuid).await;
+            assert!(matches!(
+                resp,
+                AppResp::Success(PollStatusResp { stage: TaskStatus::Queued, queue_position: Some(n), priority: Some(0), .. })
+                    if n == i + 1
+            ));
+        }
+
+        // an urgent task jumps ahead of both
+        let init = |priority| {
+            let req = InitiateReq {
+                priority: Some(priority),
+                ..init_req("")
+            };
+            init_summary(State(state.clone()), HeaderMap::new(), Json(req))
+        };
+        let body = to_bytes(init(10).await.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let resp = serde_json::from_slice::<AppRespOwned<InitiateResp>>(&body).unwrap();
+        assert!(matches!(
+            resp,
+            AppRespOwned::Exception(e) if e.code == "validation" && e.fields[0].field == "priority"
+        ));
+        let body = to_bytes(init(2).await.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let AppRespOwned::Success(InitiateResp { uuid: urgent, .. }) =
+            serde_json::from_slice(&body).unwrap()
+        else {
+            panic!("urgent task is rejected");
+        };
+        while queue.position(&urgent).is_none() {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            poll(&state, &urgent).await,
+            AppResp::Success(PollStatusResp {
+                queue_position: Some(1),
+                priority: Some(2),
+                ..
+            })
+        ));
+        assert_eq!(queue.position(&queued[1]), Some(3));
+        queued.insert(0, urgent);
+
+        drop(running);
+        for uuid in &queued {
+            loop {
+                match poll(&state, uuid).await {
+                    AppResp::Success(data) if data.done => break,
+                    AppResp::Success(_) => tokio::task::yield_now().await,
+                    AppResp::Exception(e) => panic!("{e:?}"),
+                }
+            }
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.idempotency_keys = Some(Arc::new(IdempotencyKeys::new(Duration::from_secs(60))));
+        let init = |key: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(key) = key {
+                headers.insert(IDEMPOTENCY_HEADER, key.parse().unwrap());
+            }
+            let state = state.clone();
+            async move {
+                let resp = init_summary(State(state), headers, Json(init_req(""))).await;
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<AppRespOwned<InitiateResp>>(&body).unwrap()
+            }
+        };
+        let uuid = |resp| match resp {
+            AppRespOwned::Success(InitiateResp { uuid, .. }) => uuid,
+            AppRespOwned::Exception(e) => panic!("{e:?}"),
+        };
+
+        let first = uuid(init(Some("retry-1")).await);
+        assert_eq!(uuid(init(Some("retry-1")).await), first);
+        assert_eq!(state.task_count().await, 1);
+        // replayed without the uuid even once the task is done, spawning nothing
+        loop {
+            match poll(&state, &first).await {
+                AppResp::Success(data) if data.done => break,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        }
+        assert_eq!(uuid(init(Some("retry-1")).await), first);
+        assert_eq!(state.task_count().await, 0);
+        assert_ne!(uuid(init(Some("retry-2")).await), first);
+        assert_ne!(uuid(init(None).await), first);
+        assert!(matches!(
+            init(Some("not a key")).await,
+            AppRespOwned::Exception(e) if e.code == "malformed_request"
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_tasks() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.sessions = Some(Arc::new(Sessions::new(Duration::from_secs(60))));
+        let init = |session: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(SESSION_HEADER, session.parse().unwrap());
+            let state = state.clone();
+            async move {
+                let resp = init_summary(State(state), headers, Json(init_req(""))).await;
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<AppRespOwned<InitiateResp>>(&body).unwrap()
+            }
+        };
+        let mut tab1 = Vec::new();
+        for session in ["tab-1", "tab-2", "tab-1"] {
+            let AppRespOwned::Success(data) = init(session).await else {
+                panic!("task is expected");
+            };
+            if session == "tab-1" {
+                tab1.push(data.uuid);
+            }
+        }
+        assert!(matches!(
+            init("not a session").await,
+            AppRespOwned::Exception(e) if e.code == "malformed_request"
+        ));
+
+        let tasks = |session: &str| {
+            let req = SessionTasksReq {
+                session_id: session.into(),
+            };
+            let state = state.clone();
+            async move {
+                let AppResp::Success(data) = session_tasks(State(state), Json(req)).await.0 else {
+                    panic!("lookup fails");
+                };
+                data.uuids
+            }
+        };
+        assert_eq!(tasks("tab-1").await, tab1);
+        assert_eq!(tasks("tab-2").await.len(), 1);
+        assert!(tasks("tab-3").await.is_empty());
+        // tasks gone from the task table are left out
+        state.remove_task(&tab1[0]).await;
+        assert_eq!(tasks("tab-1").await, tab1[1..]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_estimate() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            default_model_duration: Duration::from_secs(60),
+            ..Config::default()
+        });
+        let load = |state: &ServerState| {
+            let state = state.clone();
+            async move {
+                let AppResp::Success(data) = estimate(State(state)).await.0 else {
+                    panic!("estimate fails");
+                };
+                (data.queued, data.running, data.est_wait_secs)
+            }
+        };
+        assert_eq!(load(&state).await, (0, 0, 0));
+
+        let queue = Arc::new(ModelQueue::new(1));
+        state.model_queue = Some(Arc::clone(&queue));
+        let running = queue.acquire("running", 0).await;
+        assert_eq!(load(&state).await, (0, 1, 60));
+        let uuid = init_uuid(&state, "").await;
+        while queue.position(&uuid).is_none() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(load(&state).await, (1, 1, 120));
+
+        drop(running);
+        while !matches!(poll(&state, &uuid).await, AppResp::Success(data) if data.done) {
+            tokio::task::yield_now().await;
+        }
+        // the mock model takes no time
+        assert_eq!(load(&state).await, (0, 0, 0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_min_poll_interval() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            min_poll_interval: Some(Duration::from_secs(60)),
+            ..Config::default()
+        });
+        // keep tasks queued, so that polls do not remove them
+        let queue = Arc::new(ModelQueue::new(1));
+        state.model_queue = Some(Arc::clone(&queue));
+        let _running = queue.acquire("running", 0).await;
+        let uuid = init_uuid(&state, "a").await;
+        let other = init_uuid(&state, "b").await;
+
+        assert!(matches!(poll(&state, &uuid).await, AppResp::Success(_)));
+        match poll(&state, &uuid).await {
+            AppResp::Exception(AppError::Client(ClientError::PollTooFast { retry_after_ms })) => {
+                assert!(
+                    retry_after_ms > 0 && retry_after_ms <= 60_000,
+                    "{retry_after_ms}"
+                )
+            }
+            _ => panic!("second poll is let through"),
+        }
+        // polls of other tasks are unaffected
+        assert!(matches!(poll(&state, &other).await, AppResp::Success(_)));
+
+        state
+            .modify_task(&uuid, |task| {
+                task.polled_at = task.polled_at.map(|t| t - Duration::from_secs(60))
+            })
+            .await;
+        assert!(matches!(poll(&state, &uuid).await, AppResp::Success(_)));
+        assert!(matches!(
+            poll(&state, &uuid).await,
+            AppResp::Exception(AppError::Client(ClientError::PollTooFast { .. }))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stream_summary() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let stream = |uuid: &str| {
+            let resp = stream_summary(State(state.clone()), UrlPath(uuid.into()));
+            async move {
+                let body = resp.await.into_body();
+                let bytes = to_bytes(body, usize::MAX).await.unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+        let summary = |uuid: &str| dir.join(uuid).join(&state.config.summary_filename);
+
+        state.update_task("done", TaskStatus::Pending).await;
+        fs::create_dir_all(dir.join("done")).unwrap();
+        // split within a character
+        fs::write(summary("done"), &"first\r\n\u{4f60}".as_bytes()[..8]).unwrap();
+        let events = tokio::spawn(stream("done"));
+        tokio::time::sleep(TAIL_INTERVAL * 2).await;
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(summary("done"))
+            .unwrap();
+        file.write_all(&"\u{4f60}second".as_bytes()[1..]).unwrap();
+        state.update_task("done", TaskStatus::Done).await;
+        let events = events.await.unwrap();
+        assert_eq!(
+            events,
+            "data: first\ndata: \n\ndata: \u{4f60}second\n\nevent: done\ndata: \n\n"
+        );
+        assert!(state.get_task_entry("done").await.is_none());
+
+        state.update_task("failed", TaskStatus::Pending).await;
+        let events = tokio::spawn(stream("failed"));
+        tokio::time::sleep(TAIL_INTERVAL).await;
+        let e = ServerError::ModelStalled(60);
+        state.update_task("failed", TaskStatus::Err(e.into())).await;
+        let events = events.await.unwrap();
+        assert!(events.starts_with("event: error\ndata: {"), "{events}");
+        assert!(events.contains("\"code\":\"model_stalled\""), "{events}");
+
+        let resp = stream("absent").await;
+        assert!(resp.contains("token_not_exist"), "{resp}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_events() {
+        let dir = temp_dir();
+        let state = ServerState::for_test(dir.clone());
+        let events = |uuids: Vec<String>| {
+            let resp = batch_events(State(state.clone()), Json(BatchEventsReq { uuids }));
+            async move {
+                let body = resp.await.into_body();
+                let bytes = to_bytes(body, usize::MAX).await.unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+        let [done, failed, absent] = [(); 3].map(|()| Uuid::new_v4().to_string());
+        state.update_task(&done, TaskStatus::Queued).await;
+        state.update_task(&failed, TaskStatus::Pending).await;
+        let watched = vec![done.clone(), failed.clone(), absent.clone(), done.clone()];
+        let stream = tokio::spawn(events(watched));
+        tokio::time::sleep(STATUS_INTERVAL * 2).await;
+        state.update_task(&done, TaskStatus::Pending).await;
+        tokio::time::sleep(STATUS_INTERVAL * 2).await;
+        state.update_task(&done, TaskStatus::Compressing).await;
+        let e = ServerError::ModelStalled(60);
+        state.update_task(&failed, TaskStatus::Err(e.into())).await;
+        let stream = stream.await.unwrap();
+
+        let data: Vec<serde_json::Value> = stream
+            .split("\n\n")
+            .filter(|event| !event.is_empty())
+            .map(|event| {
+                let data = event.strip_prefix("event: status\ndata: ").unwrap();
+                serde_json::from_str(data).unwrap()
+            })
+            .collect();
+        let of = |uuid: &str| -> Vec<&serde_json::Value> {
+            data.iter().filter(|event| event["uuid"] == uuid).collect()
+        };
+        let stages = |uuid: &str| -> Vec<&str> {
+            of(uuid)
+                .iter()
+                .filter_map(|event| event["stage"].as_str())
+                .collect()
+        };
+        // duplicates are watched once
+        assert_eq!(stages(&done), ["Queued", "Pending", "Done"]);
+        assert_eq!(stages(&failed), ["Pending", "Err"]);
+        assert_eq!(of(&failed)[1]["err"]["err"]["code"], "model_stalled");
+        assert_eq!(of(&absent).len(), 1);
+        assert_eq!(of(&absent)[0]["err"]["err"]["code"], "token_not_exist");
+        // watching keeps finished tasks
+        assert!(state.get_task_entry(&done).await.is_some());
+
+        for uuids in [
+            Vec::new(),
+            vec!["../x".to_string()],
+            vec![done; MAX_BATCH_UUIDS + 1],
+        ] {
+            let resp = events(uuids).await;
+            let AppRespOwned::<()>::Exception(e) = serde_json::from_str(&resp).unwrap() else {
+                panic!("invalid uuids accepted");
+            };
+            assert_eq!(e.code, "validation");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_init_upload() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            max_upload_bytes: 8,
+            ..Config::default()
+        });
+        let upload = |content_type: &str, filename: Option<&str>, body: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+            let req = UploadReq {
+                filename: filename.map(String::from),
+                language: None,
+                model: None,
+                mode: None,
+                backend: None,
+            };
+            let state = state.clone();
+            async move {
+                let resp = init_upload(State(state), Query(req), headers, Body::from(body)).await;
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<AppRespOwned<InitiateResp>>(&body).unwrap()
+            }
+        };
+        let AppRespOwned::Success(InitiateResp { uuid, .. }) =
+            upload("audio/mpeg; charset=binary", None, "audio").await
+        else {
+            panic!("upload is rejected");
+        };
+        assert_eq!(
+            fs::read(dir.join(&uuid).join("audio.mp3")).unwrap(),
+            b"audio"
+        );
+        let data = loop {
+            match poll(&state, &uuid).await {
+                AppResp::Success(data) if data.done => break data,
+                AppResp::Success(data) => {
+                    // never downloads
+                    assert!(matches!(data.stage, TaskStatus::Pending));
+                    tokio::task::yield_now().await;
+                }
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        };
+        assert_eq!(data.result.unwrap(), "a summary");
+
+        let AppRespOwned::Success(InitiateResp { uuid, .. }) =
+            upload("application/octet-stream", Some("talk.WAV"), "audio").await
+        else {
+            panic!("upload is rejected");
+        };
+        assert!(dir.join(&uuid).join("audio.wav").exists());
+
+        for (content_type, body) in [
+            ("text/plain", "audio"),
+            ("audio/mpeg", "too large audio"),
+            ("audio/mpeg", ""),
+        ] {
+            let resp = upload(content_type, None, body).await;
+            assert!(matches!(resp, AppRespOwned::Exception(e) if e.code == "malformed_request"));
+        }
+        // rejected uploads leave nothing behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            max_upload_bytes: 8,
+            ..Config::default()
+        });
+        let json = |resp: Response| async move {
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<AppRespOwned<serde_json::Value>>(&body).unwrap()
+        };
+        let req = UploadReq {
+            filename: Some("talk.ogg".into()),
+            language: None,
+            model: None,
+            mode: None,
+            backend: None,
+        };
+        let resp = upload_init(State(state.clone()), Query(req), HeaderMap::new()).await;
+        let AppRespOwned::Success(data) = json(resp).await else {
+            panic!("upload is rejected");
+        };
+        let id = data["upload_id"].as_str().unwrap().to_string();
+        assert_eq!(data["offset"], 0);
+
+        let chunk = |offset: u64, body: &'static str| {
+            let (state, id) = (state.clone(), UrlPath(id.clone()));
+            let query = Query(UploadChunkReq { offset });
+            async move {
+                upload_chunk(State(state), id, query, Body::from(body))
+                    .await
+                    .0
+            }
+        };
+        assert!(matches!(
+            chunk(0, "aud").await,
+            AppResp::Success(UploadResp { offset: 3, .. })
+        ));
+        // a chunk resent after its response is lost
+        assert!(matches!(
+            chunk(0, "aud").await,
+            AppResp::Exception(AppError::Client(ClientError::UploadOffsetMismatch {
+                expected: 3,
+                actual: 0,
+            }))
+        ));
+        assert!(matches!(
+            chunk(3, "io").await,
+            AppResp::Success(UploadResp { offset: 5, .. })
+        ));
+        assert!(matches!(
+            chunk(5, "too large").await,
+            AppResp::Exception(AppError::Client(ClientError::MalformedRequest(_)))
+        ));
+        let Json(status) = upload_status(State(state.clone()), UrlPath(id.clone())).await;
+        assert!(matches!(
+            status,
+            AppResp::Success(UploadResp { offset: 5, .. })
+        ));
+
+        let resp = upload_finish(State(state.clone()), UrlPath(id.clone())).await;
+        let AppRespOwned::Success(data) = json(resp).await else {
+            panic!("upload is not finished");
+        };
+        let uuid = data["uuid"].as_str().unwrap();
+        assert_eq!(
+            fs::read(dir.join(uuid).join("audio.ogg")).unwrap(),
+            b"audio"
+        );
+        assert!(!state.uploads.path(&id).exists());
+        let data = loop {
+            match poll(&state, uuid).await {
+                AppResp::Success(data) if data.done => break data,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        };
+        assert_eq!(data.result.unwrap(), "a summary");
+
+        // gone once finished
+        assert!(matches!(
+            chunk(5, "x").await,
+            AppResp::Exception(AppError::Client(ClientError::TokenNotExist(_)))
+        ));
+        let resp = upload_finish(State(state.clone()), UrlPath(id)).await;
+        assert!(
+            matches!(json(resp).await, AppRespOwned::Exception(e) if e.code == "token_not_exist")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_video_metadata() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        // previewed regardless of the limit
+        state.config = Arc::new(Config {
+            max_duration_secs: Some(600),
+            ..Config::default()
+        });
+        state.executor = Arc::new(MockExecutor {
+            duration: Some(3600.4),
+            ..MockExecutor::default()
+        });
+        let metadata = |url: &str| {
+            let req = MetadataReq { url: url.into() };
+            video_metadata(State(state.clone()), Json(req))
+        };
+        let Json(resp) = metadata("https://a.b.c").await;
+        let AppResp::Success(resp) = resp else {
+            panic!("metadata query fails");
+        };
+        assert_eq!(
+            resp,
+            MetadataResp {
+                title: "title".into(),
+                uploader: Some("uploader".into()),
+                duration_secs: Some(3600),
+                thumbnail_url: None,
+            }
+        );
+
+        let Json(resp) = metadata("--exec=rm").await;
+        assert!(matches!(
+            resp,
+            AppResp::Exception(AppError::Client(ClientError::VideoLinkNotExist(_)))
+        ));
+        // nothing is spawned
+        assert_eq!(state.task_count().await, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_init_video_too_long() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            max_duration_secs: Some(600),
+            ..Config::default()
+        });
+        state.executor = Arc::new(MockExecutor {
+            duration: Some(3600.0),
+            ..MockExecutor::default()
+        });
+        let resp = init_summary(State(state.clone()), HeaderMap::new(), Json(init_req(""))).await;
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let resp: AppRespOwned<InitiateResp> = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(resp, AppRespOwned::Exception(e) if e.info.contains("3600")));
+
+        state.executor = Arc::new(MockExecutor {
+            duration: Some(59.6),
+            ..MockExecutor::default()
+        });
+        let uuid = init_uuid(&state, "").await;
+        let task = state.get_task_entry(&uuid).await.unwrap();
+        assert_eq!(task.duration_secs, Some(60));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_video_too_large() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        // smaller than the downloaded `audio`
+        state.config = Arc::new(Config {
+            max_audio_bytes: Some(4),
+            ..Config::default()
+        });
+        let failure = |state: ServerState, uuid: String| async move {
+            loop {
+                match poll(&state, &uuid).await {
+                    AppResp::Success(data) => {
+                        assert!(!data.done);
+                        tokio::task::yield_now().await;
+                    }
+                    AppResp::Exception(e) => break e,
+                }
+            }
+        };
+        let uuid = init_uuid(&state, "").await;
+        let err = failure(state.clone(), uuid.clone()).await;
+        assert!(matches!(
+            err,
+            AppError::Client(ClientError::VideoTooLarge { bytes: 5, max: 4 })
+        ));
+        let audio_path = dir.join(&uuid).join(&state.config.audio_filename);
+        // with the whole dir, as a client error
+        assert!(!audio_path.exists() && !dir.join(&uuid).exists());
+
+        // refused by the downloader, leaving a partial file
+        let uuid = Uuid::new_v4().to_string();
+        let audio_path = dir.join(&uuid).join(&state.config.audio_filename);
+        fs::create_dir_all(dir.join(&uuid)).unwrap();
+        fs::write(&audio_path, "aud").unwrap();
+        state.executor = Arc::new(MockExecutor {
+            download_err: Some(ClientError::VideoTooLarge { bytes: 0, max: 4 }.into()),
+            ..MockExecutor::default()
+        });
+        let request = TaskRequest {
+            url: "https://a.b.c".into(),
+            options: ModelOptions::default(),
+            upload: None,
+            priority: 0,
+        };
+        let queued = state.queue_slots.take();
+        let status = summarize(&state, &uuid, &request, None, DownloadMode::Fresh, queued).await;
+        assert!(matches!(
+            status,
+            TaskStatus::Err(AppError::Client(ClientError::VideoTooLarge {
+                bytes: 0,
+                ..
+            }))
+        ));
+        assert!(!audio_path.exists());
+
+        state.config = Arc::new(Config {
+            max_audio_bytes: Some(5),
+            ..Config::default()
+        });
+        state.executor = Arc::new(MockExecutor::default());
+        let uuid = init_uuid(&state, "").await;
+        loop {
+            match poll(&state, &uuid).await {
+                AppResp::Success(data) if data.done => break,
+                AppResp::Success(_) => tokio::task::yield_now().await,
+                AppResp::Exception(e) => panic!("{e:?}"),
+            }
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.executor = Arc::new(MockExecutor {
+            model_err: Some(ServerError::AiModel("out of memory".into()).into()),
+            ..MockExecutor::default()
+        });
+        let uuid = init_uuid(&state, "").await;
+        while !matches!(state.get_task(&uuid).await, Some(TaskStatus::Err(_))) {
+            tokio::task::yield_now().await;
+        }
+
+        let retry = |state: &ServerState, uuid: &str| {
+            let req = RetryReq { uuid: uuid.into() };
+            let resp = retry_task(State(state.clone()), Json(req));
+            async move {
+                let body = to_bytes(resp.await.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<AppRespOwned<serde_json::Value>>(&body).unwrap()
+            }
+        };
+        let resp = retry(&state, "unknown").await;
+        assert!(matches!(resp, AppRespOwned::Exception(e) if e.code == "token_not_exist"));
+
+        // admitted like a new task, the failed one stays retryable
+        state.paused.store(true, Ordering::Relaxed);
+        let resp = retry(&state, &uuid).await;
+        assert!(matches!(resp, AppRespOwned::Exception(e) if e.code == "service_paused"));
+        assert!(matches!(
+            state.get_task(&uuid).await,
+            Some(TaskStatus::Err(_))
+        ));
+        state.paused.store(false, Ordering::Relaxed);
+
+        // audio survived the failure, so only the model is re-run
+        state.executor = ServerState::for_test(dir.clone()).executor;
+        let resp = retry(&state, &uuid).await;
+        assert!(matches!(resp, AppRespOwned::Success(data) if data["stage"] == "Pending"));
+        while !matches!(state.get_task(&uuid).await, Some(TaskStatus::Done)) {
+            tokio::task::yield_now().await;
+        }
+        let resp = retry(&state, &uuid).await;
+        assert!(matches!(resp, AppRespOwned::Exception(e) if e.code == "not_retryable"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retry_dedup_urls() {
+        let dir = temp_dir();
+        let queue = Arc::new(ModelQueue::new(1));
+        let mut state = ServerState::builder()
+            .work_dir(dir.clone())
+            .executor(MockExecutor {
+                model_err: Some(ServerError::AiModel("out of memory".into()).into()),
+                ..MockExecutor::default()
+            })
+            .dedup_urls(true)
+            .build()
+            .unwrap();
+        let uuid = init_uuid(&state, "").await;
+        while !matches!(state.get_task(&uuid).await, Some(TaskStatus::Err(_))) {
+            tokio::task::yield_now().await;
+        }
+
+        // the retried task is shared again while in progress
+        state.executor = ServerState::for_test(dir.clone()).executor;
+        state.model_queue = Some(Arc::clone(&queue));
+        let running = queue.acquire("running", 0).await;
+        let req = RetryReq { uuid: uuid.clone() };
+        let resp = retry_task(State(state.clone()), Json(req)).await;
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let resp: AppRespOwned<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(resp, AppRespOwned::Success(_)));
+        assert_eq!(init_uuid(&state, "").await, uuid);
+        drop(running);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_task_panic() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.executor = Arc::new(MockExecutor {
+            panic: true,
+            ..MockExecutor::default()
+        });
+        let uuid = init_uuid(&state, "").await;
+        let err = loop {
+            match state.get_task(&uuid).await {
+                Some(TaskStatus::Err(e)) => break e,
+                _ => tokio::task::yield_now().await,
+            }
+        };
+        assert!(!err.recoverable());
+        assert!(
+            matches!(err, AppError::Server(ServerError::Internal(cause)) if cause == "mock executor panics")
+        );
+
+        // retried on demand all the same
+        state.executor = ServerState::for_test(dir.clone()).executor;
+        let req = RetryReq { uuid: uuid.clone() };
+        let resp = retry_task(State(state.clone()), Json(req)).await;
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let resp: AppRespOwned<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(resp, AppRespOwned::Success(data) if data["stage"] == "Download"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recover_tasks() {
+        let dir = temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            recover_tasks: true,
+            ..Config::default()
+        });
+        let interrupted = Uuid::new_v4().to_string();
+        fs::create_dir(dir.join(&interrupted)).unwrap();
+        let request = r#"{"url":"https://a.b.c","options":{"language":null,"model":null}}"#;
+        fs::write(dir.join(&interrupted).join(REQUEST_FILE), request).unwrap();
+        // unknown request
+        let abandoned = Uuid::new_v4().to_string();
+        fs::create_dir(dir.join(&abandoned)).unwrap();
+        // already summarized
+        let finished = Uuid::new_v4().to_string();
+        fs::create_dir(dir.join(&finished)).unwrap();
+        fs::write(dir.join(&finished).join(REQUEST_FILE), request).unwrap();
+        fs::write(dir.join(&finished).join("summary.txt"), "a summary").unwrap();
+
+        recover_tasks(&state).await;
+        assert!(!state.has_task(&abandoned).await);
+        assert!(!state.has_task(&finished).await);
+        // request file is removed once finished
+        while dir.join(&interrupted).join(REQUEST_FILE).exists() {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(
+            state.get_task(&interrupted).await,
+            Some(TaskStatus::Done)
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_summary_valid() {
+        let dir = temp_dir();
+        let path = dir.join("summary.txt");
+        fs::write(&path, "a summary").unwrap();
+        assert_eq!(read_summary(&path, "id").await.unwrap(), "a summary");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_summary_missing() {
+        let dir = temp_dir();
+        let path = dir.join("summary.txt");
+        let result = read_summary(&path, "id").await;
+        assert!(matches!(result, Err(ServerError::ResultMissing(uuid)) if uuid == "id"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_summary_invalid_utf8() {
+        let dir = temp_dir();
+        let path = dir.join("summary.txt");
+        fs::write(&path, b"a summary \xff").unwrap();
+        let result = read_summary(&path, "id").await;
+        assert!(matches!(result, Err(ServerError::InvalidUtf8(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_summary_unreadable() {
+        let dir = temp_dir();
+        // a directory exists at the path, yet cannot be read as a file
+        let path = dir.join("summary.txt");
+        fs::create_dir(&path).unwrap();
+        let result = read_summary(&path, "id").await;
+        assert!(matches!(result, Err(ServerError::ReadFile(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_archive_files() {
+        let dir = temp_dir();
+        fs::create_dir_all(dir.join("frames")).unwrap();
+        for name in [
+            "summary.txt",
+            "raw_sub.txt",
+            "subtitle.srt",
+            "audio.mp3",
+            "archive.zip",
+            "frames/1.txt",
+        ] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let files = archive_files(&dir, &Config::default()).await.unwrap();
+        assert_eq!(
+            files,
+            [
+                "audio.mp3",
+                "frames/1.txt",
+                "raw_sub.txt",
+                "subtitle.srt",
+                "summary.txt"
+            ]
+        );
+
+        let config = Config {
+            archive_include: vec!["*.txt".into(), "sub?itle.*".into()],
+            ..Config::default()
+        };
+        let files = archive_files(&dir, &config).await.unwrap();
+        assert_eq!(files, ["raw_sub.txt", "subtitle.srt", "summary.txt"]);
+
+        let config = Config {
+            archive_include: vec!["*.pdf".into()],
+            ..Config::default()
+        };
+        let err = archive_files(&dir, &config).await.unwrap_err();
+        assert!(matches!(err, ServerError::ResultMissing(_)));
+        fs::remove_dir_all(dir).unwrap();
+
+        assert!(glob_match("*/*.txt", "frames/1.txt"));
+        assert!(glob_match("**", "audio.mp3"));
+        assert!(!glob_match("a*", "a/b"));
+        assert!(!glob_match("summary.txt", "summary.txt.bak"));
+    }
+
+    #[test]
+    fn test_archive_name() {
+        let uuid = "bb58281b-e2d3-49b4-a43a-6a1bb24a595d";
+        let template = "summary-{title}-{uuid8}.zip";
+        assert_eq!(
+            archive_name(template, Some("Rust: in 100 s?"), uuid),
+            "summary-Rust_ in 100 s_-bb58281b.zip"
+        );
+        assert_eq!(
+            archive_name(template, None, uuid),
+            "summary-untitled-bb58281b.zip"
+        );
+        assert_eq!(sanitize_filename("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize_filename("\"/"), "__");
+        assert_eq!(sanitize_filename("..."), "download");
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition("archive.zip"),
+            "attachment; filename=\"archive.zip\""
+        );
+        assert_eq!(
+            content_disposition("总结 a.zip"),
+            "attachment; filename=\"__ a.zip\"; filename*=UTF-8''%E6%80%BB%E7%BB%93%20a.zip"
+        );
+    }
 }
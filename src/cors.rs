@@ -0,0 +1,163 @@
+//! Cross-origin policy of the API.
+//!
+//! - With `--cors-permissive`, any origin, method and header is allowed, which suits development.  
+//! - With `--allowed-origin` (repeatable), only listed origins may call the API, using methods
+//!   `GET`, `POST`, `OPTIONS` and request headers `content-type` and `x-api-key`, exposing
+//!   response header `x-task-id`.  
+//! - With neither, no CORS header is emitted, so browsers only allow same-origin calls.
+//!
+//! `--cors-allow-credentials` lets listed origins send cookies, which browsers refuse along with
+//! a wildcard origin, so it requires `--allowed-origin`. `--cors-max-age-secs` lets browsers cache
+//! preflight responses for that long.
+use std::time::Duration;
+
+use axum::http::{header, HeaderName, HeaderValue, Method, Uri};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::{
+    api_key::API_KEY_HEADER, controller::TASK_ID_HEADER, exception::ServerError,
+    request_id::REQUEST_ID_HEADER,
+};
+
+/// Cross-origin settings from command line.
+#[derive(Default)]
+pub struct CorsOptions {
+    pub allowed_origins: Vec<HeaderValue>,
+    pub permissive: bool,
+    pub allow_credentials: bool,
+    pub max_age: Option<Duration>,
+}
+
+/// Fails with [`ServerError::InvalidConfig`] if credentials are allowed without an explicit
+/// origin.
+pub fn cors_layer(options: CorsOptions) -> Result<CorsLayer, ServerError> {
+    if options.allow_credentials && options.allowed_origins.is_empty() {
+        return Err(ServerError::InvalidConfig(
+            "--cors-allow-credentials requires --allowed-origin".to_string(),
+        ));
+    }
+    let layer = match options.permissive {
+        true => CorsLayer::very_permissive(),
+        false => CorsLayer::new()
+            .allow_origin(AllowOrigin::list(options.allowed_origins))
+            .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+            .allow_headers([
+                header::CONTENT_TYPE,
+                HeaderName::from_static(API_KEY_HEADER),
+            ])
+            .expose_headers([TASK_ID_HEADER, REQUEST_ID_HEADER])
+            .allow_credentials(options.allow_credentials),
+    };
+    Ok(match options.max_age {
+        Some(max_age) => layer.max_age(max_age),
+        None => layer,
+    })
+}
+
+/// Parse an origin of format `scheme://host[:port]`, as accepted by `--allowed-origin`.
+pub fn parse_origin(s: &str) -> Result<HeaderValue, String> {
+    let malformed = || format!("malformed origin \"{s}\", expect format like https://example.com");
+    let uri: Uri = s.parse().map_err(|_| malformed())?;
+    let has_path = uri.path_and_query().is_some_and(|p| p.as_str() != "/");
+    if uri.scheme().is_none() || uri.authority().is_none() || has_path || s.ends_with('/') {
+        return Err(malformed());
+    }
+    HeaderValue::from_str(s).map_err(|_| malformed())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use axum::{
+        body::Body,
+        http::{header, Request},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::{cors_layer, parse_origin, CorsOptions};
+
+    #[test]
+    fn test_parse_origin() {
+        assert!(parse_origin("https://example.com").is_ok());
+        assert!(parse_origin("http://localhost:8080").is_ok());
+        for malformed in [
+            "example.com",
+            "https://example.com/",
+            "https://a.com/doc",
+            "",
+        ] {
+            assert!(parse_origin(malformed).is_err(), "{malformed}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allowed_origin() {
+        let origin = parse_origin("https://example.com").unwrap();
+        let app = Router::new().route("/", get(|| async {})).layer(
+            cors_layer(CorsOptions {
+                allowed_origins: vec![origin],
+                ..CorsOptions::default()
+            })
+            .unwrap(),
+        );
+
+        let req = |origin: &str| {
+            Request::get("/")
+                .header(header::ORIGIN, origin)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let resp = app
+            .clone()
+            .oneshot(req("https://example.com"))
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://example.com"
+        );
+        let resp = app.oneshot(req("https://evil.com")).await.unwrap();
+        assert!(!resp
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn test_credentials() {
+        // browsers refuse credentials along with any origin
+        for permissive in [false, true] {
+            let options = CorsOptions {
+                permissive,
+                allow_credentials: true,
+                ..CorsOptions::default()
+            };
+            assert!(cors_layer(options).is_err());
+        }
+
+        let options = CorsOptions {
+            allowed_origins: vec![parse_origin("https://example.com").unwrap()],
+            allow_credentials: true,
+            max_age: Some(Duration::from_secs(600)),
+            ..CorsOptions::default()
+        };
+        let app = Router::new()
+            .route("/", get(|| async {}))
+            .layer(cors_layer(options).unwrap());
+        let preflight = Request::options("/")
+            .header(header::ORIGIN, "https://example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(preflight).await.unwrap();
+        let headers = resp.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://example.com"
+        );
+    }
+}
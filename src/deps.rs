@@ -0,0 +1,147 @@
+//! Startup check of the external programs tasks rely on, see `--strict-deps`.
+//!
+//! Tasks shell out to `conda` for the scripts and `yt-dlp` in the `server` env, and to `zip` for
+//! archives, so a missing one would otherwise only surface as the first task fails. Every program
+//! is probed concurrently, each by running it the way tasks do, e.g. `yt-dlp --version` within the
+//! env rather than on the server's own `PATH`.
+use std::{process::Stdio, time::Duration};
+
+use tokio::{process::Command, task::JoinSet};
+
+/// Probes taking longer count as failed, `conda run` alone may take a few seconds.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Found if executable, either by path or on `PATH` of the env.
+const FIND_SCRIPT: &str = r#"case $1 in */*) test -x "$1" ;; *) command -v "$1" ;; esac"#;
+
+/// An external program, available if `program args..` exits successfully.
+pub struct Probe {
+    name: String,
+    program: String,
+    args: Vec<String>,
+}
+
+impl Probe {
+    fn new(name: impl Into<String>, program: &str, args: &[&str]) -> Self {
+        Self {
+            name: name.into(),
+            program: program.into(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    /// `conda`, `yt-dlp` and `zip`, along with each distinct one of `scripts`.
+    pub fn all<'a>(scripts: impl IntoIterator<Item = &'a str>) -> Vec<Self> {
+        let mut probes = vec![
+            Self::new("conda", "conda", &["--version"]),
+            Self::new(
+                "yt-dlp",
+                "conda",
+                &["run", "-n", "server", "yt-dlp", "--version"],
+            ),
+            Self::new("zip", "zip", &["-v"]),
+        ];
+        for script in scripts {
+            if probes.iter().any(|probe| probe.name == script) {
+                continue;
+            }
+            probes.push(Self::new(
+                script,
+                "conda",
+                &["run", "-n", "server", "sh", "-c", FIND_SCRIPT, "sh", script],
+            ));
+        }
+        probes
+    }
+
+    /// `Err` with the reason if unavailable.
+    async fn run(&self) -> Result<(), String> {
+        let status = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status();
+        match tokio::time::timeout(PROBE_TIMEOUT, status).await {
+            Ok(Ok(status)) if status.success() => Ok(()),
+            Ok(Ok(status)) => Err(format!("`{}` exits with {status}", self.command())),
+            Ok(Err(e)) => Err(format!("`{}` cannot be run: {e}", self.program)),
+            Err(_) => Err(format!(
+                "`{}` takes over {} seconds",
+                self.command(),
+                PROBE_TIMEOUT.as_secs()
+            )),
+        }
+    }
+
+    fn command(&self) -> String {
+        format!("{} {}", self.program, self.args.join(" "))
+    }
+}
+
+/// Run `probes` concurrently, logging the outcome of each, and return names of the unavailable.
+pub async fn check_dependencies(probes: Vec<Probe>) -> Vec<String> {
+    let total = probes.len();
+    let mut set = JoinSet::new();
+    for (i, probe) in probes.into_iter().enumerate() {
+        set.spawn(async move { (i, probe.run().await, probe.name) });
+    }
+    let mut missing = Vec::new();
+    while let Some(joined) = set.join_next().await {
+        let Ok((i, result, name)) = joined else {
+            continue;
+        };
+        match result {
+            Ok(()) => tracing::info!("Dependency {name} is available."),
+            Err(e) => {
+                tracing::error!("Dependency {name} is missing: {e}.");
+                missing.push((i, name));
+            }
+        }
+    }
+    // in the order probed, regardless of which finished first
+    missing.sort();
+    tracing::info!(
+        "Dependency check: {} of {total} available.",
+        total - missing.len()
+    );
+    missing.into_iter().map(|(_, name)| name).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_dependencies, Probe, FIND_SCRIPT};
+
+    #[tokio::test]
+    async fn test_check_dependencies() {
+        let script = std::env::temp_dir().join(format!("{}.sh", uuid::Uuid::new_v4()));
+        std::fs::write(&script, "#!/bin/sh\n").unwrap();
+        let script = script.to_str().unwrap();
+        let find =
+            |name: &str, script: &str| Probe::new(name, "sh", &["-c", FIND_SCRIPT, "sh", script]);
+        let probes = vec![
+            Probe::new("sh", "sh", &["-c", "true"]),
+            Probe::new("absent", "surely-not-a-program", &[]),
+            Probe::new("failing", "sh", &["-c", "false"]),
+            find("on path", "sh"),
+            // not executable yet
+            find("by path", script),
+        ];
+        assert_eq!(
+            check_dependencies(probes).await,
+            ["absent", "failing", "by path"]
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let executable = std::fs::Permissions::from_mode(0o755);
+            std::fs::set_permissions(script, executable).unwrap();
+            assert!(check_dependencies(vec![find("by path", script)])
+                .await
+                .is_empty());
+        }
+        std::fs::remove_file(script).unwrap();
+    }
+}
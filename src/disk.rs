@@ -0,0 +1,130 @@
+//! Free space inspection of the filesystem holding `work_dir`, and startup access probes.
+use std::{fs, io, path::Path};
+
+use uuid::Uuid;
+
+use crate::exception::ServerError;
+
+/// Create and remove a scratch subdirectory in `dir`.
+pub fn probe_writable(dir: &Path) -> io::Result<()> {
+    let probe = dir.join(format!(".probe-{}", Uuid::new_v4()));
+    fs::create_dir(&probe)?;
+    fs::remove_dir(&probe)
+}
+
+/// List entries of `dir`.
+pub fn probe_readable(dir: &Path) -> io::Result<()> {
+    fs::read_dir(dir)?.next().transpose()?;
+    Ok(())
+}
+
+/// Refuse `work_dir` and `doc_dir` if one is inside the other, after resolving symlinks, as
+/// `/doc` would then serve task files, or tasks would be written into the public docs.
+///
+/// With `allow_overlap` the overlap is only warned about, see `--allow-overlap`.
+pub fn check_disjoint(
+    work_dir: &Path,
+    doc_dir: &Path,
+    allow_overlap: bool,
+) -> Result<(), ServerError> {
+    let canonical = |dir: &Path| {
+        dir.canonicalize()
+            .map_err(|_| ServerError::ParsePath(dir.display().to_string()))
+    };
+    let (work, doc) = (canonical(work_dir)?, canonical(doc_dir)?);
+    if !work.starts_with(&doc) && !doc.starts_with(&work) {
+        return Ok(());
+    }
+    let err = ServerError::DirsOverlap {
+        work_dir: work.display().to_string(),
+        doc_dir: doc.display().to_string(),
+    };
+    if !allow_overlap {
+        return Err(err);
+    }
+    tracing::warn!("{err} Task files may be publicly served, as allowed by --allow-overlap.");
+    Ok(())
+}
+
+/// Bytes available to unprivileged users on the filesystem containing `path`.
+#[cfg(unix)]
+pub fn available_bytes(path: &Path) -> io::Result<u64> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is a valid NUL terminated string and `stat` is writable.
+    let ret = unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `statvfs` returned success, so `stat` is initialized.
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Free space is not inspected on other platforms.
+#[cfg(not(unix))]
+pub fn available_bytes(_path: &Path) -> io::Result<u64> {
+    Ok(u64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{available_bytes, check_disjoint, probe_readable, probe_writable};
+    use crate::exception::ServerError;
+
+    #[test]
+    fn test_available_bytes() {
+        assert!(available_bytes(&std::env::temp_dir()).unwrap() > 0);
+        assert!(available_bytes("/non/existing/path".as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_probe() {
+        let dir = std::env::temp_dir();
+        assert!(probe_writable(&dir).is_ok());
+        assert!(probe_readable(&dir).is_ok());
+        let missing = dir.join(uuid::Uuid::new_v4().to_string());
+        assert!(probe_writable(&missing).is_err());
+        assert!(probe_readable(&missing).is_err());
+    }
+
+    #[test]
+    fn test_check_disjoint() {
+        let root = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let (work, doc) = (root.join("work"), root.join("doc"));
+        fs::create_dir_all(work.join("docs")).unwrap();
+        fs::create_dir_all(&doc).unwrap();
+        assert!(check_disjoint(&work, &doc, false).is_ok());
+
+        // same dir spelled differently, one inside the other either way
+        for (work_dir, doc_dir) in [
+            (work.clone(), root.join("doc/../work")),
+            (work.clone(), work.join("docs")),
+            (root.join("doc/.."), doc.clone()),
+        ] {
+            assert!(
+                matches!(
+                    check_disjoint(&work_dir, &doc_dir, false),
+                    Err(ServerError::DirsOverlap { .. })
+                ),
+                "{} {}",
+                work_dir.display(),
+                doc_dir.display()
+            );
+            assert!(check_disjoint(&work_dir, &doc_dir, true).is_ok());
+        }
+        #[cfg(unix)]
+        {
+            let link = root.join("link");
+            std::os::unix::fs::symlink(&work, &link).unwrap();
+            assert!(check_disjoint(&work, &link, false).is_err());
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+}
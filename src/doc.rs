@@ -0,0 +1,281 @@
+//! Static documentation service mounted at `/doc`.
+//!
+//! Files are served by [`ServeDir`], which already handles `Last-Modified`/`If-Modified-Since`.
+//! On top of that, [`cache_headers`] attaches a weak `ETag` (derived from modification time, size
+//! and encoding), answers matching `If-None-Match` with `304 Not Modified`, and sets
+//! `Cache-Control` when `--doc-cache-secs` is given.
+//!
+//! A directory is answered with its `--doc-index` file, redirecting to the path with a trailing
+//! slash first so that relative links resolve. A path matching no file is answered with the
+//! `--doc-fallback` file if set, for single-page doc apps routing on the client, or else with
+//! a bare 404.
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    path::Path,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use tower_http::services::{ServeDir, ServeFile};
+
+/// Index file of directories unless `--doc-index` is given.
+pub const DEFAULT_DOC_INDEX: &str = "index.html";
+
+/// Doc settings from command line.
+pub struct DocOptions {
+    pub cache_secs: Option<u64>,
+    /// Look for `.gz` and `.br` variants next to the original file.
+    pub precompressed: bool,
+    /// File served for a directory, in that directory.
+    pub index: String,
+    /// File in the doc dir served for paths matching no file.
+    pub fallback: Option<String>,
+}
+
+impl Default for DocOptions {
+    fn default() -> Self {
+        Self {
+            cache_secs: None,
+            precompressed: false,
+            index: DEFAULT_DOC_INDEX.to_string(),
+            fallback: None,
+        }
+    }
+}
+
+/// Build the router serving `dir` under `/doc`.
+pub fn doc_router<S>(dir: &Path, options: DocOptions) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let mut serve_dir = ServeDir::new(dir).append_index_html_on_directories(true);
+    if options.precompressed {
+        serve_dir = serve_dir.precompressed_gzip().precompressed_br();
+    }
+    let router = match &options.fallback {
+        Some(fallback) => {
+            let mut fallback = ServeFile::new(dir.join(fallback));
+            if options.precompressed {
+                fallback = fallback.precompressed_gzip().precompressed_br();
+            }
+            Router::new().nest_service("/doc", serve_dir.fallback(fallback))
+        }
+        None => Router::new().nest_service("/doc", serve_dir),
+    };
+    let router = router.layer(middleware::from_fn_with_state(
+        options.cache_secs,
+        cache_headers,
+    ));
+    // `ServeDir` only knows `index.html`
+    match options.index == DEFAULT_DOC_INDEX {
+        true => router,
+        false => router.layer(middleware::from_fn_with_state(
+            Arc::new(options.index),
+            index_path,
+        )),
+    }
+}
+
+/// Rewrite a directory path, ending with a slash, to its index file.
+async fn index_path(State(index): State<Arc<String>>, mut req: Request, next: Next) -> Response {
+    let uri = req.uri();
+    if uri.path().ends_with('/') {
+        let path_and_query = match uri.query() {
+            Some(query) => format!("{}{index}?{query}", uri.path()),
+            None => format!("{}{index}", uri.path()),
+        };
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = path_and_query.parse().ok();
+        if let Ok(uri) = Uri::from_parts(parts) {
+            *req.uri_mut() = uri;
+        }
+    }
+    next.run(req).await
+}
+
+/// Fails if `fallback` of the doc dir `dir` is not a readable file, which would answer every
+/// missing path with an error.
+pub fn check_fallback(dir: &Path, fallback: &str) -> Result<(), String> {
+    let path = dir.join(fallback);
+    match std::fs::File::open(&path).and_then(|file| file.metadata()) {
+        Ok(metadata) if metadata.is_file() => Ok(()),
+        Ok(_) => Err(format!("\"{}\" is not a file", path.display())),
+        Err(e) => Err(format!("\"{}\" is unreadable: {e}", path.display())),
+    }
+}
+
+async fn cache_headers(
+    State(cache_secs): State<Option<u64>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).cloned();
+    let mut resp = next.run(req).await;
+    if resp.status() != StatusCode::OK {
+        return resp;
+    }
+    let Some(etag) = etag(resp.headers()) else {
+        return resp;
+    };
+
+    let headers = resp.headers_mut();
+    headers.insert(header::ETAG, etag.clone());
+    if let Some(secs) = cache_secs {
+        let value = HeaderValue::from_str(&format!("public, max-age={secs}")).unwrap();
+        headers.insert(header::CACHE_CONTROL, value);
+    }
+
+    if if_none_match.is_some_and(|tags| etag_matches(&tags, &etag)) {
+        let mut not_modified = StatusCode::NOT_MODIFIED.into_response();
+        for name in [
+            header::ETAG,
+            header::LAST_MODIFIED,
+            header::CACHE_CONTROL,
+            header::VARY,
+        ] {
+            if let Some(value) = resp.headers().get(&name) {
+                not_modified.headers_mut().insert(name, value.clone());
+            }
+        }
+        return not_modified;
+    }
+    resp
+}
+
+/// Weak validator of a file response, `None` if the response carries no `Last-Modified`.
+fn etag(headers: &HeaderMap) -> Option<HeaderValue> {
+    let last_modified = headers.get(header::LAST_MODIFIED)?;
+    let mut hasher = DefaultHasher::new();
+    last_modified.as_bytes().hash(&mut hasher);
+    for name in [header::CONTENT_LENGTH, header::CONTENT_ENCODING] {
+        headers
+            .get(name)
+            .map(HeaderValue::as_bytes)
+            .hash(&mut hasher);
+    }
+    HeaderValue::from_str(&format!("W/\"{:016x}\"", hasher.finish())).ok()
+}
+
+/// Weak comparison of `If-None-Match` against `etag`, see RFC 9110 section 13.1.2.
+fn etag_matches(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(tags) = if_none_match.to_str() else {
+        return false;
+    };
+    let strip_weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = strip_weak(etag.to_str().unwrap_or_default());
+    tags.split(',')
+        .any(|tag| tag.trim() == "*" || strip_weak(tag) == etag)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use super::{doc_router, DocOptions};
+
+    #[tokio::test]
+    async fn test_not_modified() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        let options = DocOptions {
+            cache_secs: Some(60),
+            ..DocOptions::default()
+        };
+        let router = doc_router::<()>(&dir, options);
+
+        let req = Request::get("/doc/index.html").body(Body::empty()).unwrap();
+        let resp = router.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CACHE_CONTROL], "public, max-age=60");
+        let etag = resp.headers()[header::ETAG].clone();
+
+        let req = Request::get("/doc/index.html")
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()[header::ETAG], etag);
+
+        let req = Request::get("/doc/index.html")
+            .header(header::IF_NONE_MATCH, "W/\"0000000000000000\"")
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    async fn get(router: &axum::Router, uri: &str) -> (StatusCode, String) {
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+        let resp = router.clone().oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_index_fallback() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(dir.join("guide")).unwrap();
+        fs::write(dir.join("index.html"), "index").unwrap();
+        fs::write(dir.join("guide/index.html"), "guide").unwrap();
+        fs::write(dir.join("guide/home.html"), "home").unwrap();
+        fs::write(dir.join("app.html"), "app").unwrap();
+
+        let router = doc_router::<()>(&dir, DocOptions::default());
+        assert_eq!(
+            get(&router, "/doc/").await,
+            (StatusCode::OK, "index".into())
+        );
+        assert_eq!(
+            get(&router, "/doc/guide/").await,
+            (StatusCode::OK, "guide".into())
+        );
+        // redirected to the directory with a trailing slash
+        let (status, _) = get(&router, "/doc/guide").await;
+        assert_eq!(status, StatusCode::TEMPORARY_REDIRECT);
+        let (status, _) = get(&router, "/doc/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let options = DocOptions {
+            index: "home.html".into(),
+            fallback: Some("app.html".into()),
+            ..DocOptions::default()
+        };
+        let router = doc_router::<()>(&dir, options);
+        assert_eq!(
+            get(&router, "/doc/guide/").await,
+            (StatusCode::OK, "home".into())
+        );
+        assert_eq!(
+            get(&router, "/doc/guide/index.html").await,
+            (StatusCode::OK, "guide".into())
+        );
+        // client side route
+        assert_eq!(
+            get(&router, "/doc/settings/profile").await,
+            (StatusCode::OK, "app".into())
+        );
+        assert!(super::check_fallback(&dir, "app.html").is_ok());
+        assert!(super::check_fallback(&dir, "guide").is_err());
+        assert!(super::check_fallback(&dir, "none.html").is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! Durations of time-based flags, e.g. `--older-than 7d` or `--request-timeout-secs 30s`.
+//!
+//! Flags taking a plain number before durations were supported still accept one, in the unit
+//! named by the flag, so `--min-poll-interval-ms 500` is half a second.
+use std::time::Duration;
+
+/// Parse a duration like `500ms`, `90s`, `30m`, `12h` or `7d`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let malformed = || format!("malformed duration \"{s}\", expect format like 30s, 5m, 12h or 7d");
+    let unit_at = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(malformed)?;
+    let value: u64 = s[..unit_at].parse().map_err(|_| malformed())?;
+    let unit_millis = match &s[unit_at..] {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return Err(malformed()),
+    };
+    value
+        .checked_mul(unit_millis)
+        .map(Duration::from_millis)
+        .ok_or_else(malformed)
+}
+
+/// [`parse_duration`], or a bare number of seconds.
+pub fn parse_secs(s: &str) -> Result<Duration, String> {
+    parse_or_bare(s, 1000)
+}
+
+/// [`parse_duration`], or a bare number of milliseconds.
+pub fn parse_millis(s: &str) -> Result<Duration, String> {
+    parse_or_bare(s, 1)
+}
+
+/// [`parse_duration`], or a bare number of days.
+pub fn parse_days(s: &str) -> Result<Duration, String> {
+    parse_or_bare(s, 24 * 60 * 60 * 1000)
+}
+
+fn parse_or_bare(s: &str, unit_millis: u64) -> Result<Duration, String> {
+    match s.parse::<u64>() {
+        Ok(value) => value
+            .checked_mul(unit_millis)
+            .map(Duration::from_millis)
+            .ok_or_else(|| format!("duration \"{s}\" is too long")),
+        Err(_) => parse_duration(s),
+    }
+}
+
+/// `parse` rejecting a zero duration, for flags where it makes no sense.
+pub fn positive(
+    parse: fn(&str) -> Result<Duration, String>,
+) -> impl Fn(&str) -> Result<Duration, String> + Clone + Send + Sync + 'static {
+    move |s| match parse(s)? {
+        Duration::ZERO => Err(format!("duration \"{s}\" must be positive")),
+        duration => Ok(duration),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{parse_days, parse_duration, parse_millis, parse_secs, positive};
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 86400)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        for malformed in [
+            "",
+            "7",
+            "d",
+            "1w",
+            "-1h",
+            "1.5h",
+            "5 m",
+            "99999999999999999d",
+        ] {
+            assert!(parse_duration(malformed).is_err(), "{malformed}");
+        }
+    }
+
+    #[test]
+    fn test_parse_bare() {
+        assert_eq!(parse_secs("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_secs("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_millis("500"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_millis("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_days("7"), Ok(Duration::from_secs(7 * 86400)));
+        assert_eq!(parse_days("12h"), Ok(Duration::from_secs(12 * 3600)));
+        assert!(parse_secs("-30").is_err());
+        assert!(parse_days("99999999999999999").is_err());
+
+        let parse = positive(parse_secs);
+        assert_eq!(parse("1"), Ok(Duration::from_secs(1)));
+        assert!(parse("0").is_err());
+        assert!(parse("0m").is_err());
+    }
+}
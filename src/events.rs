@@ -0,0 +1,76 @@
+//! Status of several tasks streamed as server-sent events, see `/events/batch`.
+//!
+//! Each task is looked up in the task table every [`STATUS_INTERVAL`], and an event sent when
+//! its stage changes. The streams of all tasks are merged into one, which ends once every task
+//! is done, failed or gone. Unlike `/poll`, watching a task never removes it from the table.
+use std::{convert::Infallible, mem, time::Duration};
+
+use axum::response::sse::Event;
+use futures_util::{stream, Stream};
+
+use crate::{
+    exception::ClientError,
+    models::{ServerState, TaskEvent, TaskStatus},
+};
+
+/// How often the stage of each task is checked.
+pub const STATUS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Most tasks watched by one stream.
+pub const MAX_BATCH_UUIDS: usize = 100;
+
+/// `status` events of all `uuids`, ending once none of them can change anymore.
+pub fn status_events(
+    state: ServerState,
+    uuids: Vec<String>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let streams = uuids.into_iter().map(|uuid| {
+        let watch = (state.clone(), uuid, None::<TaskStatus>);
+        Box::pin(stream::unfold(Some(watch), |watch| async move {
+            let (state, uuid, last) = watch?;
+            loop {
+                let status = match state.get_task(&uuid).await {
+                    // archive of a done task being generated
+                    Some(TaskStatus::Compressing) => TaskStatus::Done,
+                    Some(status) => status,
+                    None => {
+                        let e = ClientError::TokenNotExist(uuid.clone());
+                        let event = TaskEvent {
+                            uuid,
+                            stage: None,
+                            err: Some(e.into()),
+                        };
+                        return Some((Ok(status_event(&event)), None));
+                    }
+                };
+                let changed = last
+                    .as_ref()
+                    .is_none_or(|last| mem::discriminant(last) != mem::discriminant(&status));
+                if !changed {
+                    tokio::time::sleep(STATUS_INTERVAL).await;
+                    continue;
+                }
+                let (err, finished) = match &status {
+                    TaskStatus::Err(e) => (Some(e.clone()), true),
+                    TaskStatus::Done => (None, true),
+                    _ => (None, false),
+                };
+                let event = TaskEvent {
+                    uuid: uuid.clone(),
+                    stage: Some(status.clone()),
+                    err,
+                };
+                let next = (!finished).then_some((state, uuid, Some(status)));
+                return Some((Ok(status_event(&event)), next));
+            }
+        }))
+    });
+    stream::select_all(streams)
+}
+
+fn status_event(event: &TaskEvent) -> Event {
+    Event::default()
+        .event("status")
+        .json_data(event)
+        .unwrap_or_default()
+}
@@ -1,7 +1,14 @@
 //! Data types for client and server error.
-use serde::{ser::SerializeStruct, Serialize};
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{ser::SerializeStruct, Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::models::AppResp;
+
 pub type AppResult<T> = Result<T, AppError>;
 
 /// Sum type for error.
@@ -11,11 +18,14 @@ pub enum AppError {
     Client(#[from] ClientError),
     #[error("Server error: {0}")]
     Server(#[from] ServerError),
+    #[error("Transient error: {0}")]
+    Transient(#[from] TransientError),
 }
 
 /// Errors due to server's fault.
 ///
-/// That is, cannot recover at client.
+/// That is, cannot be fixed by client, though some go away by retrying, see
+/// [`ServerError::recoverable`].
 #[derive(Error, Debug, Clone)]
 pub enum ServerError {
     /// Probably port has been occupied, or permission issue.
@@ -24,24 +34,100 @@ pub enum ServerError {
     /// Error related to path handling.
     #[error("Parsing {0} failed.")]
     ParsePath(String),
+    /// Startup probe failed to create a subdirectory in `work_dir`.
+    #[error("Work dir {0} is not writable.")]
+    WorkDirNotWritable(String),
+    /// Startup probe failed to list `doc_dir`.
+    #[error("Doc dir {0} is not readable.")]
+    DocDirNotReadable(String),
+    /// Error during opening a file for writing.
+    #[error("Open file {0} failed.")]
+    OpenFile(String),
     /// Error during async read file
     #[error("Async read file {0} failed.")]
     ReadFile(String),
+    /// Error during removing task files, see `/delete`.
+    #[error("Remove {0} failed.")]
+    RemoveFile(String),
+    /// Error during moving results of a succeeded task to `--output-dir`.
+    #[error("Move {0} to output dir failed.")]
+    MoveFile(String),
+    /// Text written by the model script is not valid UTF-8.
+    #[error("File {0} is not valid UTF-8.")]
+    InvalidUtf8(String),
+    /// Result of a completed task no longer exists, probably consumed or cleaned up.
+    #[error("Result of task {0} is missing.")]
+    ResultMissing(String),
     /// It does not mean command returns with failure, but rather failed to launch at all.
     #[error("Issue command {0} failed.")]
     IssueCommand(String),
     /// Failed to compress files.
     #[error("Failed to compress files.")]
     CompressFile,
+    /// `--work_dir` and `--doc_dir` overlap, so that task files could be publicly served.
+    #[error("Work dir {work_dir} and doc dir {doc_dir} overlap.")]
+    DirsOverlap { work_dir: String, doc_dir: String },
+    /// Settings file is unreadable or has unacceptable values, see `--config` and
+    /// `--runtime-config`.
+    #[error("Invalid config {0}.")]
+    InvalidConfig(String),
+    /// External program tasks rely on is unavailable at startup, with `--strict-deps`.
+    #[error("Missing dependency {0}.")]
+    MissingDependency(String),
     /// Need to inspect `main()`.
     #[error("Axum serve failed.")]
     AxumServe,
     /// Either whisper or openai returns an error.
     #[error("AI model abort with failure {0}.")]
     AiModel(String),
+    /// Bug of server or state that should not happen, e.g. a task pipeline or handler panics.
+    #[error("Internal error: {0}.")]
+    Internal(String),
     /// `yt-dlp` cli returns an error given a valid url.
     #[error("video download failed, cause: {0}.")]
     VideoDownload(String),
+    /// `yt-dlp` cli fails to provide metadata of a valid url.
+    #[error("video metadata query failed, cause: {0}.")]
+    VideoMetadata(String),
+    /// Request not answered within `--request-timeout-secs`.
+    #[error("Request is not answered within {0} seconds.")]
+    Timeout(u64),
+    /// Model run makes no progress within `--stall-timeout`.
+    #[error("AI model makes no progress for {0} seconds.")]
+    ModelStalled(u64),
+    /// Translation script fails, see `--translate-script`.
+    #[error("Translation abort with failure {0}.")]
+    Translation(String),
+    /// AI model exits successfully but writes less than `--min-summary-bytes` of result, not
+    /// counting surrounding whitespace, e.g. after an internal error it does not report.
+    #[error("AI model writes {bytes} bytes of result, fewer than the minimum of {min}.")]
+    EmptySummary { bytes: u64, min: u64 },
+}
+
+/// Rejections as the server is at capacity.
+///
+/// That is, neither side is at fault, and the same request succeeds once load drops, after about
+/// [`TransientError::retry_after_secs`]. Answered with HTTP 503 and `Retry-After`.
+#[derive(Error, Debug, Clone)]
+pub enum TransientError {
+    /// Free space of `work_dir` is below `--min-free-bytes`.
+    #[error("Insufficient disk space, {available} bytes available while {needed} needed.")]
+    InsufficientDiskSpace { needed: u64, available: u64 },
+    /// Task table holds `--max-active-tasks` entries.
+    #[error("Server is busy with {0} tasks, try again later.")]
+    ServerBusy(usize),
+    /// `--max-inflight-requests` requests are being handled.
+    #[error("Server is handling {0} requests, try again later.")]
+    Overloaded(usize),
+    /// Server is draining for shutdown, e.g. during a rolling deploy, see `--shutdown-drain`.
+    #[error("Server is shutting down, try again later.")]
+    ShuttingDown,
+    /// New tasks are paused for maintenance by `/admin/pause`.
+    #[error("Server is paused for maintenance, try again later.")]
+    ServicePaused,
+    /// `--queue-capacity` tasks wait for a model or download on their way to one.
+    #[error("Model queue is full with {0} tasks, try again later.")]
+    QueueFull(usize),
 }
 
 /// Errors due to user's fault.
@@ -55,6 +141,345 @@ pub enum ClientError {
     /// Link not accessible by server.
     #[error("The link ({0}) to video does not exist.")]
     VideoLinkNotExist(String),
+    /// Video is only visible to signed-in users, e.g. age-restricted or members-only, and
+    /// `--cookies-file` is unset or not entitled to it.
+    #[error("The video ({0}) requires sign-in, e.g. age-restricted or members-only.")]
+    VideoRequiresAuth(String),
+    /// Video is private, and not shared with the account of `--cookies-file` if any.
+    #[error("The video ({0}) is private.")]
+    VideoPrivate(String),
+    /// Video is not available in the country of the server or its `--download-proxy`.
+    #[error("The video ({0}) is not available in the region of the server.")]
+    VideoGeoBlocked(String),
+    /// Video was taken down, by its uploader or the site, or along with its channel.
+    #[error("The video ({0}) has been removed.")]
+    VideoRemoved(String),
+    /// Too many requests from the same client IP, see `--rate-limit`.
+    #[error("Too many requests, try again later.")]
+    RateLimited,
+    /// Only a failed task whose files are still around can be retried.
+    #[error("Task {0} cannot be retried.")]
+    NotRetryable(String),
+    /// Video is longer than `--max-duration-secs`.
+    #[error("Video lasts {secs} seconds, exceeding the limit of {max} seconds.")]
+    VideoTooLong { secs: u64, max: u64 },
+    /// Downloaded audio exceeds `--max-audio-bytes`, `bytes` being 0 if `yt-dlp` refused it
+    /// without telling the size.
+    #[error("Audio of the video takes {bytes} bytes, exceeding the limit of {max} bytes.")]
+    VideoTooLarge { bytes: u64, max: u64 },
+    /// Request body is well-formed JSON, but some field has unacceptable value.
+    #[error("Malformed request: {0}.")]
+    MalformedRequest(String),
+    /// Admin endpoint requested without the token of `--admin-token`, or any endpoint without
+    /// the key of `--api-key`.
+    #[error("Unauthorized.")]
+    Unauthorized,
+    /// Task has not produced the requested file yet, e.g. audio while downloading.
+    #[error("Task {0} is not ready yet.")]
+    NotReady(String),
+    /// No route matches the requested path.
+    #[error("No route for path {0}.")]
+    RouteNotFound(String),
+    /// Route of the requested path does not accept the method, e.g. `GET /init`.
+    #[error("Method {method} is not allowed for path {path}.")]
+    MethodNotAllowed { method: String, path: String },
+    /// Chunk of a resumable upload does not begin where the previous one ended.
+    #[error("Upload continues at offset {expected}, not {actual}.")]
+    UploadOffsetMismatch { expected: u64, actual: u64 },
+    /// Archive requested `inline` is larger than `--max-inline-bytes`.
+    #[error("Archive of {size} bytes exceeds the inline limit of {max} bytes, download it as binary without `inline`.")]
+    InlineTooLarge { size: u64, max: u64 },
+    /// Task polled again within `--min-poll-interval-ms`.
+    #[error("Polled too fast, retry after {retry_after_ms} ms.")]
+    PollTooFast { retry_after_ms: u64 },
+    /// Some fields of the request have unacceptable values, all of which are listed.
+    #[error("Invalid fields: {}.", join_fields(.0))]
+    Validation(Vec<FieldError>),
+    /// `X-Signature` of the request is absent or not the HMAC of its body under `--api-secret`.
+    #[error("Request signature is missing or invalid.")]
+    InvalidSignature,
+}
+
+/// A field of the request and what is wrong with it, see [`ClientError::Validation`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+fn join_fields(fields: &[FieldError]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|e| format!("{} {}", e.field, e.message))
+        .collect();
+    fields.join("; ")
+}
+
+impl AppError {
+    /// Stable identifier of the error kind, for clients to branch on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Client(e) => e.code(),
+            Self::Server(e) => e.code(),
+            Self::Transient(e) => e.code(),
+        }
+    }
+
+    /// Which side is at fault, the `source` field of the serialized error.
+    pub fn source(&self) -> &'static str {
+        match self {
+            Self::Client(_) => "client",
+            Self::Server(_) => "server",
+            Self::Transient(_) => "transient",
+        }
+    }
+
+    /// Whether the same request may succeed if retried later, e.g. to offer a retry button.
+    pub fn recoverable(&self) -> bool {
+        match self {
+            Self::Client(e) => e.recoverable(),
+            Self::Server(e) => e.recoverable(),
+            Self::Transient(_) => true,
+        }
+    }
+}
+
+impl ServerError {
+    /// Stable snake case identifier of the variant.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BindPort(..) => "bind_port",
+            Self::ParsePath(..) => "parse_path",
+            Self::WorkDirNotWritable(..) => "work_dir_not_writable",
+            Self::DocDirNotReadable(..) => "doc_dir_not_readable",
+            Self::OpenFile(..) => "open_file",
+            Self::ReadFile(..) => "read_file",
+            Self::RemoveFile(..) => "remove_file",
+            Self::MoveFile(..) => "move_file",
+            Self::InvalidUtf8(..) => "invalid_utf8",
+            Self::ResultMissing(..) => "result_missing",
+            Self::IssueCommand(..) => "issue_command",
+            Self::CompressFile => "compress_file",
+            Self::InvalidConfig(..) => "invalid_config",
+            Self::DirsOverlap { .. } => "dirs_overlap",
+            Self::MissingDependency(..) => "missing_dependency",
+            Self::AxumServe => "axum_serve",
+            Self::AiModel(..) => "ai_model",
+            Self::Internal(..) => "internal",
+            Self::VideoDownload(..) => "video_download",
+            Self::VideoMetadata(..) => "video_metadata",
+            Self::Timeout(..) => "timeout",
+            Self::ModelStalled(..) => "model_stalled",
+            Self::Translation(..) => "translation",
+            Self::EmptySummary { .. } => "empty_summary",
+        }
+    }
+
+    /// Transient failures are recoverable, while misconfiguration and bugs are not.
+    pub fn recoverable(&self) -> bool {
+        match self {
+            Self::BindPort(..)
+            | Self::ParsePath(..)
+            | Self::WorkDirNotWritable(..)
+            | Self::DocDirNotReadable(..)
+            | Self::OpenFile(..)
+            | Self::InvalidUtf8(..)
+            | Self::InvalidConfig(..)
+            | Self::DirsOverlap { .. }
+            | Self::MissingDependency(..)
+            | Self::AxumServe
+            | Self::Internal(..) => false,
+            Self::ReadFile(..)
+            | Self::RemoveFile(..)
+            | Self::MoveFile(..)
+            | Self::ResultMissing(..)
+            | Self::IssueCommand(..)
+            | Self::CompressFile
+            | Self::AiModel(..)
+            | Self::VideoDownload(..)
+            | Self::VideoMetadata(..)
+            | Self::Timeout(..)
+            | Self::ModelStalled(..)
+            | Self::Translation(..)
+            | Self::EmptySummary { .. } => true,
+        }
+    }
+}
+
+impl TransientError {
+    /// Stable snake case identifier of the variant.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InsufficientDiskSpace { .. } => "insufficient_disk_space",
+            Self::ServerBusy(..) => "server_busy",
+            Self::Overloaded(..) => "overloaded",
+            Self::ShuttingDown => "shutting_down",
+            Self::ServicePaused => "service_paused",
+            Self::QueueFull(..) => "queue_full",
+        }
+    }
+
+    /// Seconds a client is asked to wait before trying again.
+    pub fn retry_after_secs(&self) -> u64 {
+        match self {
+            // freed by the cleanup of expired tasks
+            Self::InsufficientDiskSpace { .. } => 300,
+            Self::ServerBusy(..) => 30,
+            Self::Overloaded(..) => 1,
+            // by then a replacement is probably up
+            Self::ShuttingDown => 5,
+            Self::ServicePaused => 60,
+            Self::QueueFull(..) => 30,
+        }
+    }
+}
+
+/// HTTP 503 with `Retry-After`, carrying the error in the usual envelope.
+impl IntoResponse for TransientError {
+    fn into_response(self) -> Response {
+        let retry_after = self.retry_after_secs();
+        let body: AppResp<()> = AppResp::Exception(self.into());
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, retry_after)],
+            Json(body),
+        )
+            .into_response()
+    }
+}
+
+impl ClientError {
+    /// Stable snake case identifier of the variant.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::TokenNotExist(..) => "token_not_exist",
+            Self::VideoLinkNotExist(..) => "video_link_not_exist",
+            Self::VideoRequiresAuth(..) => "video_requires_auth",
+            Self::VideoPrivate(..) => "video_private",
+            Self::VideoGeoBlocked(..) => "video_geo_blocked",
+            Self::VideoRemoved(..) => "video_removed",
+            Self::RateLimited => "rate_limited",
+            Self::NotRetryable(..) => "not_retryable",
+            Self::VideoTooLong { .. } => "video_too_long",
+            Self::VideoTooLarge { .. } => "video_too_large",
+            Self::MalformedRequest(..) => "malformed_request",
+            Self::Unauthorized => "unauthorized",
+            Self::NotReady(..) => "not_ready",
+            Self::RouteNotFound(..) => "route_not_found",
+            Self::MethodNotAllowed { .. } => "method_not_allowed",
+            Self::UploadOffsetMismatch { .. } => "upload_offset_mismatch",
+            Self::InlineTooLarge { .. } => "inline_too_large",
+            Self::PollTooFast { .. } => "poll_too_fast",
+            Self::Validation(..) => "validation",
+            Self::InvalidSignature => "invalid_signature",
+        }
+    }
+
+    /// Only waiting helps, the request itself has to change otherwise.
+    pub fn recoverable(&self) -> bool {
+        match self {
+            Self::RateLimited | Self::NotReady(..) | Self::PollTooFast { .. } => true,
+            Self::TokenNotExist(..)
+            | Self::VideoLinkNotExist(..)
+            | Self::VideoRequiresAuth(..)
+            | Self::VideoPrivate(..)
+            | Self::VideoGeoBlocked(..)
+            | Self::VideoRemoved(..)
+            | Self::NotRetryable(..)
+            | Self::VideoTooLong { .. }
+            | Self::VideoTooLarge { .. }
+            | Self::MalformedRequest(..)
+            | Self::Unauthorized
+            | Self::RouteNotFound(..)
+            | Self::MethodNotAllowed { .. }
+            | Self::UploadOffsetMismatch { .. }
+            | Self::InlineTooLarge { .. }
+            | Self::Validation(..)
+            | Self::InvalidSignature => false,
+        }
+    }
+}
+
+/// Error as observed by API consumers, which only carries what is serialized.
+///
+/// Used to deserialize [`AppRespOwned`][`crate::models::AppRespOwned`].
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RemoteError {
+    pub source: ErrorSource,
+    /// Absent in responses of older servers.
+    #[serde(default)]
+    pub code: String,
+    pub info: String,
+    /// Absent in responses of older servers.
+    #[serde(default)]
+    pub recoverable: bool,
+    /// Id of the failed request, see [`crate::request_id`].
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// Every invalid field of a `validation` error, empty otherwise.
+    #[serde(default)]
+    pub fields: Vec<FieldError>,
+    /// How long to wait after a `poll_too_fast` error.
+    #[serde(default)]
+    pub retry_after_ms: Option<u64>,
+    /// How long to wait after a `transient` error.
+    #[serde(default)]
+    pub retry_after_secs: Option<u64>,
+}
+
+/// Which side is at fault, the `source` field of an error.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[cfg(any(test, feature = "test-util"))]
+pub enum ErrorSource {
+    Client,
+    Server,
+    Transient,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl From<&AppError> for RemoteError {
+    fn from(err: &AppError) -> Self {
+        match err {
+            AppError::Client(e) => Self {
+                source: ErrorSource::Client,
+                code: e.code().to_string(),
+                info: e.to_string(),
+                recoverable: e.recoverable(),
+                request_id: None,
+                fields: match e {
+                    ClientError::Validation(fields) => fields.clone(),
+                    _ => Vec::new(),
+                },
+                retry_after_ms: match e {
+                    ClientError::PollTooFast { retry_after_ms } => Some(*retry_after_ms),
+                    _ => None,
+                },
+                retry_after_secs: None,
+            },
+            AppError::Server(e) => Self {
+                source: ErrorSource::Server,
+                code: e.code().to_string(),
+                info: e.to_string(),
+                recoverable: e.recoverable(),
+                request_id: None,
+                fields: Vec::new(),
+                retry_after_ms: None,
+                retry_after_secs: None,
+            },
+            AppError::Transient(e) => Self {
+                source: ErrorSource::Transient,
+                code: e.code().to_string(),
+                info: e.to_string(),
+                recoverable: true,
+                request_id: None,
+                fields: Vec::new(),
+                retry_after_ms: None,
+                retry_after_secs: Some(e.retry_after_secs()),
+            },
+        }
+    }
 }
 
 impl Serialize for AppError {
@@ -63,7 +488,7 @@ impl Serialize for AppError {
         S: serde::Serializer,
     {
         let mut struct_s = serializer.serialize_struct("AppError", 2)?;
-        struct_s.serialize_field("success", "false")?;
+        struct_s.serialize_field("success", &false)?;
         match self {
             Self::Client(e) => {
                 struct_s.serialize_field("err", e)?;
@@ -71,6 +496,9 @@ impl Serialize for AppError {
             Self::Server(e) => {
                 struct_s.serialize_field("err", e)?;
             }
+            Self::Transient(e) => {
+                struct_s.serialize_field("err", e)?;
+            }
         };
         struct_s.end()
     }
@@ -81,9 +509,26 @@ impl Serialize for ServerError {
     where
         S: serde::Serializer,
     {
-        let mut struct_s = serializer.serialize_struct("ServerError", 2)?;
+        let mut struct_s = serializer.serialize_struct("ServerError", 4)?;
         struct_s.serialize_field("source", "server")?;
         struct_s.serialize_field("info", &self.to_string())?;
+        struct_s.serialize_field("code", self.code())?;
+        struct_s.serialize_field("recoverable", &self.recoverable())?;
+        struct_s.end()
+    }
+}
+
+impl Serialize for TransientError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut struct_s = serializer.serialize_struct("TransientError", 5)?;
+        struct_s.serialize_field("source", "transient")?;
+        struct_s.serialize_field("info", &self.to_string())?;
+        struct_s.serialize_field("code", self.code())?;
+        struct_s.serialize_field("recoverable", &true)?;
+        struct_s.serialize_field("retry_after_secs", &self.retry_after_secs())?;
         struct_s.end()
     }
 }
@@ -93,9 +538,161 @@ impl Serialize for ClientError {
     where
         S: serde::Serializer,
     {
-        let mut struct_s = serializer.serialize_struct("ClientError", 2)?;
+        let mut struct_s = serializer.serialize_struct("ClientError", 4)?;
         struct_s.serialize_field("source", "client")?;
         struct_s.serialize_field("info", &self.to_string())?;
+        struct_s.serialize_field("code", self.code())?;
+        struct_s.serialize_field("recoverable", &self.recoverable())?;
+        match self {
+            Self::Validation(fields) => struct_s.serialize_field("fields", fields)?,
+            Self::PollTooFast { retry_after_ms } => {
+                struct_s.serialize_field("retry_after_ms", retry_after_ms)?
+            }
+            _ => (),
+        }
         struct_s.end()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use axum::{body::to_bytes, http::StatusCode, response::IntoResponse};
+
+    use super::{AppError, ClientError, ErrorSource, RemoteError, ServerError, TransientError};
+    use crate::models::AppRespOwned;
+
+    /// Every variant, along with whether it is recoverable.
+    fn all_errors() -> Vec<(AppError, bool)> {
+        let s = String::new;
+        vec![
+            (ServerError::BindPort(0).into(), false),
+            (ServerError::WorkDirNotWritable(s()).into(), false),
+            (ServerError::DocDirNotReadable(s()).into(), false),
+            (ServerError::ParsePath(s()).into(), false),
+            (ServerError::OpenFile(s()).into(), false),
+            (ServerError::ReadFile(s()).into(), true),
+            (ServerError::RemoveFile(s()).into(), true),
+            (ServerError::MoveFile(s()).into(), true),
+            (ServerError::InvalidUtf8(s()).into(), false),
+            (ServerError::ResultMissing(s()).into(), true),
+            (ServerError::IssueCommand(s()).into(), true),
+            (ServerError::CompressFile.into(), true),
+            (ServerError::InvalidConfig(s()).into(), false),
+            (
+                ServerError::DirsOverlap {
+                    work_dir: s(),
+                    doc_dir: s(),
+                }
+                .into(),
+                false,
+            ),
+            (ServerError::MissingDependency(s()).into(), false),
+            (ServerError::AxumServe.into(), false),
+            (ServerError::AiModel(s()).into(), true),
+            (ServerError::Internal(s()).into(), false),
+            (ServerError::VideoDownload(s()).into(), true),
+            (ServerError::VideoMetadata(s()).into(), true),
+            (ServerError::Timeout(0).into(), true),
+            (ServerError::ModelStalled(0).into(), true),
+            (ServerError::Translation(s()).into(), true),
+            (ServerError::EmptySummary { bytes: 0, min: 0 }.into(), true),
+            (
+                TransientError::InsufficientDiskSpace {
+                    needed: 0,
+                    available: 0,
+                }
+                .into(),
+                true,
+            ),
+            (TransientError::ServerBusy(0).into(), true),
+            (TransientError::Overloaded(0).into(), true),
+            (TransientError::ShuttingDown.into(), true),
+            (TransientError::ServicePaused.into(), true),
+            (TransientError::QueueFull(0).into(), true),
+            (ClientError::TokenNotExist(s()).into(), false),
+            (ClientError::VideoLinkNotExist(s()).into(), false),
+            (ClientError::VideoRequiresAuth(s()).into(), false),
+            (ClientError::VideoPrivate(s()).into(), false),
+            (ClientError::VideoGeoBlocked(s()).into(), false),
+            (ClientError::VideoRemoved(s()).into(), false),
+            (ClientError::RateLimited.into(), true),
+            (ClientError::NotRetryable(s()).into(), false),
+            (ClientError::VideoTooLong { secs: 0, max: 0 }.into(), false),
+            (
+                ClientError::VideoTooLarge { bytes: 0, max: 0 }.into(),
+                false,
+            ),
+            (ClientError::MalformedRequest(s()).into(), false),
+            (ClientError::Unauthorized.into(), false),
+            (ClientError::NotReady(s()).into(), true),
+            (ClientError::RouteNotFound(s()).into(), false),
+            (
+                ClientError::MethodNotAllowed {
+                    method: s(),
+                    path: s(),
+                }
+                .into(),
+                false,
+            ),
+            (
+                ClientError::UploadOffsetMismatch {
+                    expected: 0,
+                    actual: 0,
+                }
+                .into(),
+                false,
+            ),
+            (
+                ClientError::InlineTooLarge { size: 0, max: 0 }.into(),
+                false,
+            ),
+            (ClientError::PollTooFast { retry_after_ms: 0 }.into(), true),
+            (ClientError::Validation(Vec::new()).into(), false),
+            (ClientError::InvalidSignature.into(), false),
+        ]
+    }
+
+    #[test]
+    fn test_unique_codes() {
+        let errs: Vec<AppError> = all_errors().into_iter().map(|(e, _)| e).collect();
+        let codes: HashSet<_> = errs.iter().map(AppError::code).collect();
+        assert_eq!(codes.len(), errs.len());
+        assert!(codes.contains("video_link_not_exist"));
+        assert!(codes.contains("ai_model"));
+    }
+
+    #[test]
+    fn test_recoverable() {
+        for (e, recoverable) in all_errors() {
+            assert_eq!(e.recoverable(), recoverable, "{}", e.code());
+            let json = serde_json::to_value(&e).unwrap();
+            assert_eq!(json["err"]["recoverable"], recoverable, "{}", e.code());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transient_response() {
+        let resp = TransientError::ServerBusy(4).into_response();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()["retry-after"], "30");
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["success"], false);
+        // the error is wrapped once more
+        let err = &json["err"]["err"];
+        assert_eq!(err["source"], "transient");
+        assert_eq!(err["code"], "server_busy");
+        assert_eq!(err["recoverable"], true);
+        assert_eq!(err["retry_after_secs"], 30);
+
+        let AppRespOwned::<()>::Exception(e) = serde_json::from_slice(&body).unwrap() else {
+            panic!("transient error parsed as success");
+        };
+        let expected = RemoteError::from(&AppError::from(TransientError::ServerBusy(4)));
+        assert_eq!(e, expected);
+        assert_eq!(e.source, ErrorSource::Transient);
+        assert_eq!(e.retry_after_secs, Some(30));
+    }
+}
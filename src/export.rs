@@ -0,0 +1,314 @@
+//! Zips written on the fly, of all done summaries for `/admin/export`, and of the files of an
+//! incomplete task for `/download` with `partial`.
+//!
+//! The zip is written as it is sent, one entry per file, so that only one file is held in memory
+//! at a time besides the central directory. Entries are stored uncompressed, summaries being
+//! small and audio compressed already, and without zip64, so a zip is capped by
+//! `--export-max-bytes`, at most 4 GiB, and 65535 entries. Files past the cap are left out, and
+//! the zip ends early but valid.
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::body::Bytes;
+use futures_util::{stream, Stream};
+
+/// Size of the end of central directory record.
+const END_LEN: u64 = 22;
+
+/// Most entries without zip64.
+const MAX_ENTRIES: usize = u16::MAX as usize;
+
+/// Header fields shared by the local and the central header of an entry.
+struct Entry {
+    name: String,
+    crc: u32,
+    len: u32,
+    /// MS-DOS time and date of last modification.
+    modified: (u16, u16),
+    offset: u32,
+}
+
+/// Content of an entry of [`zip_stream`].
+pub enum Content {
+    /// File read once its turn comes.
+    File(PathBuf),
+    /// Generated content, e.g. a manifest.
+    Bytes(Vec<u8>),
+}
+
+struct Bundle {
+    /// Name in the zip and content of each entry not yet written.
+    files: VecDeque<(String, Content)>,
+    max_bytes: u64,
+    central: Vec<u8>,
+    entries: usize,
+    /// Bytes sent so far.
+    offset: u64,
+}
+
+/// Zip of `files`, each a name in the zip and its content, written while streamed.
+///
+/// Files unreadable meanwhile are skipped, and files which would make the zip larger than
+/// `max_bytes` left out.
+pub fn zip_stream(
+    files: Vec<(String, Content)>,
+    max_bytes: u64,
+) -> impl Stream<Item = std::io::Result<Bytes>> {
+    let bundle = Bundle {
+        files: files.into(),
+        max_bytes: max_bytes.min(u32::MAX as u64),
+        central: Vec::new(),
+        entries: 0,
+        offset: 0,
+    };
+    stream::unfold(Some(bundle), |bundle| async move {
+        let mut bundle = bundle?;
+        while let Some((name, content)) = bundle.files.pop_front() {
+            let (data, modified) = match content {
+                Content::File(path) => {
+                    let Ok(data) = tokio::fs::read(&path).await else {
+                        tracing::warn!("\nZip skips unreadable \"{}\".", path.display());
+                        continue;
+                    };
+                    let modified = tokio::fs::metadata(&path)
+                        .await
+                        .and_then(|metadata| metadata.modified())
+                        .unwrap_or(UNIX_EPOCH);
+                    (data, modified)
+                }
+                Content::Bytes(data) => (data, SystemTime::now()),
+            };
+            let header_len = 30 + name.len() as u64;
+            let central_len = 46 + name.len() as u64;
+            let total = bundle.offset
+                + header_len
+                + data.len() as u64
+                + bundle.central.len() as u64
+                + central_len
+                + END_LEN;
+            if total > bundle.max_bytes || bundle.entries == MAX_ENTRIES {
+                tracing::warn!(
+                    "\nZip reaches {} bytes, leaving out {} files from \"{name}\" on.",
+                    bundle.max_bytes,
+                    bundle.files.len() + 1,
+                );
+                break;
+            }
+            let entry = Entry {
+                name,
+                crc: crc32(&data),
+                len: data.len() as u32,
+                modified: dos_time(modified),
+                offset: bundle.offset as u32,
+            };
+            let mut chunk = entry.local_header();
+            chunk.extend(data);
+            bundle.central.extend(entry.central_header());
+            bundle.entries += 1;
+            bundle.offset += chunk.len() as u64;
+            return Some((Ok(Bytes::from(chunk)), Some(bundle)));
+        }
+        let mut end = std::mem::take(&mut bundle.central);
+        end.extend(end_of_central_directory(
+            bundle.entries as u16,
+            end.len() as u32,
+            bundle.offset as u32,
+        ));
+        Some((Ok(Bytes::from(end)), None))
+    })
+}
+
+impl Entry {
+    /// Fields from "version needed" to "name length", see APPNOTE.TXT 4.3.7.
+    fn common(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(26);
+        bytes.extend(10u16.to_le_bytes());
+        // names are UTF-8
+        bytes.extend(0x0800u16.to_le_bytes());
+        // stored
+        bytes.extend(0u16.to_le_bytes());
+        bytes.extend(self.modified.0.to_le_bytes());
+        bytes.extend(self.modified.1.to_le_bytes());
+        bytes.extend(self.crc.to_le_bytes());
+        bytes.extend(self.len.to_le_bytes());
+        bytes.extend(self.len.to_le_bytes());
+        bytes.extend((self.name.len() as u16).to_le_bytes());
+        bytes
+    }
+
+    fn local_header(&self) -> Vec<u8> {
+        let mut bytes = 0x04034b50u32.to_le_bytes().to_vec();
+        bytes.extend(self.common());
+        // extra field length
+        bytes.extend(0u16.to_le_bytes());
+        bytes.extend(self.name.as_bytes());
+        bytes
+    }
+
+    fn central_header(&self) -> Vec<u8> {
+        let mut bytes = 0x02014b50u32.to_le_bytes().to_vec();
+        // made by
+        bytes.extend(20u16.to_le_bytes());
+        bytes.extend(self.common());
+        // extra field, comment, disk number, internal and external attributes
+        bytes.extend([0; 12]);
+        bytes.extend(self.offset.to_le_bytes());
+        bytes.extend(self.name.as_bytes());
+        bytes
+    }
+}
+
+fn end_of_central_directory(entries: u16, central_len: u32, central_offset: u32) -> Vec<u8> {
+    let mut bytes = 0x06054b50u32.to_le_bytes().to_vec();
+    // disk numbers
+    bytes.extend([0; 4]);
+    bytes.extend(entries.to_le_bytes());
+    bytes.extend(entries.to_le_bytes());
+    bytes.extend(central_len.to_le_bytes());
+    bytes.extend(central_offset.to_le_bytes());
+    // comment length
+    bytes.extend(0u16.to_le_bytes());
+    bytes
+}
+
+/// MS-DOS time and date of `time` in UTC, clamped to 1980 to 2107 which they can hold.
+fn dos_time(time: SystemTime) -> (u16, u16) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    if year > 2107 {
+        return ((23 << 11) | (59 << 5) | 29, (127 << 9) | (12 << 5) | 31);
+    }
+    let time = (secs / 3600) << 11 | (secs % 3600 / 60) << 5 | (secs % 60 / 2);
+    let date = (year - 1980) << 9 | month << 5 | day;
+    (time as u16, date as u16)
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => 0xedb88320 ^ (crc >> 1),
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (ISO 3309) of `data`, as zip checks entries with.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use futures_util::StreamExt;
+    use uuid::Uuid;
+
+    use super::{crc32, dos_time, zip_stream, Content};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        // 2024-02-29 13:45:10 UTC
+        let time = UNIX_EPOCH + Duration::from_secs(1709214310);
+        assert_eq!(
+            dos_time(time),
+            ((13 << 11) | (45 << 5) | 5, (44 << 9) | (2 << 5) | 29)
+        );
+        assert_eq!(dos_time(UNIX_EPOCH), (0, (1 << 5) | 1));
+    }
+
+    #[tokio::test]
+    async fn test_zip_stream() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(dir.join(name), name.repeat(100)).unwrap();
+        }
+        let zip = |max_bytes| {
+            let mut files: Vec<_> = ["a", "b", "missing", "c"]
+                .into_iter()
+                .map(|name| (format!("{name}/summary.txt"), Content::File(dir.join(name))))
+                .collect();
+            files.push(("manifest.json".into(), Content::Bytes(b"{}".to_vec())));
+            async move {
+                let chunks: Vec<_> = zip_stream(files, max_bytes).collect().await;
+                let bytes: Vec<u8> = chunks.into_iter().flat_map(Result::unwrap).collect();
+                bytes
+            }
+        };
+
+        let bytes = zip(u64::MAX).await;
+        let archive = dir.join("all.zip");
+        fs::write(&archive, &bytes).unwrap();
+        let listing = std::process::Command::new("unzip")
+            .arg("-l")
+            .arg(&archive)
+            .output()
+            .unwrap();
+        let listing = String::from_utf8(listing.stdout).unwrap();
+        for name in ["a", "b", "c"] {
+            assert!(
+                listing.contains(&format!("{name}/summary.txt")),
+                "{listing}"
+            );
+        }
+        assert!(!listing.contains("missing"), "{listing}");
+        assert!(listing.contains("manifest.json"), "{listing}");
+        let tested = std::process::Command::new("unzip")
+            .arg("-t")
+            .arg(&archive)
+            .status()
+            .unwrap();
+        assert!(tested.success());
+
+        // room for one entry, the others left out
+        let capped = zip(200 + 2 * (30 + 46 + 13) + 22).await;
+        fs::write(&archive, &capped).unwrap();
+        let listing = std::process::Command::new("unzip")
+            .arg("-l")
+            .arg(&archive)
+            .output()
+            .unwrap();
+        let listing = String::from_utf8(listing.stdout).unwrap();
+        assert!(listing.contains("a/summary.txt"), "{listing}");
+        assert!(!listing.contains("b/summary.txt"), "{listing}");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
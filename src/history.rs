@@ -0,0 +1,171 @@
+//! Bounded in-memory history of finished tasks, queried by operators via `/admin/history`.
+//!
+//! Only the latest `--history-size` tasks are kept. Urls are omitted unless `--admin-show-urls`
+//! is set, the same goes for error details which may quote the url.
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::models::TaskStatus;
+
+pub struct History {
+    capacity: usize,
+    show_urls: bool,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct HistoryEntry {
+    /// RFC 3339, UTC.
+    pub ts: String,
+    pub uuid: String,
+    pub stage: TaskStatus,
+    pub duration_secs: f64,
+    pub error: Option<HistoryError>,
+    /// Absent unless `--admin-show-urls` is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct HistoryError {
+    pub source: &'static str,
+    pub code: &'static str,
+    /// Absent unless `--admin-show-urls` is set, as it may quote the url.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<String>,
+}
+
+/// Query string of `/admin/history`.
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    /// `done` or `err`, case insensitive.
+    pub stage: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+    #[serde(default)]
+    pub offset: usize,
+}
+
+fn default_limit() -> usize {
+    50
+}
+
+#[derive(Serialize)]
+pub struct HistoryResp {
+    /// Number of entries matching the filter, regardless of pagination.
+    pub total: usize,
+    /// Newest first.
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    pub fn new(capacity: usize, show_urls: bool) -> Self {
+        Self {
+            capacity,
+            show_urls,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Remember the terminal `status` of `uuid`, evicting the oldest entry when full.
+    pub fn record(&self, uuid: &str, status: &TaskStatus, duration: Duration, url: &str) {
+        let error = match status {
+            TaskStatus::Err(e) => Some(HistoryError {
+                source: e.source(),
+                code: e.code(),
+                info: self.show_urls.then(|| e.to_string()),
+            }),
+            _ => None,
+        };
+        let entry = HistoryEntry {
+            ts: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            uuid: uuid.to_string(),
+            stage: status.clone(),
+            duration_secs: duration.as_secs_f64(),
+            error,
+            url: self.show_urls.then(|| url.to_string()),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Entries matching `query.stage`, newest first, paginated.
+    ///
+    /// `None` if the stage is not one of `done` and `err`.
+    pub fn query(&self, query: &HistoryQuery) -> Option<HistoryResp> {
+        let want_err = match query.stage.as_deref().map(str::to_ascii_lowercase) {
+            None => None,
+            Some(stage) if stage == "done" => Some(false),
+            Some(stage) if stage == "err" => Some(true),
+            Some(_) => return None,
+        };
+        let entries = self.entries.lock().unwrap();
+        let matched = entries.iter().rev().filter(|entry| {
+            want_err.is_none_or(|want_err| matches!(entry.stage, TaskStatus::Err(_)) == want_err)
+        });
+        let total = matched.clone().count();
+        let entries = matched
+            .skip(query.offset)
+            .take(query.limit)
+            .cloned()
+            .collect();
+        Some(HistoryResp { total, entries })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{History, HistoryQuery};
+    use crate::{
+        exception::{AppError, ServerError},
+        models::TaskStatus,
+    };
+
+    fn query(stage: Option<&str>, limit: usize, offset: usize) -> HistoryQuery {
+        HistoryQuery {
+            stage: stage.map(String::from),
+            limit,
+            offset,
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let history = History::new(4, false);
+        let err = TaskStatus::Err(AppError::from(ServerError::CompressFile));
+        for (uuid, status) in [
+            ("a", &TaskStatus::Done),
+            ("b", &err),
+            ("c", &TaskStatus::Done),
+            ("d", &err),
+            ("e", &TaskStatus::Done),
+        ] {
+            history.record(uuid, status, Duration::ZERO, "https://a.b.c");
+        }
+
+        // "a" is evicted, newest first
+        let resp = history.query(&query(None, 50, 0)).unwrap();
+        let uuids: Vec<_> = resp.entries.iter().map(|e| e.uuid.as_str()).collect();
+        assert_eq!((resp.total, uuids), (4, vec!["e", "d", "c", "b"]));
+
+        let resp = history.query(&query(Some("ERR"), 1, 1)).unwrap();
+        assert_eq!(resp.total, 2);
+        assert_eq!(resp.entries[0].uuid, "b");
+        assert_eq!(
+            resp.entries[0].error.as_ref().unwrap().code,
+            "compress_file"
+        );
+        assert!(resp.entries[0].url.is_none());
+
+        assert!(history.query(&query(Some("pending"), 50, 0)).is_none());
+    }
+}
@@ -0,0 +1,153 @@
+//! Replayed `/init` requests recognized by their `Idempotency-Key` header, enabled by
+//! `--idempotency-window`.
+//!
+//! A retrying client may never see the response to its first attempt, and so cannot echo back
+//! the uuid it was assigned. Instead, the key it chose is remembered along with the uuid, and a
+//! request with the same key within the window is answered with that uuid without spawning
+//! another task. Keys are dropped by the sweeper once the window passes.
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::http::HeaderMap;
+
+use crate::exception::ClientError;
+
+pub const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+/// Longest key accepted.
+const MAX_KEY_LEN: usize = 255;
+
+pub struct IdempotencyKeys {
+    window: Duration,
+    /// Uuid assigned to each key, with when it was first seen.
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl IdempotencyKeys {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Uuid assigned to `key` within the window, `None` if unseen.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(_, seen)| seen.elapsed() < self.window)
+            .map(|(uuid, _)| uuid.clone())
+    }
+
+    /// Assign `uuid` to `key`, unless a request racing with this one has done so within the
+    /// window, whose uuid is returned instead.
+    pub fn claim(&self, key: &str, uuid: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((existing, seen)) if seen.elapsed() < self.window => Some(existing.clone()),
+            _ => {
+                entries.insert(key.to_string(), (uuid.to_string(), Instant::now()));
+                None
+            }
+        }
+    }
+
+    /// Reassign a claimed `key` to `uuid`, e.g. an identical task it turns out to share.
+    pub fn reassign(&self, key: &str, uuid: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(key) {
+            entry.0 = uuid.to_string();
+        }
+    }
+
+    /// Drop `key` if still assigned to `uuid`, e.g. a task never spawned.
+    pub fn release(&self, key: &str, uuid: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.get(key).is_some_and(|(owner, _)| owner == uuid) {
+            entries.remove(key);
+        }
+    }
+
+    /// Drop keys seen over `--idempotency-window` ago, returning how many.
+    pub fn purge_expired(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, (_, seen)| seen.elapsed() < self.window);
+        before - entries.len()
+    }
+}
+
+/// Idempotency key in `headers`, `None` if absent.
+///
+/// Fails with [`ClientError::MalformedRequest`] unless it is 1 to 255 visible ASCII characters.
+pub fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, ClientError> {
+    let Some(value) = headers.get(IDEMPOTENCY_HEADER) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key)
+            if !key.is_empty()
+                && key.len() <= MAX_KEY_LEN
+                && key.bytes().all(|b| b.is_ascii_graphic()) =>
+        {
+            Ok(Some(key.to_string()))
+        }
+        _ => Err(ClientError::MalformedRequest(format!(
+            "{IDEMPOTENCY_HEADER} must be 1 to {MAX_KEY_LEN} visible ASCII characters"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use axum::http::HeaderMap;
+
+    use super::{idempotency_key, IdempotencyKeys, IDEMPOTENCY_HEADER};
+
+    #[test]
+    fn test_idempotency_keys() {
+        let keys = IdempotencyKeys::new(Duration::from_secs(60));
+        assert_eq!(keys.get("k1"), None);
+        assert_eq!(keys.claim("k1", "a"), None);
+        assert_eq!(keys.claim("k1", "b").as_deref(), Some("a"));
+        assert_eq!(keys.claim("k2", "c"), None);
+        keys.reassign("k2", "d");
+        assert_eq!(keys.get("k1").as_deref(), Some("a"));
+        assert_eq!(keys.get("k2").as_deref(), Some("d"));
+        assert_eq!(keys.purge_expired(), 0);
+        // only by the uuid it is assigned to
+        keys.release("k2", "c");
+        assert_eq!(keys.get("k2").as_deref(), Some("d"));
+        assert_eq!(keys.claim("k3", "f"), None);
+        keys.release("k3", "f");
+        assert_eq!(keys.get("k3"), None);
+
+        keys.entries.lock().unwrap().get_mut("k1").unwrap().1 -= Duration::from_secs(61);
+        // expired keys are claimed afresh even before purged
+        assert_eq!(keys.get("k1"), None);
+        assert_eq!(keys.claim("k1", "e"), None);
+        keys.entries.lock().unwrap().get_mut("k1").unwrap().1 -= Duration::from_secs(61);
+        assert_eq!(keys.purge_expired(), 1);
+        assert_eq!(keys.get("k2").as_deref(), Some("d"));
+    }
+
+    #[test]
+    fn test_idempotency_key() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(idempotency_key(&headers).unwrap(), None);
+        headers.insert(IDEMPOTENCY_HEADER, "retry-42".parse().unwrap());
+        assert_eq!(
+            idempotency_key(&headers).unwrap().as_deref(),
+            Some("retry-42")
+        );
+        for invalid in ["", "a b", &"x".repeat(256)] {
+            headers.insert(IDEMPOTENCY_HEADER, invalid.parse().unwrap());
+            assert!(idempotency_key(&headers).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Cap of concurrently handled requests, see `--max-inflight-requests`.
+//!
+//! Requests beyond the cap are shed at once with [`TransientError::Overloaded`] in the usual JSON
+//! envelope (HTTP 503) rather than queued, protecting cheap endpoints like `/poll` from
+//! connection floods. Streaming routes such as `/download` and `/init/upload` are left alone, so
+//! that long transfers do not hold the cap against everyone else.
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::sync::Semaphore;
+
+use crate::exception::TransientError;
+
+/// Permits shared by every route the middleware is applied to.
+#[derive(Clone)]
+pub struct InflightLimit {
+    max: usize,
+    permits: Arc<Semaphore>,
+}
+
+impl InflightLimit {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            permits: Arc::new(Semaphore::new(max)),
+        }
+    }
+}
+
+/// Middleware shedding requests once `limit` requests are in flight.
+///
+/// No-op if `limit` is `None`.
+pub async fn limit_inflight(
+    State(limit): State<Option<InflightLimit>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(limit) = limit else {
+        return next.run(req).await;
+    };
+    let Ok(_permit) = limit.permits.try_acquire() else {
+        tracing::warn!(
+            "\nRequest to {} is shed with {} requests in flight.",
+            req.uri().path(),
+            limit.max
+        );
+        return TransientError::Overloaded(limit.max).into_response();
+    };
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+        middleware,
+        routing::get,
+        Router,
+    };
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    use super::{limit_inflight, InflightLimit};
+
+    #[tokio::test]
+    async fn test_limit_inflight() {
+        let release = Arc::new(Notify::new());
+        let waiting = Arc::clone(&release);
+        let app = Router::new()
+            .route("/fast", get(|| async {}))
+            .route(
+                "/slow",
+                get(move || async move { waiting.notified().await }),
+            )
+            .layer(middleware::from_fn_with_state(
+                Some(InflightLimit::new(1)),
+                limit_inflight,
+            ));
+        let req = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+
+        let slow = tokio::spawn(app.clone().oneshot(req("/slow")));
+        tokio::task::yield_now().await;
+        let resp = app.clone().oneshot(req("/fast")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()["retry-after"], "1");
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["err"]["err"]["code"], "overloaded");
+        assert_eq!(body["err"]["err"]["source"], "transient");
+
+        // the permit is back once the slow request is answered
+        release.notify_one();
+        assert_eq!(slow.await.unwrap().unwrap().status(), StatusCode::OK);
+        let resp = app.oneshot(req("/fast")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
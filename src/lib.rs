@@ -0,0 +1,108 @@
+//! Backend restful API for summary service  
+//!
+//! This server consists of only three Restful APIs:  
+//! 1. `/init`: [init_summary][`controller::init_summary`].  
+//! 2. `/poll`: [poll_status][`controller::poll_status`].  
+//! 3. `/download`: [fetch_archive][`controller::fetch_archive`].  
+//!
+//! Method is `POST` for all three endpoints.
+//!
+//! Besides, `POST` `/retry` re-runs a failed task, see [retry_task][`controller::retry_task`],
+//! and `POST` `/delete` erases a task with its files, see [delete_task][`controller::delete_task`].
+//! `POST` `/files` lists files of a task, see [list_files][`controller::list_files`], and
+//! `POST` `/audio` returns its audio, see [fetch_audio][`controller::fetch_audio`].
+//! `POST` `/metadata` previews a video without processing, see
+//! [video_metadata][`controller::video_metadata`].
+//! `POST` `/session/tasks` lists tasks submitted with the same `X-Session-Id`, see
+//! [session_tasks][`controller::session_tasks`], and `POST` `/events/batch` streams the stage of
+//! several tasks at once, see [batch_events][`controller::batch_events`].
+//! `GET` `/estimate` tells how long a new task would wait for a model, see
+//! [estimate][`controller::estimate`], and `GET` `/capabilities` lists the backends, languages,
+//! models and formats clients may choose, see [capabilities][`controller::capabilities`].
+//!
+//! For operators, `GET` `/metrics` exposes [metrics][`controller::metrics`] in Prometheus format,
+//! `GET` `/version` exposes [build metadata][`controller::version`], and `GET` `/health`
+//! tells whether new tasks are [accepted][`controller::health`].
+//!
+//! `GET` `/openapi.json` describes all of the above in [OpenAPI 3][`controller::openapi`].
+//!
+//! About general API response format, see [`models::AppResp`], and [`api_version`] about
+//! pinning its version.  
+//! About signing requests with `--api-secret`, see [`signature`].  
+//! About gating the API with a shared `--api-key`, see [`api_key`].  
+//! About exception handling, see [`ServerError`][`exception::ServerError`] and
+//! [`ClientError`][`exception::ClientError`].  
+//! About log output format, see [`log`], and [`task_log`] about the log of each task.  
+//!
+//! ### Safety
+//! - A minimum idempotency is maintained by [`init_summary`][`controller::init_summary`] controller.  
+//! - APIs are stateful, but states are limited in current session. That is, uuid for `/poll` cannot
+//!   servive a page refresh.  
+//!
+//! #### "Why not make video link the primary key, so that result can be cached and retrieved at any moment?"  
+//! It will leak the information that someone else have requested a summary for a link.  
+//!
+//! #### "Why not make (uuid, video link) the primary key?"
+//! It wouldn't help resolve the original problem, as uuid still does not survive a page refresh.  
+//!   
+//! #### "Why not implement authentication, and associate tasks with user account?"  
+//! That would be great, but I did not have enough time. PLUS, the authentication ecosystem is  
+//! immature. At the moment I wrote this, [`axum login`](https://github.com/maxcountryman/axum-login) has only
+//! 655 stars. Usually people tend to implement their own request extractor.  
+//!
+//! ### Architecture Diagram
+//! ![arch.jpg](https://zjhpub.s3.ap-northeast-2.amazonaws.com/arch.jpg)
+// the `json!` of `openapi::schemas` outgrows the default
+#![recursion_limit = "256"]
+
+pub mod access_log;
+pub mod admin;
+pub mod alert;
+pub mod api_key;
+pub mod api_version;
+pub mod audit;
+pub mod backend;
+pub mod base64;
+pub mod blob;
+pub mod clean;
+pub mod client_ip;
+pub mod config_file;
+pub mod controller;
+pub mod cors;
+pub mod deps;
+pub mod disk;
+pub mod doc;
+pub mod duration;
+pub mod events;
+pub mod exception;
+pub mod executor;
+pub mod export;
+pub mod history;
+pub mod idempotency;
+pub mod inflight;
+pub mod log;
+pub mod metrics;
+pub mod models;
+pub mod openapi;
+pub mod output;
+pub mod output_tail;
+pub mod pretty;
+pub mod queue;
+pub mod rate_limit;
+pub mod redact;
+pub mod reload;
+pub mod request_id;
+pub mod router;
+pub mod session;
+pub mod signature;
+pub mod slots;
+pub mod stats;
+pub mod storage;
+pub mod stream;
+pub mod task_log;
+pub mod task_table;
+pub mod timeout;
+pub mod upload;
+pub mod video;
+pub mod warmup;
+pub mod watchdog;
@@ -2,10 +2,17 @@
 //!
 //! Logging consists of two layers:  
 //! 1. stdout  
-//! 2. a non-blocking file writer that group each log for one day  
+//! 2. a non-blocking file writer that group each log for one day (see `--log-rotation`)  
+//!
+//! along with the log of each task in its dir with `--per-task-logs`, see [`crate::task_log`].
+//!
+//! Rotated files are kept forever unless [`LogRetention`] is configured.
+//!
+//! The file writer hands lines over to a background thread, so recent ones may not be in the
+//! file yet. [`LogFlusher`] waits for it to catch up, see `/admin/flush-logs`.
 //!
 //! ### Example log of a success sequence of requests  
-//! ```
+//! ```text
 //!   2024/12/07-00:59:26  INFO  Server listening to port 8080.
 //!     at src/main.rs:65 on ThreadId(1)
 //!
@@ -39,7 +46,7 @@
 //! ### Example log of failures
 //! Note that [`ClientError`][`crate::exception::ClientError`] is marked as `WARN`,  
 //! while [`ServerError`][`crate::exception::ServerError`] is marked as `ERROR`.  
-//! ```
+//! ```text
 //!   2024/12/07-01:38:40  INFO  Server listening to port 8080.
 //!     at src/main.rs:66 on ThreadId(1)
 //!
@@ -66,27 +73,69 @@
 //! User 0a241e00-fd20-49af-9183-f12d88c4b attempts to download without init task.
 //!     at src/controller.rs:257 on ThreadId(21)
 //! ```
-use std::path::Path;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
+use clap::ValueEnum;
 use time::{
     macros::{format_description, offset},
     UtcOffset,
 };
 use tracing::level_filters::LevelFilter;
+use tracing_appender::{
+    non_blocking::{ErrorCounter, NonBlocking, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
 use tracing_subscriber::{
-    fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, Layer,
+    fmt::{format::FmtSpan, MakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer,
 };
 
+use crate::task_log::task_log_layer;
+
+/// How often log file is rotated, see `--log-rotation`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
 /// Initialize tracing and obtain [WorkerGuard][`tracing_appender::non_blocking::WorkerGuard`].
 ///
-/// Attempt to obtain local time zone, fallback to +9 on failure.  
+/// Use `offset` if specified (see `--log-tz-offset`), otherwise attempt to obtain local time zone,
+/// fallback to +9 on failure. The chosen offset is logged once tracing is up.  
+/// Log files are named `<prefix>.<date>` (`<prefix>` alone when never rotated), rotated by
+/// `rotation`. Each task is also logged into its dir if `per_task_logs`.  
 /// Log is of format:  
-/// ```
+/// ```text
 /// year/month/day-hour/min/sec level ThreadId(n): output
 /// ```
 /// Purpose of [WorkerGuard][`tracing_appender::non_blocking::WorkerGuard`] is to make sure its
 /// [`Drop`][`tracing_appender::non_blocking::WorkerGuard::drop()`] is invoked on abort.  
-/// ```rust
+/// ```rust,ignore
 /// fn drop(&mut self) {
 ///     match self
 ///         .sender
@@ -107,15 +156,30 @@ use tracing_subscriber::{
 ///     }
 /// }
 /// ```
-pub fn init_tracing(path: impl AsRef<Path>) -> tracing_appender::non_blocking::WorkerGuard {
-    // from_hms only returns Ok according to its source code
+pub fn init_tracing(
+    path: impl AsRef<Path>,
+    offset: Option<UtcOffset>,
+    rotation: LogRotation,
+    prefix: &str,
+    per_task_logs: bool,
+) -> (WorkerGuard, LogFlusher) {
     let fallback_offset = offset!(+9);
-    let offset = UtcOffset::current_local_offset().unwrap_or(fallback_offset);
+    let (offset, offset_source) = match offset {
+        Some(offset) => (offset, "configured"),
+        None => match UtcOffset::current_local_offset() {
+            Ok(offset) => (offset, "detected"),
+            Err(_) => (fallback_offset, "fallback"),
+        },
+    };
     let formatter = format_description!("[year]/[month]/[day]-[hour]:[minute]:[second]");
     let time = tracing_subscriber::fmt::time::OffsetTime::new(offset, formatter);
 
-    let file_appender = tracing_appender::rolling::daily(path, "log");
-    let (non_block_file_wt, guard) = tracing_appender::non_blocking(file_appender);
+    let file_appender = RollingFileAppender::builder()
+        .rotation(rotation.into())
+        .filename_prefix(prefix)
+        .build(path)
+        .expect("cannot create log file appender");
+    let (non_block_file_wt, guard, flusher) = counted_non_blocking(file_appender);
 
     let file_layer = tracing_subscriber::fmt::layer()
         .pretty()
@@ -142,6 +206,309 @@ pub fn init_tracing(path: impl AsRef<Path>) -> tracing_appender::non_blocking::W
     tracing_subscriber::registry()
         .with(file_layer)
         .with(std_layer)
+        .with(per_task_logs.then(|| task_log_layer(offset)))
         .init();
-    guard
+    tracing::info!("Log time zone offset {offset} ({offset_source}).");
+    tracing::info!("Log file \"{prefix}\" rotated {rotation:?}.");
+    (guard, flusher)
+}
+
+/// Non-blocking writer to `writer` along with a [`LogFlusher`] of it.
+fn counted_non_blocking<W: Write + Send + 'static>(
+    writer: W,
+) -> (Counted<NonBlocking>, WorkerGuard, LogFlusher) {
+    let written = Arc::new(AtomicU64::new(0));
+    let writer = Counted {
+        inner: writer,
+        lines: Arc::clone(&written),
+    };
+    let (non_blocking, guard) = tracing_appender::non_blocking(writer);
+    let flusher = LogFlusher {
+        sent: Arc::default(),
+        written,
+        dropped: non_blocking.error_counter(),
+    };
+    let writer = Counted {
+        inner: non_blocking,
+        lines: Arc::clone(&flusher.sent),
+    };
+    (writer, guard, flusher)
+}
+
+/// Writer counting lines, each passed in a single `write_all` by both the formatter and the
+/// background thread.
+#[derive(Clone)]
+struct Counted<W> {
+    inner: W,
+    lines: Arc<AtomicU64>,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.inner.write_all(buf)?;
+        self.lines.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for Counted<NonBlocking> {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Waits for the background thread of the file writer to write every line logged so far.
+///
+/// Lines dropped as the channel to the thread is full are not waited for.
+pub struct LogFlusher {
+    /// Lines handed over to the thread.
+    sent: Arc<AtomicU64>,
+    /// Lines written to the file by the thread.
+    written: Arc<AtomicU64>,
+    dropped: ErrorCounter,
+}
+
+impl LogFlusher {
+    /// Wait up to `timeout` for lines logged before the call, returning how many were not in the
+    /// file yet, or `Err` with how many still are not once timed out.
+    ///
+    /// Lines are in the file once written by the thread, though the OS may hold them in page
+    /// cache a while longer.
+    pub async fn flush(&self, timeout: Duration) -> Result<u64, u64> {
+        let target = self
+            .sent
+            .load(Ordering::Acquire)
+            .saturating_sub(self.dropped.dropped_lines() as u64);
+        let pending = target.saturating_sub(self.written.load(Ordering::Acquire));
+        let deadline = Instant::now() + timeout;
+        loop {
+            let written = self.written.load(Ordering::Acquire);
+            if written >= target {
+                return Ok(pending);
+            }
+            if Instant::now() >= deadline {
+                return Err(target - written);
+            }
+            tokio::time::sleep(FLUSH_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// How often [`LogFlusher::flush`] checks whether the thread has caught up.
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Parse a UTC offset of format `+HH:MM` or `-HH:MM`, as accepted by `--log-tz-offset`.
+///
+/// `Z` is accepted as an alias of `+00:00`.
+pub fn parse_utc_offset(s: &str) -> Result<UtcOffset, String> {
+    let malformed = || format!("malformed offset \"{s}\", expect format like +09:00 or -05:30");
+    if s == "Z" {
+        return Ok(UtcOffset::UTC);
+    }
+    let (sign, rest) = match s.split_at_checked(1) {
+        Some(("+", rest)) => (1, rest),
+        Some(("-", rest)) => (-1, rest),
+        _ => return Err(malformed()),
+    };
+    let Some((hours, minutes)) = rest.split_once(':') else {
+        return Err(malformed());
+    };
+    if hours.len() != 2 || minutes.len() != 2 {
+        return Err(malformed());
+    }
+    let hours: i8 = hours.parse().map_err(|_| malformed())?;
+    let minutes: i8 = minutes.parse().map_err(|_| malformed())?;
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        return Err(malformed());
+    }
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).map_err(|_| malformed())
+}
+
+/// Compression and deletion of rotated log files, see `--log-compress-after-days` and
+/// `--log-retention-days`.
+///
+/// Only files named `<prefix>` or `<prefix>.*` in `dir` are considered, and the newest
+/// uncompressed one is always left alone, as the writer may still hold it open.
+pub struct LogRetention {
+    pub dir: PathBuf,
+    pub prefix: String,
+    pub compress_after: Option<Duration>,
+    pub delete_after: Option<Duration>,
+}
+
+impl LogRetention {
+    /// Sweep on startup, then every hour.
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            let retention = Arc::clone(&self);
+            let result =
+                tokio::task::spawn_blocking(move || retention.sweep(SystemTime::now())).await;
+            if let Ok(Err(e)) = result {
+                tracing::error!("\nFailed to sweep log dir: {e}");
+            }
+        }
+    }
+
+    /// Gzip and delete files by their age at `now`.
+    fn sweep(&self, now: SystemTime) -> io::Result<()> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let owned = name == self.prefix
+                || name
+                    .strip_prefix(&self.prefix)
+                    .is_some_and(|rest| rest.starts_with('.'));
+            let metadata = entry.metadata()?;
+            if owned && metadata.is_file() {
+                files.push((entry.path(), metadata.modified()?));
+            }
+        }
+        let active = files
+            .iter()
+            .filter(|(path, _)| !is_gzip(path))
+            .max_by_key(|(_, modified)| *modified)
+            .map(|(path, _)| path.clone());
+
+        for (path, modified) in files {
+            if active.as_ref() == Some(&path) {
+                continue;
+            }
+            let age = now.duration_since(modified).unwrap_or_default();
+            if self.delete_after.is_some_and(|after| age >= after) {
+                match fs::remove_file(&path) {
+                    Ok(()) => tracing::info!("\nDeleted log file {}.", path.display()),
+                    Err(e) => tracing::error!("\nFailed to delete {}: {e}", path.display()),
+                }
+            } else if !is_gzip(&path) && self.compress_after.is_some_and(|after| age >= after) {
+                // gzip keeps the modification time, so age of the result stays the same
+                match Command::new("gzip").arg("-f").arg(&path).status() {
+                    Ok(status) if status.success() => {
+                        tracing::info!("\nCompressed log file {}.", path.display())
+                    }
+                    Ok(status) => {
+                        tracing::error!("\nFailed to gzip {}: {status}", path.display())
+                    }
+                    Err(e) => tracing::error!("\nFailed to issue gzip: {e}"),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    use time::macros::offset;
+    use uuid::Uuid;
+
+    use std::io::Write;
+
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::{counted_non_blocking, parse_utc_offset, LogRetention};
+
+    #[tokio::test]
+    async fn test_log_flusher() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let file = fs::File::create(&path).unwrap();
+        let (writer, guard, flusher) = counted_non_blocking(file);
+        assert_eq!(flusher.flush(Duration::from_secs(5)).await, Ok(0));
+        for i in 0..100 {
+            let line = format!("line {i}\n");
+            writer.make_writer().write_all(line.as_bytes()).unwrap();
+        }
+        let pending = flusher.flush(Duration::from_secs(5)).await.unwrap();
+        assert!(pending <= 100);
+        // every line is there while the writer is still running
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 100);
+        assert!(content.ends_with("line 99\n"));
+        drop(guard);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_log_retention() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        for (name, age_days) in [
+            ("log.2024-01-01", 10),
+            ("log.2024-01-08", 3),
+            ("log.2024-01-10", 1),
+            ("other.2024-01-01", 10),
+        ] {
+            let file = fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(now - age_days * day).unwrap();
+        }
+        let retention = LogRetention {
+            dir: dir.clone(),
+            prefix: "log".into(),
+            compress_after: Some(day),
+            delete_after: Some(7 * day),
+        };
+        retention.sweep(now).unwrap();
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        // the newest is active, though old enough for compression
+        assert_eq!(
+            names,
+            ["log.2024-01-08.gz", "log.2024-01-10", "other.2024-01-01"]
+        );
+
+        // the compressed one ages into deletion
+        retention.sweep(now + 5 * day).unwrap();
+        assert!(!dir.join("log.2024-01-08.gz").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_utc_offset() {
+        assert_eq!(parse_utc_offset("+00:00"), Ok(offset!(+0)));
+        assert_eq!(parse_utc_offset("Z"), Ok(offset!(+0)));
+        assert_eq!(parse_utc_offset("+09:00"), Ok(offset!(+9)));
+        assert_eq!(parse_utc_offset("-05:30"), Ok(offset!(-5:30)));
+        for malformed in [
+            "",
+            "9",
+            "+9",
+            "+09",
+            "09:00",
+            "+09:60",
+            "+25:00",
+            "+0a:00",
+            "+09:00:00",
+        ] {
+            assert!(parse_utc_offset(malformed).is_err(), "{malformed}");
+        }
+    }
 }
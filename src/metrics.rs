@@ -0,0 +1,251 @@
+//! Task counters exposed by `/metrics` in Prometheus text format.
+//!
+//! Everything is an atomic, so neither recording nor rendering takes a lock.
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::exception::AppError;
+
+/// Upper bounds (in seconds) of the stage duration histogram buckets.
+const DURATION_BUCKETS: [u64; 11] = [1, 5, 15, 30, 60, 120, 300, 600, 1200, 1800, 3600];
+
+#[derive(Default)]
+pub struct Metrics {
+    tasks_initiated: AtomicU64,
+    tasks_completed: AtomicU64,
+    tasks_failed_client: AtomicU64,
+    tasks_failed_server: AtomicU64,
+    tasks_failed_transient: AtomicU64,
+    download: StageMetrics,
+    model: StageMetrics,
+}
+
+/// Pipeline stages tracked by [`Metrics`].
+#[derive(Clone, Copy)]
+pub enum Stage {
+    Download,
+    Model,
+}
+
+#[derive(Default)]
+struct StageMetrics {
+    active: AtomicI64,
+    duration: Histogram,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Non-cumulative count of each bucket in [`DURATION_BUCKETS`].
+    buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    count: AtomicU64,
+    sum_millis: AtomicU64,
+}
+
+/// Marks a task as being in a stage until dropped, at which point the elapsed time is observed.
+pub struct StageTimer<'a> {
+    stage: &'a StageMetrics,
+    start: Instant,
+}
+
+impl Metrics {
+    pub fn task_initiated(&self) {
+        self.tasks_initiated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn task_completed(&self) {
+        self.tasks_completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn task_failed(&self, err: &AppError) {
+        let counter = match err {
+            AppError::Client(_) => &self.tasks_failed_client,
+            AppError::Server(_) => &self.tasks_failed_server,
+            AppError::Transient(_) => &self.tasks_failed_transient,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a task as active in `stage` for the lifetime of the returned timer.
+    pub fn enter_stage(&self, stage: Stage) -> StageTimer<'_> {
+        let stage = self.stage(stage);
+        stage.active.fetch_add(1, Ordering::Relaxed);
+        StageTimer {
+            stage,
+            start: Instant::now(),
+        }
+    }
+
+    /// Number of tasks in `stage`.
+    pub fn active(&self, stage: Stage) -> usize {
+        self.stage(stage).active.load(Ordering::Relaxed).max(0) as usize
+    }
+
+    /// Mean time spent in `stage` so far, `None` before any task leaves it.
+    pub fn mean_duration(&self, stage: Stage) -> Option<Duration> {
+        let histogram = &self.stage(stage).duration;
+        let count = histogram.count.load(Ordering::Relaxed);
+        let sum = histogram.sum_millis.load(Ordering::Relaxed);
+        (count > 0).then(|| Duration::from_millis(sum / count))
+    }
+
+    fn stage(&self, stage: Stage) -> &StageMetrics {
+        match stage {
+            Stage::Download => &self.download,
+            Stage::Model => &self.model,
+        }
+    }
+
+    /// Render all metrics in Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        out.push_str("# HELP summary_tasks_initiated_total Tasks accepted by /init.\n");
+        out.push_str("# TYPE summary_tasks_initiated_total counter\n");
+        let _ = writeln!(
+            out,
+            "summary_tasks_initiated_total {}",
+            load(&self.tasks_initiated)
+        );
+
+        out.push_str("# HELP summary_tasks_completed_total Tasks with summary generated.\n");
+        out.push_str("# TYPE summary_tasks_completed_total counter\n");
+        let _ = writeln!(
+            out,
+            "summary_tasks_completed_total {}",
+            load(&self.tasks_completed)
+        );
+
+        out.push_str("# HELP summary_tasks_failed_total Tasks aborted with error.\n");
+        out.push_str("# TYPE summary_tasks_failed_total counter\n");
+        let _ = writeln!(
+            out,
+            "summary_tasks_failed_total{{source=\"client\"}} {}",
+            load(&self.tasks_failed_client)
+        );
+        let _ = writeln!(
+            out,
+            "summary_tasks_failed_total{{source=\"server\"}} {}",
+            load(&self.tasks_failed_server)
+        );
+        let _ = writeln!(
+            out,
+            "summary_tasks_failed_total{{source=\"transient\"}} {}",
+            load(&self.tasks_failed_transient)
+        );
+
+        out.push_str("# HELP summary_tasks_active Tasks currently in each stage.\n");
+        out.push_str("# TYPE summary_tasks_active gauge\n");
+        for (name, stage) in self.stages() {
+            let _ = writeln!(
+                out,
+                "summary_tasks_active{{stage=\"{name}\"}} {}",
+                stage.active.load(Ordering::Relaxed)
+            );
+        }
+
+        out.push_str("# HELP summary_stage_duration_seconds Time spent in each stage.\n");
+        out.push_str("# TYPE summary_stage_duration_seconds histogram\n");
+        for (name, stage) in self.stages() {
+            let histogram = &stage.duration;
+            let mut cumulative = 0;
+            for (bound, bucket) in DURATION_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += load(bucket);
+                let _ = writeln!(
+                    out,
+                    "summary_stage_duration_seconds_bucket{{stage=\"{name}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let count = load(&histogram.count);
+            let _ = writeln!(
+                out,
+                "summary_stage_duration_seconds_bucket{{stage=\"{name}\",le=\"+Inf\"}} {count}"
+            );
+            let _ = writeln!(
+                out,
+                "summary_stage_duration_seconds_sum{{stage=\"{name}\"}} {:.3}",
+                load(&histogram.sum_millis) as f64 / 1000.0
+            );
+            let _ = writeln!(
+                out,
+                "summary_stage_duration_seconds_count{{stage=\"{name}\"}} {count}"
+            );
+        }
+        out
+    }
+
+    fn stages(&self) -> [(&'static str, &StageMetrics); 2] {
+        [("download", &self.download), ("model", &self.model)]
+    }
+}
+
+impl Histogram {
+    fn observe_millis(&self, millis: u64) {
+        if let Some(i) = DURATION_BUCKETS
+            .iter()
+            .position(|&bound| millis <= bound * 1000)
+        {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_millis.fetch_add(millis, Ordering::Relaxed);
+    }
+}
+
+impl Drop for StageTimer<'_> {
+    fn drop(&mut self) {
+        self.stage.active.fetch_sub(1, Ordering::Relaxed);
+        let millis = self.start.elapsed().as_millis() as u64;
+        self.stage.duration.observe_millis(millis);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Metrics, Stage};
+    use crate::exception::{AppError, ClientError};
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.task_initiated();
+        metrics.task_initiated();
+        metrics.task_failed(&AppError::Client(ClientError::VideoLinkNotExist(
+            "url".into(),
+        )));
+        let timer = metrics.enter_stage(Stage::Model);
+        metrics.download.duration.observe_millis(4_000);
+        metrics.download.duration.observe_millis(7_200_000);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("summary_tasks_initiated_total 2\n"));
+        assert!(rendered.contains("summary_tasks_completed_total 0\n"));
+        assert!(rendered.contains("summary_tasks_failed_total{source=\"client\"} 1\n"));
+        assert!(rendered.contains("summary_tasks_active{stage=\"model\"} 1\n"));
+        assert!(rendered
+            .contains("summary_stage_duration_seconds_bucket{stage=\"download\",le=\"1\"} 0\n"));
+        assert!(rendered
+            .contains("summary_stage_duration_seconds_bucket{stage=\"download\",le=\"5\"} 1\n"));
+        assert!(rendered
+            .contains("summary_stage_duration_seconds_bucket{stage=\"download\",le=\"3600\"} 1\n"));
+        assert!(rendered
+            .contains("summary_stage_duration_seconds_bucket{stage=\"download\",le=\"+Inf\"} 2\n"));
+        assert!(
+            rendered.contains("summary_stage_duration_seconds_sum{stage=\"download\"} 7204.000\n")
+        );
+
+        assert_eq!(metrics.active(Stage::Model), 1);
+        assert_eq!(metrics.mean_duration(Stage::Model), None);
+        assert_eq!(
+            metrics.mean_duration(Stage::Download),
+            Some(std::time::Duration::from_millis(3_602_000))
+        );
+        drop(timer);
+        let rendered = metrics.render();
+        assert!(rendered.contains("summary_tasks_active{stage=\"model\"} 0\n"));
+        assert!(rendered.contains("summary_stage_duration_seconds_count{stage=\"model\"} 1\n"));
+    }
+}
@@ -1,41 +1,840 @@
 //! Data types for http request and response.
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    mem,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc},
+    time::{Duration, Instant},
+};
 
+use axum::http::{HeaderName, HeaderValue};
+use clap::ValueEnum;
 use serde::{ser::SerializeStruct, Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::{sync::RwLock, task::AbortHandle};
 
-use crate::exception::AppError;
+use crate::{
+    alert::Alerter,
+    api_version::{self, ApiVersion},
+    audit::AuditLog,
+    backend::Backends,
+    blob::{BlobStore, BLOB_DIR},
+    exception::{AppError, ServerError},
+    executor::{TaskExecutor, Warnings},
+    history::History,
+    idempotency::IdempotencyKeys,
+    log::LogFlusher,
+    metrics::Metrics,
+    output_tail::{OutputTail, OUTPUT_TAIL_LINES},
+    queue::ModelQueue,
+    rate_limit::RateLimiter,
+    reload::RuntimeConfig,
+    request_id,
+    session::Sessions,
+    slots::{Slot, Slots},
+    stats::Stats,
+    storage::{LocalStorage, Storage},
+    task_table::TaskTable,
+    upload::{Uploads, UPLOAD_DIR},
+};
 
 #[derive(Clone)]
 pub enum TaskStatus {
     Done,
     Err(AppError),
     Download,
+    /// Downloaded, waiting for a model to be free, see `--max-concurrent-models`.
+    Queued,
     Pending,
+    /// Summarized, being translated into [`InitiateReq::output_language`].
+    Translating,
+    /// Archive of a done task is being generated by `/download`, back to `Done` once ready.
+    Compressing,
 }
 
-pub type TaskMap = HashMap<String, TaskStatus>;
+/// Entry of the task table, a [`TaskStatus`] along with when each stage began.
+#[derive(Clone)]
+pub struct Task {
+    pub status: TaskStatus,
+    pub download_at: Option<Instant>,
+    pub queued_at: Option<Instant>,
+    pub pending_at: Option<Instant>,
+    pub done_at: Option<Instant>,
+    pub err_at: Option<Instant>,
+    /// Video duration, known if queried before download, see `--max-duration-secs`.
+    pub duration_secs: Option<u64>,
+    /// Kept for `/retry`.
+    pub request: Option<Arc<TaskRequest>>,
+    /// Non-fatal warnings of finished steps.
+    pub warnings: Warnings,
+    /// Last `/poll` let through, see `--min-poll-interval-ms`.
+    pub polled_at: Option<Instant>,
+    /// Stops the pipeline of the task while it runs, see `/delete`.
+    pub abort: Option<AbortHandle>,
+    /// Last lines printed by its subprocesses, see [`crate::output_tail`].
+    pub output: OutputTail,
+    /// Clients sharing the task besides the one which initiated it, see `--dedup-urls`. Each
+    /// observes the result before the task is removed, see [`ServerState::observe_finished`].
+    pub sharers: usize,
+    /// Failure of the last compression by `/download`, reported once by the next one.
+    pub archive_err: Option<AppError>,
+    /// Counts the task against `--max-active-tasks` until it is removed, see [`crate::slots`].
+    pub slot: Option<Arc<Slot>>,
+}
+
+/// Seconds spent in each stage of a finished task.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StageTimings {
+    pub download_secs: f64,
+    pub model_secs: f64,
+}
+
+impl Task {
+    pub fn new(status: TaskStatus, now: Instant) -> Self {
+        let mut task = Self {
+            status: TaskStatus::Pending,
+            download_at: None,
+            queued_at: None,
+            pending_at: None,
+            done_at: None,
+            err_at: None,
+            duration_secs: None,
+            request: None,
+            warnings: Warnings::new(),
+            polled_at: None,
+            abort: None,
+            output: OutputTail::new(OUTPUT_TAIL_LINES),
+            sharers: 0,
+            archive_err: None,
+            slot: None,
+        };
+        task.transition(status, now);
+        task
+    }
+
+    /// Move to `status`, recording `now` as the entry time of the stage if it is a new one.
+    ///
+    /// Returns the previous status.
+    pub fn transition(&mut self, status: TaskStatus, now: Instant) -> TaskStatus {
+        let entered_at = match status {
+            TaskStatus::Download => Some(&mut self.download_at),
+            TaskStatus::Queued => Some(&mut self.queued_at),
+            TaskStatus::Pending => Some(&mut self.pending_at),
+            TaskStatus::Done => Some(&mut self.done_at),
+            TaskStatus::Err(_) => Some(&mut self.err_at),
+            TaskStatus::Translating | TaskStatus::Compressing => None,
+        };
+        if let Some(entered_at) = entered_at {
+            entered_at.get_or_insert(now);
+        }
+        mem::replace(&mut self.status, status)
+    }
+
+    /// Record a poll at `now`, unless the previous one is less than `min` ago, in which case
+    /// return how much longer to wait.
+    pub fn throttle_poll(&mut self, now: Instant, min: Duration) -> Option<Duration> {
+        if let Some(polled_at) = self.polled_at {
+            let elapsed = now.saturating_duration_since(polled_at);
+            if elapsed < min {
+                return Some(min - elapsed);
+            }
+        }
+        self.polled_at = Some(now);
+        None
+    }
+
+    /// Whether the task has been done or failed for longer than `keep`.
+    pub fn expired(&self, now: Instant, keep: Duration) -> bool {
+        let finished_at = match self.status {
+            TaskStatus::Done => self.done_at,
+            TaskStatus::Err(_) => self.err_at,
+            TaskStatus::Download
+            | TaskStatus::Queued
+            | TaskStatus::Pending
+            | TaskStatus::Translating
+            | TaskStatus::Compressing => None,
+        };
+        finished_at.is_some_and(|at| now.saturating_duration_since(at) >= keep)
+    }
+
+    /// Stage durations, `None` unless the task went through all stages.
+    pub fn timings(&self) -> Option<StageTimings> {
+        let (download_at, pending_at, done_at) =
+            (self.download_at?, self.pending_at?, self.done_at?);
+        // time in queue counts for neither stage
+        let downloaded_at = self.queued_at.unwrap_or(pending_at);
+        Some(StageTimings {
+            download_secs: downloaded_at
+                .saturating_duration_since(download_at)
+                .as_secs_f64(),
+            model_secs: done_at.saturating_duration_since(pending_at).as_secs_f64(),
+        })
+    }
+}
+
+/// Map from request (url along with its options) to uuid of the in-progress task requesting it.
+pub type UrlMap = HashMap<(String, ModelOptions), String>;
 
 #[derive(Clone)]
 pub struct ServerState {
-    pub task_status: Arc<RwLock<TaskMap>>,
+    /// Only accessed by methods of [`ServerState`], see there.
+    task_status: Arc<TaskTable>,
     pub work_dir: Arc<PathBuf>,
+    /// `None` unless `--output-dir` is set, see [`crate::output`].
+    pub output_dir: Option<Arc<PathBuf>>,
+    /// `None` unless `--dedup-urls` is set.
+    url_tasks: Option<Arc<RwLock<UrlMap>>>,
+    pub metrics: Arc<Metrics>,
+    pub config: Arc<Config>,
+    /// Settings swapped by `/admin/reload`, see [`crate::reload`].
+    pub runtime: Arc<std::sync::RwLock<RuntimeConfig>>,
+    /// `None` unless `--rate-limit` is set.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// `None` unless `--audit-log` is set.
+    pub audit: Option<Arc<AuditLog>>,
+    /// `None` unless `--admin-token` is set.
+    pub history: Option<Arc<History>>,
+    /// `None` unless `--admin-token` is set.
+    pub stats: Option<Arc<Stats>>,
+    /// `None` unless `--alert-webhook` is set.
+    pub alerter: Option<Arc<Alerter>>,
+    /// `None` unless `--admin-token` is set.
+    pub log_flusher: Option<Arc<LogFlusher>>,
+    /// `None` unless `--max-concurrent-models` is set.
+    pub model_queue: Option<Arc<ModelQueue>>,
+    /// `None` unless `--enable-sessions` is set.
+    pub sessions: Option<Arc<Sessions>>,
+    /// `None` unless `--idempotency-window` is set.
+    pub idempotency_keys: Option<Arc<IdempotencyKeys>>,
+    /// `None` unless `--dedup-audio` is set.
+    pub blobs: Option<Arc<BlobStore>>,
+    /// Resumable uploads in progress.
+    pub uploads: Arc<Uploads>,
+    /// Runs the download, model and compression steps of tasks.
+    pub executor: Arc<dyn TaskExecutor>,
+    /// Files of tasks by key, see [`crate::storage`].
+    pub storage: Arc<dyn Storage>,
+    /// Set once shutdown begins, after which new tasks are rejected while existing ones go on.
+    pub shutting_down: Arc<AtomicBool>,
+    /// Set by `/admin/pause` and cleared by `/admin/resume`, new tasks are rejected meanwhile.
+    pub paused: Arc<AtomicBool>,
+    /// Whether the last `--model-warmup` or model run succeeded, see [`crate::warmup`].
+    pub model_ready: Arc<AtomicBool>,
+    /// Held by task entries, see `--max-active-tasks`.
+    pub task_slots: Slots,
+    /// Held by pipelines until they run a model, see `--queue-capacity`.
+    pub queue_slots: Slots,
+}
+
+/// Settings fixed at startup, mostly from command line flags.
+pub struct Config {
+    /// Values accepted for [`InitiateReq::language`], see `--allowed-languages`.
+    pub allowed_languages: Vec<String>,
+    /// Values accepted for [`InitiateReq::model`], see `--allowed-models`.
+    pub allowed_models: Vec<String>,
+    /// Identify clients by `X-Forwarded-For` instead of socket peer, see `--trust-proxy`.
+    pub trust_proxy: bool,
+    /// Reverse proxies in front of the server with `--trust-proxy`, see
+    /// `--trusted-proxy-hops` and [`crate::client_ip`].
+    pub trusted_proxy_hops: usize,
+    /// Reject new tasks when free space of `work_dir` is below it, see `--min-free-bytes`.
+    pub min_free_bytes: Option<u64>,
+    /// Warn when free space of `work_dir` is below it, see `--warn-free-bytes`.
+    pub warn_free_bytes: Option<u64>,
+    /// File name of downloaded archive, see `--download-name-template`.
+    pub download_name_template: String,
+    /// Extra headers of `/download` and `/audio` files, see `--download-header`.
+    pub download_headers: Vec<(HeaderName, HeaderValue)>,
+    /// Summary file written by the model script, see `--summary-filename`.
+    pub summary_filename: String,
+    /// Pattern of summary files, the newest match of which `/poll` returns, see `--summary-glob`.
+    pub summary_glob: Option<String>,
+    /// Audio file downloaded for the model script, see `--audio-filename`.
+    pub audio_filename: String,
+    /// Archive file generated by `/download`, see `--archive-filename`.
+    pub archive_filename: String,
+    /// Uploads larger than it are rejected, see `--max-upload-bytes`.
+    pub max_upload_bytes: u64,
+    /// Archives larger than it are not inlined by `/download`, see `--max-inline-bytes`.
+    pub max_inline_bytes: u64,
+    /// `/admin/export` and partial `/download` zips leave out files past it, see
+    /// `--export-max-bytes`.
+    pub export_max_bytes: u64,
+    /// Glob patterns of files put into the archive, all if empty, see `--archive-include`.
+    pub archive_include: Vec<String>,
+    /// Delete the audio once the model run succeeds, see `--delete-audio-after-model`.
+    pub delete_audio_after_model: bool,
+    /// Spare audio put into the archive from the above, see `--keep-archived-audio`.
+    pub keep_archived_audio: bool,
+    /// Persist requests so that tasks interrupted by restart are resumed, see `--recover-tasks`.
+    pub recover_tasks: bool,
+    /// Log each task into its own dir as well, see `--per-task-logs`.
+    pub per_task_logs: bool,
+    /// Reject videos longer than it, see `--max-duration-secs`.
+    pub max_duration_secs: Option<u64>,
+    /// Fail tasks whose downloaded audio is larger than it, see `--max-audio-bytes`.
+    pub max_audio_bytes: Option<u64>,
+    /// How long finished tasks remain pollable, see `--keep-completed-secs`.
+    ///
+    /// `None` removes a task as soon as its result is polled.
+    pub keep_completed: Option<Duration>,
+    /// Return the tail of the summary being written in `/poll` during `Pending`, see
+    /// `--stream-partial`.
+    pub stream_partial: bool,
+    /// Which failed tasks keep their task dir, see `--keep-failed-dirs`.
+    pub keep_failed_dirs: KeepFailedDirs,
+    /// Length of `preview` in `/poll`, see `--preview-chars`.
+    pub preview_chars: usize,
+    /// Tasks bound for a model beyond which new ones are rejected, see `--queue-capacity`.
+    pub queue_capacity: Option<usize>,
+    /// Strip the `result` of `/poll`, see `--normalize-summary`.
+    pub normalize_summary: bool,
+    /// Results shorter than it fail the task, see `--min-summary-bytes`.
+    pub min_summary_bytes: u64,
+    /// Base of `retry_after_ms` hints in `/poll`, which are absent if `None`, see
+    /// `--poll-interval-base`.
+    pub poll_interval_base: Option<Duration>,
+    /// Reject polls of a task closer together than it, see `--min-poll-interval-ms`.
+    pub min_poll_interval: Option<Duration>,
+    /// File reloaded by `/admin/reload`, see `--runtime-config`.
+    pub runtime_config: Option<PathBuf>,
+    /// Abandon model runs making no progress for this long, see `--stall-timeout`.
+    pub stall_timeout: Option<Duration>,
+    /// Model run time assumed by `/estimate` before any is observed, see
+    /// `--default-model-duration`.
+    pub default_model_duration: Duration,
+    /// Drop resumable uploads without a chunk for this long, see `--upload-idle-timeout`.
+    pub upload_idle_timeout: Duration,
+    /// Model scripts selectable by [`InitiateReq::backend`], see `--backend`.
+    pub backends: Backends,
+    /// Script translating the result, see `--translate-script`.
+    pub translate_script: String,
+    /// Values accepted for [`InitiateReq::output_language`], see `--output-languages`.
+    pub output_languages: Vec<String>,
+    /// Video run through the pipeline by `/admin/selftest`, see `--selftest-url`.
+    pub selftest_url: String,
+    /// Limit of a whole `/admin/selftest` run, see `--selftest-timeout`.
+    pub selftest_timeout: Duration,
+    /// Shared secret requests are signed with, unsigned requests are accepted if absent, see
+    /// `--api-secret`.
+    pub api_secret: Option<String>,
+    /// Key requests must carry in `X-Api-Key`, see `--api-key`.
+    pub api_key: Option<String>,
+    /// Script loading the model between tasks, see `--model-warmup`.
+    pub model_warmup: Option<String>,
+    /// How often the model is warmed up when idle, see `--model-warmup-interval`.
+    pub model_warmup_interval: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            allowed_languages: Vec::new(),
+            allowed_models: Vec::new(),
+            trust_proxy: false,
+            trusted_proxy_hops: 1,
+            min_free_bytes: None,
+            warn_free_bytes: None,
+            download_name_template: DEFAULT_DOWNLOAD_NAME.to_string(),
+            download_headers: Vec::new(),
+            summary_filename: DEFAULT_SUMMARY_FILENAME.to_string(),
+            summary_glob: None,
+            audio_filename: DEFAULT_AUDIO_FILENAME.to_string(),
+            archive_filename: DEFAULT_ARCHIVE_FILENAME.to_string(),
+            archive_include: Vec::new(),
+            delete_audio_after_model: false,
+            keep_archived_audio: false,
+            max_upload_bytes: DEFAULT_MAX_UPLOAD_BYTES,
+            max_inline_bytes: DEFAULT_MAX_INLINE_BYTES,
+            export_max_bytes: DEFAULT_EXPORT_MAX_BYTES,
+            recover_tasks: false,
+            per_task_logs: false,
+            max_duration_secs: None,
+            max_audio_bytes: None,
+            keep_completed: None,
+            stream_partial: false,
+            keep_failed_dirs: KeepFailedDirs::default(),
+            preview_chars: DEFAULT_PREVIEW_CHARS,
+            queue_capacity: None,
+            normalize_summary: true,
+            min_summary_bytes: DEFAULT_MIN_SUMMARY_BYTES,
+            poll_interval_base: None,
+            min_poll_interval: None,
+            runtime_config: None,
+            stall_timeout: None,
+            default_model_duration: DEFAULT_MODEL_DURATION,
+            upload_idle_timeout: DEFAULT_UPLOAD_IDLE_TIMEOUT,
+            backends: Backends::default(),
+            translate_script: DEFAULT_TRANSLATE_SCRIPT.to_string(),
+            output_languages: Vec::new(),
+            selftest_url: DEFAULT_SELFTEST_URL.to_string(),
+            selftest_timeout: DEFAULT_SELFTEST_TIMEOUT,
+            api_secret: None,
+            api_key: None,
+            model_warmup: None,
+            model_warmup_interval: DEFAULT_MODEL_WARMUP_INTERVAL,
+        }
+    }
+}
+
+pub const DEFAULT_DOWNLOAD_NAME: &str = "summary-{uuid8}.zip";
+pub const DEFAULT_SUMMARY_FILENAME: &str = "summary.txt";
+pub const DEFAULT_AUDIO_FILENAME: &str = "audio.mp3";
+pub const DEFAULT_ARCHIVE_FILENAME: &str = "archive.zip";
+pub const DEFAULT_MAX_UPLOAD_BYTES: u64 = 500 * 1024 * 1024;
+pub const DEFAULT_MAX_INLINE_BYTES: u64 = 8 * 1024 * 1024;
+pub const DEFAULT_EXPORT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+pub const DEFAULT_UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+pub const DEFAULT_MODEL_DURATION: Duration = Duration::from_secs(10 * 60);
+pub const DEFAULT_TRANSLATE_SCRIPT: &str = "translate.sh";
+pub const DEFAULT_PREVIEW_CHARS: usize = 200;
+pub const DEFAULT_MIN_SUMMARY_BYTES: u64 = 1;
+/// "Me at the zoo", 19 seconds long.
+pub const DEFAULT_SELFTEST_URL: &str = "https://www.youtube.com/watch?v=jNQXAC9IVRw";
+pub const DEFAULT_SELFTEST_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+pub const DEFAULT_MODEL_WARMUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Written by the model script along with summary.
+pub const TRANSCRIPT_FILENAME: &str = "raw_sub.txt";
+/// Transcript split into [`TranscriptSegment`]s, written by models that keep timestamps.
+pub const TRANSCRIPT_SEGMENTS_FILENAME: &str = "transcript.json";
+/// Output of the download and model commands in task dir, for diagnosing failed tasks.
+pub const RUN_LOG_FILENAME: &str = "run.log";
+
+/// What a task was initiated with.
+#[derive(Serialize, Deserialize)]
+pub struct TaskRequest {
+    /// Empty for uploaded audio.
+    pub url: String,
+    pub options: ModelOptions,
+    /// File name of the uploaded audio in task dir, `None` if audio is downloaded from `url`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<String>,
+    /// Requested priority in the model queue, see [`crate::queue`].
+    #[serde(default)]
+    pub priority: u8,
+}
+
+impl TaskRequest {
+    /// Audio file in task dir that the model runs on.
+    pub fn audio_filename<'a>(&'a self, config: &'a Config) -> &'a str {
+        self.upload.as_deref().unwrap_or(&config.audio_filename)
+    }
+}
+
+/// Per-task arguments passed to the model script.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ModelOptions {
+    pub language: Option<String>,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub mode: TaskMode,
+    /// Name of the model backend, see [`crate::backend`], the default one if absent.
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// Language the result is translated into after the model run, see
+    /// [`InitiateReq::output_language`].
+    #[serde(default)]
+    pub output_language: Option<String>,
+}
+
+/// Failed tasks whose task dir is kept, see `--keep-failed-dirs`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeepFailedDirs {
+    /// Every failed task.
+    All,
+    /// Tasks failing with server errors, whose files and run log help diagnosing, while client
+    /// errors, e.g. an unavailable video, leave nothing worth keeping.
+    #[default]
+    Server,
+    /// No failed task.
+    None,
+}
+
+impl KeepFailedDirs {
+    pub fn keeps(self, err: &AppError) -> bool {
+        match self {
+            Self::All => true,
+            Self::Server => !matches!(err, AppError::Client(_)),
+            Self::None => false,
+        }
+    }
+}
+
+/// What the model script produces, see [`InitiateReq::mode`].
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskMode {
+    /// Transcript and its summary.
+    #[default]
+    Summary,
+    /// Transcript only, skipping the summarization step.
+    Transcript,
+}
+
+impl TaskMode {
+    /// Names accepted by [`Self::parse`].
+    pub const NAMES: [&'static str; 2] = ["summary", "transcript"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "summary" => Some(Self::Summary),
+            "transcript" => Some(Self::Transcript),
+            _ => None,
+        }
+    }
+
+    /// File in user dir that `/poll` returns once the task is done.
+    pub fn result_filename(self, config: &Config) -> &str {
+        match self {
+            Self::Summary => &config.summary_filename,
+            Self::Transcript => TRANSCRIPT_FILENAME,
+        }
+    }
+
+    /// Result file translated into `language`, e.g. `summary.fr.txt` of `summary.txt`.
+    pub fn translated_filename(self, config: &Config, language: &str) -> String {
+        match self.result_filename(config).rsplit_once('.') {
+            Some((stem, extension)) => format!("{stem}.{language}.{extension}"),
+            None => format!("{}.{language}", self.result_filename(config)),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct InitiateReq {
     pub url: String,
+    /// uuid of an existing task for re-submission, empty or absent for a new task.
+    #[serde(default)]
     pub uuid: String,
+    /// Transcription language, script default if absent.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Transcription model size, script default if absent.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Only check the url, without spawning a task.
+    #[serde(default)]
+    pub validate_only: bool,
+    /// Either `summary` or `transcript`, the latter skipping summarization, `summary` if absent.
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// Name of a backend configured by `--backend`, `--default-backend` if absent.
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// Priority in the model queue from 0 to [`crate::queue::MAX_PRIORITY`], higher ones
+    /// granted a model first, 0 if absent.
+    #[serde(default)]
+    pub priority: Option<u8>,
+    /// Translate the result into this language, one of `--output-languages`, untranslated if
+    /// absent or the same as [`Self::language`].
+    #[serde(default)]
+    pub output_language: Option<String>,
 }
 
-#[derive(Serialize)]
+/// Query string of `/init/upload`, the audio being the request body.
+#[derive(Deserialize)]
+pub struct UploadReq {
+    /// Original file name, only consulted for its extension when `Content-Type` is not specific.
+    #[serde(default)]
+    pub filename: Option<String>,
+    /// Transcription language, script default if absent.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Transcription model size, script default if absent.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Same as [`InitiateReq::mode`].
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// Same as [`InitiateReq::backend`].
+    #[serde(default)]
+    pub backend: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct InitiateResp {
     pub uuid: String,
+    /// The uuid is of an existing task rather than a freshly spawned one, e.g. a re-submission.
+    #[serde(default)]
+    pub deduplicated: bool,
+}
+
+/// Query string of `PUT /upload/:id`.
+#[derive(Deserialize)]
+pub struct UploadChunkReq {
+    /// Where the chunk begins, which must be the number of bytes received so far.
+    pub offset: u64,
+}
+
+/// State of a resumable upload.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct UploadResp {
+    pub upload_id: String,
+    /// Bytes received so far, where the next chunk begins.
+    pub offset: u64,
+}
+
+#[derive(Deserialize)]
+pub struct MetadataReq {
+    pub url: String,
+}
+
+/// Preview of a video, see [`crate::video::VideoMetadata`].
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MetadataResp {
+    pub title: String,
+    pub uploader: Option<String>,
+    /// Absent for live streams.
+    pub duration_secs: Option<u64>,
+    pub thumbnail_url: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ValidateResp {
+    pub valid: bool,
+    pub title: String,
+    /// Absent for live streams.
+    pub duration_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct SessionTasksReq {
+    /// Same as the `X-Session-Id` header of `/init`.
+    pub session_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SessionTasksResp {
+    /// Tasks of the session still in the task table, earliest first.
+    pub uuids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct BatchEventsReq {
+    /// Tasks to watch, at most [`MAX_BATCH_UUIDS`][`crate::events::MAX_BATCH_UUIDS`].
+    pub uuids: Vec<String>,
+}
+
+/// Data of a `status` event of `/events/batch`, see [`crate::events`].
+#[derive(Serialize)]
+pub struct TaskEvent {
+    pub uuid: String,
+    /// Absent if the task is not in the task table.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage: Option<TaskStatus>,
+    /// Present if the task failed or is not in the task table.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub err: Option<AppError>,
+}
+
+/// Load of the model stage, see [`crate::controller::estimate`].
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct EstimateResp {
+    /// Tasks waiting for a model.
+    pub queued: usize,
+    /// Tasks running a model.
+    pub running: usize,
+    /// How long a task submitted now waits for a model, after its download.
+    pub est_wait_secs: u64,
+    /// Tasks waiting for a model or downloading on their way to one, new tasks being rejected
+    /// once it reaches `queue_capacity`.
+    pub queue_depth: usize,
+    /// Present with `--queue-capacity`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_capacity: Option<usize>,
+}
+
+/// Whether new tasks are accepted, see `/health`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct HealthResp {
+    /// One of `ok`, `paused` and `shutting_down`.
+    pub status: String,
+    pub accepting_tasks: bool,
+    /// Whether the model is loaded, `null` unless `--model-warmup` is set.
+    pub model_ready: Option<bool>,
+}
+
+/// What clients may choose from, see `/capabilities`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CapabilitiesResp {
+    /// Values of [`InitiateReq::backend`], along with the one used if absent.
+    pub backends: Vec<String>,
+    pub default_backend: String,
+    /// Values of [`InitiateReq::language`].
+    pub languages: Vec<String>,
+    /// Values of [`InitiateReq::model`].
+    pub models: Vec<String>,
+    /// Values of [`InitiateReq::mode`].
+    pub modes: Vec<String>,
+    /// Values of [`InitiateReq::output_language`], empty if results are not translated.
+    pub output_languages: Vec<String>,
+    /// Values of [`PollStatusReq::format`].
+    pub formats: Vec<String>,
+    /// Longest video accepted, unlimited if absent.
+    pub max_duration_secs: Option<u64>,
+    /// Largest file accepted by `/init/upload`.
+    pub max_upload_bytes: u64,
+    /// Largest audio downloaded for a task, unlimited if absent.
+    pub max_audio_bytes: Option<u64>,
+}
+
+/// Outcome of `/admin/selftest`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SelftestResp {
+    /// Every step succeeded within `--selftest-timeout`.
+    pub passed: bool,
+    /// Steps run, in order, up to the failed one.
+    pub steps: Vec<SelftestStep>,
+    /// Why the failed step failed, as [`AppError`] displays.
+    pub error: Option<String>,
+    /// Tail of the output of the download and model commands.
+    pub output: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SelftestStep {
+    /// One of `download`, `model` and `compress`.
+    pub name: String,
+    pub passed: bool,
+    pub secs: f64,
+}
+
+/// Live output of a task, see [`crate::output_tail`].
+#[derive(Serialize)]
+pub struct OutputResp {
+    pub stage: TaskStatus,
+    /// Last lines printed by its subprocesses, oldest first.
+    pub lines: Vec<String>,
+}
+
+/// Whether new tasks are paused after `/admin/pause` or `/admin/resume`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PauseResp {
+    pub paused: bool,
+}
+
+/// Build metadata, `git_sha` is empty when not built from a git checkout.
+#[derive(Serialize)]
+pub struct VersionResp {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    /// RFC 3339, UTC.
+    pub build_time: String,
+    pub rustc: &'static str,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteReq {
+    pub uuid: String,
+}
+
+/// What existed of a task erased by `/delete`.
+#[derive(Serialize)]
+pub struct DeleteResp {
+    /// The task was in the task table, its pipeline aborted if running.
+    pub task_removed: bool,
+    /// Task dir existed.
+    pub files_removed: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RetryReq {
+    pub uuid: String,
+}
+
+#[derive(Serialize)]
+pub struct RetryResp {
+    /// Stage the task restarts from.
+    pub stage: TaskStatus,
 }
 
 #[derive(Deserialize)]
 pub struct PollStatusReq {
     pub uuid: String,
+    /// One of `txt`, `md` and `json`, `txt` if absent.
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Omit `result` and `segments` of a done task, for listing many with only their `preview`.
+    #[serde(default)]
+    pub preview_only: bool,
+}
+
+/// Format of the summary that `/poll` returns, see [`PollStatusReq::format`].
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryFormat {
+    Txt,
+    Md,
+    Json,
+    /// Transcript with timestamps as [`TranscriptSegment`]s, in place of the summary.
+    #[serde(rename = "json-segments")]
+    JsonSegments,
+}
+
+impl SummaryFormat {
+    /// Names accepted by [`Self::parse`].
+    pub const NAMES: [&'static str; 4] = ["txt", "md", "json", "json-segments"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "txt" => Some(Self::Txt),
+            "md" => Some(Self::Md),
+            "json" => Some(Self::Json),
+            "json-segments" => Some(Self::JsonSegments),
+            _ => None,
+        }
+    }
+
+    /// Name of the result file in user dir, `--summary-filename` with extension replaced unless
+    /// `txt`, or [`TRANSCRIPT_SEGMENTS_FILENAME`] for segments.
+    pub fn file_name(self, config: &Config) -> String {
+        let extension = match self {
+            Self::Txt => return config.summary_filename.clone(),
+            Self::Md => "md",
+            Self::Json => "json",
+            Self::JsonSegments => return TRANSCRIPT_SEGMENTS_FILENAME.to_string(),
+        };
+        Path::new(&config.summary_filename)
+            .with_extension(extension)
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+/// A span of the transcript with its time in the audio, the model writing an array of them to
+/// [`TRANSCRIPT_SEGMENTS_FILENAME`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TranscriptSegment {
+    #[serde(alias = "start")]
+    pub start_secs: f64,
+    #[serde(alias = "end")]
+    pub end_secs: f64,
+    pub text: String,
+}
+
+impl TranscriptSegment {
+    /// Segments in `json`, `Err` describing the first one out of place.
+    ///
+    /// Segments must not end before they start, nor start before the previous one.
+    pub fn parse_all(json: &str) -> Result<Vec<Self>, String> {
+        let segments: Vec<Self> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let mut previous_start = 0.0;
+        for (i, segment) in segments.iter().enumerate() {
+            if segment.start_secs < previous_start {
+                return Err(format!(
+                    "segment {i} starts at {} before the previous one",
+                    segment.start_secs
+                ));
+            }
+            if segment.end_secs < segment.start_secs {
+                return Err(format!(
+                    "segment {i} ends at {} before it starts",
+                    segment.end_secs
+                ));
+            }
+            previous_start = segment.start_secs;
+        }
+        Ok(segments)
+    }
 }
 
 #[derive(Serialize)]
@@ -43,11 +842,139 @@ pub struct PollStatusResp {
     pub done: bool,
     pub stage: TaskStatus,
     pub result: Option<String>,
+    /// Format of `result`, `txt` if the requested one was not generated, present once the task
+    /// is done.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<SummaryFormat>,
+    /// Present once the task is done.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<StageTimings>,
+    /// Quality caveats reported by the downloader or the model, present once the task is done.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warnings: Option<Warnings>,
+    /// Transcript with timestamps, present once the task is done if requested by
+    /// `json-segments` format and written by the model, `result` then holding their text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segments: Option<Vec<TranscriptSegment>>,
+    /// Beginning of `result` cut at a word within `--preview-chars`, present once the task is
+    /// done.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+    /// 1-based position among tasks waiting for a model, present while queued.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
+    /// Effective priority in the model queue, raised the longer the task waits, present while
+    /// queued.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u32>,
+    /// Tail of the summary written so far, present during `Pending` with `--stream-partial`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partial_result: Option<String>,
+    /// Suggested wait before the next poll, present while the task is in progress with
+    /// `--poll-interval-base`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+}
+
+impl PollStatusResp {
+    /// Response of a task still at `stage`, without anything present only once done.
+    pub fn in_progress(stage: TaskStatus, retry_after_ms: Option<u64>) -> Self {
+        Self {
+            done: false,
+            stage,
+            result: None,
+            format: None,
+            timings: None,
+            warnings: None,
+            segments: None,
+            preview: None,
+            queue_position: None,
+            priority: None,
+            partial_result: None,
+            retry_after_ms,
+        }
+    }
+}
+
+/// Lines logged before `/admin/flush-logs` which were not in the log file yet.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FlushLogsResp {
+    pub pending_lines: u64,
+}
+
+/// Hot-reloadable settings in effect after `/admin/reload`, except the admin token.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ReloadResp {
+    pub request_timeout_secs: Option<u64>,
+    pub rate_limit: Option<u32>,
+    pub max_active_tasks: Option<usize>,
+    pub max_concurrent_models: Option<usize>,
 }
 
 #[derive(Deserialize)]
 pub struct FetchArchiveReq {
     pub uuid: String,
+    /// One of `archive`, `summary` and `transcript`, `archive` if absent.
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Return the archive as base64 in JSON rather than as binary, see [`InlineArchiveResp`].
+    #[serde(default)]
+    pub inline: bool,
+    /// Return the files of an incomplete task so far, along with a [`PartialManifest`].
+    #[serde(default)]
+    pub partial: bool,
+}
+
+/// `manifest.json` of the partial archive of an incomplete task, see
+/// [`FetchArchiveReq::partial`].
+#[derive(Serialize)]
+pub struct PartialManifest {
+    pub uuid: String,
+    pub stage: TaskStatus,
+    /// Present if the task failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub err: Option<AppError>,
+    /// Files in the archive besides the manifest.
+    pub files: Vec<FileEntry>,
+    /// Files of a complete task, e.g. audio and summary, found so far.
+    pub present: Vec<String>,
+    /// Files of a complete task not written yet.
+    pub missing: Vec<String>,
+}
+
+/// File that `/download` returns, see [`FetchArchiveReq::file`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DownloadFile {
+    Archive,
+    Summary,
+    Transcript,
+}
+
+impl DownloadFile {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "archive" => Some(Self::Archive),
+            "summary" => Some(Self::Summary),
+            "transcript" => Some(Self::Transcript),
+            _ => None,
+        }
+    }
+
+    /// Name of the file in user dir.
+    pub fn file_name(self, config: &Config) -> &str {
+        match self {
+            Self::Archive => &config.archive_filename,
+            Self::Summary => &config.summary_filename,
+            Self::Transcript => TRANSCRIPT_FILENAME,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Archive => "application/zip",
+            Self::Summary | Self::Transcript => "text/plain; charset=utf-8",
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -55,6 +982,41 @@ pub struct FetchArchiveResp {
     pub init: bool,
 }
 
+/// Archive returned by `/download` with `inline`.
+#[derive(Serialize, Deserialize)]
+pub struct InlineArchiveResp {
+    pub archive_base64: String,
+    pub filename: String,
+    /// Bytes of the archive before encoding.
+    pub size: u64,
+}
+
+#[derive(Deserialize)]
+pub struct FetchAudioReq {
+    pub uuid: String,
+}
+
+#[derive(Deserialize)]
+pub struct ListFilesReq {
+    pub uuid: String,
+}
+
+#[derive(Serialize)]
+pub struct ListFilesResp {
+    /// Sorted by path.
+    pub files: Vec<FileEntry>,
+}
+
+/// A file in the task dir.
+#[derive(Serialize)]
+pub struct FileEntry {
+    /// `/` separated, relative to the task dir.
+    pub path: String,
+    pub size: u64,
+    /// RFC 3339, UTC.
+    pub modified: String,
+}
+
 /// The enum every API controller returns
 ///
 /// A response can be  
@@ -64,19 +1026,21 @@ pub struct FetchArchiveResp {
 /// i.e. this is not possible  
 /// ` { success: bool, data: {...}, err: {...} } `  
 /// ### Examples
-/// ```rust
-/// let data = InitiateResp { uuid: "123".into() };
+/// ```rust,ignore
+/// let data = InitiateResp { uuid: "123".into(), deduplicated: false };
 /// let resp = AppResp::Success(data);
 /// let serialized = serde_json::to_string(&resp).unwrap();
-/// let expected = r#"{"success":true,"data":{"uuid":"123"}}"#;
+/// let expected = r#"{"success":true,"data":{"uuid":"123","deduplicated":false}}"#;
 /// assert_eq!(serialized, expected);
 ///
 /// let err = AppError::Server(BindPort(80));
 /// let serialized = serde_json::to_string(&err).unwrap();
 /// let expected =
-///     r#"{"success":"false","err":{"source":"server","info":"Listen to port 80 failed."}}"#;
+///     r#"{"success":false,"err":{"source":"server","info":"Listen to port 80 failed.","code":"bind_port","recoverable":false}}"#;
 /// assert_eq!(serialized, expected);
 /// ```  
+/// With [`ApiVersion::V2`], `err` of an exception is the error object itself, see
+/// [`crate::api_version`].  
 /// See [`Self::serialize()`]
 pub enum AppResp<T>
 where
@@ -100,43 +1064,364 @@ where
                 struct_s.serialize_field("success", &true)?;
                 struct_s.serialize_field("data", data)?;
             }
+            Self::Exception(err) if api_version::current() == ApiVersion::V2 => {
+                struct_s.serialize_field("success", &false)?;
+                let request_id = request_id::current();
+                match err {
+                    AppError::Client(err) => {
+                        struct_s.serialize_field("err", &FlatErr { err, request_id })?
+                    }
+                    AppError::Server(err) => {
+                        struct_s.serialize_field("err", &FlatErr { err, request_id })?
+                    }
+                    AppError::Transient(err) => {
+                        struct_s.serialize_field("err", &FlatErr { err, request_id })?
+                    }
+                }
+            }
             Self::Exception(err) => {
                 struct_s.serialize_field("success", &false)?;
                 struct_s.serialize_field("err", err)?;
+                if let Some(id) = request_id::current() {
+                    struct_s.serialize_field("request_id", &id)?;
+                }
             }
         }
         struct_s.end()
     }
 }
 
+/// Error object of [`ApiVersion::V2`], along with the id of the failed request.
+#[derive(Serialize)]
+struct FlatErr<'a, E: Serialize> {
+    #[serde(flatten)]
+    err: &'a E,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+/// Client side counterpart of [`AppResp`], for Rust consumers and tests to parse responses.
+///
+/// Since only `source` and `info` of an error reach the wire, an exception is parsed into
+/// [`RemoteError`][`crate::exception::RemoteError`] rather than [`AppError`].
+/// ### Examples
+/// ```rust,ignore
+/// let json = r#"{"success":true,"data":{"uuid":"123"}}"#;
+/// let resp: AppRespOwned<InitiateResp> = serde_json::from_str(json).unwrap();
+/// assert_eq!(resp, AppRespOwned::Success(InitiateResp { uuid: "123".into(), deduplicated: false }));
+/// ```
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, PartialEq)]
+pub enum AppRespOwned<T> {
+    Success(T),
+    Exception(crate::exception::RemoteError),
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl<'de, T> Deserialize<'de> for AppRespOwned<T>
+where
+    T: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use crate::exception::RemoteError;
+
+        /// `success` is not inspected, presence of `data` or `err` decides the branch.
+        #[derive(Deserialize)]
+        struct Envelope<T> {
+            data: Option<T>,
+            err: Option<ErrBody>,
+            #[serde(default)]
+            request_id: Option<String>,
+        }
+
+        /// [`AppResp::Exception`] serializes [`AppError`] as a whole, which wraps the error
+        /// object in another `{ success, err }`.
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum ErrBody {
+            Flat(RemoteError),
+            Wrapped { err: RemoteError },
+        }
+
+        let envelope = Envelope::<T>::deserialize(deserializer)?;
+        match (envelope.data, envelope.err) {
+            (Some(data), None) => Ok(Self::Success(data)),
+            (None, Some(ErrBody::Flat(err) | ErrBody::Wrapped { err })) => {
+                Ok(Self::Exception(RemoteError {
+                    // inside `err` in version 2
+                    request_id: envelope.request_id.or(err.request_id),
+                    ..err
+                }))
+            }
+            _ => Err(serde::de::Error::custom(
+                "expect exactly one of `data` and `err`",
+            )),
+        }
+    }
+}
+
 impl Serialize for TaskStatus {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl TaskStatus {
+    /// Name of the stage, as serialized.
+    pub fn name(&self) -> &'static str {
         match self {
-            TaskStatus::Done => serializer.serialize_str("Done"),
-            TaskStatus::Err(_) => serializer.serialize_str("Err"),
-            TaskStatus::Download => serializer.serialize_str("Download"),
-            TaskStatus::Pending => serializer.serialize_str("Pending"),
+            TaskStatus::Done => "Done",
+            TaskStatus::Err(_) => "Err",
+            TaskStatus::Download => "Download",
+            TaskStatus::Queued => "Queued",
+            TaskStatus::Pending => "Pending",
+            TaskStatus::Translating => "Translating",
+            TaskStatus::Compressing => "Compressing",
         }
     }
 }
 
+/// Builder of [`ServerState`], where `work_dir` and `executor` are required, and every other
+/// component is absent unless set.
+#[derive(Default)]
+pub struct ServerStateBuilder {
+    work_dir: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    executor: Option<Arc<dyn TaskExecutor>>,
+    storage: Option<Arc<dyn Storage>>,
+    config: Config,
+    runtime: RuntimeConfig,
+    dedup_urls: bool,
+    dedup_audio: bool,
+    rate_limiter: Option<RateLimiter>,
+    audit: Option<AuditLog>,
+    history: Option<History>,
+    stats: Option<Stats>,
+    alerter: Option<Alerter>,
+    log_flusher: Option<LogFlusher>,
+    model_queue: Option<ModelQueue>,
+    sessions: Option<Sessions>,
+    idempotency_keys: Option<IdempotencyKeys>,
+}
+
+impl ServerStateBuilder {
+    pub fn work_dir(mut self, work_dir: impl Into<PathBuf>) -> Self {
+        self.work_dir = Some(work_dir.into());
+        self
+    }
+
+    /// Move results of succeeded tasks there, see `--output-dir`.
+    pub fn output_dir(mut self, output_dir: Option<PathBuf>) -> Self {
+        self.output_dir = output_dir;
+        self
+    }
+
+    pub fn executor(mut self, executor: impl TaskExecutor + 'static) -> Self {
+        self.executor = Some(Arc::new(executor));
+        self
+    }
+
+    /// [`LocalStorage`] over `work_dir` if not set.
+    pub fn storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.storage = Some(Arc::new(storage));
+        self
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn runtime(mut self, runtime: RuntimeConfig) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Track in-progress urls for sharing, see `--dedup-urls`.
+    pub fn dedup_urls(mut self, dedup_urls: bool) -> Self {
+        self.dedup_urls = dedup_urls;
+        self
+    }
+
+    /// Store audio content-addressed, see `--dedup-audio`.
+    pub fn dedup_audio(mut self, dedup_audio: bool) -> Self {
+        self.dedup_audio = dedup_audio;
+        self
+    }
+
+    pub fn rate_limiter(mut self, rate_limiter: Option<RateLimiter>) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    pub fn audit(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn history(mut self, history: Option<History>) -> Self {
+        self.history = history;
+        self
+    }
+
+    pub fn stats(mut self, stats: Option<Stats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn alerter(mut self, alerter: Option<Alerter>) -> Self {
+        self.alerter = alerter;
+        self
+    }
+
+    pub fn log_flusher(mut self, log_flusher: Option<LogFlusher>) -> Self {
+        self.log_flusher = log_flusher;
+        self
+    }
+
+    pub fn model_queue(mut self, model_queue: Option<ModelQueue>) -> Self {
+        self.model_queue = model_queue;
+        self
+    }
+
+    pub fn sessions(mut self, sessions: Option<Sessions>) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    pub fn idempotency_keys(mut self, idempotency_keys: Option<IdempotencyKeys>) -> Self {
+        self.idempotency_keys = idempotency_keys;
+        self
+    }
+
+    /// Fails with [`ServerError::Internal`] if a required field is not set.
+    pub fn build(self) -> Result<ServerState, ServerError> {
+        let missing = |field: &str| ServerError::Internal(format!("{field} of state is not set"));
+        let work_dir = self.work_dir.ok_or_else(|| missing("work_dir"))?;
+        Ok(ServerState {
+            task_status: Arc::default(),
+            uploads: Arc::new(Uploads::new(work_dir.join(UPLOAD_DIR))),
+            blobs: self
+                .dedup_audio
+                .then(|| Arc::new(BlobStore::new(work_dir.join(BLOB_DIR)))),
+            storage: self
+                .storage
+                .unwrap_or_else(|| Arc::new(LocalStorage::new(&work_dir))),
+            work_dir: Arc::new(work_dir),
+            output_dir: self.output_dir.map(Arc::new),
+            url_tasks: self.dedup_urls.then(Arc::default),
+            metrics: Arc::default(),
+            config: Arc::new(self.config),
+            runtime: Arc::new(std::sync::RwLock::new(self.runtime)),
+            rate_limiter: self.rate_limiter.map(Arc::new),
+            audit: self.audit.map(Arc::new),
+            history: self.history.map(Arc::new),
+            stats: self.stats.map(Arc::new),
+            alerter: self.alerter.map(Arc::new),
+            log_flusher: self.log_flusher.map(Arc::new),
+            model_queue: self.model_queue.map(Arc::new),
+            sessions: self.sessions.map(Arc::new),
+            idempotency_keys: self.idempotency_keys.map(Arc::new),
+            executor: self.executor.ok_or_else(|| missing("executor"))?,
+            shutting_down: Arc::default(),
+            paused: Arc::default(),
+            model_ready: Arc::default(),
+            task_slots: Slots::default(),
+            queue_slots: Slots::default(),
+        })
+    }
+}
+
+#[allow(clippy::items_after_test_module)]
 #[cfg(test)]
 mod test {
-    use super::AppResp;
+    use std::time::{Duration, Instant};
+
+    use super::{
+        AppResp, AppRespOwned, Config, ModelOptions, ServerState, StageTimings, Task, TaskMode,
+        TaskStatus, TranscriptSegment,
+    };
     use crate::{
-        exception::{AppError, ServerError::*},
+        exception::{AppError, ClientError, RemoteError, ServerError::*},
+        executor::MockExecutor,
         models::InitiateResp,
+        queue::ModelQueue,
     };
 
+    /// Every way of accessing a task, interleaved on the same few uuids, must finish.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_no_deadlock() {
+        let state = ServerState::for_test(std::env::temp_dir());
+        let handles: Vec<_> = (0..32)
+            .map(|i| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let uuid = format!("task-{}", i % 4);
+                    let options = ModelOptions::default();
+                    for _ in 0..50 {
+                        state.update_task(&uuid, TaskStatus::Download).await;
+                        state.modify_task(&uuid, |task| task.warnings.clear()).await;
+                        state.read_task(&uuid, |task| task.status.clone()).await;
+                        state.get_task(&uuid).await;
+                        state.get_task_entry(&uuid).await;
+                        state.claim_url(&uuid, &options, &uuid).await;
+                        state.task_count().await;
+                        state.purge_finished(Duration::ZERO).await;
+                        state.release_url(&uuid, &options, &uuid).await;
+                        state.has_task(&uuid).await;
+                        state.remove_task(&uuid).await;
+                    }
+                })
+            })
+            .collect();
+        let joined = tokio::time::timeout(Duration::from_secs(10), async {
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        });
+        assert!(joined.await.is_ok(), "task table deadlocked");
+    }
+
+    #[test]
+    fn test_state_builder() {
+        let missing = ServerState::builder().work_dir("/tmp").build();
+        assert!(matches!(missing, Err(Internal(field)) if field.contains("executor")));
+        let missing = ServerState::builder()
+            .executor(MockExecutor::default())
+            .build();
+        assert!(matches!(missing, Err(Internal(field)) if field.contains("work_dir")));
+
+        let state = ServerState::builder()
+            .work_dir("/tmp")
+            .executor(MockExecutor::default())
+            .config(Config {
+                stream_partial: true,
+                ..Config::default()
+            })
+            .dedup_urls(true)
+            .model_queue(Some(ModelQueue::new(2)))
+            .build()
+            .unwrap();
+        assert!(state.config.stream_partial);
+        assert!(state.url_tasks.is_some() && state.model_queue.is_some());
+        assert!(state.rate_limiter.is_none() && state.history.is_none());
+    }
+
     #[test]
     fn test_success() {
-        let data = InitiateResp { uuid: "123".into() };
+        let data = InitiateResp {
+            uuid: "123".into(),
+            deduplicated: false,
+        };
         let resp = AppResp::Success(data);
         let serialized = serde_json::to_string(&resp).unwrap();
-        let expected = r#"{"success":true,"data":{"uuid":"123"}}"#;
+        let expected = r#"{"success":true,"data":{"uuid":"123","deduplicated":false}}"#;
         assert_eq!(serialized, expected);
     }
 
@@ -144,30 +1429,362 @@ mod test {
     fn test_exception() {
         let err = AppError::Server(BindPort(80));
         let serialized = serde_json::to_string(&err).unwrap();
-        let expected =
-            r#"{"success":"false","err":{"source":"server","info":"Listen to port 80 failed."}}"#;
+        let expected = r#"{"success":false,"err":{"source":"server","info":"Listen to port 80 failed.","code":"bind_port","recoverable":false}}"#;
         assert_eq!(serialized, expected);
     }
+
+    #[test]
+    fn test_round_trip_success() {
+        let resp = AppResp::Success(InitiateResp {
+            uuid: "123".into(),
+            deduplicated: false,
+        });
+        let serialized = serde_json::to_string(&resp).unwrap();
+        let deserialized: AppRespOwned<InitiateResp> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(
+            deserialized,
+            AppRespOwned::Success(InitiateResp {
+                uuid: "123".into(),
+                deduplicated: false
+            })
+        );
+    }
+
+    #[test]
+    fn test_round_trip_exception() {
+        let errs = [
+            AppError::Server(BindPort(80)),
+            AppError::Server(AiModel("out of memory".into())),
+            AppError::Client(ClientError::TokenNotExist("123".into())),
+            AppError::Client(ClientError::VideoLinkNotExist("https://a.b.c".into())),
+        ];
+        for err in errs {
+            let expected = RemoteError::from(&err);
+            let resp = AppResp::<InitiateResp>::Exception(err);
+            let serialized = serde_json::to_string(&resp).unwrap();
+            let deserialized: AppRespOwned<InitiateResp> =
+                serde_json::from_str(&serialized).unwrap();
+            assert_eq!(deserialized, AppRespOwned::Exception(expected));
+        }
+    }
+
+    #[test]
+    fn test_deserialize_malformed() {
+        let both = r#"{"success":true,"data":{"uuid":"1"},"err":{"source":"client","info":""}}"#;
+        assert!(serde_json::from_str::<AppRespOwned<InitiateResp>>(both).is_err());
+        let neither = r#"{"success":true}"#;
+        assert!(serde_json::from_str::<AppRespOwned<InitiateResp>>(neither).is_err());
+    }
+
+    #[test]
+    fn test_stage_timings() {
+        let start = Instant::now();
+        let mut task = Task::new(TaskStatus::Download, start);
+        assert!(task.timings().is_none());
+        // re-entering a stage keeps its original entry time
+        task.transition(TaskStatus::Download, start + Duration::from_secs(1));
+        task.transition(TaskStatus::Pending, start + Duration::from_secs(30));
+        assert!(task.timings().is_none());
+        task.transition(TaskStatus::Done, start + Duration::from_millis(92_500));
+        assert_eq!(
+            task.timings(),
+            Some(StageTimings {
+                download_secs: 30.0,
+                model_secs: 62.5,
+            })
+        );
+
+        let mut task = Task::new(TaskStatus::Download, start);
+        task.transition(TaskStatus::Queued, start + Duration::from_secs(10));
+        task.transition(TaskStatus::Pending, start + Duration::from_secs(40));
+        task.transition(TaskStatus::Done, start + Duration::from_secs(60));
+        assert_eq!(
+            task.timings(),
+            Some(StageTimings {
+                download_secs: 10.0,
+                model_secs: 20.0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_expired() {
+        let start = Instant::now();
+        let keep = Duration::from_secs(60);
+        let mut task = Task::new(TaskStatus::Pending, start);
+        // in-progress tasks never expire
+        assert!(!task.expired(start + Duration::from_secs(3600), keep));
+        task.transition(TaskStatus::Done, start + Duration::from_secs(10));
+        assert!(!task.expired(start + Duration::from_secs(69), keep));
+        assert!(task.expired(start + Duration::from_secs(70), keep));
+
+        let err = AppError::from(ClientError::RateLimited);
+        let task = Task::new(TaskStatus::Err(err), start);
+        assert!(task.expired(start + keep, keep));
+    }
+
+    #[test]
+    fn test_translated_filename() {
+        let config = Config::default();
+        assert_eq!(
+            TaskMode::Summary.translated_filename(&config, "fr"),
+            "summary.fr.txt"
+        );
+        assert_eq!(
+            TaskMode::Transcript.translated_filename(&config, "zh"),
+            "raw_sub.zh.txt"
+        );
+        let config = Config {
+            summary_filename: "summary".into(),
+            ..Config::default()
+        };
+        assert_eq!(
+            TaskMode::Summary.translated_filename(&config, "fr"),
+            "summary.fr"
+        );
+    }
+
+    #[test]
+    fn test_transcript_segments() {
+        let json = r#"[
+            { "start_secs": 0, "end_secs": 2.5, "text": " Hello" },
+            { "start": 2.5, "end": 4, "text": " world", "words": [] }
+        ]"#;
+        let segments = TranscriptSegment::parse_all(json).unwrap();
+        assert_eq!(
+            segments,
+            [
+                TranscriptSegment {
+                    start_secs: 0.0,
+                    end_secs: 2.5,
+                    text: " Hello".into(),
+                },
+                TranscriptSegment {
+                    start_secs: 2.5,
+                    end_secs: 4.0,
+                    text: " world".into(),
+                },
+            ]
+        );
+        assert_eq!(TranscriptSegment::parse_all("[]").unwrap(), []);
+
+        for malformed in [
+            "Hello world",
+            r#"{ "start_secs": 0, "end_secs": 1, "text": "" }"#,
+            r#"[{ "start_secs": 0, "text": "" }]"#,
+            r#"[{ "start_secs": "0", "end_secs": 1, "text": "" }]"#,
+            r#"[{ "start_secs": -1, "end_secs": 1, "text": "" }]"#,
+            r#"[{ "start_secs": 2, "end_secs": 1, "text": "" }]"#,
+            r#"[{ "start_secs": 2, "end_secs": 3, "text": "" }, { "start_secs": 1, "end_secs": 3, "text": "" }]"#,
+        ] {
+            assert!(
+                TranscriptSegment::parse_all(malformed).is_err(),
+                "{malformed}"
+            );
+        }
+    }
 }
 
+/// Every method locks task table or url map only for its own duration, and closures passed in
+/// are synchronous, so no guard is ever held across an `.await` by a caller.
 impl ServerState {
+    pub fn builder() -> ServerStateBuilder {
+        ServerStateBuilder::default()
+    }
+
+    /// State of controller tests, running [`MockExecutor`][`crate::executor::MockExecutor`]
+    /// with a canned summary and warning in `work_dir`.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn for_test(work_dir: impl Into<PathBuf>) -> Self {
+        use crate::executor::MockExecutor;
+
+        Self::builder()
+            .work_dir(work_dir)
+            .executor(MockExecutor {
+                summary: "a summary".into(),
+                warnings: vec!["WARNING: audio is silent".into()],
+                ..MockExecutor::default()
+            })
+            .build()
+            .unwrap()
+    }
+
+    /// Move `uuid` to `status`.
     pub async fn update_task(&self, uuid: &str, status: TaskStatus) -> Option<TaskStatus> {
-        let mut guard = self.task_status.write().await;
-        guard.insert(uuid.to_string(), status)
+        self.transition_task(uuid, status, false).await
+    }
+
+    /// Move `uuid` to terminal `status` at the end of its pipeline, recording it in stats.
+    ///
+    /// Unlike [`Self::update_task`], only called once per run, e.g. not when `/download` moves a
+    /// done task through `Compressing`.
+    pub async fn finish_task(&self, uuid: &str, status: TaskStatus) -> Option<TaskStatus> {
+        self.transition_task(uuid, status, true).await
+    }
+
+    async fn transition_task(
+        &self,
+        uuid: &str,
+        status: TaskStatus,
+        record: bool,
+    ) -> Option<TaskStatus> {
+        // `DEBUG`, only meant for the task log, see `--per-task-logs`
+        tracing::debug!("\nTask {uuid} enters stage {}.", status.name());
+        let mut guard = self.task_status.write(uuid).await;
+        let now = Instant::now();
+        let previous = match guard.get_mut(uuid) {
+            Some(task) => Some(task.transition(status, now)),
+            None => {
+                guard.insert(uuid.to_string(), Task::new(status, now));
+                None
+            }
+        };
+        if let (true, Some(stats), Some(task)) = (record, self.stats.as_ref(), guard.get(uuid)) {
+            stats.record(task, now);
+        }
+        previous
     }
 
     pub async fn get_task(&self, uuid: &str) -> Option<TaskStatus> {
-        let guard = self.task_status.read().await;
+        let guard = self.task_status.read(uuid).await;
+        guard.get(uuid).map(|task| task.status.clone())
+    }
+
+    /// Copy of the whole entry of `uuid`, including timestamps.
+    pub async fn get_task_entry(&self, uuid: &str) -> Option<Task> {
+        let guard = self.task_status.read(uuid).await;
         guard.get(uuid).cloned()
     }
 
-    pub async fn remove_task(&self, uuid: &str) -> Option<TaskStatus> {
-        let mut guard = self.task_status.write().await;
+    pub async fn remove_task(&self, uuid: &str) -> Option<Task> {
+        let mut guard = self.task_status.write(uuid).await;
         guard.remove(uuid)
     }
 
+    /// Apply `f` to the task of `uuid` under read lock, if any, so that only what `f` returns is
+    /// copied.
+    pub async fn read_task<R>(&self, uuid: &str, f: impl FnOnce(&Task) -> R) -> Option<R> {
+        let guard = self.task_status.read(uuid).await;
+        guard.get(uuid).map(f)
+    }
+
+    /// Apply `f` to the task of `uuid`, if any.
+    pub async fn modify_task(&self, uuid: &str, f: impl FnOnce(&mut Task)) {
+        let mut guard = self.task_status.write(uuid).await;
+        if let Some(task) = guard.get_mut(uuid) {
+            f(task);
+        }
+    }
+
+    /// Reset a failed task to `status` with fresh stage timestamps, returning what it requested.
+    ///
+    /// `None` if the task is absent, not failed, or does not remember its request.
+    pub async fn restart_task(&self, uuid: &str, status: TaskStatus) -> Option<Arc<TaskRequest>> {
+        let mut guard = self.task_status.write(uuid).await;
+        let task = guard.get_mut(uuid)?;
+        if !matches!(task.status, TaskStatus::Err(_)) {
+            return None;
+        }
+        let request = task.request.clone()?;
+        *task = Task {
+            request: Some(Arc::clone(&request)),
+            warnings: mem::take(&mut task.warnings),
+            duration_secs: task.duration_secs,
+            slot: task.slot.take(),
+            ..Task::new(status, Instant::now())
+        };
+        Some(request)
+    }
+
+    /// Remove tasks finished for longer than `keep`, returns how many were removed.
+    pub async fn purge_finished(&self, keep: Duration) -> usize {
+        let now = Instant::now();
+        self.task_status
+            .retain(|task| !task.expired(now, keep))
+            .await
+    }
+
+    /// Number of entries in task table, whatever their stage.
+    pub async fn task_count(&self) -> usize {
+        self.task_status.len().await
+    }
+
+    /// Dir holding the results of `uuid`, in `--output-dir` once moved there on success, or
+    /// else the task dir in `work_dir`.
+    pub fn result_dir(&self, uuid: &str) -> PathBuf {
+        match &self.output_dir {
+            Some(output_dir) if output_dir.join(uuid).is_dir() => output_dir.join(uuid),
+            _ => self.work_dir.join(uuid),
+        }
+    }
+
     pub async fn has_task(&self, uuid: &str) -> bool {
-        let guard = self.task_status.read().await;
+        let guard = self.task_status.read(uuid).await;
         guard.contains_key(uuid)
     }
+
+    /// Associate `url` requested with `options` with `uuid`, unless an in-progress task already
+    /// claimed it.
+    ///
+    /// Returns the uuid of that existing task, which gains a sharer, always `None` if url
+    /// deduplication is off. A claim whose task is already gone, finished and observed before
+    /// releasing it, is taken over.
+    pub async fn claim_url(&self, url: &str, options: &ModelOptions, uuid: &str) -> Option<String> {
+        let url_tasks = self.url_tasks.as_ref()?;
+        let mut guard = url_tasks.write().await;
+        let key = (url.to_string(), options.clone());
+        if let Some(existing) = guard.get(&key).filter(|existing| *existing != uuid) {
+            let mut tasks = self.task_status.write(existing).await;
+            if let Some(task) = tasks.get_mut(existing) {
+                task.sharers += 1;
+                return Some(existing.clone());
+            }
+        }
+        guard.insert(key, uuid.to_string());
+        None
+    }
+
+    /// Claim `url` requested with `options` again for the retry of `uuid`, unless an in-progress
+    /// task claimed it meanwhile, which is left unshared as the retried task keeps its own uuid.
+    pub async fn reclaim_url(&self, url: &str, options: &ModelOptions, uuid: &str) {
+        let Some(url_tasks) = self.url_tasks.as_ref() else {
+            return;
+        };
+        let mut guard = url_tasks.write().await;
+        let key = (url.to_string(), options.clone());
+        if let Some(existing) = guard.get(&key).filter(|existing| *existing != uuid) {
+            if self.has_task(existing).await {
+                return;
+            }
+        }
+        guard.insert(key, uuid.to_string());
+    }
+
+    /// Evict the claim made by [`Self::claim_url`] once the task finishes.
+    pub async fn release_url(&self, url: &str, options: &ModelOptions, uuid: &str) {
+        let Some(url_tasks) = self.url_tasks.as_ref() else {
+            return;
+        };
+        let mut guard = url_tasks.write().await;
+        let key = (url.to_string(), options.clone());
+        if guard.get(&key).is_some_and(|owner| owner == uuid) {
+            guard.remove(&key);
+        }
+    }
+
+    /// Record that a client observed the result of finished `uuid`, removing the task once
+    /// observed by each client sharing it. Returns whether it is removed.
+    ///
+    /// Clients are not told apart, so one observing twice takes the turn of another.
+    pub async fn observe_finished(&self, uuid: &str) -> bool {
+        let mut guard = self.task_status.write(uuid).await;
+        match guard.get_mut(uuid) {
+            Some(task) if task.sharers > 0 => {
+                task.sharers -= 1;
+                false
+            }
+            Some(_) => guard.remove(uuid).is_some(),
+            None => false,
+        }
+    }
 }
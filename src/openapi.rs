@@ -0,0 +1,819 @@
+//! OpenAPI 3 description of the API, served at `/openapi.json`.
+//!
+//! Hand-written to mirror the `Serialize` and `Deserialize` shapes in [`crate::models`], tests
+//! below check it against actual serialized responses.
+use serde_json::{json, Value};
+
+use crate::{events::MAX_BATCH_UUIDS, queue::MAX_PRIORITY};
+
+/// Whole OpenAPI document.
+pub fn spec() -> Value {
+    let upload_id = json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "description": "`upload_id` returned by `/upload/init`.",
+        "schema": { "type": "string" },
+    });
+    let session_id = json!({
+        "name": "X-Session-Id",
+        "in": "header",
+        "required": false,
+        "description": "Adds the task to this session, with `--enable-sessions`.",
+        "schema": { "type": "string", "maxLength": 128 },
+    });
+    let idempotency_key = json!({
+        "name": "Idempotency-Key",
+        "in": "header",
+        "required": false,
+        "description": "Repeated within `--idempotency-window`, returns the uuid assigned the first time instead of a new task.",
+        "schema": { "type": "string", "maxLength": 255 },
+    });
+    let mut init = with_task_id(operation(
+        "Submit a task, or only validate the url with `validate_only`.",
+        Some("InitiateReq"),
+        json!({ "oneOf": [schema_ref("InitiateResp"), schema_ref("ValidateResp")] }),
+    ));
+    init["parameters"] = json!([session_id.clone(), idempotency_key]);
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "shen-server",
+            "description": env!("CARGO_PKG_DESCRIPTION"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/init": { "post": init },
+            "/init/upload": {
+                "post": with_task_id(json!({
+                    "summary": "Submit a task on uploaded audio, skipping download.",
+                    "parameters": [
+                        query_param("filename", "Consulted for extension when Content-Type is not specific."),
+                        query_param("language", "Transcription language."),
+                        query_param("model", "Transcription model size."),
+                        query_param("mode", "`summary` (default) or `transcript`, the latter skipping summarization."),
+                        query_param("backend", "Model backend configured by `--backend`, `--default-backend` if absent."),
+                        session_id,
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "audio/*": { "schema": { "type": "string", "format": "binary" } } },
+                    },
+                    "responses": { "200": json_response(envelope(schema_ref("InitiateResp"))) },
+                })),
+            },
+            "/upload/init": {
+                "post": {
+                    "summary": "Open a resumable upload, with the same parameters as `/init/upload`.",
+                    "parameters": [
+                        query_param("filename", "Consulted for extension when Content-Type is not specific."),
+                        query_param("language", "Transcription language."),
+                        query_param("model", "Transcription model size."),
+                        query_param("mode", "`summary` (default) or `transcript`, the latter skipping summarization."),
+                        query_param("backend", "Model backend configured by `--backend`, `--default-backend` if absent."),
+                    ],
+                    "responses": { "200": json_response(envelope(schema_ref("UploadResp"))) },
+                },
+            },
+            "/upload/{id}": {
+                "get": {
+                    "summary": "Offset where a resumable upload continues.",
+                    "parameters": [upload_id.clone()],
+                    "responses": { "200": json_response(envelope(schema_ref("UploadResp"))) },
+                },
+                "put": {
+                    "summary": "Append a chunk to a resumable upload.",
+                    "parameters": [
+                        upload_id.clone(),
+                        {
+                            "name": "offset",
+                            "in": "query",
+                            "required": true,
+                            "description": "Bytes received so far, `upload_offset_mismatch` otherwise.",
+                            "schema": { "type": "integer", "minimum": 0 },
+                        },
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } },
+                    },
+                    "responses": { "200": json_response(envelope(schema_ref("UploadResp"))) },
+                },
+            },
+            "/upload/finish/{id}": {
+                "post": with_task_id(json!({
+                    "summary": "Turn a resumable upload into a task.",
+                    "parameters": [upload_id],
+                    "responses": { "200": json_response(envelope(schema_ref("InitiateResp"))) },
+                })),
+            },
+            "/metadata": {
+                "post": operation(
+                    "Preview a video without processing it.",
+                    Some("MetadataReq"),
+                    schema_ref("MetadataResp"),
+                ),
+            },
+            "/retry": {
+                "post": operation("Re-run a failed task.", Some("RetryReq"), schema_ref("RetryResp")),
+            },
+            "/poll": {
+                "post": operation(
+                    "Query stage of a task, along with summary once done.",
+                    Some("PollStatusReq"),
+                    schema_ref("PollStatusResp"),
+                ),
+            },
+            "/download": {
+                "post": {
+                    "summary": "Download result files of a done task.",
+                    "description": "The archive is generated on first request, which returns `init` until the file is sent.",
+                    "requestBody": request_body("FetchArchiveReq"),
+                    "responses": {
+                        "200": {
+                            "description": "File if ready, JSON otherwise.",
+                            "content": {
+                                "application/json": { "schema": { "oneOf": [
+                                    envelope(schema_ref("FetchArchiveResp")),
+                                    envelope(schema_ref("InlineArchiveResp")),
+                                ] } },
+                                "application/zip": { "schema": { "type": "string", "format": "binary" } },
+                                "text/plain": { "schema": { "type": "string" } },
+                            },
+                        },
+                    },
+                },
+            },
+            "/stream-summary/{uuid}": {
+                "get": {
+                    "summary": "Stream the summary as server-sent events while the model writes it.",
+                    "parameters": [{
+                        "name": "uuid",
+                        "in": "path",
+                        "required": true,
+                        "description": "Task uuid assigned by `/init`.",
+                        "schema": { "type": "string" },
+                    }],
+                    "responses": {
+                        "200": {
+                            "description": "Unnamed events of appended text, then a `done` event, or an `error` event with `ErrorResp` as data. JSON error if there is no such task.",
+                            "content": {
+                                "text/event-stream": { "schema": { "type": "string" } },
+                                "application/json": { "schema": schema_ref("ErrorResp") },
+                            },
+                        },
+                    },
+                },
+            },
+            "/events/batch": {
+                "post": {
+                    "summary": "Stream the stage of several tasks as server-sent events, until all of them finish.",
+                    "requestBody": request_body("BatchEventsReq"),
+                    "responses": {
+                        "200": {
+                            "description": "`status` events with `TaskEvent` as data, one per task and stage. JSON `validation` error if uuids are empty, too many or malformed.",
+                            "content": {
+                                "text/event-stream": { "schema": { "type": "string" } },
+                                "application/json": { "schema": schema_ref("ErrorResp") },
+                            },
+                        },
+                    },
+                },
+            },
+            "/audio": {
+                "post": {
+                    "summary": "Download the audio that the model of a task runs on.",
+                    "requestBody": request_body("FetchAudioReq"),
+                    "responses": {
+                        "200": {
+                            "description": "Audio if present, JSON error otherwise.",
+                            "content": {
+                                "audio/*": { "schema": { "type": "string", "format": "binary" } },
+                                "application/json": { "schema": schema_ref("ErrorResp") },
+                            },
+                        },
+                    },
+                },
+            },
+            "/files": {
+                "post": operation(
+                    "List files of a task with sizes and modification times, without contents.",
+                    Some("ListFilesReq"),
+                    schema_ref("ListFilesResp"),
+                ),
+            },
+            "/delete": {
+                "post": operation(
+                    "Erase a task and its files, aborting it if running.",
+                    Some("DeleteReq"),
+                    schema_ref("DeleteResp"),
+                ),
+            },
+            "/session/tasks": {
+                "post": operation(
+                    "Tasks submitted with the same `X-Session-Id`, only with `--enable-sessions`.",
+                    Some("SessionTasksReq"),
+                    schema_ref("SessionTasksResp"),
+                ),
+            },
+            "/estimate": {
+                "get": {
+                    "summary": "How long a task submitted now would wait for a model.",
+                    "responses": { "200": json_response(envelope(schema_ref("EstimateResp"))) },
+                },
+            },
+            "/capabilities": {
+                "get": {
+                    "summary": "Backends, languages, models and formats clients may choose, and limits.",
+                    "responses": { "200": json_response(envelope(schema_ref("CapabilitiesResp"))) },
+                },
+            },
+            "/version": {
+                "get": {
+                    "summary": "Version and build metadata.",
+                    "responses": { "200": json_response(envelope(schema_ref("VersionResp"))) },
+                },
+            },
+            "/health": {
+                "get": {
+                    "summary": "Whether new tasks are accepted, HTTP 200 even if not.",
+                    "responses": { "200": json_response(envelope(schema_ref("HealthResp"))) },
+                },
+            },
+            "/metrics": {
+                "get": {
+                    "summary": "Prometheus metrics.",
+                    "responses": {
+                        "200": {
+                            "description": "Prometheus text format.",
+                            "content": { "text/plain": { "schema": { "type": "string" } } },
+                        },
+                    },
+                },
+            },
+            "/admin/history": {
+                "get": {
+                    "summary": "Recently finished tasks, only mounted with `--admin-token`.",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [
+                        query_param("stage", "`done` or `err`."),
+                        {
+                            "name": "limit",
+                            "in": "query",
+                            "schema": { "type": "integer", "minimum": 0, "default": 50 },
+                        },
+                        {
+                            "name": "offset",
+                            "in": "query",
+                            "schema": { "type": "integer", "minimum": 0, "default": 0 },
+                        },
+                    ],
+                    "responses": {
+                        "200": json_response(envelope(schema_ref("HistoryResp"))),
+                        "401": json_response(schema_ref("ErrorResp")),
+                    },
+                },
+            },
+            "/admin/stats": {
+                "get": {
+                    "summary": "Performance of tasks finished within `--stats-window`, only mounted with `--admin-token`.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response(envelope(schema_ref("StatsResp"))),
+                        "401": json_response(schema_ref("ErrorResp")),
+                    },
+                },
+            },
+            "/admin/export": {
+                "get": {
+                    "summary": "Summaries of all done tasks as one zip, only mounted with `--admin-token`.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": {
+                            "description": "`<uuid>/<summary file>` of each done task.",
+                            "content": {
+                                "application/zip": { "schema": { "type": "string", "format": "binary" } },
+                            },
+                        },
+                        "401": json_response(schema_ref("ErrorResp")),
+                    },
+                },
+            },
+            "/admin/output/{uuid}": {
+                "get": {
+                    "summary": "Last lines printed by the commands of a task as they run, only mounted with `--admin-token`.",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [{
+                        "name": "uuid",
+                        "in": "path",
+                        "required": true,
+                        "description": "Task uuid assigned by `/init`.",
+                        "schema": { "type": "string" },
+                    }],
+                    "responses": {
+                        "200": json_response(envelope(schema_ref("OutputResp"))),
+                        "401": json_response(schema_ref("ErrorResp")),
+                    },
+                },
+            },
+            "/admin/reload": {
+                "post": {
+                    "summary": "Reload hot-reloadable settings from `--runtime-config`, only mounted with `--admin-token`.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response(envelope(schema_ref("ReloadResp"))),
+                        "401": json_response(schema_ref("ErrorResp")),
+                    },
+                },
+            },
+            "/admin/selftest": {
+                "post": {
+                    "summary": "Run the pipeline on `--selftest-url` as a smoke test, only mounted with `--admin-token`.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response(envelope(schema_ref("SelftestResp"))),
+                        "401": json_response(schema_ref("ErrorResp")),
+                    },
+                },
+            },
+            "/admin/pause": {
+                "post": {
+                    "summary": "Reject new tasks with `service_paused` until resumed, only mounted with `--admin-token`.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response(envelope(schema_ref("PauseResp"))),
+                        "401": json_response(schema_ref("ErrorResp")),
+                    },
+                },
+            },
+            "/admin/resume": {
+                "post": {
+                    "summary": "Accept new tasks again, only mounted with `--admin-token`.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response(envelope(schema_ref("PauseResp"))),
+                        "401": json_response(schema_ref("ErrorResp")),
+                    },
+                },
+            },
+            "/admin/flush-logs": {
+                "post": {
+                    "summary": "Wait for lines logged so far to be written to the log file, only mounted with `--admin-token`.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response(envelope(schema_ref("FlushLogsResp"))),
+                        "401": json_response(schema_ref("ErrorResp")),
+                    },
+                },
+            },
+        },
+        "components": {
+            "securitySchemes": {
+                "adminToken": { "type": "http", "scheme": "bearer" },
+                "apiKey": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "X-Api-Key",
+                    "description": "`--api-key`, required of all but docs if set, `unauthorized` error (401) otherwise.",
+                },
+                "signature": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "X-Signature",
+                    "description": "`sha256=` and the hex HMAC-SHA256 of the body under `--api-secret`, required of all but docs and audio uploads if set, `invalid_signature` error (401) otherwise.",
+                },
+            },
+            "schemas": schemas(),
+        },
+    })
+}
+
+fn schemas() -> Value {
+    let string = json!({ "type": "string" });
+    let nullable_string = json!({ "type": "string", "nullable": true });
+    let nullable_integer = json!({ "type": "integer", "nullable": true });
+    json!({
+        "InitiateReq": object(&["url"], json!({
+            "url": string,
+            "uuid": { "type": "string", "description": "Existing task for re-submission, empty for a new task." },
+            "language": nullable_string,
+            "model": nullable_string,
+            "validate_only": { "type": "boolean", "default": false },
+            "mode": { "type": "string", "enum": ["summary", "transcript"], "default": "summary" },
+            "backend": nullable_string,
+            "priority": {
+                "type": "integer", "minimum": 0, "maximum": MAX_PRIORITY, "nullable": true,
+                "description": "Higher ones are granted a model first, 0 if absent.",
+            },
+            "output_language": {
+                "type": "string", "nullable": true,
+                "description": "One of `--output-languages` the result is translated into, untranslated if absent.",
+            },
+        })),
+        "InitiateResp": object(&["uuid", "deduplicated"], json!({
+            "uuid": string,
+            "deduplicated": {
+                "type": "boolean",
+                "description": "The uuid is of an existing task, e.g. a re-submission, rather than a new one.",
+            },
+        })),
+        "ValidateResp": object(&["valid", "title", "duration_secs"], json!({
+            "valid": { "type": "boolean" },
+            "title": string,
+            "duration_secs": { "type": "integer", "nullable": true },
+        })),
+        "RetryReq": object(&["uuid"], json!({ "uuid": string })),
+        "RetryResp": object(&["stage"], json!({ "stage": schema_ref("Stage") })),
+        "PollStatusReq": object(&["uuid"], json!({
+            "uuid": string,
+            "format": { "type": "string", "enum": ["txt", "md", "json", "json-segments"], "nullable": true },
+            "preview_only": {
+                "type": "boolean", "default": false,
+                "description": "Omit `result` and `segments` of a done task, leaving its `preview`.",
+            },
+        })),
+        "PollStatusResp": object(&["done", "stage", "result"], json!({
+            "done": { "type": "boolean" },
+            "stage": schema_ref("Stage"),
+            "result": nullable_string,
+            "format": { "type": "string", "enum": ["txt", "md", "json", "json-segments"] },
+            "timings": schema_ref("StageTimings"),
+            "warnings": { "type": "array", "items": string },
+            "segments": { "type": "array", "items": schema_ref("TranscriptSegment") },
+            "preview": string,
+            "queue_position": { "type": "integer", "minimum": 1 },
+            "priority": { "type": "integer", "minimum": 0 },
+            "partial_result": string,
+            "retry_after_ms": { "type": "integer", "minimum": 0 },
+        })),
+        "TranscriptSegment": object(&["start_secs", "end_secs", "text"], json!({
+            "start_secs": { "type": "number", "minimum": 0 },
+            "end_secs": { "type": "number", "minimum": 0 },
+            "text": string,
+        })),
+        "StageTimings": object(&["download_secs", "model_secs"], json!({
+            "download_secs": { "type": "number" },
+            "model_secs": { "type": "number" },
+        })),
+        "Stage": {
+            "type": "string",
+            "enum": ["Done", "Err", "Download", "Queued", "Pending", "Translating", "Compressing"],
+        },
+        "FetchArchiveReq": object(&["uuid"], json!({
+            "uuid": string,
+            "file": { "type": "string", "enum": ["archive", "summary", "transcript"], "nullable": true },
+            "inline": {
+                "type": "boolean",
+                "description": "Return the archive as base64 in JSON, up to `--max-inline-bytes`.",
+            },
+            "partial": {
+                "type": "boolean",
+                "description": "Zip the files of an incomplete or failed task so far with `manifest.json`, see `PartialManifest`.",
+            },
+        })),
+        "FetchArchiveResp": object(&["init"], json!({ "init": { "type": "boolean" } })),
+        "InlineArchiveResp": object(&["archive_base64", "filename", "size"], json!({
+            "archive_base64": { "type": "string", "format": "byte" },
+            "filename": string,
+            "size": { "type": "integer" },
+        })),
+        "FetchAudioReq": object(&["uuid"], json!({ "uuid": string })),
+        "ListFilesReq": object(&["uuid"], json!({ "uuid": string })),
+        "DeleteReq": object(&["uuid"], json!({ "uuid": string })),
+        "DeleteResp": object(&["task_removed", "files_removed"], json!({
+            "task_removed": { "type": "boolean" },
+            "files_removed": { "type": "boolean" },
+        })),
+        "ListFilesResp": object(&["files"], json!({
+            "files": { "type": "array", "items": schema_ref("FileEntry") },
+        })),
+        "PartialManifest": object(&["uuid", "stage", "files", "present", "missing"], json!({
+            "uuid": string,
+            "stage": schema_ref("Stage"),
+            "err": schema_ref("ErrorResp"),
+            "files": { "type": "array", "items": schema_ref("FileEntry") },
+            "present": { "type": "array", "items": string },
+            "missing": { "type": "array", "items": string },
+        })),
+        "FileEntry": object(&["path", "size", "modified"], json!({
+            "path": string,
+            "size": { "type": "integer" },
+            "modified": { "type": "string", "format": "date-time" },
+        })),
+        "BatchEventsReq": object(&["uuids"], json!({
+            "uuids": { "type": "array", "items": string, "minItems": 1, "maxItems": MAX_BATCH_UUIDS },
+        })),
+        "TaskEvent": object(&["uuid"], json!({
+            "uuid": string,
+            "stage": schema_ref("Stage"),
+            "err": schema_ref("ErrorResp"),
+        })),
+        "SessionTasksReq": object(&["session_id"], json!({ "session_id": string })),
+        "SessionTasksResp": object(&["uuids"], json!({
+            "uuids": { "type": "array", "items": string },
+        })),
+        "EstimateResp": object(&["queued", "running", "est_wait_secs", "queue_depth"], json!({
+            "queued": { "type": "integer" },
+            "running": { "type": "integer" },
+            "est_wait_secs": { "type": "integer" },
+            "queue_depth": { "type": "integer" },
+            "queue_capacity": { "type": "integer", "nullable": true },
+        })),
+        "CapabilitiesResp": object(
+            &[
+                "backends", "default_backend", "languages", "models", "modes", "output_languages",
+                "formats", "max_duration_secs", "max_upload_bytes", "max_audio_bytes",
+            ],
+            json!({
+                "backends": { "type": "array", "items": string },
+                "default_backend": string,
+                "languages": { "type": "array", "items": string },
+                "models": { "type": "array", "items": string },
+                "modes": { "type": "array", "items": string },
+                "output_languages": { "type": "array", "items": string },
+                "formats": { "type": "array", "items": string },
+                "max_duration_secs": nullable_integer,
+                "max_upload_bytes": { "type": "integer" },
+                "max_audio_bytes": nullable_integer,
+            }),
+        ),
+        "HealthResp": object(&["status", "accepting_tasks", "model_ready"], json!({
+            "status": { "type": "string", "enum": ["ok", "paused", "shutting_down"] },
+            "accepting_tasks": { "type": "boolean" },
+            "model_ready": { "type": "boolean", "nullable": true },
+        })),
+        "SelftestResp": object(&["passed", "steps", "error", "output"], json!({
+            "passed": { "type": "boolean" },
+            "steps": { "type": "array", "items": schema_ref("SelftestStep") },
+            "error": nullable_string,
+            "output": nullable_string,
+        })),
+        "SelftestStep": object(&["name", "passed", "secs"], json!({
+            "name": { "type": "string", "enum": ["download", "model", "compress"] },
+            "passed": { "type": "boolean" },
+            "secs": { "type": "number" },
+        })),
+        "OutputResp": object(&["stage", "lines"], json!({
+            "stage": schema_ref("Stage"),
+            "lines": { "type": "array", "items": string },
+        })),
+        "PauseResp": object(&["paused"], json!({ "paused": { "type": "boolean" } })),
+        "VersionResp": object(&["version", "git_sha", "build_time", "rustc"], json!({
+            "version": string,
+            "git_sha": string,
+            "build_time": { "type": "string", "format": "date-time" },
+            "rustc": string,
+        })),
+        "MetadataReq": object(&["url"], json!({ "url": string })),
+        "MetadataResp": object(&["title", "uploader", "duration_secs", "thumbnail_url"], json!({
+            "title": string,
+            "uploader": nullable_string,
+            "duration_secs": nullable_integer,
+            "thumbnail_url": nullable_string,
+        })),
+        "UploadResp": object(&["upload_id", "offset"], json!({
+            "upload_id": string,
+            "offset": { "type": "integer" },
+        })),
+        "HistoryResp": object(&["total", "entries"], json!({
+            "total": { "type": "integer" },
+            "entries": { "type": "array", "items": schema_ref("HistoryEntry") },
+        })),
+        "StatsResp": object(&["window_secs", "tasks", "succeeded", "failed", "success_rate", "download", "model", "errors"], json!({
+            "window_secs": { "type": "integer" },
+            "tasks": { "type": "integer" },
+            "succeeded": { "type": "integer" },
+            "failed": { "type": "integer" },
+            "success_rate": { "type": "number", "nullable": true },
+            "download": schema_ref("StageStats"),
+            "model": schema_ref("StageStats"),
+            "errors": {
+                "type": "object",
+                "description": "Number of failed tasks by error code.",
+                "additionalProperties": { "type": "integer" },
+            },
+        })),
+        "StageStats": object(&["count", "median_secs", "p95_secs"], json!({
+            "count": { "type": "integer" },
+            "median_secs": { "type": "number", "nullable": true },
+            "p95_secs": { "type": "number", "nullable": true },
+        })),
+        "FlushLogsResp": object(&["pending_lines"], json!({
+            "pending_lines": { "type": "integer", "minimum": 0 },
+        })),
+        "ReloadResp": object(&[], json!({
+            "request_timeout_secs": nullable_integer,
+            "rate_limit": nullable_integer,
+            "max_active_tasks": nullable_integer,
+            "max_concurrent_models": nullable_integer,
+        })),
+        "HistoryEntry": object(&["ts", "uuid", "stage", "duration_secs", "error"], json!({
+            "ts": { "type": "string", "format": "date-time" },
+            "uuid": string,
+            "stage": schema_ref("Stage"),
+            "duration_secs": { "type": "number" },
+            "error": {
+                "nullable": true,
+                "allOf": [object(&["source", "code"], json!({
+                    "source": { "type": "string", "enum": ["client", "server", "transient"] },
+                    "code": string,
+                    "info": string,
+                }))],
+            },
+            "url": string,
+        })),
+        "Error": object(&["source", "info", "code", "recoverable"], json!({
+            "source": { "type": "string", "enum": ["client", "server", "transient"] },
+            "info": string,
+            "code": string,
+            "recoverable": { "type": "boolean" },
+            "fields": {
+                "type": "array",
+                "description": "Every invalid field, only in `validation` errors.",
+                "items": object(&["field", "message"], json!({ "field": string, "message": string })),
+            },
+            "retry_after_ms": {
+                "type": "integer",
+                "description": "How long to wait before polling again, only in `poll_too_fast` errors.",
+            },
+            "retry_after_secs": {
+                "type": "integer",
+                "description": "How long to wait before trying again, only in `transient` errors, which are answered with HTTP 503.",
+            },
+        })),
+        // the error is wrapped once more
+        "ErrorResp": object(&["success", "err"], json!({
+            "success": { "type": "boolean", "enum": [false] },
+            "err": object(&["success", "err"], json!({
+                "success": { "type": "boolean", "enum": [false] },
+                "err": schema_ref("Error"),
+            })),
+            "request_id": string,
+        })),
+    })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+fn object(required: &[&str], properties: Value) -> Value {
+    json!({ "type": "object", "required": required, "properties": properties })
+}
+
+/// `{ success: true, data }` or an error.
+fn envelope(data: Value) -> Value {
+    json!({
+        "oneOf": [
+            object(&["success", "data"], json!({
+                "success": { "type": "boolean", "enum": [true] },
+                "data": data,
+            })),
+            schema_ref("ErrorResp"),
+        ],
+    })
+}
+
+fn json_response(schema: Value) -> Value {
+    json!({
+        "description": "JSON envelope.",
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn request_body(name: &str) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema_ref(name) } },
+    })
+}
+
+fn query_param(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+/// POST operation taking a JSON body, answering with `data` in the envelope.
+fn operation(summary: &str, request: Option<&str>, data: Value) -> Value {
+    let mut op = json!({
+        "summary": summary,
+        "responses": { "200": json_response(envelope(data)) },
+    });
+    if let Some(request) = request {
+        op["requestBody"] = request_body(request);
+    }
+    op
+}
+
+/// Declare `X-Task-Id` on the successful response of `op`.
+fn with_task_id(mut op: Value) -> Value {
+    op["responses"]["200"]["headers"] = json!({
+        "X-Task-Id": {
+            "description": "Uuid of the task, same as in body, absent when only validating.",
+            "schema": { "type": "string" },
+        },
+    });
+    op
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Serialize;
+    use serde_json::Value;
+
+    use super::spec;
+    use crate::{
+        exception::{AppError, ClientError},
+        models::{
+            AppResp, InitiateResp, PollStatusResp, StageTimings, SummaryFormat, TaskStatus,
+            TranscriptSegment,
+        },
+    };
+
+    /// Every `$ref` in `value`.
+    fn refs<'a>(value: &'a Value, out: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(target)) = map.get("$ref") {
+                    out.push(target);
+                }
+                map.values().for_each(|v| refs(v, out));
+            }
+            Value::Array(items) => items.iter().for_each(|v| refs(v, out)),
+            _ => {}
+        }
+    }
+
+    /// Keys of serialized `value` are declared by `schema`, and include all required ones.
+    fn conforms(value: impl Serialize, schema: &Value) {
+        let value = serde_json::to_value(value).unwrap();
+        let keys: Vec<_> = value.as_object().unwrap().keys().collect();
+        let properties = schema["properties"].as_object().unwrap();
+        for key in &keys {
+            assert!(properties.contains_key(*key), "undeclared {key}");
+        }
+        for required in schema["required"].as_array().unwrap() {
+            assert!(keys.contains(&&required.as_str().unwrap().to_string()));
+        }
+    }
+
+    #[test]
+    fn test_spec() {
+        let spec = spec();
+        // round trip through text, as served
+        let spec: Value = serde_json::from_str(&spec.to_string()).unwrap();
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+        assert!(spec["info"]["title"].is_string() && spec["info"]["version"].is_string());
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            assert!(path.starts_with('/'));
+            for op in item.as_object().unwrap().values() {
+                assert!(op["responses"].as_object().is_some_and(|r| !r.is_empty()));
+            }
+        }
+        let mut targets = Vec::new();
+        refs(&spec, &mut targets);
+        let schemas = &spec["components"]["schemas"];
+        for target in targets {
+            let name = target.strip_prefix("#/components/schemas/").unwrap();
+            assert!(schemas.get(name).is_some(), "dangling {target}");
+        }
+
+        let poll = PollStatusResp {
+            done: true,
+            stage: TaskStatus::Done,
+            result: Some("summary".into()),
+            format: Some(SummaryFormat::Md),
+            timings: Some(StageTimings {
+                download_secs: 1.0,
+                model_secs: 2.0,
+            }),
+            warnings: Some(Vec::new()),
+            segments: Some(vec![TranscriptSegment {
+                start_secs: 0.0,
+                end_secs: 1.5,
+                text: "hello".into(),
+            }]),
+            preview: Some("summary".into()),
+            queue_position: Some(1),
+            priority: Some(3),
+            partial_result: Some("partial".into()),
+            retry_after_ms: Some(1000),
+        };
+        conforms(&poll, &schemas["PollStatusResp"]);
+        let uuid = "123".to_string();
+        let resp = InitiateResp {
+            uuid,
+            deduplicated: true,
+        };
+        conforms(resp, &schemas["InitiateResp"]);
+        let resp: AppResp<()> = AppResp::Exception(AppError::from(ClientError::RateLimited));
+        let resp = serde_json::to_value(resp).unwrap();
+        conforms(&resp, &schemas["ErrorResp"]);
+        conforms(&resp["err"], &schemas["ErrorResp"]["properties"]["err"]);
+        conforms(&resp["err"]["err"], &schemas["Error"]);
+    }
+}
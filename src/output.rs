@@ -0,0 +1,111 @@
+//! Results of succeeded tasks moved out of `work_dir`, see `--output-dir`.
+//!
+//! The task dir in `work_dir` is scratch space holding the audio and whatever the model writes
+//! meanwhile. Once a task succeeds, everything in it but the audio moves to the task dir in
+//! `--output-dir`, which `/poll`, `/download` and `/stream-summary` read from then on, see
+//! [`ServerState::result_dir`][`crate::models::ServerState::result_dir`]. Each entry is renamed
+//! into place, or copied aside and then renamed if the dirs are on different filesystems, so a
+//! reader never sees a file half moved.
+use std::{
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use futures_util::future::BoxFuture;
+
+/// Move the entries of `from` but `keep` into `to`, replacing what a previous attempt left.
+pub async fn publish(from: &Path, to: &Path, keep: &[&str]) -> io::Result<()> {
+    match tokio::fs::remove_dir_all(to).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+    tokio::fs::create_dir_all(to).await?;
+    let mut entries = tokio::fs::read_dir(from).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        if keep.iter().any(|kept| name == *kept) {
+            continue;
+        }
+        move_entry(&entry.path(), &to.join(&name)).await?;
+    }
+    Ok(())
+}
+
+async fn move_entry(from: &Path, to: &Path) -> io::Result<()> {
+    match tokio::fs::rename(from, to).await {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            let mut partial = to.as_os_str().to_owned();
+            partial.push(".partial");
+            copy_entry(from, Path::new(&partial)).await?;
+            tokio::fs::rename(&partial, to).await?;
+            match tokio::fs::metadata(from).await?.is_dir() {
+                true => tokio::fs::remove_dir_all(from).await,
+                false => tokio::fs::remove_file(from).await,
+            }
+        }
+        result => result,
+    }
+}
+
+/// Copy file or dir `from` to `to`, recursively.
+fn copy_entry<'a>(from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+    Box::pin(async move {
+        if !tokio::fs::metadata(from).await?.is_dir() {
+            return tokio::fs::copy(from, to).await.map(|_| ());
+        }
+        tokio::fs::create_dir_all(to).await?;
+        let mut entries = tokio::fs::read_dir(from).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            copy_entry(&entry.path(), &to.join(entry.file_name())).await?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::{copy_entry, publish};
+
+    #[tokio::test]
+    async fn test_publish() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let (from, to) = (dir.join("work"), dir.join("output"));
+        fs::create_dir_all(from.join("nested")).unwrap();
+        fs::write(from.join("audio.mp3"), "audio").unwrap();
+        fs::write(from.join("summary.txt"), "a summary").unwrap();
+        fs::write(from.join("nested").join("log.txt"), "log").unwrap();
+        // left by a failed attempt
+        fs::create_dir_all(to.join("nested")).unwrap();
+        fs::write(to.join("stale.txt"), "stale").unwrap();
+
+        publish(&from, &to, &["audio.mp3"]).await.unwrap();
+        let names = |dir| {
+            let mut names: Vec<_> = fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&from), ["audio.mp3"]);
+        assert_eq!(names(&to), ["nested", "summary.txt"]);
+        assert_eq!(
+            fs::read_to_string(to.join("nested/log.txt")).unwrap(),
+            "log"
+        );
+
+        // as across filesystems
+        let copied = dir.join("copied");
+        copy_entry(&to, &copied).await.unwrap();
+        assert_eq!(names(&copied), ["nested", "summary.txt"]);
+        assert_eq!(
+            fs::read_to_string(copied.join("summary.txt")).unwrap(),
+            "a summary"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
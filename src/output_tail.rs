@@ -0,0 +1,97 @@
+//! Last lines printed by the subprocesses of a task while they run, see `/admin/output/:uuid`.
+//!
+//! `run.log` only receives the output of a command once it exits, which for a model run may be
+//! an hour later. Meanwhile each line of stdout and stderr is also pushed, as it is read, to the
+//! [`OutputTail`] of the task, which keeps the last [`OUTPUT_TAIL_LINES`] of them. The pipeline
+//! of a task runs in [`scope`] of its tail, so that the executor finds it by [`push`] without
+//! being told which task it runs for.
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+/// Lines kept per task, older ones being dropped.
+pub const OUTPUT_TAIL_LINES: usize = 50;
+
+/// Longer lines are cut, e.g. a progress bar redrawn without newlines.
+const MAX_LINE_CHARS: usize = 1000;
+
+tokio::task_local! {
+    static TAIL: OutputTail;
+}
+
+/// Ring buffer of output lines, shared by clones.
+#[derive(Clone, Debug)]
+pub struct OutputTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl OutputTail {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Keep `line`, dropping the oldest one if full.
+    ///
+    /// A carriage return redraws the line on a terminal, so only what follows the last one is
+    /// kept, e.g. the latest state of a progress bar.
+    pub fn push(&self, line: &str) {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let line = line.rsplit('\r').next().unwrap_or(line);
+        let line: String = line.chars().take(MAX_LINE_CHARS).collect();
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        if self.capacity > 0 {
+            lines.push_back(line);
+        }
+    }
+
+    /// Lines kept so far, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Run `f` with output of subprocesses it spawns pushed to `tail`.
+pub async fn scope<F: Future>(tail: OutputTail, f: F) -> F::Output {
+    TAIL.scope(tail, f).await
+}
+
+/// Push `line` to the tail of the task being run by current task, if any.
+pub fn push(line: &str) {
+    let _ = TAIL.try_with(|tail| tail.push(line));
+}
+
+#[cfg(test)]
+mod test {
+    use super::{push, scope, OutputTail};
+
+    #[tokio::test]
+    async fn test_output_tail() {
+        let tail = OutputTail::new(3);
+        for i in 0..5 {
+            tail.push(&format!("line {i}\n"));
+        }
+        assert_eq!(tail.lines(), ["line 2", "line 3", "line 4"]);
+
+        tail.push("[download]  10.0%\r[download]  55.0%\r[download] 100.0%\r\n");
+        assert_eq!(tail.lines(), ["line 3", "line 4", "[download] 100.0%"]);
+
+        // pushed only within scope, to the tail of the scope
+        push("outside");
+        let shared = tail.clone();
+        scope(shared, async { push("inside") }).await;
+        assert_eq!(tail.lines(), ["line 4", "[download] 100.0%", "inside"]);
+
+        let long = "x".repeat(5000);
+        tail.push(&long);
+        assert_eq!(tail.lines()[2].len(), 1000);
+    }
+}
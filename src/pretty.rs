@@ -0,0 +1,97 @@
+//! Indented JSON responses for debugging with curl, see `--pretty-json`.
+//!
+//! Controllers respond through axum's compact `Json`, so JSON bodies are re-serialized here with
+//! `serde_json::to_vec_pretty` on the way out. Other bodies, e.g. streamed archives, pass as is.
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Middleware indenting `application/json` responses.
+///
+/// No-op if `pretty` is false, which is the production default.
+pub async fn pretty_json(State(pretty): State<bool>, req: Request, next: Next) -> Response {
+    let resp = next.run(req).await;
+    let is_json = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !pretty || !is_json {
+        return resp;
+    }
+    let (mut parts, body) = resp.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return parts.status.into_response();
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(value) => serde_json::to_vec_pretty(&value).map_or(bytes, Into::into),
+        Err(_) => bytes,
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Request},
+        middleware,
+        routing::get,
+        Json, Router,
+    };
+    use tower::ServiceExt;
+
+    use super::pretty_json;
+    use crate::models::{AppResp, InitiateResp};
+
+    #[tokio::test]
+    async fn test_pretty_json() {
+        let app = |pretty: bool| {
+            Router::new()
+                .route(
+                    "/",
+                    get(|| async {
+                        Json(AppResp::Success(InitiateResp {
+                            uuid: "123".into(),
+                            deduplicated: false,
+                        }))
+                    }),
+                )
+                .route("/text", get(|| async { "{\"a\":1}" }))
+                .layer(middleware::from_fn_with_state(pretty, pretty_json))
+        };
+        let body = |app: Router, path: &'static str| async move {
+            let req = Request::get(path).body(Body::empty()).unwrap();
+            let resp = app.oneshot(req).await.unwrap();
+            let content_type = resp.headers()[header::CONTENT_TYPE].clone();
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            (content_type, String::from_utf8(body.to_vec()).unwrap())
+        };
+
+        let (content_type, compact) = body(app(false), "/").await;
+        assert_eq!(content_type, "application/json");
+        assert_eq!(
+            compact,
+            r#"{"success":true,"data":{"uuid":"123","deduplicated":false}}"#
+        );
+        let (content_type, pretty) = body(app(true), "/").await;
+        assert_eq!(content_type, "application/json");
+        assert!(pretty.contains("\n  \"success\": true"), "{pretty}");
+        let parsed: serde_json::Value = serde_json::from_str(&pretty).unwrap();
+        assert_eq!(
+            parsed,
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap()
+        );
+
+        let (_, text) = body(app(true), "/text").await;
+        assert_eq!(text, "{\"a\":1}");
+    }
+}
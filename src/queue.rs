@@ -0,0 +1,311 @@
+//! Limit of concurrently running models, enabled by `--max-concurrent-models`.
+//!
+//! A task finishing its download waits here in [`Queued`][`crate::models::TaskStatus::Queued`]
+//! stage. Whenever a model is free, it is granted to the waiting task of the highest effective
+//! priority, the earliest of them on a tie. Effective priority is the requested one, up to
+//! [`MAX_PRIORITY`], raised by one for every [`AGING_INTERVAL`] spent waiting, so that a steady
+//! stream of urgent tasks cannot starve the rest. The rank of a task in that order is the number
+//! of tasks that run before it, unless others of higher priority arrive meanwhile.
+use std::{cmp::Reverse, collections::HashSet, pin::pin, sync::Mutex, time::Duration};
+
+use tokio::{sync::Notify, time::Instant};
+
+/// Highest priority a task may request, 0 being the default.
+pub const MAX_PRIORITY: u8 = 9;
+/// Waiting this long raises effective priority of a task by one.
+pub const AGING_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct ModelQueue {
+    state: Mutex<QueueState>,
+    /// Notified whenever a model is granted to a waiting task.
+    changed: Notify,
+}
+
+struct QueueState {
+    max_running: usize,
+    /// Including tasks granted a model that have not yet taken it.
+    running: usize,
+    /// Tasks without a model, earliest first.
+    waiting: Vec<Waiter>,
+    /// Uuids of tasks granted a model, to be taken by their `acquire`.
+    granted: HashSet<String>,
+}
+
+struct Waiter {
+    uuid: String,
+    priority: u8,
+    since: Instant,
+}
+
+/// Permission to run a model, released on drop.
+pub struct ModelPermit<'a> {
+    queue: &'a ModelQueue,
+}
+
+/// Removes a task from the queue if it stops waiting without a permit, e.g. aborted.
+struct Waiting<'a> {
+    queue: &'a ModelQueue,
+    uuid: &'a str,
+}
+
+impl Waiter {
+    fn effective_priority(&self, now: Instant) -> u32 {
+        let waited = now.duration_since(self.since).as_secs() / AGING_INTERVAL.as_secs();
+        (self.priority as u64 + waited)
+            .try_into()
+            .unwrap_or(u32::MAX)
+    }
+}
+
+impl QueueState {
+    /// Waiting tasks in the order they are granted models, as of `now`.
+    fn ordered(&self, now: Instant) -> Vec<&Waiter> {
+        let mut ordered = self.waiting.iter().collect::<Vec<_>>();
+        // stable, so that the earliest comes first on a tie
+        ordered.sort_by_key(|waiter| Reverse(waiter.effective_priority(now)));
+        ordered
+    }
+
+    /// Grant free models to waiting tasks, `true` if any is granted.
+    fn dispatch(&mut self) -> bool {
+        let now = Instant::now();
+        let mut dispatched = false;
+        while self.running < self.max_running {
+            let Some(next) = self.ordered(now).first().map(|waiter| waiter.uuid.clone()) else {
+                break;
+            };
+            self.waiting.retain(|waiter| waiter.uuid != next);
+            self.granted.insert(next);
+            self.running += 1;
+            dispatched = true;
+        }
+        dispatched
+    }
+}
+
+impl ModelQueue {
+    pub fn new(max_running: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                max_running: max_running.max(1),
+                running: 0,
+                waiting: Vec::new(),
+                granted: HashSet::new(),
+            }),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Retune the limit, running models beyond a lowered one are not interrupted.
+    pub fn set_max_running(&self, max_running: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.max_running = max_running.max(1);
+        if state.dispatch() {
+            self.changed.notify_waiters();
+        }
+    }
+
+    /// Wait until `uuid` is granted a model, `priority` being clamped to [`MAX_PRIORITY`].
+    pub async fn acquire<'a>(&'a self, uuid: &'a str, priority: u8) -> ModelPermit<'a> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.waiting.push(Waiter {
+                uuid: uuid.to_string(),
+                priority: priority.min(MAX_PRIORITY),
+                since: Instant::now(),
+            });
+            if state.dispatch() {
+                self.changed.notify_waiters();
+            }
+        }
+        let waiting = Waiting { queue: self, uuid };
+        loop {
+            // register before checking, so that a grant in between is not missed
+            let mut changed = pin!(self.changed.notified());
+            changed.as_mut().enable();
+            if self.state.lock().unwrap().granted.remove(uuid) {
+                std::mem::forget(waiting);
+                return ModelPermit { queue: self };
+            }
+            changed.await;
+        }
+    }
+
+    /// 1-based position of `uuid` in the order waiting tasks are granted models, `None` if it
+    /// is not waiting.
+    pub fn position(&self, uuid: &str) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        let ordered = state.ordered(Instant::now());
+        ordered
+            .iter()
+            .position(|waiter| waiter.uuid == uuid)
+            .map(|i| i + 1)
+    }
+
+    /// Effective priority of `uuid`, aging included, `None` if it is not waiting.
+    pub fn priority(&self, uuid: &str) -> Option<u32> {
+        let state = self.state.lock().unwrap();
+        let waiter = state.waiting.iter().find(|waiter| waiter.uuid == uuid)?;
+        Some(waiter.effective_priority(Instant::now()))
+    }
+
+    /// Numbers of waiting and running tasks.
+    pub fn load(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.waiting.len(), state.running)
+    }
+
+    /// How long a task enqueued now waits for a model, if every run takes `per_run`.
+    ///
+    /// Conservative in that running models are taken as just started.
+    pub fn estimate_wait(&self, per_run: Duration) -> Duration {
+        let state = self.state.lock().unwrap();
+        let ahead = state.waiting.len() + state.running;
+        // runs of all slots finishing before it gets one
+        let rounds = (ahead + 1).saturating_sub(state.max_running);
+        per_run * rounds.div_ceil(state.max_running) as u32
+    }
+}
+
+impl Drop for ModelPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.running -= 1;
+        if state.dispatch() {
+            self.queue.changed.notify_waiters();
+        }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.waiting.retain(|waiter| waiter.uuid != self.uuid);
+        // granted but never taken, pass the model on
+        if state.granted.remove(self.uuid) {
+            state.running -= 1;
+            if state.dispatch() {
+                self.queue.changed.notify_waiters();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::task::yield_now;
+
+    use super::{ModelQueue, AGING_INTERVAL, MAX_PRIORITY};
+
+    async fn settle() {
+        for _ in 0..10 {
+            yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_position() {
+        let queue = Arc::new(ModelQueue::new(1));
+        // spawned tasks hold their permits until the gate opens
+        let gate = Arc::new(tokio::sync::RwLock::new(()));
+        let closed = gate.write().await;
+        let spawn = |uuid: &'static str| {
+            let (queue, gate) = (Arc::clone(&queue), Arc::clone(&gate));
+            tokio::spawn(async move {
+                let _permit = queue.acquire(uuid, 0).await;
+                let _ = gate.read().await;
+            })
+        };
+
+        let first = queue.acquire("a", 0).await;
+        let tasks = [spawn("b"), spawn("c"), spawn("d")];
+        settle().await;
+        assert_eq!(queue.position("a"), None);
+        assert_eq!(queue.position("b"), Some(1));
+        assert_eq!(queue.position("c"), Some(2));
+        assert_eq!(queue.position("d"), Some(3));
+
+        // an aborted task leaves the queue
+        tasks[1].abort();
+        settle().await;
+        assert_eq!(queue.position("d"), Some(2));
+
+        // b starts running
+        drop(first);
+        settle().await;
+        assert_eq!(queue.position("b"), None);
+        assert_eq!(queue.position("d"), Some(1));
+
+        drop(closed);
+        let [b, _, d] = tasks;
+        b.await.unwrap();
+        d.await.unwrap();
+        assert_eq!(queue.position("d"), None);
+    }
+
+    #[tokio::test]
+    async fn test_estimate_wait() {
+        let run = Duration::from_secs(60);
+        let queue = Arc::new(ModelQueue::new(2));
+        assert_eq!(queue.estimate_wait(run), Duration::ZERO);
+        let first = queue.acquire("a", 0).await;
+        assert_eq!(queue.estimate_wait(run), Duration::ZERO);
+        let _second = queue.acquire("b", 0).await;
+        assert_eq!(queue.estimate_wait(run), run);
+
+        let waiting = ["c", "d"].map(|uuid| {
+            let queue = Arc::clone(&queue);
+            tokio::spawn(async move {
+                queue.acquire(uuid, 0).await;
+            })
+        });
+        settle().await;
+        assert_eq!(queue.load(), (2, 2));
+        assert_eq!(queue.estimate_wait(run), run * 2);
+        queue.set_max_running(1);
+        assert_eq!(queue.estimate_wait(run), run * 4);
+
+        waiting.iter().for_each(|task| task.abort());
+        drop(first);
+        settle().await;
+        assert_eq!(queue.load(), (0, 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_priority() {
+        let queue = Arc::new(ModelQueue::new(1));
+        let granted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let spawn = |uuid: &'static str, priority: u8| {
+            let (queue, granted) = (Arc::clone(&queue), Arc::clone(&granted));
+            tokio::spawn(async move {
+                let _permit = queue.acquire(uuid, priority).await;
+                granted.lock().unwrap().push(uuid);
+            })
+        };
+
+        let first = queue.acquire("a", 0).await;
+        let low = spawn("low", 0);
+        settle().await;
+        // aged past a fresher task of higher priority
+        tokio::time::advance(AGING_INTERVAL * 2).await;
+        let mid = spawn("mid", 1);
+        let high = spawn("high", u8::MAX);
+        settle().await;
+        assert_eq!(queue.priority("low"), Some(2));
+        assert_eq!(queue.priority("mid"), Some(1));
+        assert_eq!(queue.priority("high"), Some(MAX_PRIORITY as u32));
+        assert_eq!(queue.position("high"), Some(1));
+        assert_eq!(queue.position("low"), Some(2));
+        assert_eq!(queue.position("mid"), Some(3));
+
+        drop(first);
+        for task in [low, mid, high] {
+            task.await.unwrap();
+        }
+        assert_eq!(*granted.lock().unwrap(), ["high", "low", "mid"]);
+        assert_eq!(queue.priority("mid"), None);
+        assert_eq!(queue.load(), (0, 0));
+    }
+}
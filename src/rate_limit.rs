@@ -0,0 +1,156 @@
+//! Per client IP token bucket guarding `/init`.
+//!
+//! Each IP owns a bucket holding up to `--rate-limit` tokens, refilled continuously at
+//! `--rate-limit` tokens per minute. A request consumes one token, and is rejected with
+//! [`ClientError::RateLimited`] (HTTP 429) when the bucket is empty.
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::{
+    client_ip::ClientIp,
+    exception::{AppError, ClientError},
+    models::{AppResp, ServerState},
+};
+
+pub struct RateLimiter {
+    per_minute: AtomicU32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute: AtomicU32::new(per_minute),
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Retune the limit, taking effect on the next check of each bucket.
+    pub fn set_per_minute(&self, per_minute: u32) {
+        self.per_minute.store(per_minute, Ordering::Relaxed);
+    }
+
+    fn per_minute(&self) -> f64 {
+        self.per_minute.load(Ordering::Relaxed) as f64
+    }
+
+    /// Consume a token of `ip`, or obtain how long until one is available.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let capacity = self.per_minute();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.refill_rate()).min(capacity);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.refill_rate();
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+
+    /// Drop buckets that would have been refilled to full, they behave the same as absent ones.
+    pub fn purge_idle(&self) {
+        let now = Instant::now();
+        let capacity = self.per_minute();
+        let refill_rate = self.refill_rate();
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.last_refill);
+            bucket.tokens + elapsed.as_secs_f64() * refill_rate < capacity
+        });
+    }
+
+    /// Tokens per second.
+    fn refill_rate(&self) -> f64 {
+        self.per_minute() / 60.0
+    }
+}
+
+/// Middleware rejecting requests from clients that exhausted their bucket.
+///
+/// No-op if `--rate-limit` is not set.
+pub async fn rate_limit(
+    State(state): State<ServerState>,
+    ClientIp(ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (Some(limiter), Some(ip)) = (state.rate_limiter.as_ref(), ip) else {
+        return next.run(req).await;
+    };
+
+    match limiter.check(ip) {
+        Ok(()) => next.run(req).await,
+        Err(wait) => {
+            tracing::warn!("\nClient {ip} exceeds rate limit.");
+            let body: AppResp<()> = AppResp::Exception(AppError::from(ClientError::RateLimited));
+            let retry_after = HeaderValue::from(wait.as_secs().max(1));
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after)],
+                Json(body),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use super::RateLimiter;
+
+    #[test]
+    fn test_bucket() {
+        let limiter = RateLimiter::new(2);
+        let a = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let start = Instant::now();
+
+        assert!(limiter.check_at(a, start).is_ok());
+        assert!(limiter.check_at(a, start).is_ok());
+        let wait = limiter.check_at(a, start).unwrap_err();
+        assert_eq!(wait.as_secs(), 30);
+        // other clients are unaffected
+        assert!(limiter.check_at(b, start).is_ok());
+        // one token per 30 seconds
+        assert!(limiter.check_at(a, start + Duration::from_secs(30)).is_ok());
+        assert!(limiter
+            .check_at(a, start + Duration::from_secs(30))
+            .is_err());
+    }
+}
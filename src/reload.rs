@@ -0,0 +1,164 @@
+//! Settings tunable at runtime, reloaded from `--runtime-config` by `POST /admin/reload`.
+//!
+//! Hot-reloadable are the fields of [`RuntimeConfig`]:
+//! - `request_timeout_secs`, see `--request-timeout-secs`
+//! - `rate_limit`, see `--rate-limit`
+//! - `max_active_tasks`, see `--max-active-tasks`
+//! - `max_concurrent_models`, see `--max-concurrent-models`
+//! - `admin_token`, see `--admin-token`
+//!
+//! Everything else is boot-only, e.g. port, work dir, log dir and whether `/admin/*` is mounted
+//! at all. Rate limit and model concurrency can be retuned but not switched on or off, since
+//! their state is only created at startup if the flag is set.
+//!
+//! The file is a JSON object of the fields above, each optional. Values in the file override the
+//! flags at startup, and on reload replace the current ones, while absent fields are kept.
+use std::{fs, path::Path, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{exception::ServerError, models::ServerState};
+
+/// Current values of hot-reloadable settings, see the module doc.
+#[derive(Clone, Default, PartialEq)]
+pub struct RuntimeConfig {
+    pub request_timeout: Option<Duration>,
+    pub rate_limit: Option<u32>,
+    pub max_active_tasks: Option<usize>,
+    pub max_concurrent_models: Option<usize>,
+    pub admin_token: Option<String>,
+}
+
+/// Content of `--runtime-config`, where unknown fields, e.g. boot-only ones, are rejected.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RuntimeConfigFile {
+    request_timeout_secs: Option<u64>,
+    rate_limit: Option<u32>,
+    max_active_tasks: Option<usize>,
+    max_concurrent_models: Option<usize>,
+    admin_token: Option<String>,
+}
+
+impl RuntimeConfigFile {
+    /// Parse `content` of the file at `path`.
+    ///
+    /// Fails with [`ServerError::InvalidConfig`] if it is malformed, or holds a value the
+    /// corresponding flag rejects.
+    fn parse(path: &Path, content: &str) -> Result<Self, ServerError> {
+        let invalid = |e: String| ServerError::InvalidConfig(format!("{}: {e}", path.display()));
+        let file: Self = serde_json::from_str(content).map_err(|e| invalid(e.to_string()))?;
+        // `range(1..)` of the flags
+        if file.request_timeout_secs == Some(0)
+            || file.rate_limit == Some(0)
+            || file.max_active_tasks == Some(0)
+            || file.max_concurrent_models == Some(0)
+        {
+            return Err(invalid("limits must be positive".into()));
+        }
+        Ok(file)
+    }
+}
+
+impl RuntimeConfig {
+    /// `self` overridden by values in the file at `path`.
+    ///
+    /// Fails with [`ServerError::InvalidConfig`] if the file cannot be read or parsed.
+    pub fn load(&self, path: &Path) -> Result<Self, ServerError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| ServerError::InvalidConfig(format!("{}: {e}", path.display())))?;
+        Ok(self.overridden_by(RuntimeConfigFile::parse(path, &content)?))
+    }
+
+    /// `self` overridden by values present in `file`.
+    fn overridden_by(&self, file: RuntimeConfigFile) -> Self {
+        Self {
+            request_timeout: file
+                .request_timeout_secs
+                .map(Duration::from_secs)
+                .or(self.request_timeout),
+            rate_limit: file.rate_limit.or(self.rate_limit),
+            max_active_tasks: file.max_active_tasks.or(self.max_active_tasks),
+            max_concurrent_models: file.max_concurrent_models.or(self.max_concurrent_models),
+            admin_token: file.admin_token.or_else(|| self.admin_token.clone()),
+        }
+    }
+}
+
+/// Reload `--runtime-config` of `state` and swap in the new values, which are returned.
+///
+/// Fails with [`ServerError::InvalidConfig`] leaving current values intact, if the file is
+/// invalid or it tunes a component disabled at startup.
+///
+/// The file is read and parsed before locking, so that the lock is only held to swap values.
+pub async fn reload(state: &ServerState) -> Result<RuntimeConfig, ServerError> {
+    let Some(path) = state.config.runtime_config.as_deref() else {
+        return Err(ServerError::InvalidConfig(
+            "`--runtime-config` is not set".into(),
+        ));
+    };
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| ServerError::InvalidConfig(format!("{}: {e}", path.display())))?;
+    let file = RuntimeConfigFile::parse(path, &content)?;
+    let mut runtime = state.runtime.write().unwrap();
+    let new = runtime.overridden_by(file);
+    let disabled = |flag: &str| {
+        ServerError::InvalidConfig(format!("{flag} can only be tuned if set at startup"))
+    };
+    if state.rate_limiter.is_none() && new.rate_limit.is_some() {
+        return Err(disabled("--rate-limit"));
+    }
+    if state.model_queue.is_none() && new.max_concurrent_models.is_some() {
+        return Err(disabled("--max-concurrent-models"));
+    }
+    if let (Some(limiter), Some(per_minute)) = (&state.rate_limiter, new.rate_limit) {
+        limiter.set_per_minute(per_minute);
+    }
+    if let (Some(queue), Some(max)) = (&state.model_queue, new.max_concurrent_models) {
+        queue.set_max_running(max);
+    }
+    *runtime = new.clone();
+    Ok(new)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, time::Duration};
+
+    use uuid::Uuid;
+
+    use super::RuntimeConfig;
+    use crate::exception::ServerError;
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!("{}.json", Uuid::new_v4()));
+        let flags = RuntimeConfig {
+            rate_limit: Some(10),
+            admin_token: Some("token".into()),
+            ..RuntimeConfig::default()
+        };
+        fs::write(&path, r#"{ "request_timeout_secs": 5, "rate_limit": 20 }"#).unwrap();
+        let loaded = flags.load(&path).unwrap();
+        assert_eq!(loaded.request_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(loaded.rate_limit, Some(20));
+        assert_eq!(loaded.admin_token.as_deref(), Some("token"));
+
+        for invalid in [
+            r#"{ "port": 80 }"#,
+            r#"{ "rate_limit": 0 }"#,
+            r#"{ "max_active_tasks": 0 }"#,
+            "{",
+        ] {
+            fs::write(&path, invalid).unwrap();
+            let result = flags.load(&path);
+            assert!(
+                matches!(result, Err(ServerError::InvalidConfig(_))),
+                "{invalid}"
+            );
+        }
+        fs::remove_file(&path).unwrap();
+        assert!(flags.load(&path).is_err());
+    }
+}
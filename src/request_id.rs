@@ -0,0 +1,122 @@
+//! Per request id, for clients to quote in bug reports.
+//!
+//! [`request_id`] generates a short id for each request, which is recorded in the `request` span
+//! of access log, returned in `X-Request-Id`, and added to error responses as `request_id` by
+//! [`AppResp`][`crate::models::AppResp`], so that a failure at client can be located in log.
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+/// Id of a request, also found in request extensions.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+/// Middleware assigning [`RequestId`], to be the outermost so that every response has it.
+pub async fn request_id(mut req: Request, next: Next) -> Response {
+    let id = Uuid::new_v4().simple().to_string()[..12].to_string();
+    req.extensions_mut().insert(RequestId(id.clone()));
+    let mut resp = REQUEST_ID.scope(RequestId(id.clone()), next.run(req)).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    resp
+}
+
+/// Id of the request being handled by current task, `None` outside of [`request_id`].
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.0.clone()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::Request,
+        middleware,
+        routing::get,
+        Json, Router,
+    };
+    use tower::ServiceExt;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::{request_id, REQUEST_ID_HEADER};
+    use crate::{
+        access_log::access_log_layer,
+        exception::{AppError, ClientError},
+        models::{AppResp, AppRespOwned},
+    };
+
+    /// Log lines written by tests.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl MakeWriter<'_> for Captured {
+        type Writer = Self;
+
+        fn make_writer(&self) -> Self {
+            self.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        let log = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(log.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route(
+                "/fail",
+                get(|| async {
+                    let body: AppResp<()> =
+                        AppResp::Exception(AppError::from(ClientError::RateLimited));
+                    Json(body)
+                }),
+            )
+            .layer(access_log_layer(None))
+            .layer(middleware::from_fn(request_id));
+        let req = Request::builder().uri("/fail").body(Body::empty()).unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        let header = resp.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let AppRespOwned::<()>::Exception(e) = serde_json::from_slice(&body).unwrap() else {
+            panic!("error is expected");
+        };
+        assert_eq!(e.request_id.as_deref(), Some(header.as_str()));
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        assert!(log.contains(&format!("request_id={header}")), "{log}");
+    }
+}
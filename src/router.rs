@@ -0,0 +1,170 @@
+//! Routes of the API along with their middleware, see [`build_router`].
+//!
+//! Layers run outside in, from the last added: request id, API version, access log, CORS, pretty
+//! JSON and panic catching apply to every response, the signature to API routes, while the
+//! timeout, rate limit and in-flight limit only cover routes answering promptly. Streaming
+//! routes, e.g. `/download`, are exempt from the latter.
+use std::{path::PathBuf, time::Duration};
+
+use axum::{
+    middleware,
+    routing::{get, post},
+    Router,
+};
+use tower_http::{catch_panic::CatchPanicLayer, cors::CorsLayer};
+
+use crate::{
+    access_log::access_log_layer,
+    admin::require_admin,
+    api_key::require_api_key,
+    api_version::api_version,
+    controller::{
+        admin_export, admin_flush_logs, admin_history, admin_output, admin_pause, admin_reload,
+        admin_resume, admin_selftest, admin_stats, batch_events, capabilities, delete_task,
+        estimate, fetch_archive, fetch_audio, handler_panicked, health, init_summary, init_upload,
+        list_files, method_not_allowed, metrics, openapi, poll_status, retry_task, route_not_found,
+        session_tasks, stream_summary, upload_chunk, upload_finish, upload_init, upload_status,
+        version, video_metadata,
+    },
+    doc::{doc_router, DocOptions},
+    inflight::{limit_inflight, InflightLimit},
+    models::ServerState,
+    pretty::pretty_json,
+    rate_limit::rate_limit,
+    request_id::request_id,
+    signature::verify_signature,
+    timeout::request_timeout,
+};
+
+/// Router settings from command line, besides those held by [`ServerState`].
+pub struct RouterOptions {
+    pub cors: CorsLayer,
+    /// Dir served under `/doc`, none if absent.
+    pub doc: Option<(PathBuf, DocOptions)>,
+    pub max_inflight_requests: Option<usize>,
+    /// Requests taking longer are logged as `WARN`.
+    pub slow_request: Option<Duration>,
+    pub pretty_json: bool,
+}
+
+impl Default for RouterOptions {
+    fn default() -> Self {
+        Self {
+            cors: CorsLayer::new(),
+            doc: None,
+            max_inflight_requests: None,
+            slow_request: None,
+            pretty_json: false,
+        }
+    }
+}
+
+/// All routes over `state`, admin routes only if an admin token is set at this point.
+pub fn build_router(state: ServerState, options: RouterOptions) -> Router {
+    let admin = state.runtime.read().unwrap().admin_token.is_some();
+    // streaming routes are exempt, see `timeout`
+    let timeout = middleware::from_fn_with_state(state.runtime.clone(), request_timeout);
+    let mut router = Router::new()
+        .route(
+            "/init",
+            post(init_summary)
+                .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+                .layer(timeout.clone()),
+        )
+        .route(
+            "/retry",
+            post(retry_task)
+                .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+                .layer(timeout.clone()),
+        )
+        .route(
+            "/upload/init",
+            post(upload_init).layer(middleware::from_fn_with_state(state.clone(), rate_limit)),
+        )
+        .route("/upload/finish/:id", post(upload_finish))
+        .route(
+            "/metadata",
+            post(video_metadata)
+                .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+                .layer(timeout.clone()),
+        )
+        .route("/poll", post(poll_status).layer(timeout))
+        .route("/files", post(list_files))
+        .route("/delete", post(delete_task))
+        .route("/metrics", get(metrics))
+        .route("/version", get(version))
+        .route("/health", get(health))
+        .route("/estimate", get(estimate))
+        .route("/capabilities", get(capabilities))
+        .route("/openapi.json", get(openapi));
+    if admin {
+        let guard = middleware::from_fn_with_state(state.clone(), require_admin);
+        router = router
+            .route("/admin/history", get(admin_history).layer(guard.clone()))
+            .route("/admin/stats", get(admin_stats).layer(guard.clone()))
+            .route(
+                "/admin/output/:uuid",
+                get(admin_output).layer(guard.clone()),
+            )
+            .route("/admin/reload", post(admin_reload).layer(guard.clone()))
+            .route(
+                "/admin/flush-logs",
+                post(admin_flush_logs).layer(guard.clone()),
+            )
+            .route("/admin/pause", post(admin_pause).layer(guard.clone()))
+            .route("/admin/resume", post(admin_resume).layer(guard.clone()))
+            .route("/admin/selftest", post(admin_selftest).layer(guard));
+    }
+    if state.sessions.is_some() {
+        router = router.route(
+            "/session/tasks",
+            post(session_tasks).layer(middleware::from_fn_with_state(state.clone(), rate_limit)),
+        );
+    }
+    // only covers routes added so far, streaming routes are exempt
+    let mut router = router
+        .layer(middleware::from_fn_with_state(
+            options.max_inflight_requests.map(InflightLimit::new),
+            limit_inflight,
+        ))
+        .route("/stream-summary/:uuid", get(stream_summary))
+        .route("/events/batch", post(batch_events))
+        .route("/download", post(fetch_archive))
+        .route("/audio", post(fetch_audio));
+    if admin {
+        let guard = middleware::from_fn_with_state(state.clone(), require_admin);
+        router = router.route("/admin/export", get(admin_export).layer(guard));
+    }
+    // docs are browsed unsigned and without key, audio uploads are streamed to disk unsigned
+    let mut router = router
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            verify_signature,
+        ))
+        .route(
+            "/init/upload",
+            post(init_upload).layer(middleware::from_fn_with_state(state.clone(), rate_limit)),
+        )
+        .route("/upload/:id", get(upload_status).put(upload_chunk))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_api_key,
+        ));
+    if let Some((dir, doc_options)) = options.doc {
+        router = router.merge(doc_router(&dir, doc_options));
+    }
+    router
+        .fallback(route_not_found)
+        // only covers routes added so far
+        .method_not_allowed_fallback(method_not_allowed)
+        .with_state(state)
+        .layer(CatchPanicLayer::custom(handler_panicked))
+        .layer(middleware::from_fn_with_state(
+            options.pretty_json,
+            pretty_json,
+        ))
+        .layer(options.cors)
+        .layer(access_log_layer(options.slow_request))
+        .layer(middleware::from_fn(api_version))
+        .layer(middleware::from_fn(request_id))
+}
@@ -0,0 +1,145 @@
+//! Tasks grouped by a client chosen session id, enabled by `--enable-sessions`.
+//!
+//! `/init` and `/init/upload` with an `X-Session-Id` header add the task to that session, and
+//! `POST /session/tasks` lists them, so that a client finds its tasks again after a page refresh.
+//! Anyone knowing a session id sees its tasks, which is why the feature is off by default.
+//!
+//! Sessions live in memory only, and are dropped by the sweeper once no task is added to them
+//! for `--session-ttl`.
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::http::HeaderMap;
+
+use crate::exception::ClientError;
+
+pub const SESSION_HEADER: &str = "x-session-id";
+/// Longest session id accepted.
+const MAX_SESSION_ID_LEN: usize = 128;
+/// Tasks remembered per session, the oldest are forgotten beyond it.
+const MAX_SESSION_TASKS: usize = 1000;
+
+pub struct Sessions {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Session>>,
+}
+
+struct Session {
+    /// Earliest first.
+    uuids: Vec<String>,
+    last_active: Instant,
+}
+
+impl Sessions {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Add `uuid` to `session`, creating the session if absent.
+    pub fn add(&self, session: &str, uuid: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .entry(session.to_string())
+            .or_insert_with(|| Session {
+                uuids: Vec::new(),
+                last_active: Instant::now(),
+            });
+        entry.last_active = Instant::now();
+        if entry.uuids.iter().any(|u| u == uuid) {
+            return;
+        }
+        if entry.uuids.len() >= MAX_SESSION_TASKS {
+            entry.uuids.remove(0);
+        }
+        entry.uuids.push(uuid.to_string());
+    }
+
+    /// Uuids of `session`, earliest first, empty if unknown.
+    pub fn tasks(&self, session: &str) -> Vec<String> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(session)
+            .map(|session| session.uuids.clone())
+            .unwrap_or_default()
+    }
+
+    /// Drop sessions without a task added for `--session-ttl`, returning how many.
+    pub fn purge_idle(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, session| session.last_active.elapsed() < self.ttl);
+        before - entries.len()
+    }
+}
+
+/// Session id in `headers`, `None` if absent.
+///
+/// Fails with [`ClientError::MalformedRequest`] unless it is 1 to 128 visible ASCII characters.
+pub fn session_id(headers: &HeaderMap) -> Result<Option<String>, ClientError> {
+    let Some(value) = headers.get(SESSION_HEADER) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(id)
+            if !id.is_empty()
+                && id.len() <= MAX_SESSION_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic()) =>
+        {
+            Ok(Some(id.to_string()))
+        }
+        _ => Err(ClientError::MalformedRequest(format!(
+            "{SESSION_HEADER} must be 1 to {MAX_SESSION_ID_LEN} visible ASCII characters"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use axum::http::HeaderMap;
+
+    use super::{session_id, Sessions, SESSION_HEADER};
+
+    #[test]
+    fn test_sessions() {
+        let sessions = Sessions::new(Duration::from_secs(60));
+        sessions.add("s1", "a");
+        sessions.add("s1", "b");
+        sessions.add("s1", "a");
+        sessions.add("s2", "c");
+        assert_eq!(sessions.tasks("s1"), ["a", "b"]);
+        assert_eq!(sessions.tasks("s2"), ["c"]);
+        assert!(sessions.tasks("s3").is_empty());
+
+        assert_eq!(sessions.purge_idle(), 0);
+        sessions
+            .entries
+            .lock()
+            .unwrap()
+            .get_mut("s1")
+            .unwrap()
+            .last_active -= Duration::from_secs(61);
+        assert_eq!(sessions.purge_idle(), 1);
+        assert!(sessions.tasks("s1").is_empty());
+        assert_eq!(sessions.tasks("s2"), ["c"]);
+    }
+
+    #[test]
+    fn test_session_id() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_id(&headers).unwrap(), None);
+        headers.insert(SESSION_HEADER, "tab-1".parse().unwrap());
+        assert_eq!(session_id(&headers).unwrap().as_deref(), Some("tab-1"));
+        for invalid in ["", "a b", &"x".repeat(129)] {
+            headers.insert(SESSION_HEADER, invalid.parse().unwrap());
+            assert!(session_id(&headers).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Request signing for programmatic clients, see `--api-secret`.
+//!
+//! With a secret configured, each request must carry `X-Signature: sha256=<hex>`, the
+//! HMAC-SHA256 (RFC 2104) of its body under the secret in lowercase or uppercase hex, the
+//! `sha256=` prefix being optional. A body-less request signs the empty body. Requests without
+//! a valid signature are rejected with [`ClientError::InvalidSignature`] (HTTP 401) before the
+//! handler runs. Without a secret, nothing is verified, so browser clients are unaffected.
+//!
+//! The body is buffered to be verified, up to [`MAX_SIGNED_BODY_BYTES`] whatever the signature
+//! claims. Audio uploads, `/init/upload` and `PUT /upload/:id`, are streamed to disk instead and
+//! left unsigned, guarded by `--api-key` if set.
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::{
+    admin::constant_time_eq,
+    exception::{AppError, ClientError},
+    models::{AppResp, ServerState},
+};
+
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Largest body buffered for verification, far beyond any JSON request.
+const MAX_SIGNED_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Middleware rejecting requests not signed with `--api-secret`, if set.
+pub async fn verify_signature(
+    State(state): State<ServerState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(secret) = state.config.api_secret.as_deref() else {
+        return next.run(req).await;
+    };
+    let path = req.uri().path().to_string();
+    let signature = req
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_signature);
+    let Some(signature) = signature else {
+        tracing::warn!("\nUnsigned request to {path}.");
+        return rejection(StatusCode::UNAUTHORIZED, ClientError::InvalidSignature);
+    };
+    let (parts, body) = req.into_parts();
+    let Ok(body) = to_bytes(body, MAX_SIGNED_BODY_BYTES).await else {
+        tracing::warn!("\nBody of signed request to {path} is unreadable or too large.");
+        let e = ClientError::MalformedRequest("body is too large to verify".into());
+        return rejection(StatusCode::PAYLOAD_TOO_LARGE, e);
+    };
+    if !constant_time_eq(&hmac_sha256(secret.as_bytes(), &body), &signature) {
+        tracing::warn!("\nRequest to {path} has invalid signature.");
+        return rejection(StatusCode::UNAUTHORIZED, ClientError::InvalidSignature);
+    }
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+fn rejection(status: StatusCode, e: ClientError) -> Response {
+    let body: AppResp<()> = AppResp::Exception(AppError::from(e));
+    (status, Json(body)).into_response()
+}
+
+/// Digest in a header value, `None` unless 32 bytes of hex.
+fn parse_signature(value: &str) -> Option<[u8; 32]> {
+    let value = value.trim();
+    let hex = value.strip_prefix("sha256=").unwrap_or(value);
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK: usize = 64;
+    let mut block = [0; BLOCK];
+    match key.len() > BLOCK {
+        true => block[..32].copy_from_slice(&sha256(key)),
+        false => block[..key.len()].copy_from_slice(key),
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = sha256(&[&pad(0x36)[..], message].concat());
+    sha256(&[&pad(0x5c)[..], &inner[..]].concat())
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 (FIPS 180-4) of `message`.
+fn sha256(message: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend(((message.len() as u64) * 8).to_be_bytes());
+    for chunk in padded.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            (hh, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut digest = [0; 32];
+    for (bytes, word) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{
+        body::{to_bytes, Body, Bytes},
+        http::{Request, StatusCode},
+        middleware,
+        routing::post,
+        Router,
+    };
+    use futures_util::stream;
+    use tower::ServiceExt;
+
+    use super::{
+        hmac_sha256, parse_signature, sha256, verify_signature, MAX_SIGNED_BODY_BYTES,
+        SIGNATURE_HEADER,
+    };
+    use crate::models::{AppRespOwned, Config, ServerState};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(&[b'a'; 1000])),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+        // RFC 4231, test cases 2 and 6
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+
+        let digest = hmac_sha256(b"key", b"body");
+        assert_eq!(parse_signature(&hex(&digest)), Some(digest));
+        let prefixed = format!("sha256={}", hex(&digest).to_uppercase());
+        assert_eq!(parse_signature(&prefixed), Some(digest));
+        assert_eq!(parse_signature("sha256=abc"), None);
+        assert_eq!(parse_signature(&"zz".repeat(32)), None);
+    }
+
+    #[tokio::test]
+    async fn test_verify_signature() {
+        let dir = std::env::temp_dir();
+        let mut state = ServerState::for_test(dir.clone());
+        state.config = Arc::new(Config {
+            api_secret: Some("secret".into()),
+            ..Config::default()
+        });
+        let call = |state: ServerState, body: &'static str, signature: Option<String>| async move {
+            let app = Router::new()
+                .route("/init", post(|body: String| async move { body }))
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    verify_signature,
+                ))
+                .with_state(state);
+            let mut req = Request::post("/init");
+            if let Some(signature) = signature {
+                req = req.header(SIGNATURE_HEADER, signature);
+            }
+            let resp = app
+                .oneshot(req.body(Body::from(body)).unwrap())
+                .await
+                .unwrap();
+            let status = resp.status();
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        };
+        let body = r#"{"url":"https://a.b.c"}"#;
+        let signature = format!("sha256={}", hex(&hmac_sha256(b"secret", body.as_bytes())));
+
+        // handler sees the verified body
+        let resp = call(state.clone(), body, Some(signature.clone())).await;
+        assert_eq!(resp, (StatusCode::OK, body.to_string()));
+
+        let tampered = r#"{"url":"https://x.y.z"}"#;
+        for (body, signature) in [(tampered, Some(signature.clone())), (body, None)] {
+            let (status, resp) = call(state.clone(), body, signature).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            let AppRespOwned::<()>::Exception(e) = serde_json::from_str(&resp).unwrap() else {
+                panic!("unsigned request accepted");
+            };
+            assert_eq!(e.code, "invalid_signature");
+        }
+
+        // not verified without a secret
+        let resp = call(ServerState::for_test(dir), body, None).await;
+        assert_eq!(resp, (StatusCode::OK, body.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body() {
+        let mut state = ServerState::for_test(std::env::temp_dir());
+        state.config = Arc::new(Config {
+            api_secret: Some("secret".into()),
+            max_upload_bytes: 500 * 1024 * 1024,
+            ..Config::default()
+        });
+        let app = Router::new()
+            .route("/init", post(|| async {}))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                verify_signature,
+            ))
+            .with_state(state);
+        // 100 MiB under a made-up signature, counting what is read of it
+        const CHUNK: usize = 1024 * 1024;
+        let read = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&read);
+        let body = stream::iter((0..100).map(move |_| {
+            counter.fetch_add(CHUNK, Ordering::Relaxed);
+            Ok::<_, Infallible>(Bytes::from(vec![0; CHUNK]))
+        }));
+        let req = Request::post("/init")
+            .header(SIGNATURE_HEADER, "ab".repeat(32))
+            .body(Body::from_stream(body))
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(read.load(Ordering::Relaxed) <= MAX_SIGNED_BODY_BYTES + CHUNK);
+    }
+}
@@ -0,0 +1,86 @@
+//! Slots counting admitted tasks against `--max-active-tasks` and `--queue-capacity`.
+//!
+//! A slot is taken atomically against the limit by [`Slots::try_take`] before a task is admitted,
+//! rather than counting tasks and inserting later, so that concurrent `/init` requests cannot all
+//! pass the same count. The slot is then held by whatever the limit counts, e.g. the entry of the
+//! task, and released on drop.
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Number of held [`Slot`]s of one limit.
+#[derive(Clone, Default)]
+pub struct Slots(Arc<AtomicUsize>);
+
+/// One of [`Slots`], released on drop.
+pub struct Slot(Arc<AtomicUsize>);
+
+impl Slots {
+    pub fn held(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Take a slot unless `max` are already held, returning how many are held otherwise.
+    pub fn try_take(&self, max: usize) -> Result<Slot, usize> {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |held| {
+                (held < max).then_some(held + 1)
+            })
+            .map(|_| Slot(Arc::clone(&self.0)))
+    }
+
+    /// Take a slot regardless of any limit, e.g. for a task without one or recovered at startup.
+    pub fn take(&self) -> Slot {
+        self.0.fetch_add(1, Ordering::AcqRel);
+        Slot(Arc::clone(&self.0))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Barrier, thread};
+
+    use super::Slots;
+
+    #[test]
+    fn test_try_take() {
+        let slots = Slots::default();
+        let a = slots.try_take(2).unwrap();
+        let b = slots.take();
+        assert_eq!(slots.try_take(2).err(), Some(2));
+        drop(a);
+        let c = slots.try_take(2).unwrap();
+        assert_eq!(slots.held(), 2);
+        drop((b, c));
+        assert_eq!(slots.held(), 0);
+    }
+
+    #[test]
+    fn test_try_take_concurrently() {
+        let slots = Slots::default();
+        let barrier = Barrier::new(16);
+        let taken = thread::scope(|scope| {
+            let handles = (0..16)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        slots.try_take(4).ok()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .filter_map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(taken.len(), 4);
+        assert_eq!(slots.held(), 4);
+    }
+}
@@ -0,0 +1,219 @@
+//! Aggregates of recently finished tasks, queried by operators via `/admin/stats`.
+//!
+//! Unlike the cumulative histograms of `/metrics`, only tasks finished within `--stats-window`
+//! count, so that the numbers reflect current performance. Samples are kept as is and percentiles
+//! computed on query, which is cheap for the few thousand tasks a window holds.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::models::{Task, TaskStatus};
+
+/// Samples beyond it are evicted oldest first even if still within the window.
+const MAX_SAMPLES: usize = 100_000;
+
+pub struct Stats {
+    window: Duration,
+    samples: Mutex<VecDeque<Sample>>,
+}
+
+/// Outcome of a finished task.
+struct Sample {
+    finished_at: Instant,
+    /// `None` if the task failed before download finished, or skipped it, e.g. uploads.
+    download_secs: Option<f64>,
+    /// `None` unless the model succeeded.
+    model_secs: Option<f64>,
+    /// Code of the error, `None` if the task succeeded.
+    error: Option<&'static str>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StatsResp {
+    pub window_secs: u64,
+    /// Tasks finished within the window.
+    pub tasks: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Ratio of `succeeded` to `tasks`, `null` if there are no tasks.
+    pub success_rate: Option<f64>,
+    pub download: StageStats,
+    pub model: StageStats,
+    /// Number of failed tasks by error code.
+    pub errors: BTreeMap<&'static str, usize>,
+}
+
+/// Durations of a stage, where percentiles are `null` if it has not been observed.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StageStats {
+    pub count: usize,
+    pub median_secs: Option<f64>,
+    pub p95_secs: Option<f64>,
+}
+
+impl Stats {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: Mutex::default(),
+        }
+    }
+
+    /// Record `task` which has just entered a terminal status at `now`, no-op otherwise.
+    pub fn record(&self, task: &Task, now: Instant) {
+        let error = match &task.status {
+            TaskStatus::Done => None,
+            TaskStatus::Err(e) => Some(e.code()),
+            TaskStatus::Download
+            | TaskStatus::Queued
+            | TaskStatus::Pending
+            | TaskStatus::Translating
+            | TaskStatus::Compressing => return,
+        };
+        let downloaded_at = task.queued_at.or(task.pending_at);
+        let elapsed = |from: Option<Instant>, to: Option<Instant>| {
+            Some(to?.saturating_duration_since(from?).as_secs_f64())
+        };
+        let sample = Sample {
+            finished_at: now,
+            download_secs: elapsed(task.download_at, downloaded_at),
+            model_secs: elapsed(task.pending_at, task.done_at),
+            error,
+        };
+        let mut samples = self.samples.lock().unwrap();
+        self.evict(&mut samples, now);
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// Aggregates of tasks finished within the window before `now`.
+    pub fn snapshot(&self, now: Instant) -> StatsResp {
+        let mut samples = self.samples.lock().unwrap();
+        self.evict(&mut samples, now);
+        let mut errors = BTreeMap::new();
+        for code in samples.iter().filter_map(|sample| sample.error) {
+            *errors.entry(code).or_default() += 1;
+        }
+        let tasks = samples.len();
+        let failed = errors.values().sum();
+        let succeeded = tasks - failed;
+        StatsResp {
+            window_secs: self.window.as_secs(),
+            tasks,
+            succeeded,
+            failed,
+            success_rate: (tasks > 0).then(|| succeeded as f64 / tasks as f64),
+            download: StageStats::new(samples.iter().filter_map(|s| s.download_secs).collect()),
+            model: StageStats::new(samples.iter().filter_map(|s| s.model_secs).collect()),
+            errors,
+        }
+    }
+
+    fn evict(&self, samples: &mut VecDeque<Sample>, now: Instant) {
+        while samples
+            .front()
+            .is_some_and(|sample| now.saturating_duration_since(sample.finished_at) > self.window)
+        {
+            samples.pop_front();
+        }
+    }
+}
+
+impl StageStats {
+    fn new(mut secs: Vec<f64>) -> Self {
+        secs.sort_by(f64::total_cmp);
+        Self {
+            count: secs.len(),
+            median_secs: percentile(&secs, 50),
+            p95_secs: percentile(&secs, 95),
+        }
+    }
+}
+
+/// Nearest-rank percentile `p` of ascending `sorted`, `None` if empty.
+fn percentile(sorted: &[f64], p: usize) -> Option<f64> {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted.get(rank - 1).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{percentile, StageStats, Stats};
+    use crate::{
+        exception::{AppError, ClientError, ServerError},
+        models::{Task, TaskStatus},
+    };
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&[], 50), None);
+        assert_eq!(percentile(&[3.0], 95), Some(3.0));
+        let secs: Vec<_> = (1..=20).map(f64::from).collect();
+        assert_eq!(percentile(&secs, 50), Some(10.0));
+        assert_eq!(percentile(&secs, 95), Some(19.0));
+    }
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        let empty = stats.snapshot(start);
+        assert_eq!((empty.tasks, empty.success_rate), (0, None));
+        let unobserved = || StageStats {
+            count: 0,
+            median_secs: None,
+            p95_secs: None,
+        };
+        assert_eq!((empty.download, empty.model), (unobserved(), unobserved()));
+        assert!(empty.errors.is_empty());
+
+        let secs = |secs: u64| start + Duration::from_secs(secs);
+        let mut task = Task::new(TaskStatus::Download, start);
+        task.transition(TaskStatus::Pending, secs(2));
+        task.transition(TaskStatus::Done, secs(10));
+        stats.record(&task, secs(10));
+
+        let mut task = Task::new(TaskStatus::Download, start);
+        task.transition(TaskStatus::Pending, secs(4));
+        let err = AppError::from(ServerError::AiModel("out of memory".into()));
+        task.transition(TaskStatus::Err(err), secs(5));
+        stats.record(&task, secs(5));
+
+        let err = AppError::from(ClientError::VideoLinkNotExist("url".into()));
+        let task = Task::new(TaskStatus::Err(err), secs(30));
+        stats.record(&task, secs(30));
+
+        // in progress, not counted
+        stats.record(&Task::new(TaskStatus::Pending, secs(30)), secs(30));
+
+        let resp = stats.snapshot(secs(30));
+        assert_eq!((resp.tasks, resp.succeeded, resp.failed), (3, 1, 2));
+        assert_eq!(resp.success_rate, Some(1.0 / 3.0));
+        assert_eq!(
+            resp.download,
+            StageStats {
+                count: 2,
+                median_secs: Some(2.0),
+                p95_secs: Some(4.0),
+            }
+        );
+        assert_eq!(resp.model.count, 1);
+        assert_eq!(resp.model.median_secs, Some(8.0));
+        assert_eq!(resp.errors["ai_model"], 1);
+        assert_eq!(resp.errors["video_link_not_exist"], 1);
+
+        // the first two fall out of the window
+        let resp = stats.snapshot(secs(71));
+        assert_eq!((resp.tasks, resp.failed), (1, 1));
+        assert_eq!(resp.download.count, 0);
+    }
+}
@@ -0,0 +1,166 @@
+//! Files of tasks behind keys rather than paths, so that they may live elsewhere than the local
+//! disk later, e.g. in an object store shared by several instances.
+//!
+//! A key is a relative, `/` separated path such as `<uuid>/request.json`, without `.` or `..`.
+//! [`LocalStorage`] maps keys to files under the work dir, as the server always stored them.
+//! Only some files go through [`Storage`] so far, others are still accessed by path.
+use std::{
+    io::{self, ErrorKind},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::executor::BoxFuture;
+
+pub trait Storage: Send + Sync {
+    /// Store `data` at `key`, replacing what was there.
+    fn write<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Content at `key`, [`ErrorKind::NotFound`] if absent.
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Vec<u8>>>;
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, bool>;
+
+    /// Remove `key`, [`ErrorKind::NotFound`] if absent.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Keys under `prefix`, a key itself or empty for all, sorted.
+    #[allow(dead_code)]
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, io::Result<Vec<String>>>;
+}
+
+/// Keys as files under `root`.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Path of `key`, [`ErrorKind::InvalidInput`] if it would escape `root`.
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        let relative = Path::new(key);
+        let valid = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !valid {
+            let e = format!("storage key \"{key}\" is not a relative path");
+            return Err(io::Error::new(ErrorKind::InvalidInput, e));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl Storage for LocalStorage {
+    fn write<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(path, data).await
+        })
+    }
+
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Vec<u8>>> {
+        Box::pin(async move { tokio::fs::read(self.path(key)?).await })
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            match self.path(key) {
+                Ok(path) => tokio::fs::metadata(path)
+                    .await
+                    .is_ok_and(|metadata| metadata.is_file()),
+                Err(_) => false,
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move { tokio::fs::remove_file(self.path(key)?).await })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, io::Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            let prefix = prefix.trim_end_matches('/');
+            let mut pending = vec![prefix.to_string()];
+            while let Some(dir) = pending.pop() {
+                let mut entries = match tokio::fs::read_dir(self.path(&dir)?).await {
+                    Ok(entries) => entries,
+                    // nothing stored under it yet
+                    Err(e) if e.kind() == ErrorKind::NotFound && dir == prefix => break,
+                    Err(e) => return Err(e),
+                };
+                while let Some(entry) = entries.next_entry().await? {
+                    let Ok(name) = entry.file_name().into_string() else {
+                        continue;
+                    };
+                    let key = match dir.is_empty() {
+                        true => name,
+                        false => format!("{dir}/{name}"),
+                    };
+                    match entry.file_type().await?.is_dir() {
+                        true => pending.push(key),
+                        false => keys.push(key),
+                    }
+                }
+            }
+            keys.sort();
+            Ok(keys)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::ErrorKind};
+
+    use uuid::Uuid;
+
+    use super::{LocalStorage, Storage};
+
+    #[tokio::test]
+    async fn test_local_storage() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let storage = LocalStorage::new(&dir);
+        assert_eq!(storage.list("").await.unwrap(), Vec::<String>::new());
+
+        storage
+            .write("a/request.json", b"{}".to_vec())
+            .await
+            .unwrap();
+        storage.write("a/b/c.txt", b"c".to_vec()).await.unwrap();
+        storage.write("d.txt", b"d".to_vec()).await.unwrap();
+        assert_eq!(fs::read(dir.join("a/b/c.txt")).unwrap(), b"c");
+        assert_eq!(storage.read("a/request.json").await.unwrap(), b"{}");
+        assert!(storage.exists("d.txt").await);
+        // dirs are not keys
+        assert!(!storage.exists("a").await);
+        assert_eq!(
+            storage.list("").await.unwrap(),
+            ["a/b/c.txt", "a/request.json", "d.txt"]
+        );
+        assert_eq!(
+            storage.list("a/").await.unwrap(),
+            ["a/b/c.txt", "a/request.json"]
+        );
+        assert_eq!(storage.list("x").await.unwrap(), Vec::<String>::new());
+
+        storage.delete("a/request.json").await.unwrap();
+        assert!(!storage.exists("a/request.json").await);
+        let e = storage.read("a/request.json").await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+        let e = storage.delete("a/request.json").await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+
+        for escaping in ["../x", "/etc/passwd", "a/../../x", "./a"] {
+            let e = storage.read(escaping).await.unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput, "{escaping}");
+            assert!(!storage.exists(escaping).await);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
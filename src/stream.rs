@@ -0,0 +1,117 @@
+//! Summary streamed as server-sent events while the model writes it, see `/stream-summary`.
+//!
+//! The result file is tailed every [`TAIL_INTERVAL`], each appended chunk sent as an unnamed
+//! event. The task table tells when to stop: the task reaching `Done` ends the stream with a
+//! `done` event after the last chunk, and the task failing ends it with an `error` event holding
+//! the error as `/poll` would return it.
+use std::{convert::Infallible, io::SeekFrom, path::PathBuf, time::Duration};
+
+use axum::response::sse::Event;
+use futures_util::{stream, Stream};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::{
+    exception::{AppError, ClientError, ServerError},
+    models::{ServerState, TaskStatus},
+};
+
+/// How often the result file is checked for appended text.
+pub const TAIL_INTERVAL: Duration = Duration::from_millis(250);
+
+struct Tail {
+    state: ServerState,
+    uuid: String,
+    /// Result file in the task dir, moved along with it with `--output-dir`.
+    filename: String,
+    /// Bytes of the file read so far.
+    offset: u64,
+    /// Incomplete character at the end of what was read.
+    pending: Vec<u8>,
+}
+
+/// Events of the summary of `uuid` written to `filename`, ending once the task finishes.
+///
+/// A finished task is removed from the task table unless `--keep-completed`, as in `/poll`.
+pub fn summary_events(
+    state: ServerState,
+    uuid: String,
+    filename: String,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let tail = Tail {
+        state,
+        uuid,
+        filename,
+        offset: 0,
+        pending: Vec::new(),
+    };
+    stream::unfold(Some(tail), |tail| async move {
+        let mut tail = tail?;
+        loop {
+            // status before text, so that all text of a finished task is read
+            let status = tail.state.get_task(&tail.uuid).await;
+            let finished = matches!(status, None | Some(TaskStatus::Done | TaskStatus::Err(_)));
+            let chunk = match tail.read(finished).await {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    let path = tail.path();
+                    tracing::error!("\nFailed to tail \"{}\": {e}", path.display());
+                    let e = ServerError::ReadFile(path.display().to_string());
+                    return Some((Ok(error_event(&e.into())), None));
+                }
+            };
+            if !chunk.is_empty() {
+                return Some((Ok(Event::default().data(chunk)), Some(tail)));
+            }
+            let event = match status {
+                None => error_event(&ClientError::TokenNotExist(tail.uuid.clone()).into()),
+                Some(TaskStatus::Done) => Event::default().event("done").data(""),
+                Some(TaskStatus::Err(e)) => error_event(&e),
+                Some(_) => {
+                    tokio::time::sleep(TAIL_INTERVAL).await;
+                    continue;
+                }
+            };
+            if tail.state.config.keep_completed.is_none() {
+                tail.state.observe_finished(&tail.uuid).await;
+            }
+            tracing::info!("\nUser {} finishes streaming summary.", tail.uuid);
+            return Some((Ok(event), None));
+        }
+    })
+}
+
+impl Tail {
+    fn path(&self) -> PathBuf {
+        self.state.result_dir(&self.uuid).join(&self.filename)
+    }
+
+    /// Text appended since the last read, all of it if `flush`, or else up to the last complete
+    /// character.
+    async fn read(&mut self, flush: bool) -> std::io::Result<String> {
+        let mut file = match tokio::fs::File::open(self.path()).await {
+            Ok(file) => file,
+            // not yet created by the model
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(String::new()),
+            Err(e) => return Err(e),
+        };
+        file.seek(SeekFrom::Start(self.offset)).await?;
+        let read = file.read_to_end(&mut self.pending).await?;
+        self.offset += read as u64;
+        let end = match std::str::from_utf8(&self.pending) {
+            // incomplete character being written
+            Err(e) if e.error_len().is_none() && !flush => e.valid_up_to(),
+            _ => self.pending.len(),
+        };
+        let text = String::from_utf8_lossy(&self.pending[..end]).into_owned();
+        self.pending.drain(..end);
+        // carriage returns cannot be sent in events
+        Ok(text.replace("\r\n", "\n").replace('\r', "\n"))
+    }
+}
+
+fn error_event(e: &AppError) -> Event {
+    Event::default()
+        .event("error")
+        .json_data(e)
+        .unwrap_or_default()
+}
@@ -0,0 +1,157 @@
+//! Log of each task in its own dir, see `--per-task-logs`.
+//!
+//! The pipeline of a task runs in a `task` span, whose `log` field is the path of [`TASK_LOG`] in
+//! the task dir. [`TaskLogLayer`] appends every event emitted within that span to the file, so a
+//! task can be followed without grepping the shared log for its uuid. Stage transitions are
+//! logged as `DEBUG`, thus only found in the task log.
+//!
+//! The file lives among the results, so it is listed by `/files`, put into the archive, and moved
+//! to `--output-dir` along with them, see [`moved_to`].
+use std::{
+    fmt::{self, Write as _},
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use time::{macros::format_description, OffsetDateTime, UtcOffset};
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    span::{Attributes, Id, Record},
+    Event, Span, Subscriber,
+};
+use tracing_subscriber::{
+    filter::{Filtered, Targets},
+    layer::Context,
+    registry::LookupSpan,
+    Layer,
+};
+
+pub const TASK_LOG: &str = "task.log";
+
+const TASK_SPAN: &str = "task";
+
+/// Span of `uuid` logging into `dir`, none unless `enabled`.
+pub fn task_span(enabled: bool, uuid: &str, dir: &Path) -> Span {
+    if !enabled {
+        return Span::none();
+    }
+    tracing::info_span!(
+        TASK_SPAN,
+        uuid = %uuid,
+        log = %dir.join(TASK_LOG).display()
+    )
+}
+
+/// Log the rest of the current task into `dir`, once its log is moved there.
+pub fn moved_to(dir: &Path) {
+    Span::current().record("log", tracing::field::display(dir.join(TASK_LOG).display()));
+}
+
+/// [`TaskLogLayer`] receiving `DEBUG` events of this crate, `INFO` of others.
+pub fn task_log_layer<S>(offset: UtcOffset) -> Filtered<TaskLogLayer, Targets, S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let filter = Targets::new()
+        .with_default(LevelFilter::INFO)
+        .with_target(env!("CARGO_CRATE_NAME"), LevelFilter::DEBUG);
+    TaskLogLayer { offset }.with_filter(filter)
+}
+
+/// Appends events within a `task` span to its log, with time at `offset`.
+pub struct TaskLogLayer {
+    offset: UtcOffset,
+}
+
+/// Current path of the log of a `task` span.
+struct TaskLogPath(PathBuf);
+
+impl<S> Layer<S> for TaskLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != TASK_SPAN {
+            return;
+        }
+        let mut visitor = LogField::default();
+        attrs.record(&mut visitor);
+        if let (Some(path), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(TaskLogPath(path));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut visitor = LogField::default();
+        values.record(&mut visitor);
+        if let (Some(path), Some(span)) = (visitor.0, ctx.span(id)) {
+            if let Some(log) = span.extensions_mut().get_mut::<TaskLogPath>() {
+                log.0 = path;
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(path) = ctx.event_scope(event).and_then(|mut scope| {
+            scope.find_map(|span| {
+                span.extensions()
+                    .get::<TaskLogPath>()
+                    .map(|log| log.0.clone())
+            })
+        }) else {
+            return;
+        };
+        let mut fields = EventFields::default();
+        event.record(&mut fields);
+        let time = OffsetDateTime::now_utc()
+            .to_offset(self.offset)
+            .format(format_description!(
+                "[year]/[month]/[day]-[hour]:[minute]:[second]"
+            ))
+            .unwrap_or_default();
+        let line = format!(
+            "{time} {:>5}  {}{}\n",
+            event.metadata().level().as_str(),
+            fields.message.trim(),
+            fields.rest
+        );
+        // never creates the task dir, e.g. once deleted
+        let _ = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+    }
+}
+
+/// `log` field of a `task` span.
+#[derive(Default)]
+struct LogField(Option<PathBuf>);
+
+impl Visit for LogField {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "log" {
+            self.0 = Some(PathBuf::from(format!("{value:?}")));
+        }
+    }
+}
+
+/// Message of an event, along with its other fields as `name=value`.
+#[derive(Default)]
+struct EventFields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for EventFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{value:?}"),
+            name => {
+                let _ = write!(self.rest, " {name}={value:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Task table split into independently locked shards, keyed by hash of uuid.
+//!
+//! Every request touches the table, a single lock would serialize all of them. With shards,
+//! requests of different tasks rarely wait for each other, while those of the same task still
+//! observe a consistent entry.
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::models::Task;
+
+pub type TaskMap = HashMap<String, Task>;
+
+/// Enough to keep contention low for a few dozen cores.
+const DEFAULT_SHARDS: usize = 16;
+
+pub struct TaskTable {
+    shards: Box<[RwLock<TaskMap>]>,
+}
+
+impl Default for TaskTable {
+    fn default() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+}
+
+impl TaskTable {
+    pub fn with_shards(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| RwLock::default()).collect(),
+        }
+    }
+
+    /// Lock the shard holding `uuid` for reading.
+    pub async fn read(&self, uuid: &str) -> RwLockReadGuard<'_, TaskMap> {
+        self.shard(uuid).read().await
+    }
+
+    /// Lock the shard holding `uuid` for writing.
+    pub async fn write(&self, uuid: &str) -> RwLockWriteGuard<'_, TaskMap> {
+        self.shard(uuid).write().await
+    }
+
+    /// Number of tasks, locking one shard at a time.
+    pub async fn len(&self) -> usize {
+        let mut len = 0;
+        for shard in self.shards.iter() {
+            len += shard.read().await.len();
+        }
+        len
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        for shard in self.shards.iter() {
+            if !shard.read().await.is_empty() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Keep only tasks satisfying `f`, locking one shard at a time. Returns how many were removed.
+    pub async fn retain(&self, mut f: impl FnMut(&Task) -> bool) -> usize {
+        let mut removed = 0;
+        for shard in self.shards.iter() {
+            let mut guard = shard.write().await;
+            let before = guard.len();
+            guard.retain(|_, task| f(task));
+            removed += before - guard.len();
+        }
+        removed
+    }
+
+    fn shard(&self, uuid: &str) -> &RwLock<TaskMap> {
+        let mut hasher = DefaultHasher::new();
+        uuid.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::TaskTable;
+    use crate::models::{Task, TaskStatus};
+
+    /// `tasks` concurrent writers, each transitioning its own task `rounds` times.
+    async fn load(table: Arc<TaskTable>, tasks: usize, rounds: usize) -> Duration {
+        let start = Instant::now();
+        let handles: Vec<_> = (0..tasks)
+            .map(|i| {
+                let table = Arc::clone(&table);
+                tokio::spawn(async move {
+                    let uuid = format!("task-{i}");
+                    for _ in 0..rounds {
+                        let now = Instant::now();
+                        let mut guard = table.write(&uuid).await;
+                        match guard.get_mut(&uuid) {
+                            Some(task) => {
+                                task.transition(TaskStatus::Pending, now);
+                            }
+                            None => {
+                                guard.insert(uuid.clone(), Task::new(TaskStatus::Download, now));
+                            }
+                        }
+                        drop(guard);
+                        let _ = table.read(&uuid).await.get(&uuid).is_some();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        start.elapsed()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_tasks() {
+        let table = Arc::new(TaskTable::default());
+        load(Arc::clone(&table), 64, 100).await;
+        for i in 0..64 {
+            let uuid = format!("task-{i}");
+            let guard = table.read(&uuid).await;
+            assert!(matches!(guard[&uuid].status, TaskStatus::Pending));
+        }
+        let removed = table
+            .retain(|task| !matches!(task.status, TaskStatus::Pending))
+            .await;
+        assert_eq!(removed, 64);
+    }
+
+    /// Compare a single lock against shards under load, run with
+    /// `cargo test --release -- --ignored --nocapture bench_contention`.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore]
+    async fn bench_contention() {
+        for shards in [1, 16] {
+            let elapsed = load(Arc::new(TaskTable::with_shards(shards)), 256, 2000).await;
+            println!("{shards} shards: {elapsed:?}");
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Deadline of non-streaming requests, see `--request-timeout-secs`.
+//!
+//! Requests not answered in time are cut off with [`ServerError::Timeout`] in the usual JSON
+//! envelope (HTTP 408), whether the client is slow to send the body or the handler is slow to
+//! respond. Streaming routes such as `/download` and `/init/upload` are left alone, so that large
+//! transfers are not interrupted. A cut off `/init` releases the url and idempotency key it
+//! claimed, unless its task is already spawned.
+use std::sync::{Arc, RwLock};
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::{
+    exception::{AppError, ServerError},
+    models::AppResp,
+    reload::RuntimeConfig,
+};
+
+/// Middleware failing requests that take longer than the current `request_timeout`.
+///
+/// No-op if `request_timeout` is `None`.
+pub async fn request_timeout(
+    State(runtime): State<Arc<RwLock<RuntimeConfig>>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(timeout) = runtime.read().unwrap().request_timeout else {
+        return next.run(req).await;
+    };
+    let path = req.uri().path().to_string();
+    match tokio::time::timeout(timeout, next.run(req)).await {
+        Ok(resp) => resp,
+        Err(_) => {
+            tracing::warn!("\nRequest to {path} times out after {timeout:?}.");
+            let body: AppResp<()> =
+                AppResp::Exception(AppError::from(ServerError::Timeout(timeout.as_secs())));
+            (StatusCode::REQUEST_TIMEOUT, Json(body)).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, RwLock},
+        time::Duration,
+    };
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+        middleware,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::request_timeout;
+    use crate::reload::RuntimeConfig;
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let app = Router::new()
+            .route("/fast", get(|| async {}))
+            .route("/slow", get(|| tokio::time::sleep(Duration::from_secs(60))))
+            .layer(middleware::from_fn_with_state(
+                Arc::new(RwLock::new(RuntimeConfig {
+                    request_timeout: Some(Duration::from_millis(10)),
+                    ..RuntimeConfig::default()
+                })),
+                request_timeout,
+            ));
+        let req = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+
+        let resp = app.clone().oneshot(req("/fast")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = app.oneshot(req("/slow")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::REQUEST_TIMEOUT);
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["err"]["err"]["code"], "timeout");
+    }
+}
@@ -0,0 +1,180 @@
+//! Resumable uploads of large audio, for clients on flaky connections.
+//!
+//! `POST /upload/init` opens an upload, `PUT /upload/:id?offset=N` appends a chunk and
+//! `POST /upload/finish/:id` turns it into a task, see [`crate::controller::upload_init`].
+//!
+//! Chunks are appended to `uploads/<id>` under the work dir, which task scans skip as it is not
+//! named by uuid. Uploads idle for `--upload-idle-timeout` are dropped by the sweeper, along with
+//! files left over from a previous run.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Mutex as AsyncMutex;
+use uuid::Uuid;
+
+use crate::{exception::ServerError, models::ModelOptions};
+
+/// Dir of uploads in progress under the work dir.
+pub const UPLOAD_DIR: &str = "uploads";
+
+/// Uploads in progress by id.
+pub struct Uploads {
+    dir: PathBuf,
+    entries: Mutex<HashMap<String, Arc<AsyncMutex<PendingUpload>>>>,
+}
+
+/// An upload in progress, locked by whoever appends to or finishes it.
+pub struct PendingUpload {
+    pub options: ModelOptions,
+    /// Extension of the audio, as told when the upload is opened.
+    pub extension: &'static str,
+    /// Bytes received so far, where the next chunk must begin.
+    pub received: u64,
+    pub last_active: Instant,
+    /// Set once finished or dropped, after which the file is gone.
+    pub closed: bool,
+}
+
+impl Uploads {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            entries: Mutex::default(),
+        }
+    }
+
+    /// File the chunks of `id` are appended to.
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    /// Open an upload with an empty file, returning its id.
+    pub async fn open(
+        &self,
+        options: ModelOptions,
+        extension: &'static str,
+    ) -> Result<String, ServerError> {
+        let id = Uuid::new_v4().to_string();
+        let path = self.path(&id);
+        let open_err = |e| {
+            tracing::error!("\nFailed to open upload \"{}\": {e}", path.display());
+            ServerError::OpenFile(path.display().to_string())
+        };
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(open_err)?;
+        tokio::fs::File::create(&path).await.map_err(open_err)?;
+        let upload = PendingUpload {
+            options,
+            extension,
+            received: 0,
+            last_active: Instant::now(),
+            closed: false,
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(id.clone(), Arc::new(AsyncMutex::new(upload)));
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<AsyncMutex<PendingUpload>>> {
+        self.entries.lock().unwrap().get(id).cloned()
+    }
+
+    /// Stop tracking `id`, whose holder of the lock is responsible for its file.
+    pub fn remove(&self, id: &str) {
+        self.entries.lock().unwrap().remove(id);
+    }
+
+    /// Drop uploads idle for `idle`, and files in the upload dir not belonging to any upload,
+    /// returning the number of files removed.
+    ///
+    /// Uploads being appended to are left alone.
+    pub async fn purge_idle(&self, idle: Duration) -> usize {
+        let mut stale = Vec::new();
+        self.entries.lock().unwrap().retain(|id, upload| {
+            let Ok(mut upload) = upload.try_lock() else {
+                return true;
+            };
+            if upload.last_active.elapsed() < idle {
+                return true;
+            }
+            upload.closed = true;
+            stale.push(self.path(id));
+            false
+        });
+        stale.extend(self.orphans(idle).await);
+        let mut removed = 0;
+        for path in stale {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => removed += 1,
+                Err(e) => tracing::error!("\nFailed to remove upload \"{}\": {e}", path.display()),
+            }
+        }
+        removed
+    }
+
+    /// Files in the upload dir unknown to `self` and idle for `idle`, e.g. of a previous run.
+    async fn orphans(&self, idle: Duration) -> Vec<PathBuf> {
+        let Ok(mut entries) = tokio::fs::read_dir(&self.dir).await else {
+            return Vec::new();
+        };
+        let mut orphans = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let known = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|id| self.entries.lock().unwrap().contains_key(id));
+            if known || !is_idle(&path, idle).await {
+                continue;
+            }
+            orphans.push(path);
+        }
+        orphans
+    }
+}
+
+async fn is_idle(path: &Path, idle: Duration) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().unwrap_or_default() >= idle)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, time::Duration};
+
+    use uuid::Uuid;
+
+    use super::Uploads;
+    use crate::models::ModelOptions;
+
+    #[tokio::test]
+    async fn test_purge_idle() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let uploads = Uploads::new(&dir);
+        let idle = uploads.open(ModelOptions::default(), "mp3").await.unwrap();
+        let active = uploads.open(ModelOptions::default(), "mp3").await.unwrap();
+        let orphan = dir.join("orphan");
+        fs::write(&orphan, "x").unwrap();
+
+        uploads.get(&idle).unwrap().lock().await.last_active -= Duration::from_secs(60);
+        let guard = uploads.get(&active).unwrap();
+        let _locked = guard.lock().await;
+        // the orphan is not idle yet
+        assert_eq!(uploads.purge_idle(Duration::from_secs(30)).await, 1);
+        assert!(uploads.get(&idle).is_none() && !uploads.path(&idle).exists());
+        assert!(uploads.get(&active).is_some() && orphan.exists());
+
+        assert_eq!(uploads.purge_idle(Duration::ZERO).await, 1);
+        assert!(!orphan.exists() && uploads.path(&active).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+//! Queries against `yt-dlp` that do not download the video itself.
+use std::process::{Output, Stdio};
+
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    exception::{AppError, ClientError, ServerError},
+    executor::mask_credentials,
+    redact::redact_in,
+};
+
+/// `yt-dlp --dump-json` output beyond it is not parsed, the format list makes up most of it.
+const MAX_METADATA_BYTES: u64 = 8 * 1024 * 1024;
+/// Only the head of stderr is kept for error classification.
+const MAX_STDERR_BYTES: u64 = 64 * 1024;
+
+/// Subset of `yt-dlp --dump-json` output.
+#[derive(Deserialize, Debug, Clone)]
+pub struct VideoMetadata {
+    pub title: String,
+    /// Absent for live streams.
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub uploader: Option<String>,
+    /// Url of the thumbnail image.
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+}
+
+impl VideoMetadata {
+    pub fn duration_secs(&self) -> Option<u64> {
+        self.duration.map(|secs| secs.round() as u64)
+    }
+}
+
+/// Fetch metadata of `url` without downloading it, using `yt-dlp` in the `server` conda env,
+/// through `proxy` and with `cookies_file` if any.
+///
+/// An inaccessible video yields the [`ClientError`] of [`classify_download_error`].
+pub async fn fetch_metadata(
+    url: &str,
+    proxy: Option<&str>,
+    cookies_file: Option<&str>,
+) -> Result<VideoMetadata, AppError> {
+    let mut args = vec![
+        "run",
+        "-n",
+        "server",
+        "yt-dlp",
+        "--dump-json",
+        "--no-download",
+        "--no-playlist",
+    ];
+    if let Some(proxy) = proxy {
+        args.extend(["--proxy", proxy]);
+    }
+    if let Some(cookies_file) = cookies_file {
+        args.extend(["--cookies", cookies_file]);
+    }
+    args.push(url);
+    let Ok(cmd) = output_capped(&args).await else {
+        let args: Vec<_> = args.iter().map(|arg| mask_credentials(arg)).collect();
+        let command = format!("conda {}", args.join(" "));
+        tracing::error!("\nFailed to issue command \"{command}\".");
+        return Err(ServerError::IssueCommand(command).into());
+    };
+
+    if !cmd.status.success() {
+        let raw = String::from_utf8_lossy(&cmd.stderr);
+        if let Some(e) = classify_download_error(url, &raw) {
+            return Err(e.into());
+        }
+        let stderr = redact_in(&raw, url);
+        tracing::error!("\n`yt-dlp` metadata query throws unexpected error: \n{stderr}");
+        return Err(ServerError::VideoMetadata(stderr).into());
+    }
+    if cmd.stdout.len() as u64 > MAX_METADATA_BYTES {
+        tracing::error!("\n`yt-dlp` metadata exceeds {MAX_METADATA_BYTES} bytes.");
+        let msg = format!("metadata exceeds {MAX_METADATA_BYTES} bytes");
+        return Err(ServerError::VideoMetadata(msg).into());
+    }
+    serde_json::from_slice(&cmd.stdout).map_err(|e| {
+        tracing::error!("\n`yt-dlp` metadata is unexpected: {e}");
+        ServerError::VideoMetadata(e.to_string()).into()
+    })
+}
+
+/// Run `conda` with `args`, reading at most one byte beyond [`MAX_METADATA_BYTES`] of stdout and
+/// [`MAX_STDERR_BYTES`] of stderr.
+///
+/// A pipe is closed once its limit is hit, which fails further writes of the command, so that it
+/// exits rather than blocking.
+async fn output_capped(args: &[&str]) -> std::io::Result<Output> {
+    let mut child = tokio::process::Command::new("conda")
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        return Err(std::io::ErrorKind::BrokenPipe.into());
+    };
+    let (stdout, stderr) = tokio::join!(
+        read_capped(stdout, MAX_METADATA_BYTES + 1),
+        read_capped(stderr, MAX_STDERR_BYTES),
+    );
+    let (stdout, stderr) = (stdout?, stderr?);
+    if stdout.len() as u64 > MAX_METADATA_BYTES {
+        // no need to wait for the rest
+        child.start_kill()?;
+    }
+    let status = child.wait().await?;
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// Read `pipe` until EOF or `limit` bytes, dropping it either way.
+async fn read_capped(pipe: impl AsyncRead + Unpin, limit: u64) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    pipe.take(limit).read_to_end(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Reject `url` unless it is an absolute http(s) url, before it reaches `yt-dlp`, which would
+/// otherwise take e.g. `-o` or a local path.
+pub fn check_url(url: &str) -> Result<(), ClientError> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"));
+    let host = rest.and_then(|rest| rest.split(['/', '?', '#']).next());
+    let valid = host.is_some_and(|host| !host.is_empty())
+        && !url.chars().any(|c| c.is_whitespace() || c.is_control());
+    match valid {
+        true => Ok(()),
+        false => Err(ClientError::VideoLinkNotExist(url.to_string())),
+    }
+}
+
+/// Why a video cannot be accessed, as told by `yt-dlp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VideoProblem {
+    Private,
+    GeoBlocked,
+    Removed,
+    /// Only visible to signed-in users, e.g. age-restricted or members-only, see
+    /// `--cookies-file`.
+    RequiresAuth,
+    /// The url does not point to a video.
+    LinkNotExist,
+}
+
+/// Messages of `yt-dlp` and the problem each tells, the first match winning. YouTube prefixes
+/// several of them with `Video unavailable.`, so that one comes last.
+const VIDEO_PROBLEMS: &[(&str, VideoProblem)] = &[
+    ("Private video", VideoProblem::Private),
+    ("This video is private", VideoProblem::Private),
+    ("available in your country", VideoProblem::GeoBlocked),
+    ("not available from your location", VideoProblem::GeoBlocked),
+    ("geo restriction", VideoProblem::GeoBlocked),
+    ("has been removed", VideoProblem::Removed),
+    (
+        "account associated with this video has been terminated",
+        VideoProblem::Removed,
+    ),
+    ("This video is no longer available", VideoProblem::Removed),
+    ("Sign in to confirm your age", VideoProblem::RequiresAuth),
+    ("members-only content", VideoProblem::RequiresAuth),
+    (
+        "Join this channel to get access",
+        VideoProblem::RequiresAuth,
+    ),
+    ("is not a valid URL", VideoProblem::LinkNotExist),
+    ("Failed to resolve", VideoProblem::LinkNotExist),
+    ("Incomplete YouTube ID", VideoProblem::LinkNotExist),
+    ("Video unavailable", VideoProblem::LinkNotExist),
+];
+
+fn video_problem(err_msg: &str) -> Option<VideoProblem> {
+    VIDEO_PROBLEMS
+        .iter()
+        .find(|(pattern, _)| err_msg.contains(pattern))
+        .map(|&(_, problem)| problem)
+}
+
+/// Error of the user if `yt-dlp` failed on `url` because the video is inaccessible, e.g.
+/// private, `None` if it failed otherwise.
+pub fn classify_download_error(url: &str, err_msg: &str) -> Option<ClientError> {
+    let url = url.to_string();
+    Some(match video_problem(err_msg)? {
+        VideoProblem::Private => ClientError::VideoPrivate(url),
+        VideoProblem::GeoBlocked => ClientError::VideoGeoBlocked(url),
+        VideoProblem::Removed => ClientError::VideoRemoved(url),
+        VideoProblem::RequiresAuth => ClientError::VideoRequiresAuth(url),
+        VideoProblem::LinkNotExist => ClientError::VideoLinkNotExist(url),
+    })
+}
+
+/// Size reported by `yt-dlp` of a file it refused for `--max-filesize`, `None` unless refused
+/// for that, 0 if the size is not stated.
+pub fn oversized_bytes(output: &str) -> Option<u64> {
+    let (_, rest) = output.split_once("File is larger than max-filesize")?;
+    let size = rest
+        .trim_start()
+        .strip_prefix('(')
+        .and_then(|rest| rest.split_once(" bytes"))
+        .and_then(|(size, _)| size.trim().parse().ok());
+    Some(size.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_url, classify_download_error, oversized_bytes, video_problem, VideoProblem};
+
+    #[test]
+    fn test_check_url() {
+        for url in [
+            "https://www.youtube.com/watch?v=abc",
+            "http://youtu.be/abc",
+            "https://example.com",
+        ] {
+            assert!(check_url(url).is_ok(), "{url}");
+        }
+        for url in [
+            "",
+            "-o /tmp/x",
+            "/etc/passwd",
+            "file:///etc/passwd",
+            "https://",
+            "https:///path",
+            "https://youtu.be/abc --exec rm",
+            "ftp://example.com",
+        ] {
+            assert!(check_url(url).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_is_auth_problem() {
+        let age = "ERROR: [youtube] abc: Sign in to confirm your age. This video may be \
+            inappropriate for some users. Use --cookies-from-browser or --cookies for the \
+            authentication.";
+        let members = "ERROR: [youtube] abc: This video is available to this channel's members \
+            on level: Member (or any higher level). Join this channel to get access to \
+            members-only content like this video, and other exclusive perks.";
+        assert_eq!(video_problem(age), Some(VideoProblem::RequiresAuth));
+        assert_eq!(video_problem(members), Some(VideoProblem::RequiresAuth));
+        assert_eq!(
+            video_problem("ERROR: [youtube] abc: Video unavailable"),
+            Some(VideoProblem::LinkNotExist)
+        );
+    }
+
+    #[test]
+    fn test_classify_download_error() {
+        for (stderr, expected) in [
+            (
+                "ERROR: [youtube] abc: Private video. Sign in if you've been granted access to \
+                this video",
+                Some(VideoProblem::Private),
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable. This video is private",
+                Some(VideoProblem::Private),
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable. The uploader has not made this video \
+                available in your country",
+                Some(VideoProblem::GeoBlocked),
+            ),
+            (
+                "ERROR: [youtube] abc: This video is not available from your location due to \
+                geo restriction",
+                Some(VideoProblem::GeoBlocked),
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable. This video has been removed by the \
+                uploader",
+                Some(VideoProblem::Removed),
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable. This video has been removed for \
+                violating YouTube's Terms of Service",
+                Some(VideoProblem::Removed),
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable. This video is no longer available \
+                because the YouTube account associated with this video has been terminated.",
+                Some(VideoProblem::Removed),
+            ),
+            (
+                "ERROR: [generic] 'abc' is not a valid URL. Set --default-search \"ytsearch\" \
+                (or run  yt-dlp \"ytsearch:abc\" ) to search YouTube",
+                Some(VideoProblem::LinkNotExist),
+            ),
+            (
+                "ERROR: [youtube:truncated_id] abc: Incomplete YouTube ID abc. URL \
+                https://www.youtube.com/watch?v=abc looks truncated.",
+                Some(VideoProblem::LinkNotExist),
+            ),
+            (
+                "ERROR: unable to download video data: HTTP Error 403: Forbidden",
+                None,
+            ),
+        ] {
+            assert_eq!(video_problem(stderr), expected, "{stderr}");
+        }
+
+        let url = "https://youtu.be/abc";
+        let private = "ERROR: [youtube] abc: Private video. Sign in if you've been granted access";
+        let e = classify_download_error(url, private).unwrap();
+        assert_eq!(
+            (e.code(), e.to_string().contains(url)),
+            ("video_private", true)
+        );
+        assert!(classify_download_error(url, "ERROR: HTTP Error 500").is_none());
+    }
+
+    #[test]
+    fn test_oversized_bytes() {
+        let refused = "[download] File is larger than max-filesize (734003200 bytes > 524288000 \
+            bytes). Aborting.";
+        assert_eq!(oversized_bytes(refused), Some(734003200));
+        let sizeless = "[download] File is larger than max-filesize. Aborting.";
+        assert_eq!(oversized_bytes(sizeless), Some(0));
+        assert_eq!(
+            oversized_bytes("ERROR: [youtube] abc: Video unavailable"),
+            None
+        );
+    }
+}
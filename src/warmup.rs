@@ -0,0 +1,92 @@
+//! Keeping the model loaded between tasks, see `--model-warmup`.
+//!
+//! Loading the weights makes the first task after a while slower than the others. With a warmup
+//! script configured, it is run once at startup and then every `--model-warmup-interval`, unless
+//! a model is running anyway. Whether the last warmup succeeded is reported by `/health` as
+//! `model_ready`, which a successful task also sets.
+use std::{sync::atomic::Ordering, time::Instant};
+
+use crate::{metrics::Stage, models::ServerState};
+
+/// Run the warmup script of `state` periodically, returning at once if there is none.
+pub async fn keep_warm(state: ServerState) {
+    let Some(script) = state.config.model_warmup.clone() else {
+        return;
+    };
+    let mut interval = tokio::time::interval(state.config.model_warmup_interval);
+    loop {
+        // the first tick is immediate, warming up at startup
+        interval.tick().await;
+        if state.metrics.active(Stage::Model) > 0 {
+            continue;
+        }
+        warm(&state, &script).await;
+    }
+}
+
+/// Run `script` once, recording in `state` whether the model is ready.
+pub async fn warm(state: &ServerState, script: &str) {
+    let start = Instant::now();
+    let ready = match state.executor.warmup(script).await {
+        Ok(_) => {
+            tracing::info!("\nModel warmup succeeds in {:?}.", start.elapsed());
+            true
+        }
+        Err(e) => {
+            tracing::error!("\nModel warmup fails: {e}");
+            false
+        }
+    };
+    state.model_ready.store(ready, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use axum::extract::State;
+
+    use super::warm;
+    use crate::{
+        controller::health,
+        exception::{AppError, ServerError},
+        executor::MockExecutor,
+        models::{AppResp, Config, ServerState},
+    };
+
+    #[tokio::test]
+    async fn test_warm() {
+        let dir = std::env::temp_dir();
+        let mut state = ServerState::for_test(dir);
+        let model_ready = |state: ServerState| async move {
+            let AppResp::Success(resp) = health(State(state)).await.0 else {
+                panic!("health fails");
+            };
+            resp.model_ready
+        };
+        // not tracked without a warmup script
+        assert_eq!(model_ready(state.clone()).await, None);
+
+        state.config = Arc::new(Config {
+            model_warmup: Some("warmup.sh".into()),
+            ..Config::default()
+        });
+        assert_eq!(model_ready(state.clone()).await, Some(false));
+        let executor = Arc::new(MockExecutor::default());
+        state.executor = executor.clone();
+        warm(&state, "warmup.sh").await;
+        assert_eq!(executor.warmups.load(Ordering::Relaxed), 1);
+        assert_eq!(model_ready(state.clone()).await, Some(true));
+
+        state.executor = Arc::new(MockExecutor {
+            warmup_err: Some(AppError::Server(ServerError::AiModel("oom".into()))),
+            warmups: AtomicUsize::new(0),
+            ..MockExecutor::default()
+        });
+        warm(&state, "warmup.sh").await;
+        assert_eq!(model_ready(state).await, Some(false));
+    }
+}
@@ -0,0 +1,68 @@
+//! Liveness watchdog of model runs, see `--stall-timeout`.
+//!
+//! Unlike a total timeout, a long model run is left alone as long as it makes progress, i.e.
+//! some file in its task dir grows or is modified. A run without progress for the whole
+//! `--stall-timeout` is abandoned, which kills the model process.
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::clean::usage;
+
+/// Resolve once nothing under `dir` changes in size or modification time for `stall`.
+pub async fn stalled(dir: &Path, stall: Duration) -> Duration {
+    let period = (stall / 4).clamp(Duration::from_millis(10), Duration::from_secs(30));
+    let mut last = snapshot(dir.to_path_buf()).await;
+    let mut since = tokio::time::Instant::now();
+    loop {
+        tokio::time::sleep(period).await;
+        let current = snapshot(dir.to_path_buf()).await;
+        if current != last {
+            last = current;
+            since = tokio::time::Instant::now();
+        } else if since.elapsed() >= stall {
+            return since.elapsed();
+        }
+    }
+}
+
+/// Latest modification time and total size under `dir`, `None` if it cannot be read.
+async fn snapshot(dir: PathBuf) -> Option<(SystemTime, u64)> {
+    tokio::task::spawn_blocking(move || usage(&dir).ok())
+        .await
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, time::Duration};
+
+    use uuid::Uuid;
+
+    use super::stalled;
+
+    #[tokio::test]
+    async fn test_stalled() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let stall = Duration::from_millis(200);
+
+        // keeps writing for longer than `stall`
+        let writer = {
+            let path = dir.join("summary.txt");
+            tokio::spawn(async move {
+                for i in 0..8 {
+                    fs::write(&path, "x".repeat(i + 1)).unwrap();
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+        };
+        let begin = tokio::time::Instant::now();
+        stalled(&dir, stall).await;
+        assert!(begin.elapsed() >= Duration::from_millis(400));
+        writer.await.unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -14,6 +14,8 @@ import librosa
 parser = argparse.ArgumentParser(description="movie to summary model")
 parser.add_argument('audio_path', type=str, help='path to input audio in mp3 format')
 parser.add_argument('output_dir', type=str, help='generated file dir')
+parser.add_argument('language', type=str, nargs='?', default='', help='transcription language, empty for en')
+parser.add_argument('model', type=str, nargs='?', default='', help='whisper model size, empty for medium')
 args = parser.parse_args()
 
 mp3_file_path = args.audio_path
@@ -41,8 +43,8 @@ def speech_recognition(model_name, input_audio, output_subtitle_path, decode_opt
     with open(output_subtitle_path, "w", encoding="utf-8") as file:
         file.write(srt_content)
 
-model_name = 'medium'
-language = 'en'
+model_name = args.model or 'medium'
+language = args.language or 'en'
 initial_prompt = ''
 temperature = 0.0
 output_subtitle_path = f"{output_dir}/subtitle.srt"
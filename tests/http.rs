@@ -0,0 +1,345 @@
+//! The API served on a local port as in production, driven over HTTP, with a
+//! [`MockExecutor`] in place of `yt-dlp` and the model.
+use std::{
+    collections::HashMap,
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+use shen_server::{
+    doc::DocOptions,
+    exception::ServerError,
+    executor::MockExecutor,
+    models::{Config, ServerState},
+    router::{build_router, RouterOptions},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use uuid::Uuid;
+
+/// Longest wait for a task to reach a stage.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Serve `state` on an ephemeral port.
+async fn serve(state: ServerState) -> SocketAddr {
+    serve_with(state, RouterOptions::default()).await
+}
+
+async fn serve_with(state: ServerState, options: RouterOptions) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = build_router(state, options);
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap();
+    });
+    addr
+}
+
+struct Resp {
+    status: u16,
+    /// Lowercase names.
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Resp {
+    fn json(&self) -> Value {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|e| panic!("{e}: {}", String::from_utf8_lossy(&self.body)))
+    }
+
+    fn header(&self, name: &str) -> &str {
+        self.headers
+            .get(name)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+}
+
+async fn request(addr: SocketAddr, method: &str, path: &str, body: Option<Value>) -> Resp {
+    request_with(addr, method, path, &[], body).await
+}
+
+/// HTTP/1.1 request with extra `headers` over a fresh connection, closed by the server once
+/// answered.
+async fn request_with(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<Value>,
+) -> Resp {
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let extra: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}\r\n"))
+        .collect();
+    let head = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n{extra}\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body.as_bytes()).await.unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await.unwrap();
+
+    let split = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8(raw[..split].to_vec()).unwrap();
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap().split(' ').nth(1).unwrap();
+    let headers: HashMap<_, _> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    let mut body = raw[split + 4..].to_vec();
+    if headers
+        .get("transfer-encoding")
+        .is_some_and(|te| te == "chunked")
+    {
+        body = dechunk(&body);
+    }
+    Resp {
+        status: status.parse().unwrap(),
+        headers,
+        body,
+    }
+}
+
+/// Body of `Transfer-Encoding: chunked`.
+fn dechunk(mut chunked: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    loop {
+        let end = chunked.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size = std::str::from_utf8(&chunked[..end]).unwrap();
+        let size = usize::from_str_radix(size.split(';').next().unwrap(), 16).unwrap();
+        if size == 0 {
+            return body;
+        }
+        let start = end + 2;
+        body.extend(&chunked[start..start + size]);
+        chunked = &chunked[start + size + 2..];
+    }
+}
+
+async fn init(addr: SocketAddr, url: &str) -> Resp {
+    request(addr, "POST", "/init", Some(json!({ "url": url }))).await
+}
+
+async fn poll(addr: SocketAddr, uuid: &str) -> Value {
+    let resp = request(addr, "POST", "/poll", Some(json!({ "uuid": uuid }))).await;
+    assert_eq!(resp.status, 200);
+    resp.json()
+}
+
+/// Poll `uuid` until it is done or fails, returning the last response and every stage seen.
+async fn poll_until_done(addr: SocketAddr, uuid: &str) -> (Value, Vec<String>) {
+    let start = Instant::now();
+    let mut stages: Vec<String> = Vec::new();
+    loop {
+        let resp = poll(addr, uuid).await;
+        if resp["success"] == false {
+            return (resp, stages);
+        }
+        let stage = resp["data"]["stage"].as_str().unwrap().to_string();
+        if stages.last() != Some(&stage) {
+            stages.push(stage);
+        }
+        if resp["data"]["done"] == true {
+            return (resp, stages);
+        }
+        assert!(start.elapsed() < TIMEOUT, "stuck in {stages:?}");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Error code of a version 1 envelope.
+fn code(resp: &Value) -> &str {
+    assert_eq!(resp["success"], false, "{resp}");
+    resp["err"]["err"]["code"].as_str().unwrap()
+}
+
+#[tokio::test]
+async fn test_init_poll_download() {
+    let dir = temp_dir();
+    let mut state = ServerState::for_test(&dir);
+    state.executor = Arc::new(MockExecutor {
+        step_delay: Duration::from_millis(200),
+        ..MockExecutor::default()
+    });
+    let addr = serve(state).await;
+
+    let resp = init(addr, "https://a.b.c").await;
+    assert_eq!(resp.status, 200);
+    assert!(resp.header("content-type").starts_with("application/json"));
+    assert!(!resp.header("x-request-id").is_empty());
+    let body = resp.json();
+    assert_eq!(body["success"], true, "{body}");
+    let uuid = body["data"]["uuid"].as_str().unwrap().to_string();
+    assert_eq!(resp.header("x-task-id"), uuid);
+
+    let (resp, stages) = poll_until_done(addr, &uuid).await;
+    assert_eq!(stages, ["Download", "Pending", "Done"]);
+    assert_eq!(resp["data"]["result"], "a summary");
+    assert_eq!(resp["data"]["preview"], "a summary");
+
+    // compressed by the first request
+    let start = Instant::now();
+    let download = json!({ "uuid": uuid });
+    let resp = loop {
+        let resp = request(addr, "POST", "/download", Some(download.clone())).await;
+        assert_eq!(resp.status, 200);
+        if resp.header("content-type") == "application/zip" {
+            break resp;
+        }
+        assert_eq!(resp.json()["data"]["init"], true);
+        assert!(start.elapsed() < TIMEOUT, "never compressed");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert!(resp.header("content-disposition").starts_with("attachment"));
+    assert_eq!(resp.header("x-content-type-options"), "nosniff");
+    assert_eq!(resp.body, b"PK");
+    // forgotten once downloaded, files staying
+    assert_eq!(code(&poll(addr, &uuid).await), "token_not_exist");
+
+    let summary = json!({ "uuid": uuid, "file": "summary" });
+    let resp = request(addr, "POST", "/download", Some(summary)).await;
+    assert_eq!(resp.status, 200);
+    assert!(resp.header("content-type").starts_with("text/plain"));
+    assert_eq!(resp.body, b"a summary");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_error_paths() {
+    let dir = temp_dir();
+    let mut state = ServerState::for_test(&dir);
+    state.executor = Arc::new(MockExecutor {
+        model_err: Some(ServerError::AiModel("out of memory".into()).into()),
+        ..MockExecutor::default()
+    });
+    let addr = serve(state).await;
+
+    let unknown = Uuid::new_v4().to_string();
+    assert_eq!(code(&poll(addr, &unknown).await), "token_not_exist");
+    let resp = request(addr, "POST", "/download", Some(json!({ "uuid": unknown }))).await;
+    assert_eq!(code(&resp.json()), "token_not_exist");
+
+    let resp = init(addr, "not a url").await;
+    let body = resp.json();
+    assert_eq!(code(&body), "validation");
+    assert_eq!(body["err"]["err"]["fields"][0]["field"], "url");
+    assert!(!resp.headers.contains_key("x-task-id"));
+
+    let resp = init(addr, "https://a.b.c").await;
+    let uuid = resp.json()["data"]["uuid"].as_str().unwrap().to_string();
+    let (resp, _) = poll_until_done(addr, &uuid).await;
+    assert_eq!(code(&resp), "ai_model");
+    assert_eq!(resp["err"]["err"]["source"], "server");
+    // reported once
+    assert_eq!(code(&poll(addr, &uuid).await), "token_not_exist");
+
+    let resp = request(addr, "POST", "/nowhere", None).await;
+    assert_eq!(resp.status, 404);
+    let resp = request(addr, "GET", "/init", None).await;
+    assert_eq!(resp.status, 405);
+    let resp = request(addr, "POST", "/poll", Some(json!({ "id": uuid }))).await;
+    assert!(resp.status >= 400, "{}", resp.status);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_init_timed_out() {
+    let dir = temp_dir();
+    let mut state = ServerState::for_test(&dir);
+    state.config = Arc::new(Config {
+        // queried by /init
+        max_duration_secs: Some(3600),
+        ..Config::default()
+    });
+    state.executor = Arc::new(MockExecutor {
+        step_delay: Duration::from_millis(200),
+        ..MockExecutor::default()
+    });
+    state.runtime.write().unwrap().request_timeout = Some(Duration::from_millis(50));
+    let addr = serve(state.clone()).await;
+
+    let resp = init(addr, "https://a.b.c").await;
+    assert_eq!(resp.status, 408);
+    assert_eq!(code(&resp.json()), "timeout");
+
+    state.runtime.write().unwrap().request_timeout = Some(Duration::from_secs(5));
+    let resp = init(addr, "https://a.b.c").await;
+    assert_eq!(resp.status, 200);
+    let uuid = resp.json()["data"]["uuid"].as_str().unwrap().to_string();
+    let (resp, _) = poll_until_done(addr, &uuid).await;
+    assert_eq!(resp["data"]["result"], "a summary");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_api_key() {
+    let dir = temp_dir();
+    let doc_dir = temp_dir();
+    fs::write(doc_dir.join("index.html"), "docs").unwrap();
+    let mut state = ServerState::for_test(&dir);
+    state.config = Arc::new(Config {
+        api_key: Some("secret".into()),
+        ..Config::default()
+    });
+    let options = RouterOptions {
+        doc: Some((doc_dir.clone(), DocOptions::default())),
+        ..RouterOptions::default()
+    };
+    let addr = serve_with(state, options).await;
+    let key = [("X-Api-Key", "secret")];
+    let wrong = [("X-Api-Key", "secreT")];
+
+    let init = json!({ "url": "https://a.b.c" });
+    for headers in [&[][..], &wrong, &[("X-Api-Key", "secre")]] {
+        let resp = request_with(addr, "POST", "/init", headers, Some(init.clone())).await;
+        assert_eq!(resp.status, 401, "{headers:?}");
+        assert_eq!(code(&resp.json()), "unauthorized");
+    }
+    let resp = request_with(addr, "POST", "/init", &key, Some(init)).await;
+    assert_eq!(resp.status, 200);
+    let uuid = resp.json()["data"]["uuid"].as_str().unwrap().to_string();
+
+    for path in ["/poll", "/download"] {
+        let body = json!({ "uuid": uuid });
+        for headers in [&[][..], &wrong] {
+            let resp = request_with(addr, "POST", path, headers, Some(body.clone())).await;
+            assert_eq!(resp.status, 401, "{path} {headers:?}");
+        }
+        let resp = request_with(addr, "POST", path, &key, Some(body)).await;
+        assert_eq!(resp.status, 200, "{path}");
+        assert_eq!(resp.json()["success"], true, "{path}");
+    }
+
+    // docs are public, with or without key
+    for headers in [&[][..], &wrong, &key] {
+        let resp = request_with(addr, "GET", "/doc/", headers, None).await;
+        assert_eq!(resp.status, 200, "{headers:?}");
+        assert_eq!(resp.body, b"docs");
+    }
+    fs::remove_dir_all(dir).unwrap();
+    fs::remove_dir_all(doc_dir).unwrap();
+}